@@ -0,0 +1,637 @@
+//! A compact binary encoding of simulation state, for replays and network sync.
+//!
+//! Everything is little-endian. A frame starts with a header:
+//!
+//! | bytes | field |
+//! |-------|-------|
+//! | 4 | magic, `PEST` |
+//! | 2 | format version |
+//! | 2 | flags, see `FLAG_DELTA`, `FLAG_QUANTIZED_POSITION` and `FLAG_QUANTIZED_VELOCITY` |
+//! | 8 | step index |
+//! | 8 | step index of the base frame, delta frames only |
+//! | 4 | size of a position quantum, quantized positions only |
+//! | 4 | size of a velocity quantum, quantized velocities only |
+//! | 4 | particle count |
+//!
+//! Each particle then holds its position, velocity, acceleration, damping, mass, inverse mass,
+//! accumulated force and a tag byte followed by the charge if it has one. Floats are stored as
+//! `f32` bits, quantized vectors as three `i32` counts of the quantum.
+//!
+//! A delta frame is encoded against an earlier frame. Each particle starts with a byte saying which
+//! fields changed and only those fields follow, so particles at rest cost a single byte. Decoding a
+//! delta frame needs the decoded base frame.
+//!
+//! The decoder reads every version up to `BINARY_VERSION`. Later versions will only add fields behind
+//! new flags, or bump the version, so frames written now keep loading.
+
+use std::{cell::RefCell, error, fmt, rc::Rc};
+
+use crate::{core::Vector3, particle::Particle};
+
+pub const BINARY_MAGIC: [u8; 4] = *b"PEST";
+/// The version written by this build
+pub const BINARY_VERSION: u16 = 1;
+
+/// The frame only holds what changed since its base frame
+pub const FLAG_DELTA: u16 = 1;
+/// Positions are stored as multiples of a quantum
+pub const FLAG_QUANTIZED_POSITION: u16 = 1 << 1;
+/// Velocities are stored as multiples of a quantum
+pub const FLAG_QUANTIZED_VELOCITY: u16 = 1 << 2;
+const KNOWN_FLAGS: u16 = FLAG_DELTA | FLAG_QUANTIZED_POSITION | FLAG_QUANTIZED_VELOCITY;
+
+// Bits of the per particle change mask in delta frames
+const CHANGED_POSITION: u8 = 1;
+const CHANGED_VELOCITY: u8 = 1 << 1;
+const CHANGED_ACCELERATION: u8 = 1 << 2;
+const CHANGED_DAMPING: u8 = 1 << 3;
+const CHANGED_MASS: u8 = 1 << 4;
+const CHANGED_FORCE: u8 = 1 << 5;
+const CHANGED_CHARGE: u8 = 1 << 6;
+const CHANGED_ALL: u8 = 0x7f;
+
+/// Why a frame could not be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinaryError {
+    /// The data does not start with `BINARY_MAGIC`
+    BadMagic,
+    /// The frame was written by a newer version of the format
+    UnsupportedVersion(u16),
+    /// The frame uses flags this version does not know
+    UnknownFlags(u16),
+    /// The data ends part way through the frame
+    Truncated,
+    /// A delta frame was decoded without a base frame
+    MissingBase,
+    /// A delta frame was decoded against a different base frame than it was encoded against
+    WrongBase { expected: u64, found: u64 },
+    /// The data is the right length but does not make sense
+    Invalid(String),
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            BinaryError::BadMagic => write!(f, "not a binary state frame"),
+            BinaryError::UnsupportedVersion(version) => {
+                write!(f, "format version {} is newer than the supported version {}", version, BINARY_VERSION)
+            }
+            BinaryError::UnknownFlags(flags) => write!(f, "unknown flags {:#06x}", flags),
+            BinaryError::Truncated => write!(f, "the frame is truncated"),
+            BinaryError::MissingBase => write!(f, "a delta frame needs its base frame to decode"),
+            BinaryError::WrongBase { expected, found } => {
+                write!(f, "the delta frame is against step {} but the base frame is step {}", expected, found)
+            }
+            BinaryError::Invalid(message) => write!(f, "{}", message),
+        };
+    }
+}
+
+impl error::Error for BinaryError {}
+
+/// Optional quantization of positions and velocities.
+/// Values are rounded to the nearest multiple of the quantum, which must be positive and finite,
+/// encoding panics otherwise. Values too large for an `i32` count of quanta are clamped.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BinaryOptions {
+    /// Size of a position quantum in metres, `None` stores full floats
+    pub position_quantum: Option<f32>,
+    /// Size of a velocity quantum in metres per second, `None` stores full floats
+    pub velocity_quantum: Option<f32>,
+}
+
+/// Appends little-endian values to a buffer.
+#[derive(Debug, Clone, Default)]
+pub struct BinaryWriter {
+    bytes: Vec<u8>,
+}
+
+impl BinaryWriter {
+    pub fn new() -> BinaryWriter {
+        return BinaryWriter::default();
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_i32(&mut self, value: i32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_bits().to_le_bytes());
+    }
+
+    pub fn write_vector3(&mut self, vector: &Vector3) {
+        self.write_f32(vector.x);
+        self.write_f32(vector.y);
+        self.write_f32(vector.z);
+    }
+
+    /// Writes the vector as three whole counts of the quantum
+    pub fn write_quantized_vector3(&mut self, vector: &Vector3, quantum: f32) {
+        self.write_i32(quantize(vector.x, quantum));
+        self.write_i32(quantize(vector.y, quantum));
+        self.write_i32(quantize(vector.z, quantum));
+    }
+
+    /// Writes every field of the particle at full precision
+    pub fn write_particle(&mut self, particle: &Particle) {
+        self.write_particle_fields(particle, CHANGED_ALL, &BinaryOptions::default());
+    }
+
+    /// Writes the fields picked by the change mask, in mask bit order
+    fn write_particle_fields(&mut self, particle: &Particle, mask: u8, options: &BinaryOptions) {
+        if mask & CHANGED_POSITION != 0 {
+            match options.position_quantum {
+                Some(quantum) => self.write_quantized_vector3(&particle.get_position(), quantum),
+                None => self.write_vector3(&particle.get_position()),
+            }
+        }
+        if mask & CHANGED_VELOCITY != 0 {
+            match options.velocity_quantum {
+                Some(quantum) => self.write_quantized_vector3(&particle.get_velocity(), quantum),
+                None => self.write_vector3(&particle.get_velocity()),
+            }
+        }
+        if mask & CHANGED_ACCELERATION != 0 { self.write_vector3(&particle.get_acceleration()); }
+        if mask & CHANGED_DAMPING != 0 { self.write_f32(particle.damping); }
+        if mask & CHANGED_MASS != 0 {
+            self.write_f32(particle.get_mass());
+            self.write_f32(particle.get_inverse_mass());
+        }
+        if mask & CHANGED_FORCE != 0 { self.write_vector3(&particle.force_accum); }
+        if mask & CHANGED_CHARGE != 0 {
+            match particle.get_charge() {
+                Some(charge) => {
+                    self.write_u8(1);
+                    self.write_f32(charge);
+                }
+                None => self.write_u8(0),
+            }
+        }
+    }
+
+    pub fn get_bytes(&self) -> &[u8] {
+        return &self.bytes;
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        return self.bytes;
+    }
+}
+
+/// Reads little-endian values from a buffer, failing with `BinaryError::Truncated` past the end.
+pub struct BinaryReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> BinaryReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> BinaryReader<'a> {
+        return BinaryReader {
+            bytes,
+            offset: 0
+        };
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], BinaryError> {
+        let end: usize = self.offset + N;
+        if end > self.bytes.len() { return Err(BinaryError::Truncated); }
+        let mut value: [u8; N] = [0; N];
+        value.copy_from_slice(&self.bytes[self.offset..end]);
+        self.offset = end;
+        return Ok(value);
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, BinaryError> {
+        return Ok(self.take::<1>()?[0]);
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, BinaryError> {
+        return Ok(u16::from_le_bytes(self.take()?));
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, BinaryError> {
+        return Ok(u32::from_le_bytes(self.take()?));
+    }
+
+    pub fn read_i32(&mut self) -> Result<i32, BinaryError> {
+        return Ok(i32::from_le_bytes(self.take()?));
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, BinaryError> {
+        return Ok(u64::from_le_bytes(self.take()?));
+    }
+
+    pub fn read_f32(&mut self) -> Result<f32, BinaryError> {
+        return Ok(f32::from_bits(u32::from_le_bytes(self.take()?)));
+    }
+
+    pub fn read_vector3(&mut self) -> Result<Vector3, BinaryError> {
+        return Ok(Vector3::new(self.read_f32()?, self.read_f32()?, self.read_f32()?));
+    }
+
+    pub fn read_quantized_vector3(&mut self, quantum: f32) -> Result<Vector3, BinaryError> {
+        return Ok(Vector3::new(
+            self.read_i32()? as f32 * quantum,
+            self.read_i32()? as f32 * quantum,
+            self.read_i32()? as f32 * quantum
+        ));
+    }
+
+    /// Reads a particle written by `BinaryWriter::write_particle`
+    pub fn read_particle(&mut self) -> Result<Particle, BinaryError> {
+        let mut particle: Particle = blank_particle();
+        self.read_particle_fields(&mut particle, CHANGED_ALL, &BinaryOptions::default())?;
+        return Ok(particle);
+    }
+
+    /// Overwrites the fields picked by the change mask
+    fn read_particle_fields(&mut self, particle: &mut Particle, mask: u8, options: &BinaryOptions) -> Result<(), BinaryError> {
+        if mask & CHANGED_POSITION != 0 {
+            particle.position = match options.position_quantum {
+                Some(quantum) => self.read_quantized_vector3(quantum)?,
+                None => self.read_vector3()?,
+            };
+        }
+        if mask & CHANGED_VELOCITY != 0 {
+            particle.velocity = match options.velocity_quantum {
+                Some(quantum) => self.read_quantized_vector3(quantum)?,
+                None => self.read_vector3()?,
+            };
+        }
+        if mask & CHANGED_ACCELERATION != 0 { particle.acceleration = self.read_vector3()?; }
+        if mask & CHANGED_DAMPING != 0 { particle.damping = self.read_f32()?; }
+        if mask & CHANGED_MASS != 0 {
+            let mass: f32 = self.read_f32()?;
+            let inverse_mass: f32 = self.read_f32()?;
+            particle.restore_mass(mass, inverse_mass);
+        }
+        if mask & CHANGED_FORCE != 0 { particle.force_accum = self.read_vector3()?; }
+        if mask & CHANGED_CHARGE != 0 {
+            match self.read_u8()? {
+                0 => particle.clear_charge(),
+                1 => particle.set_charge(self.read_f32()?),
+                tag => return Err(BinaryError::Invalid(format!("bad charge tag {}", tag))),
+            }
+        }
+        return Ok(());
+    }
+
+    /// Returns true once every byte has been read
+    pub fn is_finished(&self) -> bool {
+        return self.offset == self.bytes.len();
+    }
+}
+
+/// The particle fields are read into, every field is overwritten
+fn blank_particle() -> Particle {
+    return Particle::new(Vector3::default(), Vector3::default(), Vector3::default(), 1.0, 1.0);
+}
+
+fn is_valid_quantum(quantum: f32) -> bool {
+    return quantum > 0.0 && quantum.is_finite();
+}
+
+fn check_options(options: &BinaryOptions) {
+    for quantum in [options.position_quantum, options.velocity_quantum].into_iter().flatten() {
+        assert!(is_valid_quantum(quantum), "quanta must be positive and finite, found {}", quantum);
+    }
+}
+
+/// Reads a quantum from a header, rejecting any the encoder could not have written
+fn read_quantum(reader: &mut BinaryReader, name: &str) -> Result<f32, BinaryError> {
+    let quantum: f32 = reader.read_f32()?;
+    if !is_valid_quantum(quantum) {
+        return Err(BinaryError::Invalid(format!("the {} quantum must be positive and finite, found {}", name, quantum)));
+    }
+    return Ok(quantum);
+}
+
+fn quantize(value: f32, quantum: f32) -> i32 {
+    return (value / quantum).round() as i32;
+}
+
+fn same_bits(a: &Vector3, b: &Vector3) -> bool {
+    return a.x.to_bits() == b.x.to_bits() && a.y.to_bits() == b.y.to_bits() && a.z.to_bits() == b.z.to_bits();
+}
+
+fn same_quantized(a: &Vector3, b: &Vector3, quantum: Option<f32>) -> bool {
+    let Some(quantum) = quantum else { return same_bits(a, b); };
+    return quantize(a.x, quantum) == quantize(b.x, quantum)
+        && quantize(a.y, quantum) == quantize(b.y, quantum)
+        && quantize(a.z, quantum) == quantize(b.z, quantum);
+}
+
+/// Works out which fields of the particle differ from the base, comparing bits
+/// or, for quantized fields, the stored counts
+fn change_mask(particle: &Particle, base: &Particle, options: &BinaryOptions) -> u8 {
+    let mut mask: u8 = 0;
+    if !same_quantized(&particle.get_position(), &base.get_position(), options.position_quantum) {
+        mask |= CHANGED_POSITION;
+    }
+    if !same_quantized(&particle.get_velocity(), &base.get_velocity(), options.velocity_quantum) {
+        mask |= CHANGED_VELOCITY;
+    }
+    if !same_bits(&particle.get_acceleration(), &base.get_acceleration()) { mask |= CHANGED_ACCELERATION; }
+    if particle.damping.to_bits() != base.damping.to_bits() { mask |= CHANGED_DAMPING; }
+    if particle.get_mass().to_bits() != base.get_mass().to_bits()
+        || particle.get_inverse_mass().to_bits() != base.get_inverse_mass().to_bits() {
+        mask |= CHANGED_MASS;
+    }
+    if !same_bits(&particle.force_accum, &base.force_accum) { mask |= CHANGED_FORCE; }
+    if particle.get_charge().map(f32::to_bits) != base.get_charge().map(f32::to_bits) { mask |= CHANGED_CHARGE; }
+    return mask;
+}
+
+/// The state of every particle at one step, the unit the binary format encodes.
+#[derive(Debug, Clone)]
+pub struct BinaryFrame {
+    pub step: u64,
+    pub particles: Vec<Particle>,
+}
+
+impl BinaryFrame {
+    pub fn new(step: u64, particles: Vec<Particle>) -> BinaryFrame {
+        return BinaryFrame {
+            step,
+            particles
+        };
+    }
+
+    /// Copies the current state of the particles
+    pub fn from_particles(step: u64, particles: &[Rc<RefCell<Particle>>]) -> BinaryFrame {
+        return BinaryFrame::new(step, particles.iter().map(|p| p.borrow().clone()).collect());
+    }
+
+    /// Writes the frame's state into the particles, which must be as many as the frame holds
+    pub fn apply(&self, particles: &[Rc<RefCell<Particle>>]) {
+        assert_eq!(particles.len(), self.particles.len());
+        for (particle, state) in particles.iter().zip(self.particles.iter()) {
+            *particle.borrow_mut() = state.clone();
+        }
+    }
+
+    /// Encodes the whole frame
+    pub fn encode(&self, options: &BinaryOptions) -> Vec<u8> {
+        check_options(options);
+        let mut writer: BinaryWriter = BinaryWriter::new();
+        self.write_header(&mut writer, None, options);
+        for particle in self.particles.iter() {
+            writer.write_particle_fields(particle, CHANGED_ALL, options);
+        }
+        return writer.into_bytes();
+    }
+
+    /// Encodes only what changed since the base frame. Particles past the end of the base
+    /// are written in full, particles the base has beyond the end of this frame are dropped.
+    /// The base must be the frame the decoder will have, so with quantization
+    /// pass the decoded base rather than the original.
+    pub fn encode_delta(&self, base: &BinaryFrame, options: &BinaryOptions) -> Vec<u8> {
+        check_options(options);
+        let mut writer: BinaryWriter = BinaryWriter::new();
+        self.write_header(&mut writer, Some(base.step), options);
+        for (i, particle) in self.particles.iter().enumerate() {
+            let mask: u8 = match base.particles.get(i) {
+                Some(base_particle) => change_mask(particle, base_particle, options),
+                None => CHANGED_ALL,
+            };
+            writer.write_u8(mask);
+            writer.write_particle_fields(particle, mask, options);
+        }
+        return writer.into_bytes();
+    }
+
+    fn write_header(&self, writer: &mut BinaryWriter, base_step: Option<u64>, options: &BinaryOptions) {
+        let mut flags: u16 = 0;
+        if base_step.is_some() { flags |= FLAG_DELTA; }
+        if options.position_quantum.is_some() { flags |= FLAG_QUANTIZED_POSITION; }
+        if options.velocity_quantum.is_some() { flags |= FLAG_QUANTIZED_VELOCITY; }
+
+        for byte in BINARY_MAGIC.iter() {
+            writer.write_u8(*byte);
+        }
+        writer.write_u16(BINARY_VERSION);
+        writer.write_u16(flags);
+        writer.write_u64(self.step);
+        if let Some(base_step) = base_step { writer.write_u64(base_step); }
+        if let Some(quantum) = options.position_quantum { writer.write_f32(quantum); }
+        if let Some(quantum) = options.velocity_quantum { writer.write_f32(quantum); }
+        writer.write_u32(self.particles.len() as u32);
+    }
+
+    /// Decodes a frame, delta frames need the frame they were encoded against as the base
+    pub fn decode(bytes: &[u8], base: Option<&BinaryFrame>) -> Result<BinaryFrame, BinaryError> {
+        let mut reader: BinaryReader = BinaryReader::new(bytes);
+        let magic: [u8; 4] = reader.take()?;
+        if magic != BINARY_MAGIC { return Err(BinaryError::BadMagic); }
+        let version: u16 = reader.read_u16()?;
+        if version > BINARY_VERSION { return Err(BinaryError::UnsupportedVersion(version)); }
+        let flags: u16 = reader.read_u16()?;
+        if flags & !KNOWN_FLAGS != 0 { return Err(BinaryError::UnknownFlags(flags & !KNOWN_FLAGS)); }
+
+        let step: u64 = reader.read_u64()?;
+        let base: Option<&BinaryFrame> = if flags & FLAG_DELTA != 0 {
+            let base_step: u64 = reader.read_u64()?;
+            let Some(base) = base else { return Err(BinaryError::MissingBase); };
+            if base.step != base_step {
+                return Err(BinaryError::WrongBase { expected: base_step, found: base.step });
+            }
+            Some(base)
+        } else {
+            None
+        };
+        let mut options: BinaryOptions = BinaryOptions::default();
+        if flags & FLAG_QUANTIZED_POSITION != 0 { options.position_quantum = Some(read_quantum(&mut reader, "position")?); }
+        if flags & FLAG_QUANTIZED_VELOCITY != 0 { options.velocity_quantum = Some(read_quantum(&mut reader, "velocity")?); }
+        let count: usize = reader.read_u32()? as usize;
+
+        let mut particles: Vec<Particle> = Vec::with_capacity(count.min(bytes.len()));
+        for i in 0..count {
+            let (mut particle, mask): (Particle, u8) = match base {
+                Some(base) => {
+                    let mask: u8 = reader.read_u8()?;
+                    if mask & !CHANGED_ALL != 0 {
+                        return Err(BinaryError::Invalid(format!("bad change mask {:#04x}", mask)));
+                    }
+                    match base.particles.get(i) {
+                        Some(base_particle) => (base_particle.clone(), mask),
+                        None if mask == CHANGED_ALL => (blank_particle(), mask),
+                        None => {
+                            return Err(BinaryError::Invalid(format!("particle {} is not in the base frame", i)));
+                        }
+                    }
+                }
+                None => (blank_particle(), CHANGED_ALL),
+            };
+            reader.read_particle_fields(&mut particle, mask, &options)?;
+            particles.push(particle);
+        }
+        if !reader.is_finished() {
+            return Err(BinaryError::Invalid("unexpected bytes after the last particle".to_string()));
+        }
+        return Ok(BinaryFrame::new(step, particles));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every field of the particle at full precision, for comparing particles bit for bit
+    fn particle_bytes(particle: &Particle) -> Vec<u8> {
+        let mut writer: BinaryWriter = BinaryWriter::new();
+        writer.write_particle(particle);
+        return writer.into_bytes();
+    }
+
+    fn assert_same_frame(found: &BinaryFrame, expected: &BinaryFrame) {
+        assert_eq!(found.step, expected.step);
+        assert_eq!(found.particles.len(), expected.particles.len());
+        for (a, b) in found.particles.iter().zip(expected.particles.iter()) {
+            assert_eq!(particle_bytes(a), particle_bytes(b));
+        }
+    }
+
+    fn assert_within(found: &Vector3, expected: &Vector3, quantum: f32) {
+        for (a, b) in [(found.x, expected.x), (found.y, expected.y), (found.z, expected.z)] {
+            assert!((a - b).abs() <= 0.5 * quantum + 1.0e-6, "{:?} expected {:?}", found, expected);
+        }
+    }
+
+    fn fixture_base() -> BinaryFrame {
+        let mut charged: Particle = Particle::new(
+            Vector3::new(1.5, -2.25, 3.0),
+            Vector3::new(0.1, 0.2, -0.3),
+            Vector3::new(0.0, -9.81, 0.0),
+            0.99,
+            2.0
+        );
+        charged.set_charge(1.0e-6);
+        charged.force_accum = Vector3::new(1.0, 2.0, 3.0);
+        let fixed: Particle = Particle::new(Vector3::new(0.0, 10.0, 0.0), Vector3::default(), Vector3::default(), 1.0, f32::INFINITY);
+        let plain: Particle = Particle::new(Vector3::new(-4.0, 0.5, 7.0), Vector3::new(3.0, 0.0, 0.0), Vector3::default(), 0.95, 0.25);
+        return BinaryFrame::new(10, vec![charged, fixed, plain]);
+    }
+
+    /// The base a step later, with the charged particle moved, the plain one slowed and a new
+    /// particle on the end
+    fn fixture_next() -> BinaryFrame {
+        let mut next: BinaryFrame = fixture_base();
+        next.step = 11;
+        next.particles[0].position = Vector3::new(1.501, -2.248, 2.997);
+        next.particles[2].velocity = Vector3::new(2.5, 0.0, 0.0);
+        next.particles.push(Particle::new(Vector3::new(0.25, 0.5, 0.75), Vector3::default(), Vector3::default(), 1.0, 4.0));
+        return next;
+    }
+
+    const FIXTURE_OPTIONS: BinaryOptions = BinaryOptions { position_quantum: Some(0.001), velocity_quantum: Some(0.01) };
+
+    #[test]
+    fn frames_read_back_bit_for_bit() {
+        let frame: BinaryFrame = fixture_base();
+        let decoded: BinaryFrame = BinaryFrame::decode(&frame.encode(&BinaryOptions::default()), None).unwrap();
+        assert_same_frame(&decoded, &frame);
+        assert_eq!(decoded.particles[0].get_charge(), Some(1.0e-6));
+        assert_eq!(decoded.particles[1].get_inverse_mass(), 0.0);
+    }
+
+    #[test]
+    fn quantized_frames_read_back_within_a_quantum() {
+        let frame: BinaryFrame = fixture_next();
+        let decoded: BinaryFrame = BinaryFrame::decode(&frame.encode(&FIXTURE_OPTIONS), None).unwrap();
+        for (found, expected) in decoded.particles.iter().zip(frame.particles.iter()) {
+            assert_within(&found.get_position(), &expected.get_position(), 0.001);
+            assert_within(&found.get_velocity(), &expected.get_velocity(), 0.01);
+            assert_eq!(found.damping, expected.damping);
+        }
+    }
+
+    #[test]
+    fn delta_frames_only_hold_what_changed() {
+        let base: BinaryFrame = fixture_base();
+        let unchanged: Vec<u8> = BinaryFrame::new(11, base.particles.clone()).encode_delta(&base, &BinaryOptions::default());
+        let header: usize = 4 + 2 + 2 + 8 + 8 + 4;
+        assert_eq!(unchanged.len(), header + base.particles.len());
+
+        let next: BinaryFrame = fixture_next();
+        let decoded: BinaryFrame = BinaryFrame::decode(&next.encode_delta(&base, &BinaryOptions::default()), Some(&base)).unwrap();
+        assert_same_frame(&decoded, &next);
+    }
+
+    #[test]
+    fn version_one_frames_still_load() {
+        let base: BinaryFrame = BinaryFrame::decode(include_bytes!("../tests/fixtures/binary_state_v1.bin"), None).unwrap();
+        assert_same_frame(&base, &fixture_base());
+
+        let next: BinaryFrame =
+            BinaryFrame::decode(include_bytes!("../tests/fixtures/binary_state_v1_delta.bin"), Some(&base)).unwrap();
+        let expected: BinaryFrame = fixture_next();
+        assert_eq!(next.step, 11);
+        assert_eq!(next.particles.len(), 4);
+        for (found, expected) in next.particles.iter().zip(expected.particles.iter()) {
+            assert_within(&found.get_position(), &expected.get_position(), 0.001);
+            assert_within(&found.get_velocity(), &expected.get_velocity(), 0.01);
+            assert_eq!(found.get_mass(), expected.get_mass());
+            assert_eq!(found.get_charge(), expected.get_charge());
+        }
+    }
+
+    #[test]
+    fn header_quanta_must_be_positive() {
+        let bytes: Vec<u8> = fixture_base().encode(&BinaryOptions { position_quantum: Some(0.5), velocity_quantum: None });
+        // The quantum follows the magic, version, flags and step
+        let offset: usize = 4 + 2 + 2 + 8;
+        for quantum in [0.0, -0.5, f32::NAN, f32::INFINITY] {
+            let mut corrupt: Vec<u8> = bytes.clone();
+            corrupt[offset..offset + 4].copy_from_slice(&quantum.to_bits().to_le_bytes());
+            match BinaryFrame::decode(&corrupt, None) {
+                Err(BinaryError::Invalid(message)) => assert!(message.contains("quantum"), "{}", message),
+                other => panic!("quantum {} decoded as {:?}", quantum, other),
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "must be positive")]
+    fn encoding_rejects_a_zero_quantum() {
+        fixture_base().encode(&BinaryOptions { position_quantum: Some(0.0), velocity_quantum: None });
+    }
+
+    #[test]
+    fn bad_frames_are_rejected() {
+        let base: BinaryFrame = fixture_base();
+        let full: Vec<u8> = base.encode(&BinaryOptions::default());
+        let delta: Vec<u8> = fixture_next().encode_delta(&base, &BinaryOptions::default());
+
+        assert_eq!(BinaryFrame::decode(b"NOPE", None).unwrap_err(), BinaryError::BadMagic);
+        assert_eq!(BinaryFrame::decode(&full[..full.len() - 1], None).unwrap_err(), BinaryError::Truncated);
+        let mut newer: Vec<u8> = full.clone();
+        newer[4..6].copy_from_slice(&(BINARY_VERSION + 1).to_le_bytes());
+        assert_eq!(BinaryFrame::decode(&newer, None).unwrap_err(), BinaryError::UnsupportedVersion(BINARY_VERSION + 1));
+        let mut flagged: Vec<u8> = full.clone();
+        flagged[6..8].copy_from_slice(&0x80u16.to_le_bytes());
+        assert_eq!(BinaryFrame::decode(&flagged, None).unwrap_err(), BinaryError::UnknownFlags(0x80));
+        let mut longer: Vec<u8> = full.clone();
+        longer.push(0);
+        assert!(matches!(BinaryFrame::decode(&longer, None), Err(BinaryError::Invalid(_))));
+
+        assert_eq!(BinaryFrame::decode(&delta, None).unwrap_err(), BinaryError::MissingBase);
+        let other_base: BinaryFrame = BinaryFrame::new(3, base.particles.clone());
+        assert_eq!(BinaryFrame::decode(&delta, Some(&other_base)).unwrap_err(), BinaryError::WrongBase { expected: 10, found: 3 });
+    }
+}
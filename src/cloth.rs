@@ -0,0 +1,568 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{
+    core::Vector3,
+    particle::{Particle, ParticleIntegrator},
+    particle_implicit_spring::add_damped_spring_force,
+    spatial_grid::SpatialGrid,
+};
+
+/// What a cloth spring resists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClothSpringKind {
+    /// Joins neighbouring vertices along a row or a column, resists stretching
+    Structural,
+    /// Joins diagonal neighbours across a cell, resists shearing
+    Shear,
+    /// Joins vertices two apart along a row or a column, resists folding
+    Bend,
+}
+
+/// What has to survive for a spring to stay attached once the cloth tears.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClothSupport {
+    /// Only tears by itself
+    None,
+    /// Shear springs go once both triangles of their cell are gone
+    Cell(usize),
+    /// Bend springs go once either structural spring they span is gone
+    Middle(usize),
+}
+
+/// A damped spring between two vertices of a `Cloth`.
+#[derive(Debug, Clone)]
+pub struct ClothSpring {
+    /// Index of the vertex at one end of the spring
+    pub a: usize,
+    /// Index of the vertex at the other end of the spring
+    pub b: usize,
+    pub kind: ClothSpringKind,
+    /// Holds the spring constant
+    pub spring_constant: f32,
+    /// Holds the damping coefficient, applied to the relative velocity along the spring
+    pub damping: f32,
+    /// Holds the rest length of the spring
+    pub rest_length: f32,
+    support: ClothSupport,
+}
+
+/// A triangle of the render mesh, two per grid cell.
+#[derive(Debug, Clone, Copy)]
+struct ClothTriangle {
+    vertices: [usize; 3],
+    cell: usize,
+}
+
+/// How a `Cloth` is built.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClothSettings {
+    pub structural_stiffness: f32,
+    pub shear_stiffness: f32,
+    pub bend_stiffness: f32,
+    /// Damping along every spring
+    pub spring_damping: f32,
+    /// The damping given to every vertex particle, as `Particle::damping`
+    pub damping: f32,
+    /// A spring stretched by more than this fraction of its rest length tears.
+    /// `None` means the cloth never tears.
+    pub tear_strain: Option<f32>,
+    /// Vertices that are not grid neighbours are kept at least this far apart.
+    /// Zero turns self-collision off.
+    pub thickness: f32,
+}
+
+impl Default for ClothSettings {
+    fn default() -> Self {
+        return Self {
+            structural_stiffness: 200.0,
+            shear_stiffness: 50.0,
+            bend_stiffness: 10.0,
+            spring_damping: 0.5,
+            damping: 0.99,
+            tear_strain: None,
+            thickness: 0.0
+        };
+    }
+}
+
+/// The state of a cloth a renderer needs for one frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClothFrame {
+    pub positions: Vec<Vector3>,
+    /// Unit vertex normals, averaged from the triangles around each vertex by area.
+    /// A vertex with no triangles left has a zero normal.
+    pub normals: Vec<Vector3>,
+    /// Vertex indices of the triangles still attached, wound so the normals face out of them
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl ClothFrame {
+    /// Writes the frame as a Wavefront OBJ mesh
+    pub fn write_obj<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for position in self.positions.iter() {
+            writeln!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+        }
+        for normal in self.normals.iter() {
+            writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+        // OBJ indices start at one
+        for [a, b, c] in self.triangles.iter().map(|t| t.map(|i| i + 1)) {
+            writeln!(writer, "f {}//{} {}//{} {}//{}", a, a, b, b, c, c)?;
+        }
+        return Ok(());
+    }
+}
+
+/// A rectangular sheet of particles held together by structural, shear and bend springs.
+///
+/// `step` moves the vertices itself, so they must not also be integrated with `Particle::integrate`.
+/// Forces other generators leave in a vertex's accumulator, and its acceleration, are included.
+/// Pinned vertices have infinite mass and only move when they are moved by hand.
+///
+/// Tearing removes the overstretched spring, the triangles along it and any shear or bend spring
+/// that no longer has cloth to act through. The vertices themselves are never split.
+pub struct Cloth {
+    particles: Vec<Rc<RefCell<Particle>>>,
+    /// Vertices along a row
+    columns: usize,
+    /// Vertices along a column
+    rows: usize,
+    springs: Vec<ClothSpring>,
+    triangles: Vec<ClothTriangle>,
+    /// The mass of each vertex, kept while it is pinned
+    masses: Vec<f32>,
+    pinned: Vec<bool>,
+    gravity: Vector3,
+    /// The velocity of the air
+    wind: Vector3,
+    /// Half the air density times the drag coefficient
+    air_drag: f32,
+    tear_strain: Option<f32>,
+    thickness: f32,
+    /// Used for self-collision, `None` when it is off
+    grid: Option<SpatialGrid>,
+}
+
+impl Cloth {
+    /// Builds a grid of `columns` by `rows` vertices starting at the origin.
+    /// `across` runs from the first to the last vertex of a row, and `down` from the first to the
+    /// last vertex of a column. The cloth starts at rest and unpinned.
+    pub fn grid(
+        origin: Vector3,
+        across: Vector3,
+        down: Vector3,
+        columns: usize,
+        rows: usize,
+        vertex_mass: f32,
+        settings: &ClothSettings
+    ) -> Cloth {
+        assert!(columns >= 2 && rows >= 2, "a cloth needs at least two vertices each way");
+        assert!(vertex_mass > 0.0, "vertex mass must be positive");
+
+        let step_across: Vector3 = across * (1.0 / (columns - 1) as f32);
+        let step_down: Vector3 = down * (1.0 / (rows - 1) as f32);
+        let mut particles: Vec<Rc<RefCell<Particle>>> = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                let mut position: Vector3 = origin;
+                position.add_scaled_vector(&step_across, column as f32);
+                position.add_scaled_vector(&step_down, row as f32);
+                particles.push(Rc::new(RefCell::new(Particle::new(
+                    position,
+                    Vector3::default(),
+                    Vector3::default(),
+                    settings.damping,
+                    vertex_mass
+                ))));
+            }
+        }
+
+        let mut cloth: Cloth = Cloth {
+            particles,
+            columns,
+            rows,
+            springs: Vec::new(),
+            triangles: Vec::new(),
+            masses: vec![vertex_mass; columns * rows],
+            pinned: vec![false; columns * rows],
+            gravity: Vector3::default(),
+            wind: Vector3::default(),
+            air_drag: 0.0,
+            tear_strain: settings.tear_strain,
+            thickness: settings.thickness,
+            grid: if settings.thickness > 0.0 { Some(SpatialGrid::new(settings.thickness)) } else { None }
+        };
+
+        for row in 0..rows {
+            for column in 0..columns {
+                let here: usize = cloth.get_index(column, row);
+                if column + 1 < columns {
+                    cloth.add_spring(here, here + 1, ClothSpringKind::Structural, settings.structural_stiffness, settings, ClothSupport::None);
+                }
+                if row + 1 < rows {
+                    cloth.add_spring(here, here + columns, ClothSpringKind::Structural, settings.structural_stiffness, settings, ClothSupport::None);
+                }
+                if column + 2 < columns {
+                    cloth.add_spring(here, here + 2, ClothSpringKind::Bend, settings.bend_stiffness, settings, ClothSupport::Middle(here + 1));
+                }
+                if row + 2 < rows {
+                    cloth.add_spring(here, here + 2 * columns, ClothSpringKind::Bend, settings.bend_stiffness, settings, ClothSupport::Middle(here + columns));
+                }
+                if column + 1 < columns && row + 1 < rows {
+                    let cell: usize = row * (columns - 1) + column;
+                    let right: usize = here + 1;
+                    let below: usize = here + columns;
+                    let diagonal: usize = below + 1;
+                    cloth.add_spring(here, diagonal, ClothSpringKind::Shear, settings.shear_stiffness, settings, ClothSupport::Cell(cell));
+                    cloth.add_spring(right, below, ClothSpringKind::Shear, settings.shear_stiffness, settings, ClothSupport::Cell(cell));
+                    cloth.triangles.push(ClothTriangle { vertices: [here, right, diagonal], cell });
+                    cloth.triangles.push(ClothTriangle { vertices: [here, diagonal, below], cell });
+                }
+            }
+        }
+        return cloth;
+    }
+
+    fn add_spring(
+        &mut self,
+        a: usize,
+        b: usize,
+        kind: ClothSpringKind,
+        spring_constant: f32,
+        settings: &ClothSettings,
+        support: ClothSupport
+    ) {
+        let rest_length: f32 = (self.particles[a].borrow().get_position() - &self.particles[b].borrow().get_position()).magnitude();
+        self.springs.push(ClothSpring {
+            a,
+            b,
+            kind,
+            spring_constant,
+            damping: settings.spring_damping,
+            rest_length,
+            support
+        });
+    }
+
+    /// The index of the vertex in the given column and row
+    pub fn get_index(&self, column: usize, row: usize) -> usize {
+        assert!(column < self.columns && row < self.rows);
+        return row * self.columns + column;
+    }
+
+    pub fn get_columns(&self) -> usize {
+        return self.columns;
+    }
+
+    pub fn get_rows(&self) -> usize {
+        return self.rows;
+    }
+
+    /// The vertex particles in row order, for attaching other generators or reading positions
+    pub fn get_particles(&self) -> &[Rc<RefCell<Particle>>] {
+        return &self.particles;
+    }
+
+    pub fn get_particle(&self, column: usize, row: usize) -> &Rc<RefCell<Particle>> {
+        return &self.particles[self.get_index(column, row)];
+    }
+
+    /// The springs still attached
+    pub fn get_springs(&self) -> &[ClothSpring] {
+        return &self.springs;
+    }
+
+    /// Holds the vertex where it is, it will only move if moved by hand
+    pub fn pin(&mut self, index: usize) {
+        let mut particle = self.particles[index].borrow_mut();
+        particle.restore_mass(self.masses[index], 0.0);
+        particle.velocity = Vector3::default();
+        self.pinned[index] = true;
+    }
+
+    /// Lets a pinned vertex move again, with the mass it had before
+    pub fn unpin(&mut self, index: usize) {
+        self.particles[index].borrow_mut().restore_mass(self.masses[index], 1.0 / self.masses[index]);
+        self.pinned[index] = false;
+    }
+
+    pub fn is_pinned(&self, index: usize) -> bool {
+        return self.pinned[index];
+    }
+
+    /// Sets the mass of one vertex, a pinned vertex takes it when it is unpinned
+    pub fn set_vertex_mass(&mut self, index: usize, mass: f32) {
+        assert!(mass > 0.0, "vertex mass must be positive");
+        self.masses[index] = mass;
+        let inverse_mass: f32 = if self.pinned[index] { 0.0 } else { 1.0 / mass };
+        self.particles[index].borrow_mut().restore_mass(mass, inverse_mass);
+    }
+
+    pub fn get_vertex_mass(&self, index: usize) -> f32 {
+        return self.masses[index];
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector3) {
+        self.gravity = gravity;
+    }
+
+    /// Sets the velocity of the air the cloth moves through
+    pub fn set_wind(&mut self, wind: Vector3) {
+        self.wind = wind;
+    }
+
+    /// Sets half the air density times the drag coefficient.
+    /// Each triangle feels `-air_drag * area * |v| (v . n) n`, where v is its velocity through the air.
+    pub fn set_air_drag(&mut self, air_drag: f32) {
+        self.air_drag = air_drag;
+    }
+
+    /// Advances the cloth by the duration with semi-implicit Euler, then pushes apart vertices that
+    /// came too close and tears overstretched springs. Returns the number of springs removed.
+    pub fn step(&mut self, duration: f32) -> usize {
+        assert!(duration > 0.0);
+        let positions: Vec<Vector3> = self.get_positions();
+        let velocities: Vec<Vector3> = self.particles.iter().map(|p| p.borrow().get_velocity()).collect();
+        let mut forces: Vec<Vector3> = vec![Vector3::default(); self.particles.len()];
+
+        for spring in self.springs.iter() {
+            add_damped_spring_force(
+                &positions,
+                &velocities,
+                &mut forces,
+                (spring.a, spring.b),
+                spring.spring_constant,
+                spring.damping,
+                spring.rest_length
+            );
+        }
+
+        if self.air_drag > 0.0 {
+            for triangle in self.triangles.iter() {
+                let [a, b, c] = triangle.vertices;
+                let mut normal: Vector3 = (positions[b] - &positions[a]).vector_product(&(positions[c] - &positions[a]));
+                let area: f32 = 0.5 * normal.magnitude();
+                if area == 0.0 { continue; }
+                normal.normalize();
+                let mut air_velocity: Vector3 = (velocities[a] + &velocities[b] + &velocities[c]) * (1.0 / 3.0);
+                air_velocity -= &self.wind;
+
+                // Only the part of the motion across the surface pushes on it
+                let force: Vector3 = normal * (-self.air_drag * area * air_velocity.magnitude() * (air_velocity * &normal));
+                let share: Vector3 = force * (1.0 / 3.0);
+                forces[a] += &share;
+                forces[b] += &share;
+                forces[c] += &share;
+            }
+        }
+
+        for (i, particle) in self.particles.iter().enumerate() {
+            let mut particle = particle.borrow_mut();
+            if self.pinned[i] {
+                particle.clear_accumulator();
+                continue;
+            }
+            particle.add_force(forces[i] + &(self.gravity * self.masses[i]));
+            particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, duration);
+        }
+
+        self.collide_self();
+        return self.tear();
+    }
+
+    /// Separates vertices closer than the thickness, leaving out pairs still joined by a spring
+    /// since the spring already keeps those in place. Once a spring tears its ends collide again.
+    /// Vertices are treated as spheres, so a vertex can still pass through the middle of a
+    /// large triangle.
+    fn collide_self(&mut self) {
+        let Some(grid) = self.grid.as_mut() else { return; };
+        let joined: HashSet<(usize, usize)> = self.springs.iter().map(|s| edge(s.a, s.b)).collect();
+        let positions: Vec<Vector3> = self.particles.iter().map(|p| p.borrow().get_position()).collect();
+        grid.rebuild(&positions);
+
+        let mut candidates: Vec<usize> = Vec::new();
+        for (i, position) in positions.iter().enumerate() {
+            grid.get_candidates(position, &mut candidates);
+            for &j in candidates.iter() {
+                if j <= i || joined.contains(&edge(i, j)) { continue; }
+                let mut first = self.particles[i].borrow_mut();
+                let mut second = self.particles[j].borrow_mut();
+
+                let mut normal: Vector3 = second.get_position() - &first.get_position();
+                let distance: f32 = normal.magnitude();
+                if distance >= self.thickness || distance == 0.0 { continue; }
+                let total_inverse_mass: f32 = first.get_inverse_mass() + second.get_inverse_mass();
+                if total_inverse_mass <= 0.0 { continue; }
+                normal *= 1.0 / distance;
+                let first_share: f32 = first.get_inverse_mass() / total_inverse_mass;
+                let second_share: f32 = second.get_inverse_mass() / total_inverse_mass;
+
+                let depth: f32 = self.thickness - distance;
+                first.position.add_scaled_vector(&normal, -depth * first_share);
+                second.position.add_scaled_vector(&normal, depth * second_share);
+
+                // Stop them moving further into each other
+                let closing: f32 = (second.get_velocity() - &first.get_velocity()) * &normal;
+                if closing < 0.0 {
+                    first.velocity.add_scaled_vector(&normal, closing * first_share);
+                    second.velocity.add_scaled_vector(&normal, -closing * second_share);
+                }
+            }
+        }
+    }
+
+    /// Removes springs stretched past the tear strain and everything that depended on them
+    fn tear(&mut self) -> usize {
+        let Some(tear_strain) = self.tear_strain else { return 0; };
+        let positions: Vec<Vector3> = self.get_positions();
+        let before: usize = self.springs.len();
+        self.springs.retain(|s| {
+            let length: f32 = (positions[s.a] - &positions[s.b]).magnitude();
+            return length - s.rest_length <= tear_strain * s.rest_length;
+        });
+        if self.springs.len() == before { return 0; }
+
+        let edges: HashSet<(usize, usize)> = self.springs.iter()
+            .filter(|s| s.kind != ClothSpringKind::Bend)
+            .map(|s| edge(s.a, s.b))
+            .collect();
+        self.triangles.retain(|t| {
+            let [a, b, c] = t.vertices;
+            return edges.contains(&edge(a, b)) && edges.contains(&edge(b, c)) && edges.contains(&edge(c, a));
+        });
+        let cells: HashSet<usize> = self.triangles.iter().map(|t| t.cell).collect();
+        self.springs.retain(|s| {
+            return match s.support {
+                ClothSupport::None => true,
+                ClothSupport::Cell(cell) => cells.contains(&cell),
+                ClothSupport::Middle(middle) => edges.contains(&edge(s.a, middle)) && edges.contains(&edge(middle, s.b)),
+            };
+        });
+        return before - self.springs.len();
+    }
+
+    pub fn get_positions(&self) -> Vec<Vector3> {
+        return self.particles.iter().map(|p| p.borrow().get_position()).collect();
+    }
+
+    /// Unit vertex normals, see `ClothFrame::normals`
+    pub fn calculate_normals(&self) -> Vec<Vector3> {
+        let positions: Vec<Vector3> = self.get_positions();
+        let mut normals: Vec<Vector3> = vec![Vector3::default(); positions.len()];
+        for triangle in self.triangles.iter() {
+            let [a, b, c] = triangle.vertices;
+            // The cross product's length is twice the area, which weights the average
+            let normal: Vector3 = (positions[b] - &positions[a]).vector_product(&(positions[c] - &positions[a]));
+            normals[a] += &normal;
+            normals[b] += &normal;
+            normals[c] += &normal;
+        }
+        for normal in normals.iter_mut() {
+            normal.normalize();
+        }
+        return normals;
+    }
+
+    /// Copies out what a renderer needs to draw the cloth as it is now
+    pub fn get_frame(&self) -> ClothFrame {
+        return ClothFrame {
+            positions: self.get_positions(),
+            normals: self.calculate_normals(),
+            triangles: self.triangles.iter().map(|t| t.vertices.map(|i| i as u32)).collect()
+        };
+    }
+}
+
+/// The key a spring between the two vertices is stored under, whichever way round they are
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    return (a.min(b), a.max(b));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A sheet hanging in the xy plane with its vertices one apart
+    fn sheet(columns: usize, rows: usize, settings: &ClothSettings) -> Cloth {
+        let across: Vector3 = Vector3::new((columns - 1) as f32, 0.0, 0.0);
+        let down: Vector3 = Vector3::new(0.0, -((rows - 1) as f32), 0.0);
+        return Cloth::grid(Vector3::default(), across, down, columns, rows, 0.1, settings);
+    }
+
+    fn has_spring(cloth: &Cloth, a: usize, b: usize) -> bool {
+        return cloth.get_springs().iter().any(|s| edge(s.a, s.b) == edge(a, b));
+    }
+
+    #[test]
+    fn grids_have_every_kind_of_spring() {
+        let cloth: Cloth = sheet(4, 3, &ClothSettings::default());
+        let count = |kind: ClothSpringKind| -> usize { return cloth.get_springs().iter().filter(|s| s.kind == kind).count(); };
+        // Three rows of three and four columns of two
+        assert_eq!(count(ClothSpringKind::Structural), 17);
+        // Two across each of the six cells
+        assert_eq!(count(ClothSpringKind::Shear), 12);
+        // Three rows of two and four columns of one
+        assert_eq!(count(ClothSpringKind::Bend), 10);
+        assert_eq!(cloth.get_frame().triangles.len(), 12);
+    }
+
+    #[test]
+    fn pinned_vertices_stay_put() {
+        let mut cloth: Cloth = sheet(4, 4, &ClothSettings::default());
+        cloth.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+        let corner: usize = cloth.get_index(0, 0);
+        cloth.pin(corner);
+        let start: Vec<Vector3> = cloth.get_positions();
+
+        for _ in 0..120 {
+            cloth.step(1.0 / 240.0);
+        }
+        let end: Vec<Vector3> = cloth.get_positions();
+        assert_eq!(end[corner], start[corner]);
+        let free: usize = cloth.get_index(3, 3);
+        assert!(end[free].y < start[free].y - 0.1, "the free corner should have fallen");
+    }
+
+    #[test]
+    fn tearing_removes_what_depended_on_the_spring() {
+        let settings: ClothSettings = ClothSettings { tear_strain: Some(0.5), ..ClothSettings::default() };
+        let mut cloth: Cloth = sheet(3, 2, &settings);
+        assert_eq!(cloth.get_springs().len(), 13);
+
+        // Lifting the middle of the top row overstretches every spring on it, but not the bend
+        // spring across it or the shear spring of the other cell
+        cloth.get_particles()[1].borrow_mut().position = Vector3::new(1.0, 2.0, 0.0);
+        assert_eq!(cloth.step(1.0e-4), 7);
+
+        for (a, b) in [(0, 1), (1, 2), (1, 4), (1, 3), (1, 5)] {
+            assert!(!has_spring(&cloth, a, b), "the stretched spring {}-{} should have torn", a, b);
+        }
+        assert!(!has_spring(&cloth, 0, 2), "the bend spring over the torn edge should be gone");
+        assert!(!has_spring(&cloth, 2, 4), "the shear spring of the emptied cell should be gone");
+        for (a, b) in [(0, 3), (3, 4), (4, 5), (2, 5), (0, 4), (3, 5)] {
+            assert!(has_spring(&cloth, a, b), "the spring {}-{} should still be attached", a, b);
+        }
+        assert_eq!(cloth.get_frame().triangles, vec![[0, 4, 3]]);
+    }
+
+    #[test]
+    fn torn_neighbours_collide() {
+        let settings: ClothSettings = ClothSettings { tear_strain: Some(0.5), thickness: 0.5, ..ClothSettings::default() };
+        let mut cloth: Cloth = sheet(3, 2, &settings);
+        cloth.get_particles()[1].borrow_mut().position = Vector3::new(1.0, 2.0, 0.0);
+        cloth.step(1.0e-4);
+        assert!(!has_spring(&cloth, 0, 1));
+
+        // Nothing joins the two any more, so they are pushed apart like any other pair
+        cloth.get_particles()[1].borrow_mut().position = Vector3::new(0.1, 0.0, 0.0);
+        cloth.step(1.0e-4);
+        let positions: Vec<Vector3> = cloth.get_positions();
+        let distance: f32 = (positions[1] - &positions[0]).magnitude();
+        assert!(distance >= 0.5 - 1.0e-4, "the vertices are only {} apart", distance);
+    }
+}
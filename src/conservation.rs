@@ -0,0 +1,287 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{core::Vector3, particle::Particle, particle_force_gen::ParticleForceRegistry};
+
+/// System-wide quantities that a closed, conservative simulation should keep constant.
+/// Particles with infinite mass are left out of the kinetic energy, momenta and centre of mass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConservationSample {
+    pub step: u64,
+    pub time: f64,
+    pub kinetic_energy: f32,
+    /// What the registered generators report, see `ParticleForceGenerator::potential_energy`
+    pub potential_energy: f32,
+    pub linear_momentum: Vector3,
+    /// Angular momentum about the monitor's reference point
+    pub angular_momentum: Vector3,
+    pub centre_of_mass: Vector3,
+    /// The total finite mass
+    pub mass: f32,
+}
+
+impl ConservationSample {
+    /// Measures the particles, with the angular momentum taken about the given point
+    pub fn measure(
+        particles: &[Rc<RefCell<Particle>>],
+        registry: &ParticleForceRegistry,
+        about: &Vector3
+    ) -> ConservationSample {
+        let mut sample: ConservationSample = ConservationSample {
+            step: 0,
+            time: 0.0,
+            kinetic_energy: 0.0,
+            potential_energy: registry.potential_energy(),
+            linear_momentum: Vector3::default(),
+            angular_momentum: Vector3::default(),
+            centre_of_mass: Vector3::default(),
+            mass: 0.0
+        };
+        let mut weighted_position: Vector3 = Vector3::default();
+        for particle in particles.iter() {
+            let particle = particle.borrow();
+            if !particle.has_finite_mass() { continue; }
+            let mass: f32 = particle.get_mass();
+            let momentum: Vector3 = particle.get_velocity() * mass;
+
+            sample.kinetic_energy += particle.calculate_kinetic_energy();
+            sample.linear_momentum += &momentum;
+            // L = (r - o) x mv
+            sample.angular_momentum += &(particle.get_position() - about).vector_product(&momentum);
+            weighted_position.add_scaled_vector(&particle.get_position(), mass);
+            sample.mass += mass;
+        }
+        if sample.mass > 0.0 {
+            sample.centre_of_mass = weighted_position * (1.0 / sample.mass);
+        }
+        return sample;
+    }
+
+    /// Kinetic plus potential energy
+    pub fn get_total_energy(&self) -> f32 {
+        return self.kinetic_energy + self.potential_energy;
+    }
+}
+
+/// How far each conserved quantity moved over a run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DriftReport {
+    /// Total energy at the end minus at the start
+    pub energy_drift: f32,
+    /// The energy drift as a fraction of the starting total energy, infinite if that was zero
+    pub relative_energy_drift: f32,
+    /// The furthest the total energy got from its starting value at any sample
+    pub max_energy_error: f32,
+    /// Length of the change in linear momentum
+    pub linear_momentum_drift: f32,
+    /// Length of the change in angular momentum
+    pub angular_momentum_drift: f32,
+    /// How far the centre of mass moved
+    pub centre_of_mass_shift: f32,
+}
+
+/// The largest drift a run is allowed before `ConservationMonitor::check` reports it.
+/// Leave a tolerance at infinity to skip the check, for example momentum when there is gravity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConservationTolerances {
+    pub relative_energy: f32,
+    pub linear_momentum: f32,
+    pub angular_momentum: f32,
+}
+
+impl Default for ConservationTolerances {
+    fn default() -> Self {
+        return Self {
+            relative_energy: f32::INFINITY,
+            linear_momentum: f32::INFINITY,
+            angular_momentum: f32::INFINITY
+        };
+    }
+}
+
+/// Samples the conserved quantities every step and reports how far they drift, so integrator
+/// and generator bugs show up as numbers a test can check.
+///
+/// Call `record` at the same point of every step, after integrating is the natural place,
+/// so the kinetic and potential energy are measured from the same positions.
+pub struct ConservationMonitor {
+    /// The point angular momentum is measured about
+    about: Vector3,
+    samples: Vec<ConservationSample>,
+    step: u64,
+    time: f64,
+}
+
+impl ConservationMonitor {
+    pub fn new(about: Vector3) -> ConservationMonitor {
+        return ConservationMonitor {
+            about,
+            samples: Vec::new(),
+            step: 0,
+            time: 0.0
+        };
+    }
+
+    /// Takes a sample, then moves the step count and clock on by the duration
+    pub fn record(
+        &mut self,
+        particles: &[Rc<RefCell<Particle>>],
+        registry: &ParticleForceRegistry,
+        duration: f32
+    ) -> ConservationSample {
+        let mut sample: ConservationSample = ConservationSample::measure(particles, registry, &self.about);
+        sample.step = self.step;
+        sample.time = self.time;
+        self.samples.push(sample);
+        self.step += 1;
+        self.time += duration as f64;
+        return sample;
+    }
+
+    pub fn get_samples(&self) -> &[ConservationSample] {
+        return &self.samples;
+    }
+
+    /// Compares the last sample with the first, `None` until there is a sample
+    pub fn report(&self) -> Option<DriftReport> {
+        let first: &ConservationSample = self.samples.first()?;
+        let last: &ConservationSample = self.samples.last()?;
+        let start_energy: f32 = first.get_total_energy();
+        let energy_drift: f32 = last.get_total_energy() - start_energy;
+        let max_energy_error: f32 = self.samples.iter()
+            .map(|s| (s.get_total_energy() - start_energy).abs())
+            .fold(0.0, f32::max);
+
+        return Some(DriftReport {
+            energy_drift,
+            relative_energy_drift: if start_energy == 0.0 {
+                if energy_drift == 0.0 { 0.0 } else { f32::INFINITY }
+            } else {
+                energy_drift / start_energy.abs()
+            },
+            max_energy_error,
+            linear_momentum_drift: (last.linear_momentum - &first.linear_momentum).magnitude(),
+            angular_momentum_drift: (last.angular_momentum - &first.angular_momentum).magnitude(),
+            centre_of_mass_shift: (last.centre_of_mass - &first.centre_of_mass).magnitude()
+        });
+    }
+
+    /// Returns a message for each quantity that drifted further than its tolerance
+    pub fn check(&self, tolerances: &ConservationTolerances) -> Vec<String> {
+        let mut failures: Vec<String> = Vec::new();
+        let Some(report) = self.report() else { return failures; };
+        if report.relative_energy_drift.is_nan() || report.relative_energy_drift.abs() > tolerances.relative_energy {
+            failures.push(format!(
+                "energy drifted by {} ({:.3}%), more than the allowed {:.3}%",
+                report.energy_drift,
+                report.relative_energy_drift * 100.0,
+                tolerances.relative_energy * 100.0
+            ));
+        }
+        if report.linear_momentum_drift.is_nan() || report.linear_momentum_drift > tolerances.linear_momentum {
+            failures.push(format!(
+                "linear momentum drifted by {}, more than the allowed {}",
+                report.linear_momentum_drift,
+                tolerances.linear_momentum
+            ));
+        }
+        if report.angular_momentum_drift.is_nan() || report.angular_momentum_drift > tolerances.angular_momentum {
+            failures.push(format!(
+                "angular momentum drifted by {}, more than the allowed {}",
+                report.angular_momentum_drift,
+                tolerances.angular_momentum
+            ));
+        }
+        return failures;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{particle::ParticleIntegrator, particle_force_gen::{ParticleForceGenerator, ParticleSpring}};
+
+    fn shared_particle(x: f32, vy: f32) -> Rc<RefCell<Particle>> {
+        return Rc::new(RefCell::new(Particle::new(Vector3::new(x, 0.0, 0.0), Vector3::new(0.0, vy, 0.0), Vector3::default(), 1.0, 1.0)));
+    }
+
+    fn spring(other: &Rc<RefCell<Particle>>) -> Rc<RefCell<dyn ParticleForceGenerator>> {
+        return Rc::new(RefCell::new(ParticleSpring::new(Rc::clone(other), 10.0, 1.0)));
+    }
+
+    /// Two particles on an undamped spring, stretched and spinning about their centre a little
+    /// slower than a circular orbit, so the spring breathes without ever being compressed
+    fn spring_pair() -> (Vec<Rc<RefCell<Particle>>>, ParticleForceRegistry) {
+        let particles: Vec<Rc<RefCell<Particle>>> = vec![shared_particle(-0.75, -1.8), shared_particle(0.75, 1.8)];
+        let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+        registry.add(&particles[0], &spring(&particles[1]));
+        registry.add(&particles[1], &spring(&particles[0]));
+        return (particles, registry);
+    }
+
+    #[test]
+    fn spring_pairs_conserve_energy_and_momentum() {
+        let (particles, mut registry) = spring_pair();
+        let mut monitor: ConservationMonitor = ConservationMonitor::new(Vector3::default());
+        let first: ConservationSample = monitor.record(&particles, &registry, 0.0);
+        // Each end reports half of k x^2 / 2, and they add up to the whole
+        assert!((first.potential_energy - 0.5 * 10.0 * 0.25).abs() < 1.0e-6);
+        assert!((first.kinetic_energy - 1.8 * 1.8).abs() < 1.0e-5);
+
+        // Semi-implicit Euler keeps the energy error bounded where explicit Euler would gain energy
+        let duration: f32 = 1.0e-3;
+        for _ in 0..3000 {
+            registry.update_forces(duration);
+            for particle in particles.iter() {
+                particle.borrow_mut().integrate_with(ParticleIntegrator::SemiImplicitEuler, duration);
+            }
+            monitor.record(&particles, &registry, duration);
+        }
+        assert_eq!(monitor.get_samples().len(), 3001);
+        assert_eq!(monitor.get_samples()[3000].step, 3000);
+
+        let report: DriftReport = monitor.report().unwrap();
+        assert!(report.relative_energy_drift.abs() < 0.01, "{:?}", report);
+        assert!(report.max_energy_error < 0.02 * first.get_total_energy(), "{:?}", report);
+        assert!(report.linear_momentum_drift < 1.0e-5, "{:?}", report);
+        assert!(report.angular_momentum_drift < 1.0e-4, "{:?}", report);
+        assert!(report.centre_of_mass_shift < 1.0e-5, "{:?}", report);
+    }
+
+    #[test]
+    fn checks_report_what_drifted_too_far() {
+        let (particles, mut registry) = spring_pair();
+        let mut monitor: ConservationMonitor = ConservationMonitor::new(Vector3::default());
+        assert!(monitor.report().is_none());
+        assert!(monitor.check(&ConservationTolerances::default()).is_empty());
+
+        monitor.record(&particles, &registry, 0.01);
+        // Only one end pulled, so momentum is not conserved
+        registry.remove_particle(&particles[1]);
+        for _ in 0..10 {
+            registry.update_forces(0.01);
+            particles[0].borrow_mut().integrate(0.01);
+            monitor.record(&particles, &registry, 0.01);
+        }
+
+        assert!(monitor.check(&ConservationTolerances::default()).is_empty());
+        let strict: ConservationTolerances = ConservationTolerances {
+            relative_energy: 1.0e-6,
+            linear_momentum: 1.0e-6,
+            angular_momentum: f32::INFINITY
+        };
+        let failures: Vec<String> = monitor.check(&strict);
+        assert_eq!(failures.len(), 2, "{:?}", failures);
+        assert!(failures[0].starts_with("energy drifted"), "{:?}", failures);
+        assert!(failures[1].starts_with("linear momentum drifted"), "{:?}", failures);
+    }
+
+    #[test]
+    fn one_sided_pair_forces_count_their_whole_energy() {
+        let (particles, mut registry) = spring_pair();
+        let both: f32 = registry.potential_energy();
+        registry.remove_particle(&particles[1]);
+        assert!((registry.potential_energy() - both).abs() < 1.0e-6);
+        assert!((both - 0.5 * 10.0 * 0.25).abs() < 1.0e-6);
+    }
+}
+
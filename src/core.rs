@@ -1,35 +1,44 @@
 use std::{io::Error, ops::{Add, AddAssign, Div, Mul, MulAssign, Rem, RemAssign, Sub, SubAssign}};
 
+use crate::precision::Real;
+
 const DEFAULT: f32 = 0.0;
-const NEGATION: f32 = -1.0;
 
 /// Holds a vector of three dimenions.
 /// Four members are allocated to ensure alignment in an array.
+/// The components are `f32` unless another `Real` type is given, such as `Fixed32`.
 #[derive(Debug, Clone, Copy)]
-pub struct Vector3 {
-    pub x: f32,
-    pub y: f32,
-    pub z: f32,
-    pad: f32, // padding to ensure four word alingnment
+pub struct Vector3<R: Real = f32> {
+    pub x: R,
+    pub y: R,
+    pub z: R,
+    pad: R, // padding to ensure four word alingnment
 }
 
-impl Default for Vector3 {
+/// Compares the components, the padding is ignored.
+impl<R: Real> PartialEq for Vector3<R> {
+    fn eq(&self, other: &Vector3<R>) -> bool {
+        return self.x == other.x && self.y == other.y && self.z == other.z;
+    }
+}
+
+impl<R: Real> Default for Vector3<R> {
     fn default() -> Self {
         return Self {
-            x: DEFAULT,
-            y: DEFAULT,
-            z: DEFAULT,
-            pad: DEFAULT
+            x: R::zero(),
+            y: R::zero(),
+            z: R::zero(),
+            pad: R::zero()
         };
     }
 }
 
 // OPERATOR OVERLOADS
 
-impl Mul<f32> for Vector3 {
-    type Output = Vector3;
+impl<R: Real> Mul<R> for Vector3<R> {
+    type Output = Vector3<R>;
 
-    fn mul(self, _rhs: f32) -> Vector3 {
+    fn mul(self, _rhs: R) -> Vector3<R> {
         return Vector3 {
             x: _rhs * self.x,
             y: _rhs * self.y,
@@ -39,26 +48,26 @@ impl Mul<f32> for Vector3 {
     }
 }
 
-impl MulAssign<f32> for Vector3 {
-    fn mul_assign(&mut self, _rhs: f32) {
+impl<R: Real> MulAssign<R> for Vector3<R> {
+    fn mul_assign(&mut self, _rhs: R) {
         self.x *= _rhs;
         self.y *= _rhs;
         self.z *= _rhs;
     }
 }
 
-impl Mul<&Vector3> for Vector3 {
-    type Output = f32;
+impl<R: Real> Mul<&Vector3<R>> for Vector3<R> {
+    type Output = R;
     /// Used to calculate the Scalar Product between two vectors
-    fn mul(self, _rhs: &Vector3) -> f32 {
+    fn mul(self, _rhs: &Vector3<R>) -> R {
         return self.x * _rhs.x + self.y * _rhs.y + self.z * _rhs.z;
     }
 }
 
-impl Div<f32> for Vector3 {
-    type Output = Vector3;
+impl<R: Real> Div<R> for Vector3<R> {
+    type Output = Vector3<R>;
 
-    fn div(self, _rhs: f32) -> Vector3 {
+    fn div(self, _rhs: R) -> Vector3<R> {
         return Vector3 {
             x: self.x / _rhs,
             y: self.y / _rhs,
@@ -68,10 +77,10 @@ impl Div<f32> for Vector3 {
     }
 }
 
-impl Add<f32> for Vector3 {
-    type Output = Vector3;
+impl<R: Real> Add<R> for Vector3<R> {
+    type Output = Vector3<R>;
 
-    fn add(self, _rhs: f32) -> Vector3 {
+    fn add(self, _rhs: R) -> Vector3<R> {
         return Vector3 {
             x: self.x + _rhs,
             y: self.y + _rhs,
@@ -81,10 +90,10 @@ impl Add<f32> for Vector3 {
     }
 }
 
-impl Add<&Vector3> for Vector3 {
-    type Output = Vector3;
+impl<R: Real> Add<&Vector3<R>> for Vector3<R> {
+    type Output = Vector3<R>;
 
-    fn add(self, _rhs: &Vector3) -> Vector3 {
+    fn add(self, _rhs: &Vector3<R>) -> Vector3<R> {
         return Vector3 {
             x: self.x + _rhs.x,
             y: self.y + _rhs.y,
@@ -94,18 +103,18 @@ impl Add<&Vector3> for Vector3 {
     }
 }
 
-impl AddAssign<&Vector3> for Vector3 {
-    fn add_assign(&mut self, _rhs: &Vector3) {
+impl<R: Real> AddAssign<&Vector3<R>> for Vector3<R> {
+    fn add_assign(&mut self, _rhs: &Vector3<R>) {
         self.x += _rhs.x;
         self.y += _rhs.y;
         self.z += _rhs.z;
     }
 }
 
-impl Sub<f32> for Vector3 {
-    type Output = Vector3;
+impl<R: Real> Sub<R> for Vector3<R> {
+    type Output = Vector3<R>;
 
-    fn sub(self, _rhs: f32) -> Vector3 {
+    fn sub(self, _rhs: R) -> Vector3<R> {
         return Vector3 {
             x: self.x - _rhs,
             y: self.y - _rhs,
@@ -115,10 +124,10 @@ impl Sub<f32> for Vector3 {
     }
 }
 
-impl Sub<&Vector3> for Vector3 {
-    type Output = Vector3;
+impl<R: Real> Sub<&Vector3<R>> for Vector3<R> {
+    type Output = Vector3<R>;
 
-    fn sub(self, _rhs: &Vector3) -> Vector3 {
+    fn sub(self, _rhs: &Vector3<R>) -> Vector3<R> {
         return Vector3 {
             x: self.x - _rhs.x,
             y: self.y - _rhs.y,
@@ -128,79 +137,79 @@ impl Sub<&Vector3> for Vector3 {
     }
 }
 
-impl SubAssign<&Vector3> for Vector3 {
-    fn sub_assign(&mut self, _rhs: &Vector3) {
-        self.x - _rhs.x;
-        self.y - _rhs.y;
-        self.z - _rhs.z;
+impl<R: Real> SubAssign<&Vector3<R>> for Vector3<R> {
+    fn sub_assign(&mut self, _rhs: &Vector3<R>) {
+        self.x -= _rhs.x;
+        self.y -= _rhs.y;
+        self.z -= _rhs.z;
     }
 }
 
-impl RemAssign<&Vector3> for &mut Vector3 {
-    fn rem_assign(&mut self, _rhs: &Vector3) {
-        let temp: Vector3 = Vector3::new(self.x, self.y, self.z);
+impl<R: Real> RemAssign<&Vector3<R>> for &mut Vector3<R> {
+    fn rem_assign(&mut self, _rhs: &Vector3<R>) {
+        let temp: Vector3<R> = Vector3::new(self.x, self.y, self.z);
         self.update_by_vector3( temp.vector_product(_rhs) );
     }
 }
 
-impl Rem<&Vector3> for &Vector3 {
-    type Output = Vector3;
+impl<R: Real> Rem<&Vector3<R>> for &Vector3<R> {
+    type Output = Vector3<R>;
 
-    fn rem(self, _rhs: &Vector3) -> Vector3 {
+    fn rem(self, _rhs: &Vector3<R>) -> Vector3<R> {
         return self.vector_product(_rhs);
     }
 }
 
-impl Rem<&Vector3> for &mut Vector3 {
-    type Output = Vector3;
+impl<R: Real> Rem<&Vector3<R>> for &mut Vector3<R> {
+    type Output = Vector3<R>;
     
-    fn rem(self, _rhs: &Vector3) -> Vector3 {
+    fn rem(self, _rhs: &Vector3<R>) -> Vector3<R> {
         return self.vector_product(_rhs);
     }
 }
 
 // `Vector3` IMLEMENTATION
 
-impl Vector3 {
-    pub fn new(x: f32, y: f32, z: f32) -> Self {
+impl<R: Real> Vector3<R> {
+    pub fn new(x: R, y: R, z: R) -> Self {
         return Self {
             x,
             y,
             z,
-            pad: DEFAULT,
+            pad: R::zero(),
         };
     }
 
     /// Inverts the Vector by flipping the signs of each axis, x, y and z
     pub fn invert(&mut self) {
-        self.x *= NEGATION;
-        self.y *= NEGATION;
-        self.z *= NEGATION;
+        self.x = -self.x;
+        self.y = -self.y;
+        self.z = -self.z;
     }
 
     /// Returns the magnitude of the vector: Square Root of the Sum of the square of each axis
-    pub fn magnitude(&self) -> f32 {
+    pub fn magnitude(&self) -> R {
         return (self.x * self.x + self.y * self.y + self.z * self.z).sqrt();
     }
 
     /// Returns the square magnitude: Sum of the squares of each axis
-    pub fn square_magnitude(&self) -> f32 {
+    pub fn square_magnitude(&self) -> R {
         return self.x * self.x + self.y * self.y + self.z * self.z;
     }
 
     /// Returns the square magnitude: Sum of the squares of each axis. Takes a mut ref of self.
-    pub fn square_magnitude_mut(&mut self) -> f32 {
+    pub fn square_magnitude_mut(&mut self) -> R {
         return self.x * self.x + self.y * self.y + self.z * self.z;
     }
 
-    /// Scales `other: &Vector3` by `scalar: f32` then adds the resulting vector to `self`.
-    pub fn add_scaled_vector(&mut self, other: &Vector3, scalar: f32) {
+    /// Scales `other: &Vector3` by `scalar` then adds the resulting vector to `self`.
+    pub fn add_scaled_vector(&mut self, other: &Vector3<R>, scalar: R) {
         self.x += other.x * scalar;
         self.y += other.y * scalar;
-        self.x += other.z * scalar;
+        self.z += other.z * scalar;
     }
 
-    pub fn update_by_vector3(&mut self, other: Vector3) {
+    pub fn update_by_vector3(&mut self, other: Vector3<R>) {
         self.x = other.x;
         self.y = other.y;
         self.z = other.z;
@@ -209,8 +218,10 @@ impl Vector3 {
     /// Normalizes the vector.
     /// To normalize a Vector, you multiply each axis of the vector by the vector,
     /// then dived by the magnitude of the vector.
+    /// A zero length vector is left as it is.
     pub fn normalize(&mut self) {
-        let length: f32 = self.magnitude();
+        let length: R = self.magnitude();
+        if length <= R::zero() { return; }
         // for vector a, you dived each component by the magnitude of vector a
         self.update_by_vector3(
             Vector3 { x: self.x, y: self.y, z: self.z, pad: self.pad } / length
@@ -219,7 +230,7 @@ impl Vector3 {
 
     /// Cacluates and returns a component-wise product by multiplying self * other.
     /// Returns a new `Vector3` with the results.
-    pub fn component_product(&self, other: &Vector3) -> Self {
+    pub fn component_product(&self, other: &Vector3<R>) -> Self {
         return Self {
             x: self.x * other.x,
             y: self.y * other.y,
@@ -230,14 +241,14 @@ impl Vector3 {
 
     /// Performs a component-wise product with the given vector
     /// and sets this vector to its result.
-    pub fn component_product_update(&mut self, other: &Vector3) {
+    pub fn component_product_update(&mut self, other: &Vector3<R>) {
         self.x *= other.x;
         self.y *= other.y;
         self.z *= other.z;
     }
 
     /// Calculates and returns the vector product of this vector with the given other vector.
-    pub fn vector_product(self, other: &Vector3) -> Vector3 {
+    pub fn vector_product(self, other: &Vector3<R>) -> Vector3<R> {
         return Vector3 {
             x: self.y * other.z - self.z * other.y,
             y: self.z * other.x - self.x * other.z,
@@ -250,15 +261,15 @@ impl Vector3 {
     /// Finds Vector c by performing the cross-product: `c = a X b`.
     /// If c has a zero magnitude then a and b are parallel.
     /// Then ensure that a and b are right angles to each other using the cross product: `b = c X a`
-    pub fn make_orthonormal_basis(a: &mut Vector3, b: &mut Vector3, c: &mut Vector3) -> Result<(), Error> {
+    pub fn make_orthonormal_basis(a: &mut Vector3<R>, b: &mut Vector3<R>, c: &mut Vector3<R>) -> Result<(), Error> {
         a.normalize();
 
         c.update_by_vector3(
             a.rem(b)
         );
 
-        if c.square_magnitude_mut() == 0.0 {
-            return Err(Error::new(std::io::ErrorKind::Other, "Vector a is parallel to Vector b"));
+        if c.square_magnitude_mut() == R::zero() {
+            return Err(Error::other("Vector a is parallel to Vector b"));
         }
 
         c.normalize();
@@ -268,3 +279,153 @@ impl Vector3 {
         return Ok(());
     }
 }
+
+/// Holds a 3 x 3 row major matrix.
+/// Used for the per-spring Jacobian blocks in the implicit spring solver and the
+/// per-element deformation in the soft body solver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix3 {
+    pub data: [f32; 9],
+}
+
+impl Default for Matrix3 {
+    fn default() -> Self {
+        return Self {
+            data: [DEFAULT; 9]
+        };
+    }
+}
+
+impl Mul<&Vector3> for Matrix3 {
+    type Output = Vector3;
+
+    fn mul(self, _rhs: &Vector3) -> Vector3 {
+        return self.transform(_rhs);
+    }
+}
+
+impl Mul<f32> for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, _rhs: f32) -> Matrix3 {
+        let mut result: Matrix3 = self;
+        for value in result.data.iter_mut() {
+            *value *= _rhs;
+        }
+        return result;
+    }
+}
+
+impl Add<&Matrix3> for Matrix3 {
+    type Output = Matrix3;
+
+    fn add(self, _rhs: &Matrix3) -> Matrix3 {
+        let mut result: Matrix3 = self;
+        for (value, other) in result.data.iter_mut().zip(_rhs.data.iter()) {
+            *value += other;
+        }
+        return result;
+    }
+}
+
+impl Mul<&Matrix3> for Matrix3 {
+    type Output = Matrix3;
+
+    fn mul(self, _rhs: &Matrix3) -> Matrix3 {
+        let mut result: Matrix3 = Matrix3::default();
+        for row in 0..3 {
+            for column in 0..3 {
+                result.data[row * 3 + column] = (0..3)
+                    .map(|k| self.data[row * 3 + k] * _rhs.data[k * 3 + column])
+                    .sum();
+            }
+        }
+        return result;
+    }
+}
+
+// `Matrix3` IMPLEMENTATION
+
+impl Matrix3 {
+    pub fn identity() -> Self {
+        return Self {
+            data: [
+                1.0, 0.0, 0.0,
+                0.0, 1.0, 0.0,
+                0.0, 0.0, 1.0
+            ]
+        };
+    }
+
+    /// Returns the outer product `a b^T`
+    pub fn outer_product(a: &Vector3, b: &Vector3) -> Self {
+        return Self {
+            data: [
+                a.x * b.x, a.x * b.y, a.x * b.z,
+                a.y * b.x, a.y * b.y, a.y * b.z,
+                a.z * b.x, a.z * b.y, a.z * b.z
+            ]
+        };
+    }
+
+    /// Builds the matrix with the given vectors as its columns
+    pub fn from_columns(a: &Vector3, b: &Vector3, c: &Vector3) -> Self {
+        return Self {
+            data: [
+                a.x, b.x, c.x,
+                a.y, b.y, c.y,
+                a.z, b.z, c.z
+            ]
+        };
+    }
+
+    pub fn get_column(&self, column: usize) -> Vector3 {
+        return Vector3::new(self.data[column], self.data[3 + column], self.data[6 + column]);
+    }
+
+    pub fn transpose(&self) -> Self {
+        let d: &[f32; 9] = &self.data;
+        return Self {
+            data: [
+                d[0], d[3], d[6],
+                d[1], d[4], d[7],
+                d[2], d[5], d[8]
+            ]
+        };
+    }
+
+    pub fn trace(&self) -> f32 {
+        return self.data[0] + self.data[4] + self.data[8];
+    }
+
+    pub fn determinant(&self) -> f32 {
+        let d: &[f32; 9] = &self.data;
+        return d[0] * (d[4] * d[8] - d[5] * d[7])
+            - d[1] * (d[3] * d[8] - d[5] * d[6])
+            + d[2] * (d[3] * d[7] - d[4] * d[6]);
+    }
+
+    /// Returns the inverse, or `None` if the matrix is singular
+    pub fn inverse(&self) -> Option<Self> {
+        let determinant: f32 = self.determinant();
+        if determinant == 0.0 { return None; }
+        let d: &[f32; 9] = &self.data;
+        let adjugate: Matrix3 = Matrix3 {
+            data: [
+                d[4] * d[8] - d[5] * d[7], d[2] * d[7] - d[1] * d[8], d[1] * d[5] - d[2] * d[4],
+                d[5] * d[6] - d[3] * d[8], d[0] * d[8] - d[2] * d[6], d[2] * d[3] - d[0] * d[5],
+                d[3] * d[7] - d[4] * d[6], d[1] * d[6] - d[0] * d[7], d[0] * d[4] - d[1] * d[3]
+            ]
+        };
+        return Some(adjugate * (1.0 / determinant));
+    }
+
+    /// Transforms the given vector by this matrix.
+    pub fn transform(&self, vector: &Vector3) -> Vector3 {
+        return Vector3::new(
+            self.data[0] * vector.x + self.data[1] * vector.y + self.data[2] * vector.z,
+            self.data[3] * vector.x + self.data[4] * vector.y + self.data[5] * vector.z,
+            self.data[6] * vector.x + self.data[7] * vector.y + self.data[8] * vector.z
+        );
+    }
+}
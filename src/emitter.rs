@@ -0,0 +1,499 @@
+use std::{cell::RefCell, f32::consts::PI, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::{Particle, ParticleIntegrator},
+    particle_force_gen::ParticleForceGenerator,
+    precision::{real_cbrt, real_cos, real_sin},
+    random::Random,
+};
+
+/// Where an emitter places new particles, relative to the emitter's position, and which way it
+/// sends them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EmitterShape {
+    /// Everything starts at the emitter's position, heading in any direction
+    Point,
+    /// Anywhere inside the ball, heading out from its centre
+    Sphere { radius: f32 },
+    /// At the emitter's position, heading within the angle in radians of the axis
+    Cone { axis: Vector3, angle: f32 },
+    /// Anywhere inside the box, heading in any direction
+    Box { half_extents: Vector3 },
+    /// Anywhere on the triangles, evenly by area, heading along the triangle's normal.
+    /// The normals follow the winding as in `ClothFrame`.
+    MeshSurface { vertices: Vec<Vector3>, triangles: Vec<[u32; 3]> },
+}
+
+/// How an emitter's particles start out. Each range is picked from evenly for every particle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmitterSettings {
+    /// Particles emitted per second while emitting
+    pub rate: f32,
+    /// The most particles alive at once, the size of the pool they are recycled through.
+    /// Nothing is emitted while the pool is full.
+    pub capacity: usize,
+    /// Seconds each particle lives
+    pub lifetime: (f32, f32),
+    /// Speed in the direction the shape sends the particle
+    pub speed: (f32, f32),
+    /// Added to every particle's velocity, for example the velocity of whatever carries the emitter
+    pub velocity: Vector3,
+    pub mass: (f32, f32),
+    /// As `Particle::damping`
+    pub damping: (f32, f32),
+    /// The constant acceleration every particle gets, usually gravity
+    pub acceleration: Vector3,
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        return Self {
+            rate: 10.0,
+            capacity: 100,
+            lifetime: (1.0, 2.0),
+            speed: (1.0, 2.0),
+            velocity: Vector3::default(),
+            mass: (1.0, 1.0),
+            damping: (0.99, 0.99),
+            acceleration: Vector3::new(0.0, -9.81, 0.0)
+        };
+    }
+}
+
+/// A slot in the pool and the particle it holds
+struct EmitterSlot {
+    particle: Rc<RefCell<Particle>>,
+    age: f32,
+    lifetime: f32,
+    alive: bool,
+}
+
+/// Spawns particles from a shape at a steady rate and retires them when their lifetime is up.
+///
+/// Particles live in a fixed pool of slots which are reused, so the `Rc` in a slot stays the
+/// same particle object for the life of the emitter and nothing is allocated once the pool is
+/// full. Slot numbers are stable, use them to look up a particle's age.
+///
+/// The emitter integrates its own live particles with semi-implicit Euler in `update`, applying
+/// the force generators attached to it, so do not register its particles with a
+/// `ParticleForceRegistry` as well. Randomness comes from the seed, so an emitter driven with
+/// the same steps always produces the same particles.
+pub struct ParticleEmitter {
+    shape: EmitterShape,
+    settings: EmitterSettings,
+    position: Vector3,
+    emitting: bool,
+    random: Random,
+    slots: Vec<EmitterSlot>,
+    /// Slots not holding a live particle, taken from the end
+    free: Vec<usize>,
+    /// The part of a particle owed by the rate that has not been emitted yet
+    owed: f32,
+    /// Running total of triangle areas, for picking a triangle of a mesh by area
+    areas: Vec<f32>,
+    force_gens: Vec<Rc<RefCell<dyn ParticleForceGenerator>>>,
+}
+
+impl ParticleEmitter {
+    pub fn new(shape: EmitterShape, settings: &EmitterSettings, seed: u64) -> ParticleEmitter {
+        assert!(settings.rate >= 0.0, "rate must not be negative");
+        assert!(settings.lifetime.0 > 0.0 && settings.lifetime.0 <= settings.lifetime.1, "lifetimes must be positive and in order");
+        assert!(settings.mass.0 > 0.0 && settings.mass.0 <= settings.mass.1, "masses must be positive and in order");
+        assert!(settings.speed.0 <= settings.speed.1 && settings.damping.0 <= settings.damping.1);
+
+        if let EmitterShape::Cone { axis, .. } = &shape {
+            assert!(axis.square_magnitude() > 0.0, "a cone needs an axis");
+        }
+        let mut areas: Vec<f32> = Vec::new();
+        if let EmitterShape::MeshSurface { vertices, triangles } = &shape {
+            assert!(!triangles.is_empty(), "a mesh surface needs at least one triangle");
+            let mut total: f32 = 0.0;
+            for triangle in triangles.iter() {
+                assert!(triangle.iter().all(|&v| (v as usize) < vertices.len()), "triangle refers to a missing vertex");
+                total += triangle_normal(vertices, triangle).magnitude() * 0.5;
+                areas.push(total);
+            }
+            assert!(total > 0.0, "a mesh surface needs some area");
+        }
+
+        let mut slots: Vec<EmitterSlot> = Vec::with_capacity(settings.capacity);
+        for _ in 0..settings.capacity {
+            slots.push(EmitterSlot {
+                particle: Rc::new(RefCell::new(Particle::new(
+                    Vector3::default(),
+                    Vector3::default(),
+                    Vector3::default(),
+                    1.0,
+                    1.0
+                ))),
+                age: 0.0,
+                lifetime: 0.0,
+                alive: false
+            });
+        }
+        return ParticleEmitter {
+            shape,
+            settings: *settings,
+            position: Vector3::default(),
+            emitting: true,
+            random: Random::new(seed),
+            slots,
+            // Reversed so slots fill from the front
+            free: (0..settings.capacity).rev().collect(),
+            owed: 0.0,
+            areas,
+            force_gens: Vec::new()
+        };
+    }
+
+    pub fn get_position(&self) -> Vector3 {
+        return self.position;
+    }
+
+    /// Moves the emitter, live particles stay where they are
+    pub fn set_position(&mut self, position: Vector3) {
+        self.position = position;
+    }
+
+    pub fn get_settings(&self) -> &EmitterSettings {
+        return &self.settings;
+    }
+
+    pub fn set_rate(&mut self, rate: f32) {
+        assert!(rate >= 0.0);
+        self.settings.rate = rate;
+    }
+
+    /// Stops or restarts the steady emission, live particles carry on until their time is up
+    pub fn set_emitting(&mut self, emitting: bool) {
+        self.emitting = emitting;
+        if !emitting { self.owed = 0.0; }
+    }
+
+    pub fn is_emitting(&self) -> bool {
+        return self.emitting;
+    }
+
+    /// Applies the generator to every live particle of this emitter each update
+    pub fn add_force_generator(&mut self, force_gen: &Rc<RefCell<dyn ParticleForceGenerator>>) {
+        self.force_gens.push(Rc::clone(force_gen));
+    }
+
+    pub fn remove_force_generator(&mut self, force_gen: &Rc<RefCell<dyn ParticleForceGenerator>>) {
+        self.force_gens.retain(|f| !Rc::ptr_eq(f, force_gen));
+    }
+
+    /// The number of slots, live or not
+    pub fn capacity(&self) -> usize {
+        return self.slots.len();
+    }
+
+    pub fn alive_count(&self) -> usize {
+        return self.slots.len() - self.free.len();
+    }
+
+    pub fn is_alive(&self, slot: usize) -> bool {
+        return self.slots[slot].alive;
+    }
+
+    /// The particle in the slot, which is left where it died once it is no longer alive
+    pub fn get_particle(&self, slot: usize) -> &Rc<RefCell<Particle>> {
+        return &self.slots[slot].particle;
+    }
+
+    /// Seconds since the particle in the slot was emitted
+    pub fn get_age(&self, slot: usize) -> f32 {
+        return self.slots[slot].age;
+    }
+
+    pub fn get_lifetime(&self, slot: usize) -> f32 {
+        return self.slots[slot].lifetime;
+    }
+
+    /// How far through its life the particle in the slot is, from 0 to 1, for fading effects
+    pub fn get_life_fraction(&self, slot: usize) -> f32 {
+        let slot: &EmitterSlot = &self.slots[slot];
+        return (slot.age / slot.lifetime).min(1.0);
+    }
+
+    /// The slot numbers of the live particles, in slot order
+    pub fn get_alive_slots(&self) -> Vec<usize> {
+        return (0..self.slots.len()).filter(|&slot| self.slots[slot].alive).collect();
+    }
+
+    /// Emits up to the count at once, whatever the rate. Returns how many the pool had room for.
+    pub fn burst(&mut self, count: usize) -> usize {
+        let mut emitted: usize = 0;
+        while emitted < count && self.emit().is_some() {
+            emitted += 1;
+        }
+        return emitted;
+    }
+
+    /// Emits one particle, returning its slot, or `None` if the pool is full
+    pub fn emit(&mut self) -> Option<usize> {
+        let slot: usize = self.free.pop()?;
+        let (offset, direction) = self.sample_shape();
+        let speed: f32 = self.random.random_real_range(self.settings.speed.0, self.settings.speed.1);
+        let mass: f32 = self.random.random_real_range(self.settings.mass.0, self.settings.mass.1);
+        let damping: f32 = self.random.random_real_range(self.settings.damping.0, self.settings.damping.1);
+        let lifetime: f32 = self.random.random_real_range(self.settings.lifetime.0, self.settings.lifetime.1);
+
+        let mut velocity: Vector3 = self.settings.velocity;
+        velocity.add_scaled_vector(&direction, speed);
+        let slot_data: &mut EmitterSlot = &mut self.slots[slot];
+        *slot_data.particle.borrow_mut() = Particle::new(self.position + &offset, velocity, self.settings.acceleration, damping, mass);
+        slot_data.age = 0.0;
+        slot_data.lifetime = lifetime;
+        slot_data.alive = true;
+        return Some(slot);
+    }
+
+    /// Retires the particle in the slot early, freeing it for reuse
+    pub fn kill(&mut self, slot: usize) {
+        if !self.slots[slot].alive { return; }
+        self.slots[slot].alive = false;
+        self.free.push(slot);
+    }
+
+    /// Retires every live particle
+    pub fn clear(&mut self) {
+        for slot in 0..self.slots.len() {
+            self.kill(slot);
+        }
+    }
+
+    /// Ages the live particles and retires those whose time is up, moves the rest under their
+    /// forces, then emits what the rate owes for the duration
+    pub fn update(&mut self, duration: f32) {
+        assert!(duration > 0.0);
+        for slot in 0..self.slots.len() {
+            if !self.slots[slot].alive { continue; }
+            self.slots[slot].age += duration;
+            if self.slots[slot].age >= self.slots[slot].lifetime {
+                self.kill(slot);
+                continue;
+            }
+            let mut particle = self.slots[slot].particle.borrow_mut();
+            for force_gen in self.force_gens.iter() {
+                force_gen.borrow_mut().update_force(&mut particle, duration);
+            }
+            particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, duration);
+        }
+        for force_gen in self.force_gens.iter() {
+            force_gen.borrow_mut().advance_time(duration);
+        }
+
+        if self.emitting {
+            self.owed += self.settings.rate * duration;
+            while self.owed >= 1.0 {
+                self.owed -= 1.0;
+                if self.emit().is_none() {
+                    // Full, what is owed is lost rather than all let out at once later
+                    self.owed = 0.0;
+                    break;
+                }
+            }
+        }
+    }
+
+    /// An offset from the emitter's position and a unit direction for a new particle
+    fn sample_shape(&mut self) -> (Vector3, Vector3) {
+        let random: &mut Random = &mut self.random;
+        match &self.shape {
+            EmitterShape::Point => {
+                return (Vector3::default(), random.random_unit_vector());
+            }
+            EmitterShape::Sphere { radius } => {
+                let direction: Vector3 = random.random_unit_vector();
+                // The cube root spreads the points evenly through the volume
+                let distance: f32 = radius * real_cbrt(random.random_real());
+                return (direction * distance, direction);
+            }
+            EmitterShape::Cone { axis, angle } => {
+                let mut axis: Vector3 = *axis;
+                axis.normalize();
+                let (across, up) = perpendiculars(&axis);
+                // Even over the cap of the cone's directions
+                let cos_angle: f32 = 1.0 - random.random_real() * (1.0 - real_cos(*angle));
+                let sin_angle: f32 = (1.0 - cos_angle * cos_angle).max(0.0).sqrt();
+                let turn: f32 = random.random_real_range(0.0, 2.0 * PI);
+                let mut direction: Vector3 = axis * cos_angle;
+                direction.add_scaled_vector(&across, sin_angle * real_cos(turn));
+                direction.add_scaled_vector(&up, sin_angle * real_sin(turn));
+                return (Vector3::default(), direction);
+            }
+            EmitterShape::Box { half_extents } => {
+                let offset: Vector3 = random.random_vector_range(&(*half_extents * -1.0), half_extents);
+                return (offset, random.random_unit_vector());
+            }
+            EmitterShape::MeshSurface { vertices, triangles } => {
+                let total: f32 = self.areas[self.areas.len() - 1];
+                let pick: f32 = random.random_real() * total;
+                let index: usize = self.areas.partition_point(|&area| area <= pick).min(triangles.len() - 1);
+                let triangle: &[u32; 3] = &triangles[index];
+                // Folding the square onto the triangle keeps the points even
+                let mut u: f32 = random.random_real();
+                let mut v: f32 = random.random_real();
+                if u + v > 1.0 {
+                    u = 1.0 - u;
+                    v = 1.0 - v;
+                }
+                let a: Vector3 = vertices[triangle[0] as usize];
+                let mut offset: Vector3 = a;
+                offset.add_scaled_vector(&(vertices[triangle[1] as usize] - &a), u);
+                offset.add_scaled_vector(&(vertices[triangle[2] as usize] - &a), v);
+                let mut normal: Vector3 = triangle_normal(vertices, triangle);
+                normal.normalize();
+                return (offset, normal);
+            }
+        }
+    }
+}
+
+/// The cross product of a triangle's edges, as long as twice its area
+fn triangle_normal(vertices: &[Vector3], triangle: &[u32; 3]) -> Vector3 {
+    let a: Vector3 = vertices[triangle[0] as usize];
+    return (vertices[triangle[1] as usize] - &a).vector_product(&(vertices[triangle[2] as usize] - &a));
+}
+
+/// Two unit vectors at right angles to the unit axis and each other
+fn perpendiculars(axis: &Vector3) -> (Vector3, Vector3) {
+    let helper: Vector3 = if axis.x.abs() < 0.9 { Vector3::new(1.0, 0.0, 0.0) } else { Vector3::new(0.0, 1.0, 0.0) };
+    let mut across: Vector3 = axis.vector_product(&helper);
+    across.normalize();
+    let up: Vector3 = axis.vector_product(&across);
+    return (across, up);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cone_emitter(seed: u64) -> ParticleEmitter {
+        let settings: EmitterSettings = EmitterSettings { rate: 60.0, capacity: 32, lifetime: (0.2, 0.5), ..EmitterSettings::default() };
+        return ParticleEmitter::new(EmitterShape::Cone { axis: Vector3::new(0.0, 1.0, 0.0), angle: 0.4 }, &settings, seed);
+    }
+
+    /// Bursts a full pool from the shape at (1, 2, 3) at unit speed, returning each particle's
+    /// offset from the emitter and velocity
+    fn emitted_from(shape: EmitterShape) -> Vec<(Vector3, Vector3)> {
+        let settings: EmitterSettings = EmitterSettings { capacity: 400, speed: (1.0, 1.0), velocity: Vector3::default(), ..EmitterSettings::default() };
+        let mut emitter: ParticleEmitter = ParticleEmitter::new(shape, &settings, 11);
+        let position: Vector3 = Vector3::new(1.0, 2.0, 3.0);
+        emitter.set_position(position);
+        assert_eq!(emitter.burst(400), 400);
+        return emitter.get_alive_slots().into_iter().map(|slot| {
+            let particle = emitter.get_particle(slot).borrow();
+            return (particle.get_position() - &position, particle.get_velocity());
+        }).collect();
+    }
+
+    fn is_unit(vector: &Vector3) -> bool {
+        return (vector.magnitude() - 1.0).abs() < 1.0e-5;
+    }
+
+    #[test]
+    fn points_emit_from_the_emitter_in_every_direction() {
+        let emitted: Vec<(Vector3, Vector3)> = emitted_from(EmitterShape::Point);
+        assert!(emitted.iter().all(|(offset, velocity)| *offset == Vector3::default() && is_unit(velocity)));
+        // Every octant gets some
+        for octant in 0..8 {
+            let sign = |bit: usize, value: f32| -> bool { (octant >> bit) & 1 == usize::from(value < 0.0) };
+            assert!(emitted.iter().any(|(_, v)| sign(0, v.x) && sign(1, v.y) && sign(2, v.z)), "octant {}", octant);
+        }
+    }
+
+    #[test]
+    fn spheres_emit_inside_the_ball_heading_out() {
+        let emitted: Vec<(Vector3, Vector3)> = emitted_from(EmitterShape::Sphere { radius: 0.5 });
+        for (offset, velocity) in emitted.iter() {
+            assert!(offset.magnitude() <= 0.5 + 1.0e-6, "{:?} is outside the ball", offset);
+            assert!(is_unit(velocity));
+            assert!(offset.vector_product(velocity).magnitude() < 1.0e-5 && *offset * velocity >= 0.0);
+        }
+        // Even through the volume, so about an eighth fall within half the radius
+        let inner: usize = emitted.iter().filter(|(offset, _)| offset.magnitude() < 0.25).count();
+        assert!((25..=75).contains(&inner), "{} of 400 within half the radius", inner);
+    }
+
+    #[test]
+    fn boxes_emit_inside_their_extents() {
+        let half_extents: Vector3 = Vector3::new(1.0, 2.0, 3.0);
+        let emitted: Vec<(Vector3, Vector3)> = emitted_from(EmitterShape::Box { half_extents });
+        for (offset, velocity) in emitted.iter() {
+            assert!(offset.x.abs() <= 1.0 && offset.y.abs() <= 2.0 && offset.z.abs() <= 3.0, "{:?} is outside the box", offset);
+            assert!(is_unit(velocity));
+        }
+        // Reaching well out along every axis
+        assert!(emitted.iter().any(|(offset, _)| offset.x > 0.9) && emitted.iter().any(|(offset, _)| offset.x < -0.9));
+        assert!(emitted.iter().any(|(offset, _)| offset.y > 1.8) && emitted.iter().any(|(offset, _)| offset.y < -1.8));
+        assert!(emitted.iter().any(|(offset, _)| offset.z > 2.7) && emitted.iter().any(|(offset, _)| offset.z < -2.7));
+    }
+
+    #[test]
+    fn meshes_emit_on_their_triangles_along_the_normal() {
+        // A unit square on the floor split along its diagonal, wound to face up
+        let vertices: Vec<Vector3> = vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 1.0),
+            Vector3::new(0.0, 0.0, 1.0),
+        ];
+        let emitted: Vec<(Vector3, Vector3)> = emitted_from(EmitterShape::MeshSurface { vertices, triangles: vec![[0, 2, 1], [0, 3, 2]] });
+        for (offset, velocity) in emitted.iter() {
+            assert!(offset.y.abs() < 1.0e-6 && (0.0..=1.0).contains(&offset.x) && (0.0..=1.0).contains(&offset.z), "{:?} is off the square", offset);
+            assert!((*velocity - &Vector3::new(0.0, 1.0, 0.0)).magnitude() < 1.0e-6);
+        }
+        // The two halves have the same area, so get about the same share
+        let below_diagonal: usize = emitted.iter().filter(|(offset, _)| offset.x > offset.z).count();
+        assert!((150..=250).contains(&below_diagonal), "{} of 400 below the diagonal", below_diagonal);
+    }
+
+    #[test]
+    fn the_same_seed_emits_the_same_particles() {
+        let mut first: ParticleEmitter = cone_emitter(3);
+        let mut second: ParticleEmitter = cone_emitter(3);
+        for _ in 0..90 {
+            first.update(1.0 / 60.0);
+            second.update(1.0 / 60.0);
+        }
+        assert!(first.alive_count() > 0);
+        assert_eq!(first.get_alive_slots(), second.get_alive_slots());
+        for slot in first.get_alive_slots() {
+            let (a, b) = (first.get_particle(slot).borrow(), second.get_particle(slot).borrow());
+            assert_eq!(a.get_position(), b.get_position());
+            assert_eq!(a.get_velocity(), b.get_velocity());
+            assert_eq!(a.get_mass(), b.get_mass());
+            assert_eq!(first.get_age(slot), second.get_age(slot));
+            assert_eq!(first.get_lifetime(slot), second.get_lifetime(slot));
+        }
+    }
+
+    #[test]
+    fn slots_are_recycled_without_allocating() {
+        let mut emitter: ParticleEmitter = cone_emitter(5);
+        let pointers: Vec<*const RefCell<Particle>> = (0..emitter.capacity()).map(|slot| Rc::as_ptr(emitter.get_particle(slot))).collect();
+        let (slots_capacity, free_capacity) = (emitter.slots.capacity(), emitter.free.capacity());
+
+        // Several lifetimes over, so every slot is reused many times
+        let mut emitted: usize = 0;
+        for _ in 0..300 {
+            let before: usize = emitter.alive_count();
+            emitter.update(1.0 / 60.0);
+            emitted += emitter.alive_count().saturating_sub(before);
+        }
+        assert!(emitted > emitter.capacity());
+        for (slot, pointer) in pointers.iter().enumerate() {
+            assert_eq!(Rc::as_ptr(emitter.get_particle(slot)), *pointer);
+        }
+        assert_eq!(emitter.slots.capacity(), slots_capacity);
+        assert_eq!(emitter.free.capacity(), free_capacity);
+
+        // A full pool turns emits away
+        emitter.clear();
+        assert_eq!(emitter.burst(100), emitter.capacity());
+        assert_eq!(emitter.emit(), None);
+    }
+}
@@ -13,7 +13,7 @@ pub fn exercise2() {
 
     // 2.2
     // (a) calculate the scalar product vec(3 1 2) * vec(0 2 -1)
-    let scalar_product2: f32 = 3.0 * 0.0 + 1.0 * 2.0 + 2.0 * -1.0;
+    let scalar_product2: f32 = Vector3::new(3.0, 1.0, 2.0) * &Vector3::new(0.0, 2.0, -1.0);
     println!("Scalar Product 2: {}", scalar_product2); // 0
     // (b) what does the result of (a) tell about the angle?
     // since it is 0, that means the vectors are ether 90 degress or 270 degrees, right angles
@@ -59,7 +59,7 @@ pub fn exercise2() {
     // a = 1 / sqrt(2) [ 0 1 1 ]
     // b = [ 1 2 3 ]
     // (a) calculate the scalar product c = ^a . b
-    let a6: Vector3 = Vector3::new( (1.0 / (f32::sqrt(2.0))) * 0.0, (1.0 / (f32::sqrt(2.0))) * 1.0, (1.0 / (f32::sqrt(2.0)) * 1.0) );
+    let a6: Vector3 = Vector3::new( (1.0 / (f32::sqrt(2.0))) * 0.0, (1.0 / (f32::sqrt(2.0))) * 1.0, 1.0 / (f32::sqrt(2.0)) * 1.0 );
     let b6: Vector3 = Vector3::new(1.0, 2.0, 3.0);
     let scalar_product2_6: f32 = a6 * &b6;
     println!("2.6: a. Scalar Product: {}", scalar_product2_6);
@@ -77,9 +77,9 @@ pub fn exercise2() {
     let delta_time: u32 = 10;
 
     for _i in 0..delta_time {
-        start_2_7.x = start_2_7.x * velocity_2_7.x;
-        start_2_7.y = start_2_7.y * velocity_2_7.y;
-        start_2_7.z = start_2_7.z * velocity_2_7.z;
+        start_2_7.x *= velocity_2_7.x;
+        start_2_7.y *= velocity_2_7.y;
+        start_2_7.z *= velocity_2_7.z;
         println!("Steps: {:?}", start_2_7);
     }
     println!("End 2_7: {:?}", start_2_7);
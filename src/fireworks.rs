@@ -0,0 +1,280 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::{Particle, ParticleIntegrator},
+    random::Random,
+};
+
+/// Fireworks of a kind to spawn when a firework dies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FireworkPayload {
+    /// Index of the child's rule in the display's rules
+    pub kind: usize,
+    pub count: usize,
+}
+
+/// How one kind of firework behaves. The kind of a firework is the index of its rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FireworkRule {
+    /// Seconds the firework burns before it bursts
+    pub lifetime: (f32, f32),
+    /// Each component is picked between those of the two and added to the parent's velocity,
+    /// or to the launch velocity for a firework with no parent
+    pub velocity: (Vector3, Vector3),
+    /// As `Particle::damping`
+    pub damping: f32,
+    /// What bursts out when the firework dies
+    pub payloads: Vec<FireworkPayload>,
+}
+
+impl FireworkRule {
+    pub fn new(lifetime: (f32, f32), velocity: (Vector3, Vector3), damping: f32) -> FireworkRule {
+        assert!(lifetime.0 > 0.0 && lifetime.0 <= lifetime.1, "lifetimes must be positive and in order");
+        return FireworkRule {
+            lifetime,
+            velocity,
+            damping,
+            payloads: Vec::new()
+        };
+    }
+
+    /// Adds a payload, for building rules in one expression
+    pub fn with_payload(mut self, kind: usize, count: usize) -> FireworkRule {
+        self.payloads.push(FireworkPayload { kind, count });
+        return self;
+    }
+
+    /// The nine rules of the fireworks demo in Millington's Game Physics Engine Development.
+    /// Kind 0 is a rocket bursting into kinds 2 and 4, kind 6 a tall rocket bursting into sparks.
+    pub fn classic_rules() -> Vec<FireworkRule> {
+        return vec![
+            FireworkRule::new((0.5, 1.4), (Vector3::new(-5.0, 25.0, -5.0), Vector3::new(5.0, 28.0, 5.0)), 0.1)
+                .with_payload(2, 5)
+                .with_payload(4, 5),
+            FireworkRule::new((0.5, 1.0), (Vector3::new(-5.0, 10.0, -5.0), Vector3::new(5.0, 20.0, 5.0)), 0.8)
+                .with_payload(3, 2),
+            FireworkRule::new((0.5, 1.5), (Vector3::new(-5.0, -5.0, -5.0), Vector3::new(5.0, 5.0, 5.0)), 0.1),
+            FireworkRule::new((0.25, 0.5), (Vector3::new(-20.0, 5.0, -5.0), Vector3::new(20.0, 5.0, 5.0)), 0.2),
+            FireworkRule::new((0.5, 1.0), (Vector3::new(-20.0, 2.0, -5.0), Vector3::new(20.0, 18.0, 5.0)), 0.01)
+                .with_payload(2, 5),
+            FireworkRule::new((3.0, 5.0), (Vector3::new(-5.0, 5.0, -5.0), Vector3::new(5.0, 10.0, 5.0)), 0.95),
+            FireworkRule::new((4.0, 5.0), (Vector3::new(-5.0, 50.0, -5.0), Vector3::new(5.0, 60.0, 5.0)), 0.01)
+                .with_payload(7, 10),
+            FireworkRule::new((0.25, 0.5), (Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)), 0.01),
+            FireworkRule::new((3.0, 5.0), (Vector3::new(-15.0, 10.0, -5.0), Vector3::new(15.0, 15.0, 5.0)), 0.95),
+        ];
+    }
+}
+
+/// One burning firework
+struct Firework {
+    particle: Rc<RefCell<Particle>>,
+    kind: usize,
+    /// Seconds left before it bursts
+    remaining: f32,
+    alive: bool,
+}
+
+/// A fireworks display: fireworks of the kinds its rules describe, each bursting into its
+/// rule's payloads when its time runs out or it falls below the ground.
+///
+/// There is room for a fixed number of fireworks. Once it is full new fireworks take the place
+/// of the oldest slots in turn, as in the book, so a display never allocates after it is built.
+/// The display integrates its fireworks itself, with the same seed and steps it always plays out
+/// the same way.
+pub struct Fireworks {
+    rules: Vec<FireworkRule>,
+    fireworks: Vec<Firework>,
+    /// The slot the next firework goes in
+    next: usize,
+    random: Random,
+    gravity: Vector3,
+    /// Fireworks below this height die, `None` lets them fall forever
+    ground: Option<f32>,
+}
+
+impl Fireworks {
+    pub fn new(rules: Vec<FireworkRule>, capacity: usize, seed: u64) -> Fireworks {
+        assert!(capacity > 0, "a display needs room for at least one firework");
+        for rule in rules.iter() {
+            assert!(rule.payloads.iter().all(|p| p.kind < rules.len()), "payload refers to a missing rule");
+        }
+        let mut fireworks: Vec<Firework> = Vec::with_capacity(capacity);
+        for _ in 0..capacity {
+            fireworks.push(Firework {
+                particle: Rc::new(RefCell::new(Particle::new(
+                    Vector3::default(),
+                    Vector3::default(),
+                    Vector3::default(),
+                    1.0,
+                    1.0
+                ))),
+                kind: 0,
+                remaining: 0.0,
+                alive: false
+            });
+        }
+        return Fireworks {
+            rules,
+            fireworks,
+            next: 0,
+            random: Random::new(seed),
+            gravity: Vector3::new(0.0, -9.81, 0.0),
+            ground: Some(0.0)
+        };
+    }
+
+    pub fn get_rules(&self) -> &[FireworkRule] {
+        return &self.rules;
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector3) {
+        self.gravity = gravity;
+    }
+
+    pub fn set_ground(&mut self, ground: Option<f32>) {
+        self.ground = ground;
+    }
+
+    /// Starts the random sequence again from the seed, live fireworks are left alone
+    pub fn reseed(&mut self, seed: u64) {
+        self.random.seed(seed);
+    }
+
+    /// Sets off a firework of the kind at the position, its rule's velocity is added to the one given.
+    /// Returns the slot it went in.
+    pub fn launch(&mut self, kind: usize, position: Vector3, velocity: Vector3) -> usize {
+        assert!(kind < self.rules.len(), "no rule for firework kind {}", kind);
+        let rule: &FireworkRule = &self.rules[kind];
+        let remaining: f32 = self.random.random_real_range(rule.lifetime.0, rule.lifetime.1);
+        let mut start: Vector3 = velocity;
+        start += &self.random.random_vector_range(&rule.velocity.0, &rule.velocity.1);
+
+        let slot: usize = self.next;
+        self.next = (self.next + 1) % self.fireworks.len();
+        let firework: &mut Firework = &mut self.fireworks[slot];
+        // Fireworks carry no force so the mass only has to be finite
+        *firework.particle.borrow_mut() = Particle::new(position, start, self.gravity, rule.damping, 1.0);
+        firework.kind = kind;
+        firework.remaining = remaining;
+        firework.alive = true;
+        return slot;
+    }
+
+    /// Moves every firework on by the duration, then bursts those that have died into their
+    /// payloads, which start where their parent died with its velocity
+    pub fn update(&mut self, duration: f32) {
+        assert!(duration > 0.0);
+        let mut bursts: Vec<(usize, Vector3, Vector3)> = Vec::new();
+        for firework in self.fireworks.iter_mut() {
+            if !firework.alive { continue; }
+            let mut particle = firework.particle.borrow_mut();
+            particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, duration);
+            firework.remaining -= duration;
+            let grounded: bool = self.ground.is_some_and(|ground| particle.position.y < ground);
+            if firework.remaining < 0.0 || grounded {
+                firework.alive = false;
+                bursts.push((firework.kind, particle.position, particle.velocity));
+            }
+        }
+        for (kind, position, velocity) in bursts {
+            for payload in self.rules[kind].payloads.clone() {
+                for _ in 0..payload.count {
+                    self.launch(payload.kind, position, velocity);
+                }
+            }
+        }
+    }
+
+    /// The number of slots, burning or not
+    pub fn capacity(&self) -> usize {
+        return self.fireworks.len();
+    }
+
+    pub fn alive_count(&self) -> usize {
+        return self.fireworks.iter().filter(|f| f.alive).count();
+    }
+
+    pub fn is_alive(&self, slot: usize) -> bool {
+        return self.fireworks[slot].alive;
+    }
+
+    /// The slot numbers of the burning fireworks, in slot order
+    pub fn get_alive_slots(&self) -> Vec<usize> {
+        return (0..self.fireworks.len()).filter(|&slot| self.fireworks[slot].alive).collect();
+    }
+
+    pub fn get_particle(&self, slot: usize) -> &Rc<RefCell<Particle>> {
+        return &self.fireworks[slot].particle;
+    }
+
+    pub fn get_kind(&self, slot: usize) -> usize {
+        return self.fireworks[slot].kind;
+    }
+
+    /// Seconds left before the firework in the slot bursts
+    pub fn get_remaining(&self, slot: usize) -> f32 {
+        return self.fireworks[slot].remaining;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn launch_display(seed: u64) -> Fireworks {
+        let mut display: Fireworks = Fireworks::new(FireworkRule::classic_rules(), 64, seed);
+        display.launch(0, Vector3::default(), Vector3::default());
+        display.launch(6, Vector3::new(4.0, 0.0, 0.0), Vector3::default());
+        for _ in 0..180 {
+            display.update(1.0 / 60.0);
+        }
+        return display;
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_display() {
+        let first: Fireworks = launch_display(7);
+        let second: Fireworks = launch_display(7);
+        let slots: Vec<usize> = first.get_alive_slots();
+        assert!(!slots.is_empty());
+        assert_eq!(slots, second.get_alive_slots());
+        for &slot in slots.iter() {
+            assert_eq!(first.get_kind(slot), second.get_kind(slot));
+            assert_eq!(first.get_particle(slot).borrow().get_position(), second.get_particle(slot).borrow().get_position());
+        }
+
+        let other: Fireworks = launch_display(8);
+        let moved: bool = slots.iter().any(|&slot| {
+            return !other.is_alive(slot) || other.get_particle(slot).borrow().get_position() != first.get_particle(slot).borrow().get_position();
+        });
+        assert!(moved, "another seed gave the same display");
+    }
+
+    #[test]
+    fn bursts_launch_their_payloads() {
+        let mut display: Fireworks = Fireworks::new(FireworkRule::classic_rules(), 32, 1);
+        display.set_ground(None);
+        display.launch(0, Vector3::default(), Vector3::default());
+        // Kind 0 burns for at most 1.4 s, its children for at least 0.5 s
+        while display.get_kind(0) == 0 && display.is_alive(0) {
+            display.update(0.01);
+        }
+        assert_eq!(display.alive_count(), 10);
+        let kinds: Vec<usize> = display.get_alive_slots().iter().map(|&slot| display.get_kind(slot)).collect();
+        assert_eq!(kinds.iter().filter(|&&kind| kind == 2).count(), 5);
+        assert_eq!(kinds.iter().filter(|&&kind| kind == 4).count(), 5);
+        // They start where their parent burst
+        let burst: Vector3 = display.get_particle(0).borrow().get_position();
+        assert!(display.get_alive_slots().iter().all(|&slot| display.get_particle(slot).borrow().get_position() == burst));
+    }
+
+    #[test]
+    fn full_displays_reuse_the_oldest_slots() {
+        let mut display: Fireworks = Fireworks::new(FireworkRule::classic_rules(), 4, 1);
+        let slots: Vec<usize> = (0..6).map(|_| display.launch(5, Vector3::default(), Vector3::default())).collect();
+        assert_eq!(slots, vec![0, 1, 2, 3, 0, 1]);
+        assert_eq!(display.alive_count(), 4);
+    }
+}
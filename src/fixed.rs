@@ -0,0 +1,474 @@
+//! Fixed-point scalar types, an alternative to float determinism.
+//!
+//! `Fixed16` is Q16.16, a 32-bit value with 16 fractional bits, covering about +/-32768 with a
+//! resolution of 1/65536. `Fixed32` is Q32.32, a 64-bit value covering about +/-2.1 billion with a
+//! resolution of 2.3e-10. Both only use integer arithmetic, so every platform gives the same bits.
+//!
+//! They implement `Real`, so they can be used in place of `f32`:
+//! `Particle::<Fixed32>::new(...)`, `ParticleGravity::<Fixed32>::new(...)`.
+//!
+//! Q16.16 is only precise enough for small worlds and gentle forces. Generators that divide by the
+//! square of the timestep, such as `ParticleFakeSpring`, magnify its rounding a few thousand times
+//! at 60 steps per second, so prefer `Fixed32` for those.
+//!
+//! Overflow panics in debug builds and wraps in release builds. Division by zero always panics.
+//! There is no infinity, so particles meant to be immovable should be given an inverse mass of zero
+//! rather than an infinite mass.
+//!
+//! Square root uses an integer square root. Sine and cosine use CORDIC, and exp, ln and pow use
+//! range reduction and a series, all worked in Q32.32 internally.
+
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
+};
+
+use crate::precision::Real;
+
+// Constants in Q32.32
+
+const Q32_ONE: i64 = 1 << 32;
+const Q32_PI: i64 = 13_493_037_705;
+const Q32_HALF_PI: i64 = 6_746_518_852;
+const Q32_TWO_PI: i64 = 26_986_075_409;
+const Q32_LN_2: i64 = 2_977_044_472;
+/// The CORDIC gain correction, the product of 1 / sqrt(1 + 2^-2i)
+const Q32_CORDIC_GAIN: i64 = 2_608_131_496;
+/// atan(2^-i) for each CORDIC iteration
+const Q32_CORDIC_ANGLES: [i64; 32] = [
+    3_373_259_426, 1_991_351_318, 1_052_175_346, 534_100_635, 268_086_748, 134_174_063,
+    67_103_403, 33_553_749, 16_777_131, 8_388_597, 4_194_303, 2_097_152, 1_048_576, 524_288,
+    262_144, 131_072, 65_536, 32_768, 16_384, 8_192, 4_096, 2_048, 1_024, 512, 256, 128, 64,
+    32, 16, 8, 4, 2,
+];
+
+/// Narrows a wide intermediate, panicking on overflow in debug builds.
+macro_rules! narrow {
+    ($value:expr, $target:ty, $name:expr) => {{
+        let value = $value;
+        if cfg!(debug_assertions) && <$target>::try_from(value).is_err() {
+            panic!("{} overflow", $name);
+        }
+        value as $target
+    }};
+}
+
+fn q32_mul(a: i64, b: i64) -> i64 {
+    return narrow!((a as i128 * b as i128 + (1 << 31)) >> 32, i64, "Q32.32 multiplication");
+}
+
+fn q32_div(a: i64, b: i64) -> i64 {
+    return narrow!(((a as i128) << 32) / b as i128, i64, "Q32.32 division");
+}
+
+/// Returns (sin, cos) of the angle with CORDIC
+fn q32_sin_cos(angle: i64) -> (i64, i64) {
+    // Reduce to (-PI, PI], then fold into [-PI/2, PI/2] where CORDIC converges
+    let mut theta: i64 = angle.rem_euclid(Q32_TWO_PI);
+    if theta > Q32_PI { theta -= Q32_TWO_PI; }
+    let mut cos_sign: i64 = 1;
+    if theta > Q32_HALF_PI {
+        theta = Q32_PI - theta;
+        cos_sign = -1;
+    } else if theta < -Q32_HALF_PI {
+        theta = -Q32_PI - theta;
+        cos_sign = -1;
+    }
+
+    let (mut x, mut y, mut z): (i64, i64, i64) = (Q32_CORDIC_GAIN, 0, theta);
+    for (i, step) in Q32_CORDIC_ANGLES.iter().enumerate() {
+        let (x_shift, y_shift): (i64, i64) = (x >> i, y >> i);
+        if z >= 0 {
+            x -= y_shift;
+            y += x_shift;
+            z -= step;
+        } else {
+            x += y_shift;
+            y -= x_shift;
+            z += step;
+        }
+    }
+    return (y, x * cos_sign);
+}
+
+/// e^x by reducing to `x = k ln2 + r` with `r` in [0, ln2), then a Taylor series for e^r
+fn q32_exp(value: i64) -> i64 {
+    let k: i64 = value.div_euclid(Q32_LN_2);
+    let r: i64 = value - k * Q32_LN_2;
+
+    let mut sum: i64 = Q32_ONE;
+    let mut term: i64 = Q32_ONE;
+    let mut n: i64 = 1;
+    while term != 0 {
+        term = q32_mul(term, r) / n;
+        sum += term;
+        n += 1;
+    }
+
+    if k >= 0 {
+        if cfg!(debug_assertions) && sum.leading_zeros() as i64 <= k {
+            panic!("Q32.32 exp overflow");
+        }
+        return sum.wrapping_shl(k as u32);
+    }
+    if k <= -63 { return 0; }
+    return sum >> (-k);
+}
+
+/// ln(x) by splitting `x = m 2^e` with `m` in [1, 2), then `ln(m) = 2 atanh(s)`, `s = (m - 1) / (m + 1)`
+fn q32_ln(value: i64) -> i64 {
+    assert!(value > 0, "ln of a non-positive fixed-point number");
+    let exponent: i64 = 63 - value.leading_zeros() as i64 - 32;
+    let mantissa: i64 = if exponent >= 0 { value >> exponent } else { value << (-exponent) };
+
+    let s: i64 = q32_div(mantissa - Q32_ONE, mantissa + Q32_ONE);
+    let s2: i64 = q32_mul(s, s);
+    let mut sum: i64 = 0;
+    let mut power: i64 = s;
+    let mut n: i64 = 1;
+    while power != 0 {
+        sum += power / n;
+        power = q32_mul(power, s2);
+        n += 2;
+    }
+    return 2 * sum + exponent * Q32_LN_2;
+}
+
+/// Defines a fixed-point type over a signed integer, with a wider integer for products.
+macro_rules! fixed_point {
+    ($name:ident, $raw:ty, $wide:ty, $frac:expr, $label:expr) => {
+        #[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name {
+            raw: $raw,
+        }
+
+        impl $name {
+            pub const FRACTIONAL_BITS: u32 = $frac;
+            pub const ONE: $name = $name { raw: 1 << $frac };
+            pub const MAX: $name = $name { raw: <$raw>::MAX };
+            pub const MIN: $name = $name { raw: <$raw>::MIN };
+
+            pub const fn from_raw(raw: $raw) -> $name {
+                return $name { raw };
+            }
+
+            pub const fn to_raw(self) -> $raw {
+                return self.raw;
+            }
+
+            pub fn from_int(value: $raw) -> $name {
+                return $name { raw: narrow!((value as $wide) << $frac, $raw, $label) };
+            }
+
+            pub fn from_f64(value: f64) -> $name {
+                let scaled: f64 = (value * (1u64 << $frac) as f64).round();
+                if cfg!(debug_assertions) && !(scaled >= <$raw>::MIN as f64 && scaled <= <$raw>::MAX as f64) {
+                    panic!("{} conversion overflow", $label);
+                }
+                return $name { raw: scaled as $raw };
+            }
+
+            pub fn to_f64(self) -> f64 {
+                return self.raw as f64 / (1u64 << $frac) as f64;
+            }
+
+            /// Converts to Q32.32 for the shared transcendental functions
+            fn to_q32(self) -> i64 {
+                return narrow!((self.raw as i128) << (32 - $frac), i64, $label);
+            }
+
+            fn from_q32(value: i64) -> $name {
+                let shift: u32 = 32 - $frac;
+                let rounded: i64 = if shift == 0 { value } else { (value + (1 << shift >> 1)) >> shift };
+                return $name { raw: narrow!(rounded, $raw, $label) };
+            }
+
+            /// Natural logarithm, panics for values that are not positive
+            pub fn ln(self) -> $name {
+                return $name::from_q32(q32_ln(self.to_q32()));
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                return write!(f, "{}({})", stringify!($name), self.to_f64());
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                return write!(f, "{}", self.to_f64());
+            }
+        }
+
+        impl Add for $name {
+            type Output = $name;
+
+            fn add(self, _rhs: $name) -> $name {
+                if cfg!(debug_assertions) {
+                    return $name { raw: self.raw.checked_add(_rhs.raw).expect(concat!($label, " addition overflow")) };
+                }
+                return $name { raw: self.raw.wrapping_add(_rhs.raw) };
+            }
+        }
+
+        impl Sub for $name {
+            type Output = $name;
+
+            fn sub(self, _rhs: $name) -> $name {
+                if cfg!(debug_assertions) {
+                    return $name { raw: self.raw.checked_sub(_rhs.raw).expect(concat!($label, " subtraction overflow")) };
+                }
+                return $name { raw: self.raw.wrapping_sub(_rhs.raw) };
+            }
+        }
+
+        impl Mul for $name {
+            type Output = $name;
+
+            /// Rounds to the nearest representable value
+            fn mul(self, _rhs: $name) -> $name {
+                let product: $wide = (self.raw as $wide * _rhs.raw as $wide + (1 << ($frac - 1))) >> $frac;
+                return $name { raw: narrow!(product, $raw, concat!($label, " multiplication")) };
+            }
+        }
+
+        impl Div for $name {
+            type Output = $name;
+
+            /// Truncates towards zero
+            fn div(self, _rhs: $name) -> $name {
+                let quotient: $wide = ((self.raw as $wide) << $frac) / _rhs.raw as $wide;
+                return $name { raw: narrow!(quotient, $raw, concat!($label, " division")) };
+            }
+        }
+
+        impl Neg for $name {
+            type Output = $name;
+
+            fn neg(self) -> $name {
+                if cfg!(debug_assertions) {
+                    return $name { raw: self.raw.checked_neg().expect(concat!($label, " negation overflow")) };
+                }
+                return $name { raw: self.raw.wrapping_neg() };
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, _rhs: $name) {
+                *self = *self + _rhs;
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, _rhs: $name) {
+                *self = *self - _rhs;
+            }
+        }
+
+        impl MulAssign for $name {
+            fn mul_assign(&mut self, _rhs: $name) {
+                *self = *self * _rhs;
+            }
+        }
+
+        impl DivAssign for $name {
+            fn div_assign(&mut self, _rhs: $name) {
+                *self = *self / _rhs;
+            }
+        }
+
+        impl Real for $name {
+            fn zero() -> Self { return $name { raw: 0 }; }
+            fn one() -> Self { return $name::ONE; }
+            fn from_f32(value: f32) -> Self { return $name::from_f64(value as f64); }
+            fn to_f32(self) -> f32 { return self.to_f64() as f32; }
+            fn max_value() -> Self { return $name::MAX; }
+            fn is_finite(self) -> bool { return true; }
+            fn to_bits_u64(self) -> u64 { return self.raw as u64; }
+
+            /// Rounds down, the square root of a negative number is zero
+            fn sqrt(self) -> Self {
+                if self.raw <= 0 {
+                    debug_assert!(self.raw == 0, concat!($label, " square root of a negative number"));
+                    return $name { raw: 0 };
+                }
+                let root: u128 = ((self.raw as u128) << $frac).isqrt();
+                return $name { raw: root as $raw };
+            }
+
+            fn abs(self) -> Self {
+                return if self.raw < 0 { -self } else { self };
+            }
+
+            fn sin(self) -> Self {
+                return $name::from_q32(q32_sin_cos(self.to_q32()).0);
+            }
+
+            fn cos(self) -> Self {
+                return $name::from_q32(q32_sin_cos(self.to_q32()).1);
+            }
+
+            fn exp(self) -> Self {
+                return $name::from_q32(q32_exp(self.to_q32()));
+            }
+
+            /// Only defined for positive bases, and zero
+            fn pow(self, exponent: Self) -> Self {
+                if exponent.raw == 0 { return $name::ONE; }
+                if self.raw == 0 { return self; }
+                return $name::from_q32(q32_exp(q32_mul(exponent.to_q32(), q32_ln(self.to_q32()))));
+            }
+        }
+    };
+}
+
+fixed_point!(Fixed16, i32, i64, 16, "Fixed16");
+fixed_point!(Fixed32, i64, i128, 32, "Fixed32");
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        core::Vector3,
+        particle::Particle,
+        particle_force_gen::{ParticleForceGenerator, ParticleForceRegistry, ParticleGravity},
+    };
+
+    /// Values across the range of both types, away from where Q16.16 runs out
+    const SAMPLES: [f64; 12] = [-3000.25, -100.0, -7.5, -1.0, -0.3, -0.001, 0.0, 0.002, 0.5, 1.0, 42.125, 2500.75];
+
+    fn assert_close(found: f64, expected: f64, tolerance: f64, what: &str) {
+        assert!((found - expected).abs() <= tolerance, "{}: {} not {}", what, found, expected);
+    }
+
+    #[test]
+    fn arithmetic_matches_f64() {
+        for &a in SAMPLES.iter() {
+            for &b in SAMPLES.iter() {
+                let (x16, y16) = (Fixed16::from_f64(a), Fixed16::from_f64(b));
+                let (x32, y32) = (Fixed32::from_f64(a), Fixed32::from_f64(b));
+                // Q16.16 rounds the inputs to 1/65536, which products magnify by the other value
+                let input_error: f64 = (a.abs() + b.abs() + 1.0) / 65536.0;
+                assert_close((x16 + y16).to_f64(), a + b, 2.0 / 65536.0, "Fixed16 add");
+                assert_close((x16 - y16).to_f64(), a - b, 2.0 / 65536.0, "Fixed16 sub");
+                assert_close((x32 + y32).to_f64(), a + b, 1.0e-9, "Fixed32 add");
+                assert_close((x32 - y32).to_f64(), a - b, 1.0e-9, "Fixed32 sub");
+                if (a * b).abs() < 30000.0 {
+                    assert_close((x16 * y16).to_f64(), a * b, input_error, "Fixed16 mul");
+                }
+                assert_close((x32 * y32).to_f64(), a * b, 1.0e-6, "Fixed32 mul");
+                if b.abs() >= 0.3 && (a / b).abs() < 30000.0 {
+                    assert_close((x16 / y16).to_f64(), a / b, input_error * 4.0, "Fixed16 div");
+                }
+                if b != 0.0 {
+                    let relative: f64 = ((x32 / y32).to_f64() - a / b).abs() / (a / b).abs().max(1.0);
+                    assert!(relative < 1.0e-6, "Fixed32 div {} / {}", a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn square_roots_match_f64() {
+        for value in [0.0, 1.0e-4, 0.25, 2.0, 10.0, 1234.5, 30000.0] {
+            assert_close(Fixed16::from_f64(value).sqrt().to_f64(), value.sqrt(), 2.0e-3, "Fixed16 sqrt");
+            assert_close(Fixed32::from_f64(value).sqrt().to_f64(), value.sqrt(), 1.0e-8, "Fixed32 sqrt");
+        }
+        assert_close(Fixed32::from_f64(1.0e9).sqrt().to_f64(), 1.0e9f64.sqrt(), 1.0e-6, "Fixed32 sqrt");
+    }
+
+    #[test]
+    fn sines_and_cosines_match_f64() {
+        for i in -100..=100 {
+            // Several turns either way, so the range reduction is exercised too
+            let angle: f64 = i as f64 * 0.377;
+            let x: Fixed32 = Fixed32::from_f64(angle);
+            assert_close(x.sin().to_f64(), angle.sin(), 1.0e-8, "Fixed32 sin");
+            assert_close(x.cos().to_f64(), angle.cos(), 1.0e-8, "Fixed32 cos");
+            let y: Fixed16 = Fixed16::from_f64(angle);
+            assert_close(y.sin().to_f64(), y.to_f64().sin(), 2.0 / 65536.0, "Fixed16 sin");
+            assert_close(y.cos().to_f64(), y.to_f64().cos(), 2.0 / 65536.0, "Fixed16 cos");
+        }
+    }
+
+    #[test]
+    fn exponentials_and_logarithms_match_f64() {
+        for value in [-20.0, -5.5, -1.0, -0.1, 0.0, 0.3, 1.0, 4.75, 15.0, 20.0] {
+            let expected: f64 = f64::exp(value);
+            let found: f64 = Fixed32::from_f64(value).exp().to_f64();
+            assert!((found - expected).abs() <= expected * 1.0e-7 + 1.0e-9, "Fixed32 exp {}: {} not {}", value, found, expected);
+        }
+        assert_close(Fixed16::from_f64(2.0).exp().to_f64(), 2.0f64.exp(), 1.0e-3, "Fixed16 exp");
+
+        for value in [1.0e-6, 0.01, 0.5, 1.0, 2.0, 10.0, 12345.0, 1.0e9] {
+            // Small values lose relative precision on the way in, so compare with the stored value
+            let x: Fixed32 = Fixed32::from_f64(value);
+            assert_close(x.ln().to_f64(), x.to_f64().ln(), 1.0e-8, "Fixed32 ln");
+        }
+        assert_close(Fixed16::from_f64(100.0).ln().to_f64(), 100.0f64.ln(), 1.0e-4, "Fixed16 ln");
+
+        for (base, exponent) in [(0.99, 1.0 / 60.0), (2.0, 10.0), (9.0, 0.5), (0.5, -3.0), (3.0, 0.0)] {
+            let found: f64 = Fixed32::from_f64(base).pow(Fixed32::from_f64(exponent)).to_f64();
+            let expected: f64 = f64::powf(base, exponent);
+            assert!((found - expected).abs() <= expected * 1.0e-6, "Fixed32 pow {} {}: {} not {}", base, exponent, found, expected);
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Fixed16 addition overflow")]
+    fn addition_overflow_panics_in_debug_builds() {
+        let _ = Fixed16::MAX + Fixed16::ONE;
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Fixed32 multiplication overflow")]
+    fn multiplication_overflow_panics_in_debug_builds() {
+        let _ = Fixed32::from_int(1 << 20) * Fixed32::from_int(1 << 20);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "Fixed16 division overflow")]
+    fn division_overflow_panics_in_debug_builds() {
+        let _ = Fixed16::from_int(20000) / Fixed16::from_f64(0.25);
+    }
+
+    #[test]
+    fn particles_fall_under_gravity() {
+        let duration: f64 = 1.0 / 64.0;
+        let particle: Rc<RefCell<Particle<Fixed32>>> = Rc::new(RefCell::new(Particle::new(
+            Vector3::new(Fixed32::zero(), Fixed32::from_int(100), Fixed32::zero()),
+            Vector3::new(Fixed32::from_int(3), Fixed32::zero(), Fixed32::zero()),
+            Vector3::default(),
+            Fixed32::from_f64(0.99),
+            Fixed32::from_int(2)
+        )));
+        let gravity: Rc<RefCell<dyn ParticleForceGenerator<Fixed32>>> = Rc::new(RefCell::new(ParticleGravity::new(
+            &Vector3::new(Fixed32::zero(), Fixed32::from_f64(-9.81), Fixed32::zero())
+        )));
+        let mut registry: ParticleForceRegistry<Fixed32> = ParticleForceRegistry::new();
+        registry.add(&particle, &gravity);
+
+        // The same Euler steps in f64
+        let (mut x, mut y, mut vx, mut vy): (f64, f64, f64, f64) = (0.0, 100.0, 3.0, 0.0);
+        let damping: f64 = 0.99f64.powf(duration);
+        for _ in 0..128 {
+            registry.update_forces(Fixed32::from_f64(duration));
+            particle.borrow_mut().integrate(Fixed32::from_f64(duration));
+            x += vx * duration;
+            y += vy * duration;
+            vy -= 9.81 * duration;
+            vx *= damping;
+            vy *= damping;
+        }
+        let position: Vector3<Fixed32> = particle.borrow().get_position();
+        assert_close(position.x.to_f64(), x, 1.0e-5, "x");
+        assert_close(position.y.to_f64(), y, 1.0e-5, "y");
+        assert!(position.y.to_f64() < 85.0);
+    }
+}
@@ -0,0 +1,689 @@
+//! Records the inputs applied to a running scene so the run can be replayed exactly.
+//!
+//! A run is the `Scene` it starts from, a fixed step duration and a journal of every input applied
+//! from outside, each tagged with the step it was applied before. Replaying builds the scene afresh,
+//! applies the same inputs at the same steps, and compares the state hash against checkpoints taken
+//! while recording, so a replay that drifts from the original is caught at the first checkpoint it
+//! misses. The journal holds everything the replay needs, so it can be replayed by another process.
+//!
+//! Journals are saved as text, one entry per line, with `#` starting a comment. The scene is written
+//! in its own text format between `scene` and `end scene`:
+//!
+//! ```text
+//! journal 1
+//! duration 0.016666668
+//! scene
+//! [particle ball]
+//! position = 0 8 0
+//! mass = 2
+//! end scene
+//! checkpoint 0 9f6c1c4fd2a3b2e1
+//! at 12 add_force ball 0 250 0
+//! at 30 spawn rock 0 5 0 0 0 0 0 -9.81 0 0.99 3
+//! at 45 set ball damping 0.9
+//! at 45 set rock charge none
+//! at 50 remove rock
+//! checkpoint 60 1e0b9a3f77d04c65
+//! ```
+//!
+//! Particle names follow the scene format, letters, digits, `_` and `-` only.
+//! `spawn` takes the name, position, velocity, acceleration, damping, mass and an optional charge.
+//! `set` takes `position`, `velocity` or `acceleration` with a vector, `damping` or `mass` with a
+//! number, or `charge` with a number or `none`.
+
+use std::{
+    error,
+    fmt,
+    io::{self, Write},
+};
+
+use crate::{
+    core::Vector3,
+    scene::{is_valid_name, Scene, SceneInstance, SceneParticle},
+    state_hash::hash_particles,
+};
+
+/// The version written at the top of journals
+pub const JOURNAL_VERSION: u32 = 1;
+
+/// A particle property an input can change.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParticleParameter {
+    Position(Vector3),
+    Velocity(Vector3),
+    Acceleration(Vector3),
+    Damping(f32),
+    /// Sets the mass and recalculates the inverse mass
+    Mass(f32),
+    Charge(Option<f32>),
+}
+
+/// Something done to the simulation from outside, which a replay has to repeat.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalInput {
+    /// Adds a force to the particle's accumulator for the next step only
+    AddForce { particle: String, force: Vector3 },
+    Spawn(SceneParticle),
+    Remove { particle: String },
+    SetParameter { particle: String, parameter: ParticleParameter },
+}
+
+impl JournalInput {
+    /// Applies the input to the scene. Fails if it names a particle that does not exist,
+    /// spawns one whose name is taken or not valid, or gives a mass that is not positive.
+    pub fn apply(&self, instance: &mut SceneInstance) -> Result<(), String> {
+        check_name(self.get_particle())?;
+        match self {
+            JournalInput::AddForce { particle, force } => {
+                let particle = instance.get_particle(particle).ok_or_else(|| no_particle(particle))?;
+                particle.borrow_mut().add_force(*force);
+            }
+            JournalInput::Spawn(particle) => {
+                check_mass(&particle.name, particle.mass)?;
+                if instance.add_particle(particle).is_none() {
+                    return Err(format!("a particle named '{}' already exists", particle.name));
+                }
+            }
+            JournalInput::Remove { particle } => {
+                instance.remove_particle(particle).ok_or_else(|| no_particle(particle))?;
+            }
+            JournalInput::SetParameter { particle: name, parameter } => {
+                if let ParticleParameter::Mass(mass) = parameter {
+                    check_mass(name, *mass)?;
+                }
+                let mut particle = instance.get_particle(name).ok_or_else(|| no_particle(name))?.borrow_mut();
+                match parameter {
+                    ParticleParameter::Position(position) => particle.position = *position,
+                    ParticleParameter::Velocity(velocity) => particle.velocity = *velocity,
+                    ParticleParameter::Acceleration(acceleration) => particle.acceleration = *acceleration,
+                    ParticleParameter::Damping(damping) => particle.damping = *damping,
+                    ParticleParameter::Mass(mass) => {
+                        particle.set_mass(*mass);
+                        particle.set_inverse_mass();
+                    }
+                    ParticleParameter::Charge(Some(charge)) => particle.set_charge(*charge),
+                    ParticleParameter::Charge(None) => particle.clear_charge(),
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// The name of the particle the input applies to
+    pub fn get_particle(&self) -> &str {
+        return match self {
+            JournalInput::AddForce { particle, .. }
+            | JournalInput::Remove { particle }
+            | JournalInput::SetParameter { particle, .. } => particle,
+            JournalInput::Spawn(particle) => &particle.name,
+        };
+    }
+}
+
+/// Names with spaces or other separators could not be read back from the text form
+fn check_name(name: &str) -> Result<(), String> {
+    if !is_valid_name(name) {
+        return Err(format!("'{}' is not a valid name", name));
+    }
+    return Ok(());
+}
+
+fn no_particle(name: &str) -> String {
+    return format!("there is no particle named '{}'", name);
+}
+
+fn check_mass(name: &str, mass: f32) -> Result<(), String> {
+    if mass.is_nan() || mass <= 0.0 {
+        return Err(format!("particle '{}' must have a positive mass", name));
+    }
+    return Ok(());
+}
+
+/// An input and the step it was applied before.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEvent {
+    pub step: u64,
+    pub input: JournalInput,
+}
+
+/// The state hash of every particle after the given number of steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalCheckpoint {
+    pub step: u64,
+    pub hash: u64,
+}
+
+/// A problem found while reading a journal, with the line it was found on counting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "line {}: {}", self.line, self.message);
+    }
+}
+
+impl error::Error for JournalError {}
+
+/// Why a replay failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplayError {
+    /// The state hash did not match the checkpoint taken at this step while recording
+    Desync { step: u64, expected: u64, found: u64 },
+    /// An input could not be applied
+    InvalidInput { step: u64, message: String },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            ReplayError::Desync { step, expected, found } => {
+                write!(f, "replay diverged at step {}: expected hash {:016x}, found {:016x}", step, expected, found)
+            }
+            ReplayError::InvalidInput { step, message } => write!(f, "step {}: {}", step, message),
+        };
+    }
+}
+
+impl error::Error for ReplayError {}
+
+/// The starting scene, inputs and checkpoints of a run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Journal {
+    /// The fixed duration of every step
+    pub duration: f32,
+    /// The state the run starts from
+    pub scene: Scene,
+    /// In the order they were applied
+    pub events: Vec<JournalEvent>,
+    /// In step order
+    pub checkpoints: Vec<JournalCheckpoint>,
+}
+
+impl Journal {
+    pub fn new(scene: Scene, duration: f32) -> Journal {
+        return Journal {
+            duration,
+            scene,
+            events: Vec::new(),
+            checkpoints: Vec::new()
+        };
+    }
+
+    /// The step the run ends on, the last step with an event or checkpoint
+    pub fn get_last_step(&self) -> u64 {
+        let last_event: u64 = self.events.last().map_or(0, |e| e.step);
+        let last_checkpoint: u64 = self.checkpoints.last().map_or(0, |c| c.step);
+        return last_event.max(last_checkpoint);
+    }
+
+    /// Writes the journal in its text form, events and checkpoints interleaved by step
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "journal {}", JOURNAL_VERSION)?;
+        writeln!(writer, "duration {}", self.duration)?;
+        writeln!(writer, "scene")?;
+        self.scene.write(writer)?;
+        writeln!(writer, "end scene")?;
+        let mut checkpoints = self.checkpoints.iter().peekable();
+        for event in self.events.iter() {
+            while let Some(checkpoint) = checkpoints.next_if(|c| c.step <= event.step) {
+                writeln!(writer, "checkpoint {} {:016x}", checkpoint.step, checkpoint.hash)?;
+            }
+            writeln!(writer, "at {} {}", event.step, format_input(&event.input))?;
+        }
+        for checkpoint in checkpoints {
+            writeln!(writer, "checkpoint {} {:016x}", checkpoint.step, checkpoint.hash)?;
+        }
+        return Ok(());
+    }
+
+    /// Returns the journal in its text form
+    pub fn to_text(&self) -> String {
+        let mut text: Vec<u8> = Vec::new();
+        self.write(&mut text).expect("writing to a Vec cannot fail");
+        return String::from_utf8(text).expect("the journal writer only writes UTF-8");
+    }
+
+    /// Reads a journal from its text form
+    pub fn parse(text: &str) -> Result<Journal, JournalError> {
+        let mut lines = text.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let last_line: usize = text.lines().count().max(1);
+        let ends_before = |what: &str| JournalError { line: last_line, message: format!("the journal ends before its {}", what) };
+
+        let (line_number, words) = next_entry(&mut lines).ok_or_else(|| ends_before("version"))?;
+        let error = |message: String| JournalError { line: line_number, message };
+        if words.len() != 2 || words[0] != "journal" {
+            return Err(error("a journal must start with 'journal <version>'".to_string()));
+        }
+        let version: u32 = words[1].parse().map_err(|_| error(format!("'{}' is not a version", words[1])))?;
+        if version > JOURNAL_VERSION {
+            return Err(error(format!("journal version {} is newer than the supported version {}", version, JOURNAL_VERSION)));
+        }
+
+        let (line_number, words) = next_entry(&mut lines).ok_or_else(|| ends_before("duration"))?;
+        let error = |message: String| JournalError { line: line_number, message };
+        if words.len() != 2 || words[0] != "duration" {
+            return Err(error("expected 'duration <seconds>' after the version".to_string()));
+        }
+        let duration: f32 = parse_number(words[1]).map_err(error)?;
+        if duration.is_nan() || duration <= 0.0 {
+            return Err(error("the duration must be positive".to_string()));
+        }
+
+        let (scene_line, words) = next_entry(&mut lines).ok_or_else(|| ends_before("scene"))?;
+        if words != ["scene"] {
+            return Err(JournalError { line: scene_line, message: "expected 'scene' after the duration".to_string() });
+        }
+        let mut scene_text: String = String::new();
+        let mut scene_closed: bool = false;
+        for (_, raw_line) in lines.by_ref() {
+            if entry_words(raw_line) == ["end", "scene"] {
+                scene_closed = true;
+                break;
+            }
+            scene_text.push_str(raw_line);
+            scene_text.push('\n');
+        }
+        if !scene_closed {
+            return Err(ends_before("'end scene'"));
+        }
+        // Scene lines count from the one after `scene`
+        let scene: Scene = Scene::parse(&scene_text)
+            .map_err(|e| JournalError { line: scene_line + e.line, message: e.message })?;
+        let mut journal: Journal = Journal::new(scene, duration);
+
+        while let Some((line_number, words)) = next_entry(&mut lines) {
+            let error = |message: String| JournalError { line: line_number, message };
+            match words[0] {
+                "at" => {
+                    if words.len() < 3 {
+                        return Err(error("expected 'at <step> <input> ...'".to_string()));
+                    }
+                    let step: u64 = parse_step(words[1]).map_err(error)?;
+                    if journal.events.last().is_some_and(|e| e.step > step) {
+                        return Err(error(format!("step {} comes before the previous event", step)));
+                    }
+                    let input: JournalInput = parse_input(&words[2..]).map_err(error)?;
+                    journal.events.push(JournalEvent { step, input });
+                }
+                "checkpoint" => {
+                    if words.len() != 3 {
+                        return Err(error("expected 'checkpoint <step> <hash>'".to_string()));
+                    }
+                    let step: u64 = parse_step(words[1]).map_err(error)?;
+                    if journal.checkpoints.last().is_some_and(|c| c.step >= step) {
+                        return Err(error(format!("checkpoint {} is not after the previous one", step)));
+                    }
+                    let hash: u64 = u64::from_str_radix(words[2], 16)
+                        .map_err(|_| error(format!("'{}' is not a hexadecimal hash", words[2])))?;
+                    journal.checkpoints.push(JournalCheckpoint { step, hash });
+                }
+                other => return Err(error(format!("unknown entry '{}'", other))),
+            }
+        }
+        return Ok(journal);
+    }
+}
+
+/// The words of a line, leaving out any comment
+fn entry_words(line: &str) -> Vec<&str> {
+    return line.split('#').next().unwrap_or("").split_whitespace().collect();
+}
+
+/// Returns the next line that is not blank or only a comment, with its number
+fn next_entry<'a>(lines: &mut impl Iterator<Item = (usize, &'a str)>) -> Option<(usize, Vec<&'a str>)> {
+    return lines.map(|(line_number, line)| (line_number, entry_words(line))).find(|(_, words)| !words.is_empty());
+}
+
+fn format_vector(vector: &Vector3) -> String {
+    return format!("{} {} {}", vector.x, vector.y, vector.z);
+}
+
+fn format_input(input: &JournalInput) -> String {
+    return match input {
+        JournalInput::AddForce { particle, force } => format!("add_force {} {}", particle, format_vector(force)),
+        JournalInput::Spawn(particle) => {
+            let mut text: String = format!(
+                "spawn {} {} {} {} {} {}",
+                particle.name,
+                format_vector(&particle.position),
+                format_vector(&particle.velocity),
+                format_vector(&particle.acceleration),
+                particle.damping,
+                particle.mass
+            );
+            if let Some(charge) = particle.charge {
+                text.push_str(&format!(" {}", charge));
+            }
+            text
+        }
+        JournalInput::Remove { particle } => format!("remove {}", particle),
+        JournalInput::SetParameter { particle, parameter } => {
+            let value: String = match parameter {
+                ParticleParameter::Position(v) => format!("position {}", format_vector(v)),
+                ParticleParameter::Velocity(v) => format!("velocity {}", format_vector(v)),
+                ParticleParameter::Acceleration(v) => format!("acceleration {}", format_vector(v)),
+                ParticleParameter::Damping(damping) => format!("damping {}", damping),
+                ParticleParameter::Mass(mass) => format!("mass {}", mass),
+                ParticleParameter::Charge(Some(charge)) => format!("charge {}", charge),
+                ParticleParameter::Charge(None) => "charge none".to_string(),
+            };
+            format!("set {} {}", particle, value)
+        }
+    };
+}
+
+fn parse_number(word: &str) -> Result<f32, String> {
+    return word.parse::<f32>().map_err(|_| format!("'{}' is not a number", word));
+}
+
+fn parse_step(word: &str) -> Result<u64, String> {
+    return word.parse::<u64>().map_err(|_| format!("'{}' is not a step index", word));
+}
+
+fn parse_vector(words: &[&str]) -> Result<Vector3, String> {
+    return Ok(Vector3::new(parse_number(words[0])?, parse_number(words[1])?, parse_number(words[2])?));
+}
+
+/// Checks the input has the expected number of values after its name
+fn expect_values(words: &[&str], counts: &[usize]) -> Result<(), String> {
+    if !counts.contains(&(words.len() - 1)) {
+        return Err(format!("wrong number of values for '{}'", words[0]));
+    }
+    return Ok(());
+}
+
+fn parse_input(words: &[&str]) -> Result<JournalInput, String> {
+    if let Some(name) = words.get(1) {
+        check_name(name)?;
+    }
+    return match words[0] {
+        "add_force" => {
+            expect_values(words, &[4])?;
+            Ok(JournalInput::AddForce { particle: words[1].to_string(), force: parse_vector(&words[2..5])? })
+        }
+        "spawn" => {
+            expect_values(words, &[12, 13])?;
+            let mass: f32 = parse_number(words[12])?;
+            check_mass(words[1], mass)?;
+            Ok(JournalInput::Spawn(SceneParticle {
+                name: words[1].to_string(),
+                position: parse_vector(&words[2..5])?,
+                velocity: parse_vector(&words[5..8])?,
+                acceleration: parse_vector(&words[8..11])?,
+                damping: parse_number(words[11])?,
+                mass,
+                charge: match words.get(13) {
+                    Some(word) => Some(parse_number(word)?),
+                    None => None,
+                },
+            }))
+        }
+        "remove" => {
+            expect_values(words, &[1])?;
+            Ok(JournalInput::Remove { particle: words[1].to_string() })
+        }
+        "set" => {
+            if words.len() < 4 {
+                return Err("expected 'set <particle> <parameter> <value>'".to_string());
+            }
+            let values: &[&str] = &words[2..];
+            let parameter: ParticleParameter = match values[0] {
+                "position" | "velocity" | "acceleration" => {
+                    expect_values(values, &[3])?;
+                    let vector: Vector3 = parse_vector(&values[1..4])?;
+                    match values[0] {
+                        "position" => ParticleParameter::Position(vector),
+                        "velocity" => ParticleParameter::Velocity(vector),
+                        _ => ParticleParameter::Acceleration(vector),
+                    }
+                }
+                "damping" => {
+                    expect_values(values, &[1])?;
+                    ParticleParameter::Damping(parse_number(values[1])?)
+                }
+                "mass" => {
+                    expect_values(values, &[1])?;
+                    let mass: f32 = parse_number(values[1])?;
+                    check_mass(words[1], mass)?;
+                    ParticleParameter::Mass(mass)
+                }
+                "charge" => {
+                    expect_values(values, &[1])?;
+                    if values[1] == "none" {
+                        ParticleParameter::Charge(None)
+                    } else {
+                        ParticleParameter::Charge(Some(parse_number(values[1])?))
+                    }
+                }
+                other => return Err(format!("unknown parameter '{}'", other)),
+            };
+            Ok(JournalInput::SetParameter { particle: words[1].to_string(), parameter })
+        }
+        other => Err(format!("unknown input '{}'", other)),
+    };
+}
+
+/// Runs a scene while journaling every input applied to it.
+pub struct JournalRecorder {
+    instance: SceneInstance,
+    journal: Journal,
+    /// Steps completed so far
+    step: u64,
+    /// A checkpoint is taken every this many steps
+    checkpoint_interval: u64,
+}
+
+impl JournalRecorder {
+    /// Builds the scene and starts recording it, taking a checkpoint. To carry on from a running
+    /// scene, copy its state back first with `Scene::update_particles`.
+    /// Panics if a particle name is not valid, as the journal could not be read back.
+    pub fn new(scene: &Scene, duration: f32, checkpoint_interval: u64) -> JournalRecorder {
+        assert!(duration > 0.0 && checkpoint_interval > 0);
+        assert!(scene.particles.iter().all(|p| is_valid_name(&p.name)), "particle names must be valid");
+        let mut recorder: JournalRecorder = JournalRecorder {
+            instance: scene.build(),
+            journal: Journal::new(scene.clone(), duration),
+            step: 0,
+            checkpoint_interval
+        };
+        recorder.checkpoint();
+        return recorder;
+    }
+
+    /// Applies the input before the next step and journals it. Inputs that fail are not journaled.
+    pub fn apply(&mut self, input: JournalInput) -> Result<(), String> {
+        input.apply(&mut self.instance)?;
+        self.journal.events.push(JournalEvent { step: self.step, input });
+        return Ok(());
+    }
+
+    /// Advances the scene by one step, taking a checkpoint if one is due
+    pub fn step(&mut self) {
+        self.instance.step(self.journal.duration);
+        self.step += 1;
+        if self.step.is_multiple_of(self.checkpoint_interval) {
+            self.checkpoint();
+        }
+    }
+
+    fn checkpoint(&mut self) {
+        self.journal.checkpoints.push(JournalCheckpoint {
+            step: self.step,
+            hash: hash_particles(&self.instance.particles)
+        });
+    }
+
+    /// The running scene. Change it through `apply`, or the change will not be replayed.
+    pub fn get_instance(&self) -> &SceneInstance {
+        return &self.instance;
+    }
+
+    pub fn get_step(&self) -> u64 {
+        return self.step;
+    }
+
+    pub fn get_journal(&self) -> &Journal {
+        return &self.journal;
+    }
+
+    /// Takes a final checkpoint, if one was not just taken, and hands back the instance and the journal
+    pub fn finish(mut self) -> (SceneInstance, Journal) {
+        if self.journal.checkpoints.last().is_none_or(|c| c.step != self.step) {
+            self.checkpoint();
+        }
+        return (self.instance, self.journal);
+    }
+}
+
+/// Rebuilds the run by building the journal's scene, then applying its inputs and checking every
+/// checkpoint. Returns the instance as it was at the journal's last step.
+pub fn replay(journal: &Journal) -> Result<SceneInstance, ReplayError> {
+    let mut instance: SceneInstance = journal.scene.build();
+    let mut events = journal.events.iter().peekable();
+    let mut checkpoints = journal.checkpoints.iter().peekable();
+    let last_step: u64 = journal.get_last_step();
+
+    for step in 0..=last_step {
+        // Checkpoints are taken after the step, before that step's inputs
+        if let Some(checkpoint) = checkpoints.next_if(|c| c.step == step) {
+            let found: u64 = hash_particles(&instance.particles);
+            if found != checkpoint.hash {
+                return Err(ReplayError::Desync { step, expected: checkpoint.hash, found });
+            }
+        }
+        while let Some(event) = events.next_if(|e| e.step == step) {
+            event.input.apply(&mut instance).map_err(|message| ReplayError::InvalidInput { step, message })?;
+        }
+        if step < last_step {
+            instance.step(journal.duration);
+        }
+    }
+    return Ok(instance);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::Scene;
+
+    const SCENE: &str = "\
+[particle anchor]
+position = 0 10 0
+mass = inf
+
+[particle ball]
+position = 1 8 0
+damping = 0.99
+mass = 2
+
+[force gravity]
+particles = ball
+gravity = 0 -9.81 0
+
+[force spring]
+particles = ball
+other = anchor
+spring_constant = 20
+rest_length = 1.5
+";
+
+    fn rock() -> SceneParticle {
+        return SceneParticle {
+            name: "rock".to_string(),
+            position: Vector3::new(0.0, 5.0, 0.0),
+            velocity: Vector3::default(),
+            acceleration: Vector3::new(0.0, -9.81, 0.0),
+            damping: 0.99,
+            mass: 3.0,
+            charge: None
+        };
+    }
+
+    fn record() -> (SceneInstance, Journal) {
+        let mut recorder: JournalRecorder = JournalRecorder::new(&Scene::parse(SCENE).unwrap(), 1.0 / 60.0, 10);
+        for step in 0..60 {
+            match step {
+                5 => recorder.apply(JournalInput::AddForce { particle: "ball".to_string(), force: Vector3::new(0.0, 250.0, 0.0) }).unwrap(),
+                12 => recorder.apply(JournalInput::Spawn(rock())).unwrap(),
+                20 => recorder.apply(JournalInput::SetParameter {
+                    particle: "ball".to_string(),
+                    parameter: ParticleParameter::Damping(0.9)
+                }).unwrap(),
+                40 => recorder.apply(JournalInput::Remove { particle: "rock".to_string() }).unwrap(),
+                _ => {}
+            }
+            recorder.step();
+        }
+        return recorder.finish();
+    }
+
+    #[test]
+    fn replays_of_the_text_form_match_every_checkpoint() {
+        let (instance, journal) = record();
+        assert_eq!(journal.checkpoints.len(), 7);
+
+        // Nothing is shared with the recorded run, as if the text were read by another process
+        let text: String = journal.to_text();
+        drop(journal);
+        let parsed: Journal = Journal::parse(&text).unwrap();
+        assert_eq!(parsed.scene, Scene::parse(SCENE).unwrap());
+        let replayed: SceneInstance = replay(&parsed).unwrap();
+        assert_eq!(hash_particles(&replayed.particles), hash_particles(&instance.particles));
+        assert_eq!(replayed.get_names(), ["anchor", "ball"]);
+        assert_eq!(parsed.to_text(), text);
+    }
+
+    #[test]
+    fn replays_that_drift_are_caught() {
+        let (_, mut journal) = record();
+        if let JournalInput::AddForce { force, .. } = &mut journal.events[0].input {
+            force.y += 1.0;
+        }
+        let Err(error) = replay(&journal) else { panic!("the changed journal replayed cleanly") };
+        assert!(matches!(error, ReplayError::Desync { step: 10, .. }), "{}", error);
+    }
+
+    #[test]
+    fn spawns_need_a_positive_mass() {
+        let mut instance: SceneInstance = Scene::parse(SCENE).unwrap().build();
+        for mass in [0.0, -1.0, f32::NAN] {
+            let input: JournalInput = JournalInput::Spawn(SceneParticle { mass, ..rock() });
+            assert!(input.apply(&mut instance).is_err(), "spawned with mass {}", mass);
+            let set: JournalInput = JournalInput::SetParameter { particle: "ball".to_string(), parameter: ParticleParameter::Mass(mass) };
+            assert!(set.apply(&mut instance).is_err(), "set mass {}", mass);
+        }
+        assert_eq!(instance.get_names(), ["anchor", "ball"]);
+
+        for mass in ["0", "-1", "NaN"] {
+            let text: String = format!("journal 1\nduration 0.01\nscene\nend scene\nat 0 spawn rock 0 5 0 0 0 0 0 0 0 0.99 {}\n", mass);
+            assert_eq!(Journal::parse(&text).unwrap_err().line, 5);
+        }
+    }
+
+    #[test]
+    fn names_that_could_not_be_read_back_are_rejected() {
+        let mut instance: SceneInstance = Scene::parse(SCENE).unwrap().build();
+        for name in ["two words", "tab\there", "line\nbreak", "hash#tag", ""] {
+            let spawn: JournalInput = JournalInput::Spawn(SceneParticle { name: name.to_string(), ..rock() });
+            assert!(spawn.apply(&mut instance).is_err(), "spawned {:?}", name);
+            let remove: JournalInput = JournalInput::Remove { particle: name.to_string() };
+            assert_eq!(remove.apply(&mut instance).unwrap_err(), format!("'{}' is not a valid name", name));
+        }
+        assert_eq!(instance.get_names(), ["anchor", "ball"]);
+
+        let text: &str = "journal 1\nduration 0.01\nscene\nend scene\nat 0 remove ball,rock\n";
+        assert_eq!(Journal::parse(text).unwrap_err(), JournalError { line: 5, message: "'ball,rock' is not a valid name".to_string() });
+    }
+
+    #[test]
+    fn scene_errors_give_the_journal_line() {
+        let text: &str = "journal 1\nduration 0.01\n\nscene\n[particle ball]\nmass = 0\nend scene\n";
+        assert_eq!(Journal::parse(text).unwrap_err().line, 5);
+        assert_eq!(Journal::parse("journal 1\nduration 0.01\nscene\n[particle ball]\nmass = 1\n").unwrap_err().line, 5);
+        assert_eq!(Journal::parse("journal 1\nduration 0.01\ncheckpoint 0 0\n").unwrap_err().line, 3);
+    }
+}
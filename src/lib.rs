@@ -0,0 +1,34 @@
+// The engine follows the explicit `return` style used throughout the book it is based on.
+#![allow(clippy::needless_return)]
+
+pub mod core;
+pub mod particle;
+pub mod particle_force_gen;
+pub mod particle_force_wrappers;
+pub mod particle_force_compose;
+pub mod particle_force_debug;
+pub mod particle_implicit_spring;
+pub mod timestep;
+pub mod precision;
+pub mod fixed;
+pub mod state_hash;
+pub mod particle_snapshot;
+pub mod scene;
+pub mod binary_state;
+pub mod trajectory;
+pub mod journal;
+pub mod conservation;
+pub mod spatial_grid;
+pub mod cloth;
+pub mod xpbd;
+pub mod rope;
+pub mod soft_body;
+pub mod sph;
+pub mod granular;
+pub mod random;
+pub mod emitter;
+pub mod fireworks;
+pub mod projectile;
+pub mod mass_aggregate;
+pub mod particle_electromagnetic;
+pub mod exercise_functions;
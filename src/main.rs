@@ -1,11 +1,241 @@
-use crate::exercise_functions::{exercise2, exercise3};
+// The engine follows the explicit `return` style used throughout the book it is based on.
+#![allow(clippy::needless_return)]
 
-mod core;
-mod particle;
-mod particle_force_gen;
-mod exercise_functions;
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    process::ExitCode,
+};
 
-fn main() {
-    exercise2();
-    exercise3();
+use physics_engine::{
+    binary_state::{BinaryFrame, BinaryOptions},
+    conservation::{ConservationMonitor, DriftReport},
+    core::Vector3,
+    exercise_functions::{exercise2, exercise3},
+    particle::ParticleIntegrator,
+    scene::{Scene, SceneInstance},
+    trajectory::{TrajectoryFormat, TrajectoryRecorder},
+};
+
+const USAGE: &str = "\
+Runs a scene file headless and prints a summary of the run.
+
+usage: physics_engine <scene file> [options]
+       physics_engine --exercises
+
+options:
+  --steps <n>              number of steps to run
+  --duration <seconds>     simulated time to run, used when --steps is not given (default 1)
+  --timestep <seconds>     duration of each step (default 1/60)
+  --integrator <name>      euler or semi-implicit (default euler)
+  --trajectory <file>      write every particle's trajectory to the file
+  --format <name>          trajectory format, csv or jsonl (default csv)
+  --every <n>              sample the trajectory every n steps (default 1)
+  --snapshot <file>        write the final state as a binary frame
+  --save-scene <file>      write the final state as a scene file
+  --exercises              run the book exercises instead of a scene
+  --help                   show this message";
+
+/// What the command line asked for
+struct Options {
+    scene_path: String,
+    steps: Option<u64>,
+    duration: f32,
+    timestep: f32,
+    integrator: ParticleIntegrator,
+    trajectory_path: Option<String>,
+    trajectory_format: TrajectoryFormat,
+    trajectory_interval: u64,
+    snapshot_path: Option<String>,
+    save_scene_path: Option<String>,
+}
+
+fn parse_value<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    let value: String = value.ok_or_else(|| format!("{} needs a value", option))?;
+    return value.parse::<T>().map_err(|_| format!("'{}' is not a valid value for {}", value, option));
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options: Options = Options {
+        scene_path: String::new(),
+        steps: None,
+        duration: 1.0,
+        timestep: 1.0 / 60.0,
+        integrator: ParticleIntegrator::Euler,
+        trajectory_path: None,
+        trajectory_format: TrajectoryFormat::Csv,
+        trajectory_interval: 1,
+        snapshot_path: None,
+        save_scene_path: None
+    };
+    let mut scene_path: Option<String> = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--steps" => options.steps = Some(parse_value(&arg, args.next())?),
+            "--duration" => options.duration = parse_value(&arg, args.next())?,
+            "--timestep" => options.timestep = parse_value(&arg, args.next())?,
+            "--integrator" => {
+                options.integrator = match parse_value::<String>(&arg, args.next())?.as_str() {
+                    "euler" => ParticleIntegrator::Euler,
+                    "semi-implicit" => ParticleIntegrator::SemiImplicitEuler,
+                    other => return Err(format!("unknown integrator '{}', expected euler or semi-implicit", other)),
+                };
+            }
+            "--trajectory" => options.trajectory_path = Some(parse_value(&arg, args.next())?),
+            "--format" => {
+                options.trajectory_format = match parse_value::<String>(&arg, args.next())?.as_str() {
+                    "csv" => TrajectoryFormat::Csv,
+                    "jsonl" => TrajectoryFormat::JsonLines,
+                    other => return Err(format!("unknown format '{}', expected csv or jsonl", other)),
+                };
+            }
+            "--every" => options.trajectory_interval = parse_value(&arg, args.next())?,
+            "--snapshot" => options.snapshot_path = Some(parse_value(&arg, args.next())?),
+            "--save-scene" => options.save_scene_path = Some(parse_value(&arg, args.next())?),
+            other if other.starts_with("--") => return Err(format!("unknown option '{}'", other)),
+            _ => {
+                if scene_path.is_some() {
+                    return Err(format!("unexpected argument '{}'", arg));
+                }
+                scene_path = Some(arg);
+            }
+        }
+    }
+
+    options.scene_path = scene_path.ok_or_else(|| "no scene file given".to_string())?;
+    if !options.timestep.is_finite() || options.timestep <= 0.0 {
+        return Err("--timestep must be positive and finite".to_string());
+    }
+    if !options.duration.is_finite() || options.duration < 0.0 {
+        return Err("--duration must be finite and cannot be negative".to_string());
+    }
+    if options.trajectory_interval == 0 {
+        return Err("--every must be at least 1".to_string());
+    }
+    return Ok(options);
+}
+
+/// The fastest any particle with finite mass is moving
+fn max_speed(instance: &SceneInstance) -> f32 {
+    return instance.particles.iter()
+        .map(|p| p.borrow())
+        .filter(|p| p.has_finite_mass())
+        .map(|p| p.get_velocity().magnitude())
+        .fold(0.0, f32::max);
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let text: String = fs::read_to_string(&options.scene_path)
+        .map_err(|e| format!("cannot read {}: {}", options.scene_path, e))?;
+    let mut scene: Scene = Scene::parse(&text).map_err(|e| format!("{}:{}", options.scene_path, e))?;
+    let mut instance: SceneInstance = scene.build();
+
+    let steps: u64 = options.steps.unwrap_or((options.duration / options.timestep).round() as u64);
+
+    let mut recorder: Option<TrajectoryRecorder<BufWriter<File>>> = match &options.trajectory_path {
+        Some(path) => {
+            let file: File = File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?;
+            let mut recorder = TrajectoryRecorder::new(BufWriter::new(file), options.trajectory_format, options.trajectory_interval);
+            for (name, particle) in instance.get_names().iter().zip(instance.particles.iter()) {
+                recorder.track(name, particle);
+            }
+            Some(recorder)
+        }
+        None => None,
+    };
+    let write_error = |e: std::io::Error| format!("cannot write the trajectory: {}", e);
+
+    let mut monitor: ConservationMonitor = ConservationMonitor::new(Vector3::default());
+    monitor.record(&instance.particles, &instance.registry, options.timestep);
+    let mut fastest: f32 = max_speed(&instance);
+    for _ in 0..steps {
+        instance.registry.update_forces(options.timestep);
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.record(options.timestep).map_err(write_error)?;
+        }
+        for particle in instance.particles.iter() {
+            particle.borrow_mut().integrate_with(options.integrator, options.timestep);
+        }
+        monitor.record(&instance.particles, &instance.registry, options.timestep);
+        fastest = fastest.max(max_speed(&instance));
+    }
+    if let Some(mut recorder) = recorder {
+        // The loop samples before integrating, so the state the run ended in still needs a row
+        recorder.sample().map_err(write_error)?;
+        recorder.finish().map_err(write_error)?;
+    }
+    let samples = monitor.get_samples();
+    let (start, end) = (samples[0], samples[samples.len() - 1]);
+    let drift: DriftReport = monitor.report().expect("the monitor always has the starting sample");
+
+    println!("scene:          {}", options.scene_path);
+    println!("particles:      {}", instance.particles.len());
+    println!("steps:          {} of {} s ({} s simulated)", steps, options.timestep, steps as f64 * options.timestep as f64);
+    println!("integrator:     {:?}", options.integrator);
+    println!("kinetic energy: {} J at the start, {} J at the end", start.kinetic_energy, end.kinetic_energy);
+    println!("potential:      {} J at the start, {} J at the end", start.potential_energy, end.potential_energy);
+    println!("total energy:   drifted {} J ({}%), at most {} J away", drift.energy_drift, drift.relative_energy_drift * 100.0, drift.max_energy_error);
+    println!("momentum:       ({}, {}, {}) kg m/s at the start", start.linear_momentum.x, start.linear_momentum.y, start.linear_momentum.z);
+    println!("                ({}, {}, {}) kg m/s at the end", end.linear_momentum.x, end.linear_momentum.y, end.linear_momentum.z);
+    println!("ang. momentum:  ({}, {}, {}) kg m²/s at the start, about the origin", start.angular_momentum.x, start.angular_momentum.y, start.angular_momentum.z);
+    println!("                ({}, {}, {}) kg m²/s at the end", end.angular_momentum.x, end.angular_momentum.y, end.angular_momentum.z);
+    println!("max speed:      {} m/s", fastest);
+
+    if let Some(path) = &options.snapshot_path {
+        let frame: BinaryFrame = BinaryFrame::from_particles(steps, &instance.particles);
+        fs::write(path, frame.encode(&BinaryOptions::default())).map_err(|e| format!("cannot write {}: {}", path, e))?;
+    }
+    if let Some(path) = &options.save_scene_path {
+        scene.update_particles(&instance.particles);
+        let mut file: File = File::create(path).map_err(|e| format!("cannot create {}: {}", path, e))?;
+        scene.write(&mut file).and_then(|_| file.flush()).map_err(|e| format!("cannot write {}: {}", path, e))?;
+    }
+    return Ok(());
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|a| a == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    if args.iter().any(|a| a == "--exercises") {
+        exercise2();
+        exercise3();
+        return ExitCode::SUCCESS;
+    }
+
+    let options: Options = match parse_args(args.into_iter()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("error: {}\n\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if let Err(message) = run(&options) {
+        eprintln!("error: {}", message);
+        return ExitCode::FAILURE;
+    }
+    return ExitCode::SUCCESS;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        return parse_args(args.iter().map(|arg| arg.to_string()));
+    }
+
+    #[test]
+    fn durations_and_timesteps_must_be_finite() {
+        assert!(parse(&["scene.txt", "--duration", "2"]).is_ok());
+        for bad in ["inf", "NaN", "-1"] {
+            assert!(parse(&["scene.txt", "--duration", bad]).is_err(), "--duration {} was accepted", bad);
+            assert!(parse(&["scene.txt", "--timestep", bad]).is_err(), "--timestep {} was accepted", bad);
+        }
+        assert!(parse(&["scene.txt", "--timestep", "0"]).is_err());
+    }
 }
@@ -0,0 +1,460 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::Particle,
+    xpbd::{XpbdDistanceConstraint, XpbdSolver},
+};
+
+/// How a link holds its two nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateLinkKind {
+    /// Keeps the nodes exactly its length apart, pushing and pulling
+    Rod,
+    /// Keeps the nodes at most its length apart, only pulling
+    Cable,
+}
+
+/// What a link is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AggregateLinkSettings {
+    /// Square metres, the stress is the force along the link over this
+    pub cross_section: f32,
+    /// Pascals of tension or compression past which the link breaks, infinite for never
+    pub breaking_stress: f32,
+    /// Metres per newton of stretch, zero makes the link rigid. See `XpbdConstraint`.
+    pub compliance: f32,
+}
+
+impl Default for AggregateLinkSettings {
+    /// A rigid, unbreakable link a centimetre square
+    fn default() -> Self {
+        return Self {
+            cross_section: 1.0e-4,
+            breaking_stress: f32::INFINITY,
+            compliance: 0.0
+        };
+    }
+}
+
+/// A rod or cable between two nodes of a `MassAggregate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateLink {
+    pub a: usize,
+    pub b: usize,
+    pub kind: AggregateLinkKind,
+    pub length: f32,
+    pub settings: AggregateLinkSettings,
+    /// Newtons pulling the nodes together at the peak of the last step, negative for pushing apart
+    tension: f32,
+    broken: bool,
+}
+
+impl AggregateLink {
+    /// The force along the link at the peak of the last step, positive when pulled
+    pub fn get_tension(&self) -> f32 {
+        return self.tension;
+    }
+
+    /// The tension over the cross section, negative in compression
+    pub fn get_stress(&self) -> f32 {
+        return self.tension / self.settings.cross_section;
+    }
+
+    pub fn is_broken(&self) -> bool {
+        return self.broken;
+    }
+}
+
+/// A node of a structure waiting to be built
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AggregateNode {
+    position: Vector3,
+    /// Infinite for nodes fixed in place
+    mass: f32,
+}
+
+/// Describes a structure of nodes and the links between them, then builds it.
+#[derive(Debug, Clone)]
+pub struct MassAggregateBuilder {
+    nodes: Vec<AggregateNode>,
+    links: Vec<AggregateLink>,
+    damping: f32,
+}
+
+impl Default for MassAggregateBuilder {
+    fn default() -> Self {
+        return MassAggregateBuilder::new();
+    }
+}
+
+impl MassAggregateBuilder {
+    pub fn new() -> MassAggregateBuilder {
+        return MassAggregateBuilder {
+            nodes: Vec::new(),
+            links: Vec::new(),
+            damping: 0.99
+        };
+    }
+
+    /// The damping given to every particle, as `Particle::damping`
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping;
+    }
+
+    /// Adds a node and returns its index
+    pub fn add_node(&mut self, position: Vector3, mass: f32) -> usize {
+        assert!(mass > 0.0 && mass.is_finite(), "a node needs a positive mass");
+        self.nodes.push(AggregateNode { position, mass });
+        return self.nodes.len() - 1;
+    }
+
+    /// Adds a node held where it is, such as where a bridge rests on the bank
+    pub fn add_fixed_node(&mut self, position: Vector3) -> usize {
+        self.nodes.push(AggregateNode { position, mass: f32::INFINITY });
+        return self.nodes.len() - 1;
+    }
+
+    /// Adds to a node's mass, for loading a structure
+    pub fn add_load(&mut self, node: usize, mass: f32) {
+        assert!(mass >= 0.0 && mass.is_finite(), "a load must be finite and not negative");
+        self.nodes[node].mass += mass;
+    }
+
+    pub fn get_node_position(&self, node: usize) -> Vector3 {
+        return self.nodes[node].position;
+    }
+
+    pub fn node_count(&self) -> usize {
+        return self.nodes.len();
+    }
+
+    /// Joins two nodes with a rod as long as they are apart. Returns the link's index.
+    pub fn add_rod(&mut self, a: usize, b: usize, settings: &AggregateLinkSettings) -> usize {
+        let length: f32 = (self.nodes[a].position - &self.nodes[b].position).magnitude();
+        return self.add_link(a, b, AggregateLinkKind::Rod, length, settings);
+    }
+
+    /// Joins two nodes with a cable of the length, which may be slack. Returns the link's index.
+    pub fn add_cable(&mut self, a: usize, b: usize, length: f32, settings: &AggregateLinkSettings) -> usize {
+        return self.add_link(a, b, AggregateLinkKind::Cable, length, settings);
+    }
+
+    fn add_link(&mut self, a: usize, b: usize, kind: AggregateLinkKind, length: f32, settings: &AggregateLinkSettings) -> usize {
+        assert!(a < self.nodes.len() && b < self.nodes.len() && a != b, "a link needs two different nodes");
+        assert!(length > 0.0, "a link needs a length");
+        assert!(settings.cross_section > 0.0 && settings.compliance >= 0.0);
+        self.links.push(AggregateLink {
+            a,
+            b,
+            kind,
+            length,
+            settings: *settings,
+            tension: 0.0,
+            broken: false
+        });
+        return self.links.len() - 1;
+    }
+
+    /// Creates the particles and links, at rest
+    pub fn build(&self) -> MassAggregate {
+        let mut solver: XpbdSolver = XpbdSolver::new(20);
+        solver.set_iterations(10);
+        for node in self.nodes.iter() {
+            let mut particle: Particle = Particle::new(
+                node.position,
+                Vector3::default(),
+                Vector3::default(),
+                self.damping,
+                if node.mass.is_finite() { node.mass } else { 1.0 }
+            );
+            if !node.mass.is_finite() {
+                particle.restore_mass(1.0, 0.0);
+            }
+            solver.add_particle(&Rc::new(RefCell::new(particle)));
+        }
+        // Link i is constraint i of the solver
+        for link in self.links.iter() {
+            let mut constraint: XpbdDistanceConstraint = match link.kind {
+                AggregateLinkKind::Rod => XpbdDistanceConstraint::new(link.a, link.b, link.length, link.settings.compliance),
+                AggregateLinkKind::Cable => XpbdDistanceConstraint::new_cable(link.a, link.b, link.length, link.settings.compliance)
+            };
+            constraint.set_breaking_force(link.settings.breaking_stress * link.settings.cross_section);
+            solver.add_constraint(Box::new(constraint));
+        }
+        return MassAggregate {
+            solver,
+            links: self.links.clone(),
+            gravity: Vector3::new(0.0, -9.81, 0.0)
+        };
+    }
+
+    /// A truss bridge along x from the origin, as in the bridge demo of Millington's Game Physics
+    /// Engine Development but held up by its own frame rather than hung from cables.
+    ///
+    /// Each side is a Warren truss, a deck rail of `sections + 1` nodes with a top rail of
+    /// `sections` nodes above the middles of the sections and rods zig-zagging between them.
+    /// Rods join the two sides across the deck and the top, and cross the deck diagonally.
+    /// The four deck nodes at the ends are fixed. Node `2 * i + side` is deck node `i` of the side,
+    /// the top nodes follow in the same order. The load is shared between the deck nodes nearest
+    /// the middle.
+    pub fn bridge(settings: &BridgeSettings) -> MassAggregateBuilder {
+        assert!(settings.sections >= 2, "a bridge needs at least two sections");
+        assert!(settings.load >= 0.0 && settings.load.is_finite(), "a load must be finite and not negative");
+        let mut builder: MassAggregateBuilder = MassAggregateBuilder::new();
+        let sections: usize = settings.sections;
+        let length: f32 = settings.section_length;
+        let side_z = |side: usize| -> f32 { (side as f32 - 0.5) * settings.width };
+
+        for i in 0..=sections {
+            for side in 0..2 {
+                let position: Vector3 = Vector3::new(i as f32 * length, 0.0, side_z(side));
+                if i == 0 || i == sections {
+                    builder.add_fixed_node(position);
+                } else {
+                    builder.add_node(position, settings.node_mass);
+                }
+            }
+        }
+        let top = |i: usize, side: usize| -> usize { 2 * (sections + 1) + 2 * i + side };
+        for i in 0..sections {
+            for side in 0..2 {
+                builder.add_node(Vector3::new((i as f32 + 0.5) * length, settings.height, side_z(side)), settings.node_mass);
+            }
+        }
+
+        let link: &AggregateLinkSettings = &settings.link;
+        for side in 0..2 {
+            for i in 0..sections {
+                // Deck rail, then the zig-zag up to the top rail and back down
+                builder.add_rod(2 * i + side, 2 * (i + 1) + side, link);
+                builder.add_rod(2 * i + side, top(i, side), link);
+                builder.add_rod(top(i, side), 2 * (i + 1) + side, link);
+                if i + 1 < sections {
+                    builder.add_rod(top(i, side), top(i + 1, side), link);
+                }
+            }
+        }
+        for i in 0..=sections {
+            builder.add_rod(2 * i, 2 * i + 1, link);
+        }
+        for i in 0..sections {
+            builder.add_rod(top(i, 0), top(i, 1), link);
+            builder.add_rod(2 * i, 2 * (i + 1) + 1, link);
+        }
+
+        if settings.load > 0.0 {
+            // The middle deck node of an even bridge, or the two either side of the middle
+            let middle: Vec<usize> = if sections.is_multiple_of(2) { vec![sections / 2] } else { vec![sections / 2, sections / 2 + 1] };
+            let share: f32 = settings.load / (2 * middle.len()) as f32;
+            for i in middle {
+                builder.add_load(2 * i, share);
+                builder.add_load(2 * i + 1, share);
+            }
+        }
+        return builder;
+    }
+}
+
+/// The shape of `MassAggregateBuilder::bridge`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BridgeSettings {
+    pub sections: usize,
+    pub section_length: f32,
+    pub width: f32,
+    /// Height of the top rail above the deck
+    pub height: f32,
+    pub node_mass: f32,
+    /// Extra mass on the middle of the deck
+    pub load: f32,
+    /// What every rod is made of
+    pub link: AggregateLinkSettings,
+}
+
+impl Default for BridgeSettings {
+    fn default() -> Self {
+        return Self {
+            sections: 6,
+            section_length: 2.0,
+            width: 2.0,
+            height: 1.5,
+            node_mass: 1.0,
+            load: 0.0,
+            link: AggregateLinkSettings::default()
+        };
+    }
+}
+
+/// A structure of particles joined by rods and cables, such as a bridge or a frame.
+///
+/// Each link is an `XpbdDistanceConstraint` in the aggregate's own `XpbdSolver`, rigid or compliant,
+/// and the force it carries is read back from its Lagrange multiplier, so every step knows the
+/// stress in every link. Links pushed past their breaking stress break and stay broken, keeping
+/// their index. Step the aggregate rather than integrating its particles, gravity is added to
+/// whatever else acts on them.
+pub struct MassAggregate {
+    solver: XpbdSolver,
+    links: Vec<AggregateLink>,
+    gravity: Vector3,
+}
+
+impl MassAggregate {
+    pub fn get_particles(&self) -> &[Rc<RefCell<Particle>>] {
+        return self.solver.get_particles();
+    }
+
+    pub fn get_particle(&self, node: usize) -> &Rc<RefCell<Particle>> {
+        return &self.solver.get_particles()[node];
+    }
+
+    pub fn get_links(&self) -> &[AggregateLink] {
+        return &self.links;
+    }
+
+    pub fn get_link(&self, index: usize) -> &AggregateLink {
+        return &self.links[index];
+    }
+
+    pub fn broken_count(&self) -> usize {
+        return self.links.iter().filter(|link| link.broken).count();
+    }
+
+    /// The link with the greatest stress, pulled or pushed, in the last step, if any are whole
+    pub fn get_most_stressed(&self) -> Option<usize> {
+        return (0..self.links.len())
+            .filter(|&i| !self.links[i].broken)
+            .max_by(|&i, &j| self.links[i].get_stress().abs().total_cmp(&self.links[j].get_stress().abs()));
+    }
+
+    /// Breaks a link by hand
+    pub fn break_link(&mut self, index: usize) {
+        self.get_constraint_mut(index).break_constraint();
+        self.links[index].broken = true;
+        self.links[index].tension = 0.0;
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector3) {
+        self.gravity = gravity;
+    }
+
+    /// More substeps keep fast motion accurate
+    pub fn set_substeps(&mut self, substeps: usize) {
+        self.solver.set_substeps(substeps);
+    }
+
+    /// As `XpbdSolver::set_iterations`
+    pub fn set_iterations(&mut self, iterations: usize) {
+        self.solver.set_iterations(iterations);
+    }
+
+    /// Advances the structure by the duration, updating the stress in every link.
+    /// Returns the indices of the links that broke. A load that arrives all at once, as gravity
+    /// does on the first step, briefly stresses the links up to about twice as much as it does
+    /// once the structure settles.
+    pub fn step(&mut self, duration: f32) -> Vec<usize> {
+        assert!(duration > 0.0);
+        for particle in self.solver.get_particles() {
+            let mut particle = particle.borrow_mut();
+            if !particle.has_finite_mass() { continue; }
+            let weight: Vector3 = self.gravity * particle.get_mass();
+            particle.add_force(weight);
+        }
+        for index in 0..self.links.len() {
+            self.get_constraint_mut(index).clear_force();
+        }
+        self.solver.step(duration);
+
+        let mut broken: Vec<usize> = Vec::new();
+        for index in 0..self.links.len() {
+            let constraint: &XpbdDistanceConstraint = self.solver.get_constraint_as(index).expect("links are distance constraints");
+            let (tension, is_broken): (f32, bool) = (constraint.get_force(), constraint.is_broken());
+            let link: &mut AggregateLink = &mut self.links[index];
+            if is_broken && !link.broken { broken.push(index); }
+            link.tension = tension;
+            link.broken = is_broken;
+        }
+        return broken;
+    }
+
+    fn get_constraint_mut(&mut self, index: usize) -> &mut XpbdDistanceConstraint {
+        return self.solver.get_constraint_as_mut(index).expect("links are distance constraints");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_bridge(load: f32) -> (MassAggregate, Vec<Vec<usize>>) {
+        let settings: BridgeSettings = BridgeSettings {
+            load,
+            link: AggregateLinkSettings { breaking_stress: 2.5e7, ..AggregateLinkSettings::default() },
+            ..BridgeSettings::default()
+        };
+        let mut bridge: MassAggregate = MassAggregateBuilder::bridge(&settings).build();
+        let broken: Vec<Vec<usize>> = (0..60).map(|_| bridge.step(1.0 / 60.0)).collect();
+        return (bridge, broken);
+    }
+
+    #[test]
+    fn bridges_hold_light_loads() {
+        for load in [0.0, 100.0] {
+            let (bridge, broken) = load_bridge(load);
+            assert_eq!(bridge.broken_count(), 0, "a {} kg load broke {:?}", load, broken);
+            // The top chords over the middle carry the most, squeezed
+            let most: usize = bridge.get_most_stressed().unwrap();
+            assert!(bridge.get_link(most).get_stress() < 0.0);
+        }
+    }
+
+    #[test]
+    fn bridges_break_at_the_middle_top_chords_first() {
+        let (bridge, broken) = load_bridge(300.0);
+        // The chords between top nodes 2 and 3 of each side, the middle of the six sections.
+        // Top nodes follow the 14 deck nodes.
+        let pairs: Vec<(usize, usize)> = broken[0].iter().map(|&index| {
+            let link: &AggregateLink = bridge.get_link(index);
+            assert!(link.is_broken());
+            return (link.a, link.b);
+        }).collect();
+        assert_eq!(pairs, vec![(18, 20), (19, 21)]);
+        // The rest holds a while before the loss of the chords brings it down
+        assert!(broken[1..5].iter().all(|step| step.is_empty()));
+        assert!(bridge.broken_count() > 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "a load must be finite and not negative")]
+    fn negative_loads_are_refused() {
+        let mut builder: MassAggregateBuilder = MassAggregateBuilder::new();
+        let node: usize = builder.add_node(Vector3::default(), 1.0);
+        builder.add_load(node, -2.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "a load must be finite and not negative")]
+    fn bridges_refuse_loads_that_are_not_numbers() {
+        MassAggregateBuilder::bridge(&BridgeSettings { load: f32::NAN, ..BridgeSettings::default() });
+    }
+
+    #[test]
+    fn cables_only_pull() {
+        let mut builder: MassAggregateBuilder = MassAggregateBuilder::new();
+        let anchor: usize = builder.add_fixed_node(Vector3::new(0.0, 0.0, 0.0));
+        let weight: usize = builder.add_node(Vector3::new(0.0, -1.0, 0.0), 2.0);
+        let cable: usize = builder.add_cable(anchor, weight, 1.5, &AggregateLinkSettings::default());
+        let mut aggregate: MassAggregate = builder.build();
+
+        // Slack, the weight falls freely
+        aggregate.step(1.0 / 60.0);
+        assert_eq!(aggregate.get_link(cable).get_tension(), 0.0);
+
+        // Taut, the cable holds the weight at its length with its weight in tension
+        for _ in 0..120 {
+            aggregate.step(1.0 / 60.0);
+        }
+        let hanging: Vector3 = aggregate.get_particle(weight).borrow().get_position();
+        assert!((hanging.y + 1.5).abs() < 1.0e-3, "the weight hangs at {}", hanging.y);
+        assert!((aggregate.get_link(cable).get_tension() - 2.0 * 9.81).abs() < 0.5);
+    }
+}
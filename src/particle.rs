@@ -1,128 +1,204 @@
-use crate::core::Vector3;
+use crate::{core::Vector3, precision::Real, state_hash::StateHasher};
 
-pub struct Particle {
+/// How `Particle::integrate_with` moves a particle through a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ParticleIntegrator {
+    /// Moves the particle with its old velocity then updates the velocity, as `integrate` does
+    #[default]
+    Euler,
+    /// Updates the velocity first and moves the particle with the new one.
+    /// Costs the same but keeps the energy of springs and orbits from creeping up.
+    SemiImplicitEuler,
+}
+
+/// A point mass, the scalar type is `f32` unless another `Real` type is given.
+#[derive(Debug, Clone)]
+pub struct Particle<R: Real = f32> {
     /// Holds the linear postion of the particle.
-    pub position: Vector3,
+    pub position: Vector3<R>,
     /// Holds the linear velocity of the particle.
-    pub velocity: Vector3,
+    pub velocity: Vector3<R>,
     /// Holds the acceleration of the particle. This value
     /// can be used to set the acceleration due to gravity (its primary use),
     /// or any other constant acceleration.
-    pub acceleration: Vector3,
+    pub acceleration: Vector3<R>,
     /// Holds the amount of damping applied to linear motion.
     /// Damping is required to remove energy added through numerical instability
     /// in the integrator.
-    pub damping: f32,
-    mass: f32,
+    pub damping: R,
+    mass: R,
     /// Holds the inverse of the mass of the particle.
     /// It is more useful to hold the inverse mass because integration is simpler,
     /// and because in real-time simulation it is more usefule to have objects with
     /// infinite mass (immovable) than zero mass
     /// (completely unstable in numerical simulation).
-    inverse_mass: f32,
+    inverse_mass: R,
     /// Holds the acumulated force to be applied at the next simulation iteration only.
     /// This value is zerored at each integration step
-    pub force_accum: Vector3
+    pub force_accum: Vector3<R>,
+    /// Holds the electric charge of the particle in coulombs, if it has one.
+    /// Uncharged particles are ignored by the electromagnetic force generators.
+    pub charge: Option<R>,
 }
 
-impl Particle {
+impl<R: Real> Particle<R> {
     pub fn new(
-        position: Vector3,
-        velocity: Vector3,
-        acceleration: Vector3,
-        damping: f32,
-        mass: f32
-    ) -> Particle {
+        position: Vector3<R>,
+        velocity: Vector3<R>,
+        acceleration: Vector3<R>,
+        damping: R,
+        mass: R
+    ) -> Particle<R> {
         return Particle {
             position,
             velocity,
             acceleration,
             damping,
             mass,
-            inverse_mass: 1.0 / mass,
-            force_accum: Vector3::default()
+            inverse_mass: R::one() / mass,
+            force_accum: Vector3::default(),
+            charge: None
         };
     }
     
-    pub fn integrate(&mut self, duration: f32) { // maybe use the Duration struct that will be given from the Instance struct
+    /// Integrates the particle forward in time by the given duration in seconds.
+    /// Use `timestep::FixedTimestep` to drive this from variable `Duration` frame times.
+    pub fn integrate(&mut self, duration: R) {
         // We don't integrate things with infinite mass.
-        if self.inverse_mass <= 0.0 { return; }
+        if self.inverse_mass <= R::zero() { return; }
 
-        assert!(duration > 0.0);
+        assert!(duration > R::zero());
 
         // Update linear position
         self.position.add_scaled_vector(&self.velocity, duration);
         
         // Work out the acceleration from the force.
         // (We'll add to this vector when we come to generate forces.)
-        let mut resulting_acc: Vector3 = self.acceleration;
-        resulting_acc.add_scaled_vector(&self.force_accum, duration);
+        let mut resulting_acc: Vector3<R> = self.acceleration;
+        resulting_acc.add_scaled_vector(&self.force_accum, self.inverse_mass);
         
         // Update the linear velocity from the acceleration
         self.velocity.add_scaled_vector(&resulting_acc, duration);
         
         // Impose drag
-        self.velocity *= self.damping.powf(duration);
+        self.velocity *= self.damping.pow(duration);
         
         // Clear the forces
         self.clear_accumulator();
     }
     
+    /// Integrates the particle with the chosen integrator
+    pub fn integrate_with(&mut self, integrator: ParticleIntegrator, duration: R) {
+        match integrator {
+            ParticleIntegrator::Euler => self.integrate(duration),
+            ParticleIntegrator::SemiImplicitEuler => {
+                if self.inverse_mass <= R::zero() { return; }
+                assert!(duration > R::zero());
+
+                let mut resulting_acc: Vector3<R> = self.acceleration;
+                resulting_acc.add_scaled_vector(&self.force_accum, self.inverse_mass);
+                self.velocity.add_scaled_vector(&resulting_acc, duration);
+                self.velocity *= self.damping.pow(duration);
+
+                self.position.add_scaled_vector(&self.velocity, duration);
+                self.clear_accumulator();
+            }
+        }
+    }
+
     pub fn clear_accumulator(&mut self) {
         self.force_accum = Vector3::default();
     }
     
-    pub fn calculate_kinetic_energy(&self) -> f32 {
-        return 0.5 * self.mass * self.velocity.square_magnitude();
+    /// Immovable particles, with infinite mass, have no kinetic energy
+    pub fn calculate_kinetic_energy(&self) -> R {
+        if self.inverse_mass <= R::zero() { return R::zero(); }
+        return R::from_f32(0.5) * self.mass * self.velocity.square_magnitude();
     }
     
-    pub fn add_force(&mut self, force: Vector3) {
+    pub fn add_force(&mut self, force: Vector3<R>) {
         self.force_accum += &force;
     }
     
-    pub fn set_mass(&mut self, mass: f32) {
+    pub fn set_mass(&mut self, mass: R) {
         self.mass = mass;
     }
     
-    pub fn set_velocity(&mut self, x: f32, y: f32, z: f32) {
+    pub fn set_velocity(&mut self, x: R, y: R, z: R) {
         self.velocity = Vector3::new(x, y, z);
     }
 
-    pub fn set_acceleration(&mut self, x: f32, y: f32, z: f32) {
+    pub fn set_acceleration(&mut self, x: R, y: R, z: R) {
         self.acceleration = Vector3::new(x, y, z);
     }
 
-    pub fn set_position(&mut self, x: f32, y: f32, z: f32) {
+    pub fn set_position(&mut self, x: R, y: R, z: R) {
         self.position = Vector3::new(x, y, z);
     }
 
+    pub fn set_charge(&mut self, charge: R) {
+        self.charge = Some(charge);
+    }
+
+    pub fn clear_charge(&mut self) {
+        self.charge = None;
+    }
+
     pub fn set_inverse_mass(&mut self) {
-        self.inverse_mass = 1.0 / self.mass;
+        self.inverse_mass = R::one() / self.mass;
+    }
+
+    /// Sets the mass and inverse mass exactly as given, for restoring saved state
+    pub fn restore_mass(&mut self, mass: R, inverse_mass: R) {
+        self.mass = mass;
+        self.inverse_mass = inverse_mass;
     }
 
-    pub fn get_mass(&self) -> f32 {
+    pub fn get_mass(&self) -> R {
         return self.mass;
     }
 
-    pub fn get_velocity(&self) -> Vector3 {
+    pub fn get_velocity(&self) -> Vector3<R> {
         return self.velocity;
     }
     
 
-    pub fn get_acceleration(&self) -> Vector3 {
+    pub fn get_acceleration(&self) -> Vector3<R> {
         return self.acceleration;
     }
 
 
-    pub fn get_position(&self) -> Vector3 {
+    pub fn get_position(&self) -> Vector3<R> {
         return self.position;
     }
 
-    pub fn get_inverse_mass(&self) -> f32 {
+    pub fn get_charge(&self) -> Option<R> {
+        return self.charge;
+    }
+
+    pub fn get_inverse_mass(&self) -> R {
         return self.inverse_mass;
     }
     
+    /// Feeds every field of the particle, including the accumulated force, into the hasher
+    pub fn hash_state(&self, hasher: &mut StateHasher) {
+        hasher.write_vector3(&self.position);
+        hasher.write_vector3(&self.velocity);
+        hasher.write_vector3(&self.acceleration);
+        hasher.write_real(self.damping);
+        hasher.write_real(self.mass);
+        hasher.write_real(self.inverse_mass);
+        hasher.write_vector3(&self.force_accum);
+        match self.charge {
+            Some(charge) => {
+                hasher.write_bytes(&[1]);
+                hasher.write_real(charge);
+            }
+            None => hasher.write_bytes(&[0]),
+        }
+    }
+
     pub fn has_finite_mass(&self) -> bool {
-        return self.mass > 0.0 && self.mass < f32::INFINITY;
+        return self.mass > R::zero() && self.mass < R::max_value();
     }
 }
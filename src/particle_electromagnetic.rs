@@ -0,0 +1,284 @@
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::Particle,
+    particle_force_gen::{pair_share, restore_cloned, save_cloned, ParticleForceGenerator},
+};
+
+/// Coulomb's constant, k = 1 / (4 * PI * e0), in N m^2 C^-2
+pub const COULOMB_CONSTANT: f32 = 8.987_551e9;
+/// The magnetic constant divided by 4 * PI, u0 / (4 * PI), in T m A^-1
+pub const MAGNETIC_CONSTANT_OVER_4PI: f32 = 1.0e-7;
+
+/// Applies the electrostatic force between the particle and another charged particle.
+/// Like charges repel and opposite charges attract.
+#[derive(Clone)]
+pub struct ParticleCoulomb {
+    /// The charged particle exerting the force
+    other: Rc<RefCell<Particle>>,
+    /// Holds the constant of proportionality, normally `COULOMB_CONSTANT`
+    coulomb_constant: f32,
+}
+
+impl ParticleCoulomb {
+    pub fn new(other: Rc<RefCell<Particle>>, coulomb_constant: f32) -> ParticleCoulomb {
+        return ParticleCoulomb {
+            other,
+            coulomb_constant
+        };
+    }
+}
+
+impl ParticleForceGenerator for ParticleCoulomb {
+    /// f = k * (q1 * q2 / r^2) * ^r
+    /// where r is the vector from the other particle to this one
+    fn update_force(&mut self, particle: &mut Particle, _duration: f32) {
+        // A particle cannot act on itself, and it is the only way the other
+        // particle can already be borrowed while we are updating.
+        let Ok(other) = self.other.try_borrow() else { return; };
+
+        // Both ends need a charge for there to be any interaction.
+        let (Some(charge), Some(other_charge)) = (particle.get_charge(), other.get_charge()) else { return; };
+
+        // Calculate the vector between the charges
+        let mut force: Vector3 = particle.get_position() - &other.get_position();
+        let distance_squared: f32 = force.square_magnitude();
+        if distance_squared == 0.0 { return; }
+
+        // Calculate the final force and apply it
+        force.normalize();
+        force *= self.coulomb_constant * charge * other_charge / distance_squared;
+        particle.add_force(force);
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle>>> {
+        return Some(Rc::clone(&self.other));
+    }
+
+    /// U = k * q1 * q2 / r, halved when the other particle can move
+    fn potential_energy(&self, particle: &Particle) -> Option<f32> {
+        let other = self.other.try_borrow().ok()?;
+        let (Some(charge), Some(other_charge)) = (particle.get_charge(), other.get_charge()) else { return Some(0.0); };
+        let distance: f32 = (particle.get_position() - &other.get_position()).magnitude();
+        if distance == 0.0 { return None; }
+        return Some(pair_share(&other) * self.coulomb_constant * charge * other_charge / distance);
+    }
+}
+
+/// Applies the force of a uniform electric field to a charged particle.
+#[derive(Clone)]
+pub struct ParticleElectricField {
+    /// The electric field strength in N C^-1 (or V m^-1)
+    field: Vector3,
+}
+
+impl ParticleElectricField {
+    pub fn new(field: Vector3) -> ParticleElectricField {
+        return ParticleElectricField {
+            field
+        };
+    }
+
+    pub fn set_field(&mut self, field: Vector3) {
+        self.field.update_by_vector3(field);
+    }
+}
+
+impl ParticleForceGenerator for ParticleElectricField {
+    /// f = q * E
+    fn update_force(&mut self, particle: &mut Particle, _duration: f32) {
+        let Some(charge) = particle.get_charge() else { return; };
+        particle.add_force(self.field * charge);
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+
+    /// U = -q E . p, zero at the origin
+    fn potential_energy(&self, particle: &Particle) -> Option<f32> {
+        let Some(charge) = particle.get_charge() else { return Some(0.0); };
+        return Some(-charge * (self.field * &particle.get_position()));
+    }
+}
+
+/// Applies the Lorentz force of a uniform magnetic field to a moving charged particle.
+/// The force is always perpendicular to the velocity so a charge moving across the
+/// field will circle at the cyclotron radius, r = m|v| / (|q||B|), with a period of T = 2PI * m / (|q||B|).
+#[derive(Clone)]
+pub struct ParticleMagneticField {
+    /// The magnetic flux density in teslas
+    field: Vector3,
+}
+
+impl ParticleMagneticField {
+    pub fn new(field: Vector3) -> ParticleMagneticField {
+        return ParticleMagneticField {
+            field
+        };
+    }
+
+    pub fn set_field(&mut self, field: Vector3) {
+        self.field.update_by_vector3(field);
+    }
+}
+
+impl ParticleForceGenerator for ParticleMagneticField {
+    /// f = q * (v X B)
+    fn update_force(&mut self, particle: &mut Particle, _duration: f32) {
+        let Some(charge) = particle.get_charge() else { return; };
+        let force: Vector3 = particle.get_velocity().vector_product(&self.field);
+        particle.add_force(force * charge);
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+}
+
+/// Applies the Lorentz force from the field of a fixed magnetic dipole, such as a small bar magnet,
+/// to a moving charged particle.
+#[derive(Clone)]
+pub struct ParticleMagneticDipole {
+    /// The location of the dipole
+    position: Vector3,
+    /// The magnetic moment of the dipole in A m^2, pointing from its south to north pole
+    moment: Vector3,
+    /// Holds the field constant, normally `MAGNETIC_CONSTANT_OVER_4PI`
+    magnetic_constant: f32,
+}
+
+impl ParticleMagneticDipole {
+    pub fn new(
+        position: Vector3,
+        moment: Vector3,
+        magnetic_constant: f32
+    ) -> ParticleMagneticDipole {
+        return ParticleMagneticDipole {
+            position,
+            moment,
+            magnetic_constant
+        };
+    }
+
+    /// Moves the dipole, the moment keeps its orientation
+    pub fn set_position(&mut self, position: Vector3) {
+        self.position.update_by_vector3(position);
+    }
+
+    /// Calculates the magnetic flux density of the dipole at the given point:
+    /// B = u0 / (4PI) * (3^r(m . ^r) - m) / |r|^3
+    /// Returns a zero field at the dipole itself.
+    pub fn field_at(&self, point: &Vector3) -> Vector3 {
+        let mut direction: Vector3 = *point - &self.position;
+        let distance: f32 = direction.magnitude();
+        if distance == 0.0 { return Vector3::default(); }
+        direction.normalize();
+
+        let field: Vector3 = direction * (3.0 * (self.moment * &direction)) - &self.moment;
+        return field * (self.magnetic_constant / (distance * distance * distance));
+    }
+}
+
+impl ParticleForceGenerator for ParticleMagneticDipole {
+    /// f = q * (v X B(r))
+    fn update_force(&mut self, particle: &mut Particle, _duration: f32) {
+        let Some(charge) = particle.get_charge() else { return; };
+        let field: Vector3 = self.field_at(&particle.get_position());
+        let force: Vector3 = particle.get_velocity().vector_product(&field);
+        particle.add_force(force * charge);
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::particle::ParticleIntegrator;
+
+    /// Circles a charge across a uniform field for one and a half expected periods, returning
+    /// the widest distance from its start and the time its y velocity first turned back to the
+    /// way it set off, interpolated between steps
+    fn cyclotron_orbit(mass: f32, charge: f32, speed: f32, field: f32) -> (f32, f32) {
+        let start: Vector3 = Vector3::default();
+        let mut particle: Particle = Particle::new(start, Vector3::new(speed, 0.0, 0.0), Vector3::default(), 1.0, mass);
+        particle.set_charge(charge);
+        let mut generator: ParticleMagneticField = ParticleMagneticField::new(Vector3::new(0.0, 0.0, field));
+        let period: f32 = 2.0 * PI * mass / (charge.abs() * field);
+        let duration: f32 = 1.0e-4;
+
+        let mut widest: f32 = 0.0;
+        let mut returned: Option<f32> = None;
+        let mut time: f32 = 0.0;
+        let mut heading: f32 = 0.0;
+        while time < 1.5 * period {
+            let before: f32 = particle.get_velocity().y;
+            generator.update_force(&mut particle, duration);
+            particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, duration);
+            time += duration;
+            let after: f32 = particle.get_velocity().y;
+            if heading == 0.0 { heading = after.signum(); }
+            if returned.is_none() && before * heading < 0.0 && after * heading >= 0.0 {
+                returned = Some(time - duration * after / (after - before));
+            }
+            widest = widest.max((particle.get_position() - &start).magnitude());
+        }
+        return (widest / 2.0, returned.expect("the charge never came round"));
+    }
+
+    #[test]
+    fn charges_circle_at_the_cyclotron_radius() {
+        let (mass, charge, speed, field) = (2.0, 0.5, 3.0, 4.0);
+        let (radius, _) = cyclotron_orbit(mass, charge, speed, field);
+        let expected: f32 = mass * speed / (charge * field);
+        assert!((radius - expected).abs() < 1.0e-2 * expected, "radius {} expected {}", radius, expected);
+    }
+
+    #[test]
+    fn charges_circle_with_the_cyclotron_period() {
+        let (mass, charge, speed, field) = (2.0, -0.5, 3.0, 4.0);
+        let (_, period) = cyclotron_orbit(mass, charge, speed, field);
+        let expected: f32 = 2.0 * PI * mass / (charge.abs() * field);
+        assert!((period - expected).abs() < 1.0e-2 * expected, "period {} expected {}", period, expected);
+    }
+
+    #[test]
+    fn like_charges_repel() {
+        let other: Rc<RefCell<Particle>> =
+            Rc::new(RefCell::new(Particle::new(Vector3::default(), Vector3::default(), Vector3::default(), 1.0, 1.0)));
+        other.borrow_mut().set_charge(1.0e-6);
+        let mut particle: Particle = Particle::new(Vector3::new(2.0, 0.0, 0.0), Vector3::default(), Vector3::default(), 1.0, 1.0);
+        particle.set_charge(1.0e-6);
+
+        ParticleCoulomb::new(other, COULOMB_CONSTANT).update_force(&mut particle, 0.01);
+        let expected: f32 = COULOMB_CONSTANT * 1.0e-12 / 4.0;
+        assert!((particle.force_accum.x - expected).abs() < 1.0e-6 * expected);
+        assert_eq!(particle.force_accum.y, 0.0);
+    }
+}
@@ -0,0 +1,237 @@
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::Particle,
+    particle_force_gen::{restore_wrapped, save_wrapped, ParticleForceGenerator},
+    particle_force_wrappers::apply_scaled,
+    precision::Real,
+};
+
+/// How a composite combines the forces of its generators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticleForceComposition {
+    /// Every generator sees the force accumulator as it was before the composite ran,
+    /// and the composite applies the sum of their forces.
+    Sum,
+    /// The generators run one after another, so each one sees the forces added by those before it.
+    Chain,
+}
+
+/// Groups several force generators so they can be registered, wrapped and scaled as one.
+/// Generators that expire stop being run, but are kept so a restored snapshot can bring them back.
+pub struct ParticleForceComposite<R: Real = f32> {
+    force_gens: Vec<Box<dyn ParticleForceGenerator<R>>>,
+    composition: ParticleForceComposition,
+}
+
+impl<R: Real> ParticleForceComposite<R> {
+    pub fn new(composition: ParticleForceComposition) -> ParticleForceComposite<R> {
+        return ParticleForceComposite {
+            force_gens: Vec::new(),
+            composition
+        };
+    }
+
+    /// Adds a generator to the end of the composite
+    pub fn add(&mut self, force_gen: Box<dyn ParticleForceGenerator<R>>) {
+        self.force_gens.push(force_gen);
+    }
+
+    /// Adds a generator and hands the composite back, so a composite can be built in one expression
+    pub fn with(mut self, force_gen: Box<dyn ParticleForceGenerator<R>>) -> ParticleForceComposite<R> {
+        self.add(force_gen);
+        return self;
+    }
+
+    pub fn len(&self) -> usize {
+        return self.force_gens.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.force_gens.is_empty();
+    }
+}
+
+impl<R: Real> ParticleForceGenerator<R> for ParticleForceComposite<R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        match self.composition {
+            ParticleForceComposition::Chain => {
+                for force_gen in self.force_gens.iter_mut().filter(|force_gen| !force_gen.is_expired()) {
+                    force_gen.update_force(particle, duration);
+                }
+            }
+            ParticleForceComposition::Sum => {
+                let before: Vector3<R> = particle.force_accum;
+                let mut total: Vector3<R> = Vector3::default();
+                for force_gen in self.force_gens.iter_mut().filter(|force_gen| !force_gen.is_expired()) {
+                    particle.force_accum = before;
+                    force_gen.update_force(particle, duration);
+                    total += &(particle.force_accum - &before);
+                }
+                particle.force_accum = before + &total;
+            }
+        }
+    }
+
+    /// Moves the clock on for every generator that has not expired
+    fn advance_time(&mut self, duration: R) {
+        for force_gen in self.force_gens.iter_mut().filter(|force_gen| !force_gen.is_expired()) {
+            force_gen.advance_time(duration);
+        }
+    }
+
+    /// A composite expires once all of its generators have, an empty composite never does
+    fn is_expired(&self) -> bool {
+        return !self.force_gens.is_empty() && self.force_gens.iter().all(|force_gen| force_gen.is_expired());
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        let states: Vec<Option<Box<dyn Any>>> = self.force_gens.iter().map(|force_gen| force_gen.save_state()).collect();
+        return Some(Box::new(states));
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        let Some(states) = state.downcast_ref::<Vec<Option<Box<dyn Any>>>>() else { return; };
+        for (force_gen, state) in self.force_gens.iter_mut().zip(states.iter()) {
+            if let Some(state) = state {
+                force_gen.restore_state(state.as_ref());
+            }
+        }
+    }
+
+    /// The sum of what the running generators report, `None` if none of them report anything
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        return self.force_gens.iter()
+            .filter(|force_gen| !force_gen.is_expired())
+            .filter_map(|force_gen| force_gen.potential_energy(particle))
+            .reduce(|total, energy| total + energy);
+    }
+}
+
+/// Multiplies the force produced by the wrapped generator by a constant factor.
+pub struct ParticleForceScaled<G: ParticleForceGenerator<R>, R: Real = f32> {
+    force_gen: G,
+    scale: R,
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceScaled<G, R> {
+    pub fn new(force_gen: G, scale: R) -> ParticleForceScaled<G, R> {
+        return ParticleForceScaled {
+            force_gen,
+            scale
+        };
+    }
+
+    pub fn set_scale(&mut self, scale: R) {
+        self.scale = scale;
+    }
+
+    pub fn get_scale(&self) -> R {
+        return self.scale;
+    }
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceGenerator<R> for ParticleForceScaled<G, R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        apply_scaled(&mut self.force_gen, particle, duration, self.scale);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        self.force_gen.advance_time(duration);
+    }
+
+    fn is_expired(&self) -> bool {
+        return self.force_gen.is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return self.force_gen.name();
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.force_gen.get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_wrapped(self.scale, &self.force_gen);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(scale) = restore_wrapped(&mut self.force_gen, state) {
+            self.scale = scale;
+        }
+    }
+
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        return self.force_gen.potential_energy(particle).map(|energy| energy * self.scale);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{particle_force_gen::ParticleGravity, particle_force_wrappers::ParticleForceDuration};
+
+    fn particle() -> Particle {
+        let mut particle: Particle = Particle::new(Vector3::new(0.0, 2.0, 0.0), Vector3::default(), Vector3::default(), 1.0, 3.0);
+        particle.add_force(Vector3::new(1.0, 0.0, 0.0));
+        return particle;
+    }
+
+    /// Adds a force, then doubles whatever is in the accumulator
+    fn children(composition: ParticleForceComposition) -> ParticleForceComposite {
+        return ParticleForceComposite::new(composition)
+            .with(Box::new(|p: &mut Particle, _: f32| p.add_force(Vector3::new(0.0, 2.0, 0.0))))
+            .with(Box::new(|p: &mut Particle, _: f32| p.add_force(p.force_accum)));
+    }
+
+    #[test]
+    fn sums_add_what_each_child_would_add_alone() {
+        let mut particle: Particle = particle();
+        children(ParticleForceComposition::Sum).update_force(&mut particle, 0.01);
+        // The doubling only sees the force that was there before the composite
+        assert_eq!(particle.force_accum, Vector3::new(2.0, 2.0, 0.0));
+    }
+
+    #[test]
+    fn chains_run_their_children_in_order() {
+        let mut particle: Particle = particle();
+        children(ParticleForceComposition::Chain).update_force(&mut particle, 0.01);
+        assert_eq!(particle.force_accum, Vector3::new(2.0, 4.0, 0.0));
+    }
+
+    #[test]
+    fn composites_expire_with_their_last_child() {
+        let gravity: ParticleGravity = ParticleGravity::new(&Vector3::new(0.0, -10.0, 0.0));
+        let mut composite: ParticleForceComposite = ParticleForceComposite::new(ParticleForceComposition::Sum)
+            .with(Box::new(ParticleForceDuration::new(gravity.clone(), 1.0)))
+            .with(Box::new(ParticleForceDuration::new(gravity, 2.0)));
+        let empty: ParticleForceComposite = ParticleForceComposite::new(ParticleForceComposition::Sum);
+        assert!(!empty.is_expired());
+
+        composite.advance_time(1.5);
+        assert!(!composite.is_expired());
+        let mut particle: Particle = particle();
+        composite.update_force(&mut particle, 0.01);
+        assert_eq!(particle.force_accum, Vector3::new(1.0, -30.0, 0.0));
+
+        composite.advance_time(1.0);
+        assert!(composite.is_expired());
+    }
+
+    #[test]
+    fn scaling_scales_the_force_and_the_energy() {
+        let gravity: ParticleGravity = ParticleGravity::new(&Vector3::new(0.0, -10.0, 0.0));
+        let mut scaled: ParticleForceScaled<ParticleGravity> = ParticleForceScaled::new(gravity.clone(), 0.5);
+        let mut particle: Particle = particle();
+        scaled.update_force(&mut particle, 0.01);
+        assert_eq!(particle.force_accum, Vector3::new(1.0, -15.0, 0.0));
+        assert_eq!(gravity.potential_energy(&particle), Some(60.0));
+        assert_eq!(scaled.potential_energy(&particle), Some(30.0));
+
+        scaled.set_scale(2.0);
+        assert_eq!(scaled.potential_energy(&particle), Some(120.0));
+    }
+}
+
@@ -0,0 +1,327 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, Write},
+    rc::{Rc, Weak},
+};
+
+use crate::{core::Vector3, particle::Particle, particle_force_gen::ParticleForceGenerator, precision::Real};
+
+/// Why a force contribution was flagged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParticleForceWarningKind {
+    /// The force has a NaN or infinite component
+    NonFinite,
+    /// The magnitude of the force is above the debugger's threshold
+    AboveThreshold,
+}
+
+/// The force one generator added to one particle during one step.
+#[derive(Debug, Clone)]
+pub struct ParticleForceContribution {
+    /// Index of the step, counted from when debugging was enabled
+    pub step: u64,
+    /// Identifies the particle, ids are handed out in the order the debugger first sees each particle
+    pub particle_id: usize,
+    /// The name reported by the force generator
+    pub generator: String,
+    /// The force the generator added to the accumulator
+    pub force: Vector3,
+}
+
+/// A contribution that looked wrong, kept alongside the full record.
+#[derive(Debug, Clone)]
+pub struct ParticleForceWarning {
+    pub kind: ParticleForceWarningKind,
+    pub contribution: ParticleForceContribution,
+}
+
+/// Records which generator added which force to which particle.
+/// Enable it on a `ParticleForceRegistry` with `enable_debug`. Recording costs a copy
+/// of the force accumulator and a name per registration, so leave it off in release builds.
+/// Particles the debugger has seen keep their memory until it is dropped, so ids are never reused.
+pub struct ParticleForceDebugger {
+    /// Forces with a larger magnitude than this are flagged
+    threshold: f32,
+    step: u64,
+    /// Ids by particle address. The weak reference keeps the allocation alive, so an address
+    /// cannot be reused by another particle while it is in the map.
+    particle_ids: HashMap<*const (), (usize, Weak<dyn Any>)>,
+    records: Vec<ParticleForceContribution>,
+    warnings: Vec<ParticleForceWarning>,
+}
+
+impl ParticleForceDebugger {
+    pub fn new(threshold: f32) -> ParticleForceDebugger {
+        return ParticleForceDebugger {
+            threshold,
+            step: 0,
+            particle_ids: HashMap::new(),
+            records: Vec::new(),
+            warnings: Vec::new()
+        };
+    }
+
+    /// Records the force a generator added to a particle during the current step.
+    /// Forces are stored as `f32` whatever scalar type the simulation uses.
+    pub fn record<R: Real>(&mut self, particle: &Rc<RefCell<Particle<R>>>, generator: &str, force: Vector3<R>) {
+        let particle_id: usize = self.get_particle_id(particle);
+        let force: Vector3 = Vector3::new(force.x.to_f32(), force.y.to_f32(), force.z.to_f32());
+        let contribution: ParticleForceContribution = ParticleForceContribution {
+            step: self.step,
+            particle_id,
+            generator: generator.to_string(),
+            force
+        };
+
+        let is_finite: bool = force.x.is_finite() && force.y.is_finite() && force.z.is_finite();
+        if !is_finite {
+            self.warnings.push(ParticleForceWarning {
+                kind: ParticleForceWarningKind::NonFinite,
+                contribution: contribution.clone()
+            });
+        } else if force.magnitude() > self.threshold {
+            self.warnings.push(ParticleForceWarning {
+                kind: ParticleForceWarningKind::AboveThreshold,
+                contribution: contribution.clone()
+            });
+        }
+        self.records.push(contribution);
+    }
+
+    /// Marks the end of a step, later records belong to the next one
+    pub fn end_step(&mut self) {
+        self.step += 1;
+    }
+
+    /// Returns the id used for the particle in the records, handing out a new one if needed
+    pub fn get_particle_id<R: Real>(&mut self, particle: &Rc<RefCell<Particle<R>>>) -> usize {
+        let next_id: usize = self.particle_ids.len();
+        let (id, _) = self.particle_ids.entry(Rc::as_ptr(particle) as *const ()).or_insert_with(|| {
+            let shared: Rc<dyn Any> = Rc::clone(particle) as Rc<dyn Any>;
+            return (next_id, Rc::downgrade(&shared));
+        });
+        return *id;
+    }
+
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.threshold = threshold;
+    }
+
+    /// Returns the number of completed steps
+    pub fn get_step(&self) -> u64 {
+        return self.step;
+    }
+
+    pub fn get_records(&self) -> &[ParticleForceContribution] {
+        return &self.records;
+    }
+
+    pub fn get_warnings(&self) -> &[ParticleForceWarning] {
+        return &self.warnings;
+    }
+
+    /// Returns every contribution recorded during the given step
+    pub fn records_for_step(&self, step: u64) -> impl Iterator<Item = &ParticleForceContribution> {
+        return self.records.iter().filter(move |r| r.step == step);
+    }
+
+    /// Returns every contribution recorded for the given particle, oldest first
+    pub fn records_for_particle<R: Real>(
+        &self,
+        particle: &Rc<RefCell<Particle<R>>>
+    ) -> impl Iterator<Item = &ParticleForceContribution> {
+        let particle_id: Option<usize> = self.particle_ids.get(&(Rc::as_ptr(particle) as *const ())).map(|(id, _)| *id);
+        return self.records.iter().filter(move |r| Some(r.particle_id) == particle_id);
+    }
+
+    /// Sums what each generator contributed to the particle during the step
+    pub fn total_for_particle<R: Real>(&self, particle: &Rc<RefCell<Particle<R>>>, step: u64) -> Vector3 {
+        let mut total: Vector3 = Vector3::default();
+        for r in self.records_for_particle(particle).filter(|r| r.step == step) {
+            total += &r.force;
+        }
+        return total;
+    }
+
+    /// Forgets all records and warnings, the step count and particle ids are kept
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.warnings.clear();
+    }
+
+    /// Writes the records as CSV with a header row:
+    /// `step,particle,generator,fx,fy,fz,magnitude,warning`
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "step,particle,generator,fx,fy,fz,magnitude,warning")?;
+        for r in self.records.iter() {
+            let warning: &str = if !(r.force.x.is_finite() && r.force.y.is_finite() && r.force.z.is_finite()) {
+                "non_finite"
+            } else if r.force.magnitude() > self.threshold {
+                "above_threshold"
+            } else {
+                ""
+            };
+            writeln!(
+                writer,
+                "{},{},\"{}\",{},{},{},{},{}",
+                r.step,
+                r.particle_id,
+                r.generator.replace('"', "\"\""),
+                r.force.x,
+                r.force.y,
+                r.force.z,
+                r.force.magnitude(),
+                warning
+            )?;
+        }
+        return Ok(());
+    }
+}
+
+/// Gives a force generator a readable name in the debugger, mostly useful for closures.
+pub struct ParticleForceNamed<G> {
+    force_gen: G,
+    name: String,
+}
+
+impl<G> ParticleForceNamed<G> {
+    pub fn new(force_gen: G, name: &str) -> ParticleForceNamed<G> {
+        return ParticleForceNamed {
+            force_gen,
+            name: name.to_string()
+        };
+    }
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceGenerator<R> for ParticleForceNamed<G> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        self.force_gen.update_force(particle, duration);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        self.force_gen.advance_time(duration);
+    }
+
+    fn is_expired(&self) -> bool {
+        return self.force_gen.is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.force_gen.get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return self.force_gen.save_state();
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        self.force_gen.restore_state(state);
+    }
+
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        return self.force_gen.potential_energy(particle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle_force_gen::{ParticleForceRegistry, ParticleGravity};
+
+    fn shared_particle(mass: f32) -> Rc<RefCell<Particle>> {
+        return Rc::new(RefCell::new(Particle::new(Vector3::default(), Vector3::default(), Vector3::default(), 1.0, mass)));
+    }
+
+    fn named<G: ParticleForceGenerator + 'static>(force_gen: G, name: &str) -> Rc<RefCell<dyn ParticleForceGenerator>> {
+        return Rc::new(RefCell::new(ParticleForceNamed::new(force_gen, name)));
+    }
+
+    /// Two particles under gravity, the second also pushed by a force set by the test
+    fn pushed_pair(push: Vector3) -> (ParticleForceRegistry, Rc<RefCell<Particle>>, Rc<RefCell<Particle>>) {
+        let (light, heavy) = (shared_particle(1.0), shared_particle(3.0));
+        let gravity = named(ParticleGravity::new(&Vector3::new(0.0, -10.0, 0.0)), "gravity");
+        let push = named(move |p: &mut Particle, _: f32| p.add_force(push), "push \"hard\"");
+        let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+        registry.add(&light, &gravity);
+        registry.add(&heavy, &gravity);
+        registry.add(&heavy, &push);
+        registry.enable_debug(50.0);
+        return (registry, light, heavy);
+    }
+
+    #[test]
+    fn contributions_are_recorded_per_step_and_particle() {
+        let (mut registry, light, heavy) = pushed_pair(Vector3::new(1.0, 0.0, 0.0));
+        registry.update_forces(0.01);
+        registry.update_forces(0.01);
+        let debugger: &ParticleForceDebugger = registry.get_debugger().unwrap();
+
+        assert_eq!(debugger.get_step(), 2);
+        assert_eq!(debugger.get_records().len(), 6);
+        assert!(debugger.get_warnings().is_empty());
+        assert_eq!(debugger.records_for_step(1).count(), 3);
+        assert!(debugger.records_for_step(1).all(|r| r.step == 1));
+
+        let generators: Vec<&str> = debugger.records_for_particle(&heavy).map(|r| r.generator.as_str()).collect();
+        assert_eq!(generators, vec!["gravity", "push \"hard\"", "gravity", "push \"hard\""]);
+        assert_eq!(debugger.records_for_particle(&light).count(), 2);
+        assert_eq!(debugger.total_for_particle(&heavy, 0), Vector3::new(1.0, -30.0, 0.0));
+        assert_eq!(debugger.records_for_particle(&shared_particle(1.0)).count(), 0);
+    }
+
+    #[test]
+    fn large_and_non_finite_forces_are_flagged() {
+        let (mut registry, _, _) = pushed_pair(Vector3::new(0.0, 60.0, 0.0));
+        registry.update_forces(0.01);
+        let debugger: &mut ParticleForceDebugger = registry.get_debugger_mut().unwrap();
+        let warnings: Vec<(ParticleForceWarningKind, &str)> = debugger.get_warnings().iter()
+            .map(|w| (w.kind, w.contribution.generator.as_str()))
+            .collect();
+        assert_eq!(warnings, vec![(ParticleForceWarningKind::AboveThreshold, "push \"hard\"")]);
+
+        debugger.set_threshold(100.0);
+        debugger.clear();
+        registry.update_forces(0.01);
+        assert!(registry.get_debugger().unwrap().get_warnings().is_empty());
+
+        let (mut registry, _, _) = pushed_pair(Vector3::new(f32::NAN, 0.0, 0.0));
+        registry.update_forces(0.01);
+        let warnings: Vec<ParticleForceWarningKind> = registry.get_debugger().unwrap().get_warnings().iter().map(|w| w.kind).collect();
+        assert_eq!(warnings, vec![ParticleForceWarningKind::NonFinite]);
+    }
+
+    #[test]
+    fn records_are_written_as_csv() {
+        let (mut registry, _, _) = pushed_pair(Vector3::new(0.0, 0.0, 80.0));
+        registry.update_forces(0.01);
+        let mut csv: Vec<u8> = Vec::new();
+        registry.get_debugger().unwrap().write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "\
+step,particle,generator,fx,fy,fz,magnitude,warning
+0,0,\"gravity\",0,-10,0,10,
+0,1,\"gravity\",0,-30,0,30,
+0,1,\"push \"\"hard\"\"\",0,0,80,80,above_threshold
+");
+    }
+
+    #[test]
+    fn particle_ids_are_not_reused() {
+        let mut debugger: ParticleForceDebugger = ParticleForceDebugger::new(1.0);
+        let first: Rc<RefCell<Particle>> = shared_particle(1.0);
+        assert_eq!(debugger.get_particle_id(&first), 0);
+        assert_eq!(debugger.get_particle_id(&first), 0);
+        drop(first);
+
+        // A new particle cannot take the address the first one had while the debugger remembers it
+        for expected in 1..100 {
+            assert_eq!(debugger.get_particle_id(&shared_particle(1.0)), expected);
+        }
+    }
+}
+
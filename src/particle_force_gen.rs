@@ -1,95 +1,352 @@
-use crate::{core::Vector3, particle::Particle};
+use std::{any::Any, cell::RefCell, collections::HashSet, rc::Rc};
 
-pub struct ParticleForceRegistration {
-    particle: Particle,
-}
+use crate::{
+    core::Vector3,
+    particle::Particle,
+    particle_force_debug::ParticleForceDebugger,
+    particle_snapshot::ParticleSnapshot,
+    precision::Real,
+};
 
-impl ParticleForceGenerator for ParticleForceRegistration {
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
-        
-    }
+/// Keeps track of one force generator and the particle it applies to.
+#[derive(Clone)]
+pub struct ParticleForceRegistration<R: Real = f32> {
+    particle: Rc<RefCell<Particle<R>>>,
+    force_gen: Rc<RefCell<dyn ParticleForceGenerator<R>>>,
 }
 
-pub struct ParticleForceRegistry {
-    registry: Vec<ParticleForceRegistration>,
+/// Holds all the force generators and the particles they apply to.
+#[derive(Default)]
+pub struct ParticleForceRegistry<R: Real = f32> {
+    registry: Vec<ParticleForceRegistration<R>>,
+    /// Records every generator's contribution when debugging is enabled
+    debugger: Option<ParticleForceDebugger>,
 }
 
-impl ParticleForceRegistry {
+impl<R: Real> ParticleForceRegistry<R> {
+    pub fn new() -> ParticleForceRegistry<R> {
+        return ParticleForceRegistry {
+            registry: Vec::new(),
+            debugger: None
+        };
+    }
     /// Registers the given force generator to apply to the given particle
-    fn add(&self, particle: &Particle, force_gen: Box<dyn ParticleForceGenerator>) {}
+    pub fn add(
+        &mut self,
+        particle: &Rc<RefCell<Particle<R>>>,
+        force_gen: &Rc<RefCell<dyn ParticleForceGenerator<R>>>
+    ) {
+        self.registry.push(ParticleForceRegistration {
+            particle: Rc::clone(particle),
+            force_gen: Rc::clone(force_gen)
+        });
+    }
     /// Removes the given registered pair from the registry.
     /// If the pair is not registered, this method will have no effect.
-    fn remove(&self, particle: &Particle, force_gen: Box<dyn ParticleForceGenerator>) {}
+    pub fn remove(
+        &mut self,
+        particle: &Rc<RefCell<Particle<R>>>,
+        force_gen: &Rc<RefCell<dyn ParticleForceGenerator<R>>>
+    ) {
+        self.registry.retain(|r| {
+            !(Rc::ptr_eq(&r.particle, particle) && Rc::ptr_eq(&r.force_gen, force_gen))
+        });
+    }
+    /// Removes every registration for the given particle.
+    pub fn remove_particle(&mut self, particle: &Rc<RefCell<Particle<R>>>) {
+        self.registry.retain(|r| !Rc::ptr_eq(&r.particle, particle));
+    }
     /// Clears all registrations from the registry. This will not delete the particles or the force
     /// generators themselves, just the records of their connection.
-    fn clear(&mut self) {
+    pub fn clear(&mut self) {
         self.registry.clear();
     }
-    /// Calls all the force generators to update the forces of their corresponding particles
-    fn update_forces(&mut self, duration: f32) {
-        for r in self.registry.iter_mut() {
-            let mut particle: Particle = Particle::new(
-                r.particle.position,
-                r.particle.velocity,
-                r.particle.acceleration,
-                r.particle.damping,
-                r.particle.get_mass(),
-            );
-            r.update_force(&mut particle, duration);
+    /// Returns the number of registered pairs.
+    pub fn len(&self) -> usize {
+        return self.registry.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.registry.is_empty();
+    }
+    /// Calls all the force generators to update the forces of their corresponding particles,
+    /// then moves each generator's clock on by the duration and drops the registrations
+    /// of any generator that has expired.
+    pub fn update_forces(&mut self, duration: R) {
+        for r in self.registry.iter() {
+            let mut particle = r.particle.borrow_mut();
+            let mut force_gen = r.force_gen.borrow_mut();
+            let before: Vector3<R> = particle.force_accum;
+            force_gen.update_force(&mut particle, duration);
+
+            if let Some(debugger) = self.debugger.as_mut() {
+                debugger.record(&r.particle, force_gen.name(), particle.force_accum - &before);
+            }
+        }
+        if let Some(debugger) = self.debugger.as_mut() {
+            debugger.end_step();
+        }
+
+        // A generator shared between several particles must only see the time pass once.
+        // Generators are advanced in registration order so runs are repeatable.
+        let mut advanced: HashSet<*const ()> = HashSet::with_capacity(self.registry.len());
+        for r in self.registry.iter() {
+            if advanced.insert(Rc::as_ptr(&r.force_gen) as *const ()) {
+                r.force_gen.borrow_mut().advance_time(duration);
+            }
+        }
+
+        self.remove_expired();
+    }
+    /// Starts recording the force each generator adds to each particle.
+    /// Forces with a magnitude above the threshold, or that are not finite, are flagged.
+    pub fn enable_debug(&mut self, threshold: f32) {
+        self.debugger = Some(ParticleForceDebugger::new(threshold));
+    }
+    /// Stops recording and throws away what has been recorded.
+    pub fn disable_debug(&mut self) {
+        self.debugger = None;
+    }
+
+    pub fn get_debugger(&self) -> Option<&ParticleForceDebugger> {
+        return self.debugger.as_ref();
+    }
+
+    pub fn get_debugger_mut(&mut self) -> Option<&mut ParticleForceDebugger> {
+        return self.debugger.as_mut();
+    }
+    /// Sums the potential energy every registration reports, see `ParticleForceGenerator::potential_energy`.
+    /// A pair force registered on only one of two movable ends counts its whole energy.
+    pub fn potential_energy(&self) -> R {
+        // Each (particle, other end) pair a registration pulls on
+        let pairs: HashSet<(*const (), *const ())> = self.registry.iter()
+            .filter_map(|r| {
+                let other: Rc<RefCell<Particle<R>>> = r.force_gen.borrow().get_other()?;
+                return Some((Rc::as_ptr(&r.particle) as *const (), Rc::as_ptr(&other) as *const ()));
+            })
+            .collect();
+
+        let mut total: R = R::zero();
+        for r in self.registry.iter() {
+            let force_gen = r.force_gen.borrow();
+            let Some(energy) = force_gen.potential_energy(&r.particle.borrow()) else { continue; };
+            let unpaired: bool = force_gen.get_other().is_some_and(|other| {
+                let movable: bool = other.try_borrow().is_ok_and(|o| o.get_inverse_mass() > R::zero());
+                return movable && !pairs.contains(&(Rc::as_ptr(&other) as *const (), Rc::as_ptr(&r.particle) as *const ()));
+            });
+            // The registration on the other end would have reported the other half
+            total += if unpaired { energy + energy } else { energy };
         }
+        return total;
+    }
+    /// Removes the registrations of every force generator that reports it has expired.
+    pub fn remove_expired(&mut self) {
+        self.registry.retain(|r| !r.force_gen.borrow().is_expired());
+    }
+    /// Saves the registrations, the state of every registered generator and particle, and of the
+    /// extra particles given, which should be any simulated particles with no forces registered.
+    /// The extra particles are saved first, in the order given.
+    pub fn snapshot(&self, particles: &[Rc<RefCell<Particle<R>>>]) -> ParticleSnapshot<R> {
+        let mut snapshot: ParticleSnapshot<R> = ParticleSnapshot::new(self.registry.clone());
+        for particle in particles.iter() {
+            snapshot.add_particle(particle);
+        }
+        for r in self.registry.iter() {
+            snapshot.add_particle(&r.particle);
+            snapshot.add_force_gen(&r.force_gen);
+        }
+        return snapshot;
+    }
+    /// Puts the registrations, particles and generators back as they were when the snapshot was taken.
+    /// The debugger is left alone.
+    pub fn restore(&mut self, snapshot: &ParticleSnapshot<R>) {
+        self.registry = snapshot.get_registrations().to_vec();
+        snapshot.restore_particles();
+        snapshot.restore_force_gens();
     }
 }
 
-pub trait ParticleForceGenerator {
+pub trait ParticleForceGenerator<R: Real = f32> {
     /// Overload this in implementations of the interface to calculate and
     /// update the force applied to the given particle
-    fn update_force(&mut self, particle: &mut Particle, duration: f32);        
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R);
+    /// Called once per step after the forces have been updated, with the same duration.
+    /// Overload this in generators that change over time.
+    /// The registry does this for you, call it yourself when driving a generator directly.
+    fn advance_time(&mut self, _duration: R) {}
+    /// Overload this to return true once the generator will never apply a force again,
+    /// the registry will then remove it.
+    fn is_expired(&self) -> bool {
+        return false;
+    }
+    /// The name used when reporting this generator's forces, defaults to the type name.
+    fn name(&self) -> &str {
+        return std::any::type_name::<Self>();
+    }
+    /// Overload this to return a copy of everything the generator needs to carry on exactly
+    /// where it is now, for `ParticleForceRegistry::snapshot`. Stateless generators can keep
+    /// the default. Closures used as generators cannot save what they capture.
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return None;
+    }
+    /// Puts back state returned by `save_state`, state of the wrong type is ignored.
+    fn restore_state(&mut self, _state: &dyn Any) {}
+    /// Overload this in conservative generators to return the potential energy the particle has
+    /// in the generator's field, for the conservation diagnostics. Generators that do no work or
+    /// lose energy, such as drag, keep the default of `None`.
+    /// Forces between two movable particles report half the energy for each end, and return the
+    /// particle at the other end from `get_other`.
+    fn potential_energy(&self, _particle: &Particle<R>) -> Option<R> {
+        return None;
+    }
+    /// Overload this in forces between two particles to return the one at the other end, so
+    /// `ParticleForceRegistry::potential_energy` can tell whether both ends are registered.
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return None;
+    }
+}
+
+/// Saves a copy of the whole generator, for implementing `save_state` on generators that are `Clone`.
+pub fn save_cloned<T: Clone + 'static>(force_gen: &T) -> Option<Box<dyn Any>> {
+    return Some(Box::new(force_gen.clone()));
+}
+
+/// Puts back a copy saved by `save_cloned`.
+pub fn restore_cloned<T: Clone + 'static>(force_gen: &mut T, state: &dyn Any) {
+    if let Some(saved) = state.downcast_ref::<T>() {
+        *force_gen = saved.clone();
+    }
+}
+
+/// Saves a wrapper's own value along with the state of the generator it wraps, for
+/// implementing `save_state` on wrappers.
+pub fn save_wrapped<R: Real, G: ParticleForceGenerator<R> + ?Sized>(value: R, force_gen: &G) -> Option<Box<dyn Any>> {
+    let state: (R, Option<Box<dyn Any>>) = (value, force_gen.save_state());
+    return Some(Box::new(state));
+}
+
+/// Puts back state saved by `save_wrapped` into the wrapped generator and returns the wrapper's
+/// value, or `None` if the state is of the wrong type.
+pub fn restore_wrapped<R: Real, G: ParticleForceGenerator<R> + ?Sized>(force_gen: &mut G, state: &dyn Any) -> Option<R> {
+    let (value, force_gen_state) = state.downcast_ref::<(R, Option<Box<dyn Any>>)>()?;
+    if let Some(force_gen_state) = force_gen_state {
+        force_gen.restore_state(force_gen_state.as_ref());
+    }
+    return Some(*value);
+}
+
+/// Lets any closure taking the particle and the duration be used as a force generator,
+/// for example `|p: &mut Particle, _: R| p.add_force(Vector3::new(0.0, 10.0, 0.0))`.
+impl<R: Real, F: FnMut(&mut Particle<R>, R)> ParticleForceGenerator<R> for F {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        self(particle, duration);
+    }
+}
+
+impl<R: Real> ParticleForceGenerator<R> for Box<dyn ParticleForceGenerator<R>> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        self.as_mut().update_force(particle, duration);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        self.as_mut().advance_time(duration);
+    }
+
+    fn is_expired(&self) -> bool {
+        return self.as_ref().is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return self.as_ref().name();
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.as_ref().get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return self.as_ref().save_state();
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        self.as_mut().restore_state(state);
+    }
+
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        return self.as_ref().potential_energy(particle);
+    }
+}
+
+/// The energy stored in a spring stretched or compressed by the extension
+fn spring_energy<R: Real>(spring_constant: R, extension: R) -> R {
+    return R::from_f32(0.5) * spring_constant * extension * extension;
 }
 
-pub struct ParticleGravity {
-    gravity: Vector3
+/// The share of a pair force's energy one registration reports
+pub(crate) fn pair_share<R: Real>(other: &Particle<R>) -> R {
+    return if other.get_inverse_mass() > R::zero() { R::from_f32(0.5) } else { R::one() };
 }
 
-impl ParticleGravity {
-    fn new(gravity: &Vector3) -> ParticleGravity {
+#[derive(Clone)]
+pub struct ParticleGravity<R: Real = f32> {
+    gravity: Vector3<R>
+}
+
+impl<R: Real> ParticleGravity<R> {
+    pub fn new(gravity: &Vector3<R>) -> ParticleGravity<R> {
         return ParticleGravity {
             gravity: *gravity
         };
     }
 }
 
-impl ParticleForceGenerator for ParticleGravity {
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
+impl<R: Real> ParticleForceGenerator<R> for ParticleGravity<R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, _duration: R) {
         // Check that we do not have infinite mass.
         if !particle.has_finite_mass() { return; }
         
         // Apply the mass-scaled force to the particle
         particle.add_force(self.gravity * particle.get_mass());
     }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+
+    /// U = -m g . p, zero at the origin
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        if !particle.has_finite_mass() { return Some(R::zero()); }
+        return Some(-(particle.get_mass() * (self.gravity * &particle.get_position())));
+    }
 }
 
-pub struct ParticleDrag {
+#[derive(Clone)]
+pub struct ParticleDrag<R: Real = f32> {
     /// Holds the velocity drag coefficient
-    k1: f32,
+    k1: R,
     /// Holds the velocity sqaured drag coefficient
-    k2: f32,
+    k2: R,
 }
 
-impl ParticleDrag {
-    fn new(k1: f32, k2: f32) -> ParticleDrag {
+impl<R: Real> ParticleDrag<R> {
+    pub fn new(k1: R, k2: R) -> ParticleDrag<R> {
         return ParticleDrag {
-            k1: k1,
-            k2: k2
+            k1,
+            k2
         }
     }
 }
 
-impl ParticleForceGenerator for ParticleDrag {
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
-        let mut force: Vector3 = particle.get_velocity();
+impl<R: Real> ParticleForceGenerator<R> for ParticleDrag<R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, _duration: R) {
+        let mut force: Vector3<R> = particle.get_velocity();
         
         // Calculate the total drag coefficient
-        let mut drag_coefficient: f32 = force.magnitude();
+        let mut drag_coefficient: R = force.magnitude();
         drag_coefficient = self.k1 * drag_coefficient + self.k2 * drag_coefficient * drag_coefficient;
         
         // Calculate the final force and apply it
@@ -97,19 +354,28 @@ impl ParticleForceGenerator for ParticleDrag {
         force *= -drag_coefficient;
         particle.add_force(force);
     }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
 }
 
-struct ParticleSpring {
+#[derive(Clone)]
+pub struct ParticleSpring<R: Real = f32> {
     /// The particle at the other end of the spring
-    other: Particle,
+    other: Rc<RefCell<Particle<R>>>,
     /// Holds the spring constant
-    spring_constant: f32,
+    spring_constant: R,
     /// Holds the rest lenght of the spring
-    rest_length: f32,
+    rest_length: R,
 }
 
-impl ParticleSpring {
-    fn new(other: Particle, spring_constant: f32, rest_length: f32) -> ParticleSpring {
+impl<R: Real> ParticleSpring<R> {
+    pub fn new(other: Rc<RefCell<Particle<R>>>, spring_constant: R, rest_length: R) -> ParticleSpring<R> {
         return ParticleSpring {
             other,
             spring_constant,
@@ -118,14 +384,18 @@ impl ParticleSpring {
     }
 }
 
-impl ParticleForceGenerator for ParticleSpring {
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
+impl<R: Real> ParticleForceGenerator<R> for ParticleSpring<R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, _duration: R) {
+        // A spring on its own other end has no length, and that is the only way the
+        // other particle can already be borrowed while we are updating.
+        let Ok(other) = self.other.try_borrow() else { return; };
+
         // Calculate the vector of the spring
-        let mut force: Vector3 = particle.get_position();
-        force -= &self.other.get_position();
+        let mut force: Vector3<R> = particle.get_position();
+        force -= &other.get_position();
         
         // Calculate the magnitude of the force
-        let mut magnitude: f32 = force.magnitude();
+        let mut magnitude: R = force.magnitude();
         magnitude = (magnitude - self.rest_length).abs();
         magnitude *= self.spring_constant;
         
@@ -134,20 +404,40 @@ impl ParticleForceGenerator for ParticleSpring {
         force *= -magnitude;
         particle.add_force(force);
     }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return Some(Rc::clone(&self.other));
+    }
+
+    /// U = k (|d| - l0)^2 / 2, halved when the other end can move
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        let other = self.other.try_borrow().ok()?;
+        let extension: R = (particle.get_position() - &other.get_position()).magnitude() - self.rest_length;
+        return Some(pair_share(&other) * spring_energy(self.spring_constant, extension));
+    }
 }
 
-pub struct ParticleAnchoredSpring {
-    anchor: Vector3,
-    spring_constant: f32,
-    rest_length: f32,
+#[derive(Clone)]
+pub struct ParticleAnchoredSpring<R: Real = f32> {
+    anchor: Vector3<R>,
+    spring_constant: R,
+    rest_length: R,
 }
 
-impl ParticleAnchoredSpring {
-    fn new(
-        anchor: Vector3,
-        spring_constant: f32,
-        rest_length: f32
-    ) -> ParticleAnchoredSpring {
+impl<R: Real> ParticleAnchoredSpring<R> {
+    pub fn new(
+        anchor: Vector3<R>,
+        spring_constant: R,
+        rest_length: R
+    ) -> ParticleAnchoredSpring<R> {
         return ParticleAnchoredSpring {
             anchor,
             spring_constant,
@@ -156,19 +446,19 @@ impl ParticleAnchoredSpring {
     }
     /// Updates the anchor position
     /// Could be used for having the camara follow the player as they move
-    fn set_anchor(&mut self, anchor_update: Vector3) {
+    pub fn set_anchor(&mut self, anchor_update: Vector3<R>) {
         self.anchor.update_by_vector3(anchor_update);
     }
 }
 
-impl ParticleForceGenerator for ParticleAnchoredSpring {
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
+impl<R: Real> ParticleForceGenerator<R> for ParticleAnchoredSpring<R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, _duration: R) {
         // Calculate the vector of the spring
-        let mut force: Vector3 = particle.get_position();
+        let mut force: Vector3<R> = particle.get_position();
         force -= &self.anchor;
         
         // Calculate the magnitude of the force
-        let mut magnitude: f32 = force.magnitude();
+        let mut magnitude: R = force.magnitude();
         magnitude = (self.rest_length - magnitude) * self.spring_constant;
         
         // Calculate the final force and apply it
@@ -176,20 +466,35 @@ impl ParticleForceGenerator for ParticleAnchoredSpring {
         force *= magnitude;
         particle.add_force(force);
     }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+
+    /// U = k (|d| - l0)^2 / 2
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        let extension: R = (particle.get_position() - &self.anchor).magnitude() - self.rest_length;
+        return Some(spring_energy(self.spring_constant, extension));
+    }
 }
 
-pub struct ParticleBungee {
-    other: Particle,
-    spring_constant: f32,
-    rest_length: f32,
+#[derive(Clone)]
+pub struct ParticleBungee<R: Real = f32> {
+    other: Rc<RefCell<Particle<R>>>,
+    spring_constant: R,
+    rest_length: R,
 }
 
-impl ParticleBungee {
-    fn new(
-        other: Particle,
-        spring_constant: f32,
-        rest_length: f32
-    ) -> ParticleBungee {
+impl<R: Real> ParticleBungee<R> {
+    pub fn new(
+        other: Rc<RefCell<Particle<R>>>,
+        spring_constant: R,
+        rest_length: R
+    ) -> ParticleBungee<R> {
         return ParticleBungee {
             other,
             spring_constant,
@@ -198,43 +503,67 @@ impl ParticleBungee {
     }
 }
 
-impl ParticleForceGenerator for ParticleBungee {
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
+impl<R: Real> ParticleForceGenerator<R> for ParticleBungee<R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, _duration: R) {
+        // Skip a bungee registered on its own other end, as for the spring
+        let Ok(other) = self.other.try_borrow() else { return; };
+
         // Calculate the vector of the spring
-        let mut force: Vector3 = particle.get_position() - &self.other.get_position();
+        let mut force: Vector3<R> = particle.get_position() - &other.get_position();
         
         // Check if the bungee is compressed
-        let mut magnitude: f32 = force.magnitude();
+        let mut magnitude: R = force.magnitude();
         if magnitude <= self.rest_length { return; }
         
         // Calculate the magnitude of the force
         magnitude = self.spring_constant * (self.rest_length - magnitude);
         
-        // Calculate the final force and apply it
+        // Calculate the final force and apply it, the magnitude is already negative
         force.normalize();
-        force *= -magnitude;
+        force *= magnitude;
         particle.add_force(force);
     }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return Some(Rc::clone(&self.other));
+    }
+
+    /// U = k (|d| - l0)^2 / 2 when stretched, halved when the other end can move
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        let other = self.other.try_borrow().ok()?;
+        let extension: R = (particle.get_position() - &other.get_position()).magnitude() - self.rest_length;
+        if extension <= R::zero() { return Some(R::zero()); }
+        return Some(pair_share(&other) * spring_energy(self.spring_constant, extension));
+    }
 }
 
-pub struct ParticleBuoyancy {
+#[derive(Clone)]
+pub struct ParticleBuoyancy<R: Real = f32> {
     /// Maximum submersion depth of the object before it generates its maximum buoyancy force
-    max_depth: f32,
+    max_depth: R,
     /// The volume of the object
-    volume: f32,
+    volume: R,
     /// The height of the water plane above y = 0. The place will be parallel to the XZ plane.
-    water_height: f32,
+    water_height: R,
     /// The density of the liquid. Pure water has a density of 1000 kg per cubic meter
-    liquid_density: f32,
+    liquid_density: R,
 }
 
-impl ParticleBuoyancy {
-    fn new(
-        max_depth: f32,
-        volume: f32,
-        water_height: f32,
-        liquid_density: f32, // default is intended to be 1000.0
-    ) -> ParticleBuoyancy {
+impl<R: Real> ParticleBuoyancy<R> {
+    pub fn new(
+        max_depth: R,
+        volume: R,
+        water_height: R,
+        liquid_density: R, // default is intended to be 1000.0
+    ) -> ParticleBuoyancy<R> {
         return ParticleBuoyancy {
             max_depth,
             volume,
@@ -244,17 +573,17 @@ impl ParticleBuoyancy {
     }
 }
 
-impl ParticleForceGenerator for ParticleBuoyancy {
+impl<R: Real> ParticleForceGenerator<R> for ParticleBuoyancy<R> {
     /// Assuming that the buoyancy is acting in the up direction
     /// Default density is 1000.0 kgm^3
     /// Ocean water has a density of 1020 to 1030 kgm^3 up to 1250 kgm^3 for the Dead Sea
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
+    fn update_force(&mut self, particle: &mut Particle<R>, _duration: R) {
         // Calculate the submersion depth
-        let depth: f32 = particle.get_position().y;
+        let depth: R = particle.get_position().y;
         
         // Check if we're out of the water.
         if depth >= self.water_height + self.max_depth { return; }
-        let mut force: Vector3 = Vector3::default();        
+        let mut force: Vector3<R> = Vector3::default();        
         
         // Check if we're at maximum depth.
         if depth <= self.water_height - self.max_depth {
@@ -265,27 +594,36 @@ impl ParticleForceGenerator for ParticleBuoyancy {
         
         // Otherwise we are partly submerged.
         force.y = self.liquid_density * self.volume *
-            (depth - self.max_depth - self.water_height) / 2.0
+            (depth - self.max_depth - self.water_height) / R::from_f32(2.0)
             * self.max_depth;
         particle.add_force(force);
     }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
 }
 
-pub struct ParticleFakeSpring {
+#[derive(Clone)]
+pub struct ParticleFakeSpring<R: Real = f32> {
     /// Location of the anchored end of the spring
-    anchor: Vector3,
+    anchor: Vector3<R>,
     /// Holds the spring constant
-    spring_constant: f32,
+    spring_constant: R,
     /// Holds the damping on the oscillation of the spring
-    damping: f32,
+    damping: R,
 }
 
-impl ParticleFakeSpring {
-    fn new(
-        anchor: Vector3,
-        spring_constant: f32,
-        damping: f32
-    ) -> ParticleFakeSpring {
+impl<R: Real> ParticleFakeSpring<R> {
+    pub fn new(
+        anchor: Vector3<R>,
+        spring_constant: R,
+        damping: R
+    ) -> ParticleFakeSpring<R> {
         return ParticleFakeSpring {
             anchor,
             spring_constant,
@@ -294,28 +632,100 @@ impl ParticleFakeSpring {
     }
 }
 
-impl ParticleForceGenerator for ParticleFakeSpring {
-    fn update_force(&mut self, particle: &mut Particle, duration: f32) {
+impl<R: Real> ParticleForceGenerator<R> for ParticleFakeSpring<R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
         // Check that we do not have infinite mass
         if !particle.has_finite_mass() { return; }
         
         // Calculate the relative position of the particle to the anchor
-        let position: Vector3 = particle.get_position() - &self.anchor;
+        let position: Vector3<R> = particle.get_position() - &self.anchor;
         
         // Calculate the constants and check that they are in bounds
-        let gamma: f32 = 0.5 * (4.0 * self.spring_constant - self.damping * self.damping).sqrt();
-        if gamma == 0.0 { return; }
-        let c: Vector3 = position * (self.damping / (2.0 * gamma)) +
-            &(particle.get_velocity() * (1.0 / gamma));
+        let gamma: R = R::from_f32(0.5) * (R::from_f32(4.0) * self.spring_constant - self.damping * self.damping).sqrt();
+        if gamma == R::zero() { return; }
+        let c: Vector3<R> = position * (self.damping / (R::from_f32(2.0) * gamma)) +
+            &(particle.get_velocity() * (R::one() / gamma));
         
         // Calculate the target postion
-        let mut target: Vector3 = position * (gamma * duration).cos() + 
+        let mut target: Vector3<R> = position * (gamma * duration).cos() +
             &(c * (gamma * duration).sin());
-        target *= (-0.5 * duration * self.damping).exp();
+        target *= (-R::from_f32(0.5) * duration * self.damping).exp();
         
         // Calculate the resulting acceleration, and therefore the force
-        let acceleration: Vector3 = (target - &position) * (1.0f32 / duration * duration) -
-            &(particle.get_velocity() * duration);
+        let acceleration: Vector3<R> = (target - &position) * (R::one() / (duration * duration)) -
+            &(particle.get_velocity() * (R::one() / duration));
         particle.add_force(acceleration * particle.get_mass());
     }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_cloned(self);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        restore_cloned(self, state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_particle(position: Vector3) -> Rc<RefCell<Particle>> {
+        return Rc::new(RefCell::new(Particle::new(position, Vector3::default(), Vector3::default(), 1.0, 1.0)));
+    }
+
+    /// Counts the times its clock is moved on
+    struct Clock {
+        ticks: usize,
+    }
+
+    impl ParticleForceGenerator for Clock {
+        fn update_force(&mut self, _particle: &mut Particle, _duration: f32) {}
+
+        fn advance_time(&mut self, _duration: f32) {
+            self.ticks += 1;
+        }
+    }
+
+    #[test]
+    fn shared_generators_see_each_step_once() {
+        let clock: Rc<RefCell<Clock>> = Rc::new(RefCell::new(Clock { ticks: 0 }));
+        let shared: Rc<RefCell<dyn ParticleForceGenerator>> = clock.clone();
+        let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+        for i in 0..100 {
+            registry.add(&shared_particle(Vector3::new(i as f32, 0.0, 0.0)), &shared);
+        }
+        registry.update_forces(0.01);
+        registry.update_forces(0.01);
+        assert_eq!(clock.borrow().ticks, 2);
+    }
+
+    #[test]
+    fn springs_registered_on_their_own_other_end_are_skipped() {
+        let particle = shared_particle(Vector3::new(1.0, 2.0, 3.0));
+        let spring: Rc<RefCell<dyn ParticleForceGenerator>> =
+            Rc::new(RefCell::new(ParticleSpring::new(Rc::clone(&particle), 10.0, 1.0)));
+        let bungee: Rc<RefCell<dyn ParticleForceGenerator>> =
+            Rc::new(RefCell::new(ParticleBungee::new(Rc::clone(&particle), 10.0, 0.5)));
+        let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+        registry.add(&particle, &spring);
+        registry.add(&particle, &bungee);
+
+        registry.update_forces(0.01);
+        assert_eq!(particle.borrow().force_accum, Vector3::default());
+    }
+
+    #[test]
+    fn springs_pull_towards_their_other_end() {
+        let anchor = shared_particle(Vector3::default());
+        let particle = shared_particle(Vector3::new(3.0, 0.0, 0.0));
+        let mut spring: ParticleSpring = ParticleSpring::new(Rc::clone(&anchor), 10.0, 1.0);
+        let mut bungee: ParticleBungee = ParticleBungee::new(Rc::clone(&anchor), 10.0, 1.0);
+
+        spring.update_force(&mut particle.borrow_mut(), 0.01);
+        assert_eq!(particle.borrow().force_accum, Vector3::new(-20.0, 0.0, 0.0));
+        particle.borrow_mut().clear_accumulator();
+        bungee.update_force(&mut particle.borrow_mut(), 0.01);
+        assert_eq!(particle.borrow().force_accum, Vector3::new(-20.0, 0.0, 0.0));
+    }
 }
@@ -0,0 +1,521 @@
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::Particle,
+    particle_force_gen::{restore_wrapped, save_wrapped, ParticleForceGenerator},
+    precision::Real,
+};
+
+/// Runs the force generator on the particle, then scales whatever force it added by `scale`.
+/// Forces already in the accumulator are left untouched.
+pub fn apply_scaled<R: Real, G: ParticleForceGenerator<R> + ?Sized>(
+    force_gen: &mut G,
+    particle: &mut Particle<R>,
+    duration: R,
+    scale: R
+) {
+    let before: Vector3<R> = particle.force_accum;
+    force_gen.update_force(particle, duration);
+    let added: Vector3<R> = particle.force_accum - &before;
+    particle.force_accum = before + &(added * scale);
+}
+
+/// Applies the wrapped force for a fixed amount of time, after which it expires.
+pub struct ParticleForceDuration<G: ParticleForceGenerator<R>, R: Real = f32> {
+    force_gen: G,
+    /// How long the force lasts in seconds
+    duration: R,
+    /// Time passed since the force started
+    elapsed: R,
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceDuration<G, R> {
+    pub fn new(force_gen: G, duration: R) -> ParticleForceDuration<G, R> {
+        return ParticleForceDuration {
+            force_gen,
+            duration,
+            elapsed: R::zero()
+        };
+    }
+
+    pub fn get_remaining(&self) -> R {
+        let remaining: R = self.duration - self.elapsed;
+        return if remaining > R::zero() { remaining } else { R::zero() };
+    }
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceGenerator<R> for ParticleForceDuration<G, R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        if self.elapsed >= self.duration { return; }
+        self.force_gen.update_force(particle, duration);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        self.elapsed += duration;
+        self.force_gen.advance_time(duration);
+    }
+
+    fn is_expired(&self) -> bool {
+        return self.elapsed >= self.duration || self.force_gen.is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return self.force_gen.name();
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.force_gen.get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_wrapped(self.elapsed, &self.force_gen);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(elapsed) = restore_wrapped(&mut self.force_gen, state) {
+            self.elapsed = elapsed;
+        }
+    }
+
+    /// The wrapped generator's energy while the force lasts, nothing after
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        let active: bool = self.elapsed < self.duration;
+        return self.force_gen.potential_energy(particle).map(|energy| if active { energy } else { R::zero() });
+    }
+}
+
+/// Fades the wrapped force in and out.
+/// The force ramps linearly from nothing to full strength, holds, then ramps back down and expires.
+/// Use a `hold` of `R::max_value()` for a force that ramps in and then stays on.
+pub struct ParticleForceEnvelope<G: ParticleForceGenerator<R>, R: Real = f32> {
+    force_gen: G,
+    /// Seconds taken to reach full strength
+    ramp_in: R,
+    /// Seconds spent at full strength
+    hold: R,
+    /// Seconds taken to fade back to nothing
+    ramp_out: R,
+    elapsed: R,
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceEnvelope<G, R> {
+    pub fn new(
+        force_gen: G,
+        ramp_in: R,
+        hold: R,
+        ramp_out: R
+    ) -> ParticleForceEnvelope<G, R> {
+        return ParticleForceEnvelope {
+            force_gen,
+            ramp_in,
+            hold,
+            ramp_out,
+            elapsed: R::zero()
+        };
+    }
+
+    /// Returns the current strength of the force between 0 and 1
+    pub fn get_scale(&self) -> R {
+        let mut time: R = self.elapsed;
+        if time < self.ramp_in { return time / self.ramp_in; }
+
+        time -= self.ramp_in;
+        if time < self.hold { return R::one(); }
+
+        time -= self.hold;
+        if time < self.ramp_out { return R::one() - time / self.ramp_out; }
+        return R::zero();
+    }
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceGenerator<R> for ParticleForceEnvelope<G, R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        let scale: R = self.get_scale();
+        if scale <= R::zero() { return; }
+        apply_scaled(&mut self.force_gen, particle, duration, scale);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        self.elapsed += duration;
+        self.force_gen.advance_time(duration);
+    }
+
+    fn is_expired(&self) -> bool {
+        // Taken a phase at a time so a `hold` of `R::max_value()` cannot overflow
+        let time: R = self.elapsed - self.ramp_in;
+        let finished: bool = time >= R::zero() && time >= self.hold && time - self.hold >= self.ramp_out;
+        return finished || self.force_gen.is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return self.force_gen.name();
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.force_gen.get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_wrapped(self.elapsed, &self.force_gen);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(elapsed) = restore_wrapped(&mut self.force_gen, state) {
+            self.elapsed = elapsed;
+        }
+    }
+
+    /// The wrapped generator's energy scaled by the current strength
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        return self.force_gen.potential_energy(particle).map(|energy| energy * self.get_scale());
+    }
+}
+
+/// Pulses the wrapped force, switching it on for `on_time` seconds at the start of every `period`.
+pub struct ParticleForcePeriodic<G: ParticleForceGenerator<R>, R: Real = f32> {
+    force_gen: G,
+    /// Seconds between the start of each pulse
+    period: R,
+    /// Seconds the force is applied for in each period
+    on_time: R,
+    /// Time into the current period, kept below `period` since there is no remainder for every `Real`
+    elapsed: R,
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForcePeriodic<G, R> {
+    pub fn new(force_gen: G, period: R, on_time: R) -> ParticleForcePeriodic<G, R> {
+        assert!(period > R::zero());
+        return ParticleForcePeriodic {
+            force_gen,
+            period,
+            on_time,
+            elapsed: R::zero()
+        };
+    }
+
+    pub fn is_on(&self) -> bool {
+        return self.elapsed < self.on_time;
+    }
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceGenerator<R> for ParticleForcePeriodic<G, R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        if !self.is_on() { return; }
+        self.force_gen.update_force(particle, duration);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        self.elapsed += duration;
+        while self.elapsed >= self.period {
+            self.elapsed -= self.period;
+        }
+        self.force_gen.advance_time(duration);
+    }
+
+    fn is_expired(&self) -> bool {
+        return self.force_gen.is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return self.force_gen.name();
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.force_gen.get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_wrapped(self.elapsed, &self.force_gen);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(elapsed) = restore_wrapped(&mut self.force_gen, state) {
+            self.elapsed = elapsed;
+        }
+    }
+
+    /// The wrapped generator's energy while the pulse is on, nothing between pulses
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        let on: bool = self.is_on();
+        return self.force_gen.potential_energy(particle).map(|energy| if on { energy } else { R::zero() });
+    }
+}
+
+/// Holds the wrapped force back until `start_time` seconds of simulation have passed.
+/// The clock starts when the wrapper is created, use `set_elapsed` to line it up
+/// with a simulation that is already running.
+/// The wrapped generator only sees time pass once it has started.
+pub struct ParticleForceDelayed<G: ParticleForceGenerator<R>, R: Real = f32> {
+    force_gen: G,
+    start_time: R,
+    elapsed: R,
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceDelayed<G, R> {
+    pub fn new(force_gen: G, start_time: R) -> ParticleForceDelayed<G, R> {
+        return ParticleForceDelayed {
+            force_gen,
+            start_time,
+            elapsed: R::zero()
+        };
+    }
+
+    pub fn set_elapsed(&mut self, elapsed: R) {
+        self.elapsed = elapsed;
+    }
+
+    pub fn has_started(&self) -> bool {
+        return self.elapsed >= self.start_time;
+    }
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>> ParticleForceGenerator<R> for ParticleForceDelayed<G, R> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        if !self.has_started() { return; }
+        self.force_gen.update_force(particle, duration);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        let previous: R = if self.elapsed > self.start_time { self.elapsed } else { self.start_time };
+        self.elapsed += duration;
+        if self.elapsed > previous {
+            self.force_gen.advance_time(self.elapsed - previous);
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        return self.force_gen.is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return self.force_gen.name();
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.force_gen.get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return save_wrapped(self.elapsed, &self.force_gen);
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        if let Some(elapsed) = restore_wrapped(&mut self.force_gen, state) {
+            self.elapsed = elapsed;
+        }
+    }
+
+    /// The wrapped generator's energy once it has started, nothing before
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        let started: bool = self.has_started();
+        return self.force_gen.potential_energy(particle).map(|energy| if started { energy } else { R::zero() });
+    }
+}
+
+/// Only applies the wrapped force to particles that pass the predicate,
+/// for example `|p: &Particle| p.position.y < 0.0` for a force that only acts underwater.
+/// Snapshots only save the wrapped generator, not anything the predicate captures.
+pub struct ParticleForceConditional<G, P> {
+    force_gen: G,
+    predicate: P,
+}
+
+impl<G, P> ParticleForceConditional<G, P> {
+    pub fn new(force_gen: G, predicate: P) -> ParticleForceConditional<G, P> {
+        return ParticleForceConditional {
+            force_gen,
+            predicate
+        };
+    }
+}
+
+impl<R: Real, G: ParticleForceGenerator<R>, P: FnMut(&Particle<R>) -> bool> ParticleForceGenerator<R> for ParticleForceConditional<G, P> {
+    fn update_force(&mut self, particle: &mut Particle<R>, duration: R) {
+        if !(self.predicate)(particle) { return; }
+        self.force_gen.update_force(particle, duration);
+    }
+
+    fn advance_time(&mut self, duration: R) {
+        self.force_gen.advance_time(duration);
+    }
+
+    fn is_expired(&self) -> bool {
+        return self.force_gen.is_expired();
+    }
+
+    fn name(&self) -> &str {
+        return self.force_gen.name();
+    }
+
+    fn get_other(&self) -> Option<Rc<RefCell<Particle<R>>>> {
+        return self.force_gen.get_other();
+    }
+
+    fn save_state(&self) -> Option<Box<dyn Any>> {
+        return self.force_gen.save_state();
+    }
+
+    fn restore_state(&mut self, state: &dyn Any) {
+        self.force_gen.restore_state(state);
+    }
+
+    /// The wrapped generator's energy whether or not the particle passes, since the predicate
+    /// cannot be called without changing it
+    fn potential_energy(&self, particle: &Particle<R>) -> Option<R> {
+        return self.force_gen.potential_energy(particle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixed::Fixed32, particle_force_gen::{ParticleForceRegistry, ParticleGravity}};
+
+    fn gravity() -> ParticleGravity {
+        return ParticleGravity::new(&Vector3::new(0.0, -10.0, 0.0));
+    }
+
+    #[test]
+    fn wrappers_report_the_wrapped_generator() {
+        let particle: Particle = Particle::new(Vector3::new(0.0, 2.0, 0.0), Vector3::default(), Vector3::default(), 1.0, 3.0);
+        let energy: Option<f32> = gravity().potential_energy(&particle);
+        assert_eq!(energy, Some(60.0));
+
+        let mut duration: ParticleForceDuration<ParticleGravity> = ParticleForceDuration::new(gravity(), 1.0);
+        assert_eq!(duration.name(), gravity().name());
+        assert_eq!(duration.potential_energy(&particle), energy);
+        duration.advance_time(1.0);
+        assert_eq!(duration.potential_energy(&particle), Some(0.0));
+
+        let mut envelope: ParticleForceEnvelope<ParticleGravity> = ParticleForceEnvelope::new(gravity(), 1.0, 1.0, 1.0);
+        envelope.advance_time(0.25);
+        assert_eq!(envelope.name(), gravity().name());
+        assert_eq!(envelope.potential_energy(&particle), Some(15.0));
+
+        let mut periodic: ParticleForcePeriodic<ParticleGravity> = ParticleForcePeriodic::new(gravity(), 1.0, 0.5);
+        assert_eq!(periodic.potential_energy(&particle), energy);
+        periodic.advance_time(0.75);
+        assert_eq!(periodic.potential_energy(&particle), Some(0.0));
+
+        let mut delayed: ParticleForceDelayed<ParticleGravity> = ParticleForceDelayed::new(gravity(), 1.0);
+        assert_eq!(delayed.potential_energy(&particle), Some(0.0));
+        delayed.advance_time(1.0);
+        assert_eq!(delayed.name(), gravity().name());
+        assert_eq!(delayed.potential_energy(&particle), energy);
+
+        let conditional = ParticleForceConditional::new(gravity(), |p: &Particle| p.position.y < 0.0);
+        assert_eq!(conditional.name(), gravity().name());
+        assert_eq!(conditional.potential_energy(&particle), energy);
+    }
+
+    /// Applies the generator once and returns the vertical force it gave a particle of mass 1
+    fn pull<G: ParticleForceGenerator>(force_gen: &mut G) -> f32 {
+        let mut particle: Particle = Particle::new(Vector3::default(), Vector3::default(), Vector3::default(), 1.0, 1.0);
+        force_gen.update_force(&mut particle, 0.25);
+        return particle.force_accum.y;
+    }
+
+    #[test]
+    fn the_registry_drops_expired_durations() {
+        let first: Rc<RefCell<Particle>> = Rc::new(RefCell::new(Particle::new(Vector3::default(), Vector3::default(), Vector3::default(), 1.0, 1.0)));
+        let second: Rc<RefCell<Particle>> = Rc::new(RefCell::new(Particle::new(Vector3::default(), Vector3::default(), Vector3::default(), 1.0, 1.0)));
+        let lasting: Rc<RefCell<dyn ParticleForceGenerator>> = Rc::new(RefCell::new(ParticleForceDuration::new(gravity(), 1.0)));
+        let plain: Rc<RefCell<dyn ParticleForceGenerator>> = Rc::new(RefCell::new(gravity()));
+        let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+        registry.add(&first, &lasting);
+        registry.add(&second, &lasting);
+        registry.add(&first, &plain);
+
+        // Shared by two particles, the duration still only counts each step once
+        for step in 0..4 {
+            assert_eq!(registry.len(), 3, "step {}", step);
+            first.borrow_mut().clear_accumulator();
+            second.borrow_mut().clear_accumulator();
+            registry.update_forces(0.25);
+            assert_eq!(first.borrow().force_accum.y, -20.0);
+            assert_eq!(second.borrow().force_accum.y, -10.0);
+        }
+        assert_eq!(registry.len(), 1);
+        second.borrow_mut().clear_accumulator();
+        registry.update_forces(0.25);
+        assert_eq!(second.borrow().force_accum.y, 0.0);
+    }
+
+    #[test]
+    fn envelopes_ramp_in_hold_and_ramp_out() {
+        let mut envelope: ParticleForceEnvelope<ParticleGravity> = ParticleForceEnvelope::new(gravity(), 1.0, 0.5, 0.5);
+        let mut scales: Vec<f32> = Vec::new();
+        let mut forces: Vec<f32> = Vec::new();
+        while !envelope.is_expired() {
+            scales.push(envelope.get_scale());
+            forces.push(pull(&mut envelope));
+            envelope.advance_time(0.25);
+        }
+        assert_eq!(scales, [0.0, 0.25, 0.5, 0.75, 1.0, 1.0, 1.0, 0.5]);
+        let expected: Vec<f32> = scales.iter().map(|scale| -10.0 * scale).collect();
+        assert_eq!(forces, expected);
+        // Expires the moment the ramp out ends, at two seconds
+        assert_eq!(envelope.get_scale(), 0.0);
+        assert_eq!(pull(&mut envelope), 0.0);
+    }
+
+    #[test]
+    fn periodic_forces_pulse_at_the_start_of_each_period() {
+        let mut periodic: ParticleForcePeriodic<ParticleGravity> = ParticleForcePeriodic::new(gravity(), 1.0, 0.25);
+        let mut forces: Vec<f32> = Vec::new();
+        for _ in 0..12 {
+            assert_eq!(periodic.is_on(), forces.len().is_multiple_of(4));
+            forces.push(pull(&mut periodic));
+            periodic.advance_time(0.25);
+        }
+        assert_eq!(forces, [-10.0, 0.0, 0.0, 0.0, -10.0, 0.0, 0.0, 0.0, -10.0, 0.0, 0.0, 0.0]);
+        assert!(!periodic.is_expired());
+
+        // A long step lands part way into a later period, 0.125 s into a pulse
+        periodic.advance_time(2.125);
+        assert!(periodic.is_on());
+        periodic.advance_time(0.125);
+        assert!(!periodic.is_on());
+    }
+
+    #[test]
+    fn wrappers_restore_their_clocks() {
+        let mut delayed: ParticleForceDelayed<ParticleGravity> = ParticleForceDelayed::new(gravity(), 1.0);
+        let saved: Box<dyn Any> = delayed.save_state().unwrap();
+        delayed.advance_time(2.0);
+        assert!(delayed.has_started());
+        delayed.restore_state(saved.as_ref());
+        assert!(!delayed.has_started());
+
+        // State of the wrong type is ignored
+        delayed.advance_time(2.0);
+        delayed.restore_state(&5u32);
+        assert!(delayed.has_started());
+    }
+
+    #[test]
+    fn wrappers_work_with_fixed_point() {
+        let half: Fixed32 = Fixed32::from_f32(0.5);
+        let gravity: ParticleGravity<Fixed32> = ParticleGravity::new(&Vector3::new(Fixed32::zero(), Fixed32::from_int(-8), Fixed32::zero()));
+        let mut particle: Particle<Fixed32> = Particle::new(
+            Vector3::default(), Vector3::default(), Vector3::default(), Fixed32::one(), Fixed32::from_int(2)
+        );
+
+        let mut envelope: ParticleForceEnvelope<ParticleGravity<Fixed32>, Fixed32> =
+            ParticleForceEnvelope::new(gravity.clone(), Fixed32::one(), Fixed32::max_value(), Fixed32::one());
+        envelope.advance_time(half);
+        envelope.update_force(&mut particle, half);
+        assert_eq!(particle.force_accum.y, Fixed32::from_int(-8));
+        assert!(!envelope.is_expired());
+
+        let mut periodic: ParticleForcePeriodic<ParticleGravity<Fixed32>, Fixed32> =
+            ParticleForcePeriodic::new(gravity, Fixed32::one(), half);
+        for _ in 0..5 {
+            periodic.advance_time(half);
+        }
+        assert!(!periodic.is_on());
+        periodic.advance_time(half);
+        assert!(periodic.is_on());
+    }
+}
@@ -0,0 +1,379 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    core::{Matrix3, Vector3},
+    particle::Particle,
+    precision::real_pow,
+};
+
+/// A damped spring between two particles of a `ParticleSpringNetwork`.
+#[derive(Debug, Clone)]
+pub struct ParticleNetworkSpring {
+    /// Index of the particle at one end of the spring
+    pub a: usize,
+    /// Index of the particle at the other end of the spring
+    pub b: usize,
+    /// Holds the spring constant
+    pub spring_constant: f32,
+    /// Holds the damping coefficient, applied to the relative velocity along the spring
+    pub damping: f32,
+    /// Holds the rest length of the spring
+    pub rest_length: f32,
+}
+
+/// Adds the force of a damped spring between the particles at the ends to both of them,
+/// `f = -k(|d| - l0)^d - c((va - vb) . ^d)^d` on the first. Returns the unit vector from the second
+/// end to the first and the distance between them, `None` if they are in the same place.
+pub(crate) fn add_damped_spring_force(
+    positions: &[Vector3],
+    velocities: &[Vector3],
+    forces: &mut [Vector3],
+    (a, b): (usize, usize),
+    spring_constant: f32,
+    damping: f32,
+    rest_length: f32
+) -> Option<(Vector3, f32)> {
+    let mut direction: Vector3 = positions[a] - &positions[b];
+    let length: f32 = direction.magnitude();
+    if length == 0.0 { return None; }
+    direction.normalize();
+    let relative_velocity: Vector3 = velocities[a] - &velocities[b];
+    let magnitude: f32 = spring_constant * (length - rest_length) + damping * (relative_velocity * &direction);
+    forces[a] += &(direction * -magnitude);
+    forces[b] += &(direction * magnitude);
+    return Some((direction, length));
+}
+
+/// Steps a network of springs between particles with backward (implicit) Euler, following
+/// Baraff and Witkin's "Large Steps in Cloth Simulation".
+///
+/// Instead of using the spring forces at the start of the step, as `ParticleSpring` does,
+/// the solver finds the velocity change that agrees with the forces at the end of the step:
+/// `(M - h * df/dv - h^2 * df/dx) dv = h * (f + h * df/dx * v)`
+/// The system is solved with a Jacobi preconditioned conjugate gradient that works one spring at a
+/// time, so no matrix is ever built. This stays stable for spring constants that would explode the
+/// explicit integrator, at the cost of some numerical damping.
+///
+/// `step` moves the network's particles itself, so they must not also be integrated with
+/// `Particle::integrate`. Outside forces and accelerations go on the right hand side with the
+/// springs, and particles with infinite mass do not move.
+pub struct ParticleSpringNetwork {
+    particles: Vec<Rc<RefCell<Particle>>>,
+    springs: Vec<ParticleNetworkSpring>,
+    /// The most conjugate gradient iterations to run per step
+    max_iterations: usize,
+    /// The solve stops once the residual has shrunk by this factor
+    tolerance: f32,
+}
+
+impl ParticleSpringNetwork {
+    pub fn new(max_iterations: usize, tolerance: f32) -> ParticleSpringNetwork {
+        return ParticleSpringNetwork {
+            particles: Vec::new(),
+            springs: Vec::new(),
+            max_iterations,
+            tolerance
+        };
+    }
+
+    /// Adds a particle to the network and returns its index for use in springs
+    pub fn add_particle(&mut self, particle: &Rc<RefCell<Particle>>) -> usize {
+        self.particles.push(Rc::clone(particle));
+        return self.particles.len() - 1;
+    }
+
+    /// Connects two particles of the network with a spring
+    pub fn add_spring(
+        &mut self,
+        a: usize,
+        b: usize,
+        spring_constant: f32,
+        damping: f32,
+        rest_length: f32
+    ) {
+        assert!(a < self.particles.len() && b < self.particles.len() && a != b);
+        self.springs.push(ParticleNetworkSpring {
+            a,
+            b,
+            spring_constant,
+            damping,
+            rest_length
+        });
+    }
+
+    pub fn get_particles(&self) -> &[Rc<RefCell<Particle>>] {
+        return &self.particles;
+    }
+
+    pub fn get_springs(&self) -> &[ParticleNetworkSpring] {
+        return &self.springs;
+    }
+
+    /// Advances every particle in the network by the duration.
+    /// Returns the number of conjugate gradient iterations the solve took.
+    pub fn step(&mut self, duration: f32) -> usize {
+        assert!(duration > 0.0);
+        let count: usize = self.particles.len();
+        if count == 0 { return 0; }
+
+        // Copy the state out of the particles
+        let mut positions: Vec<Vector3> = Vec::with_capacity(count);
+        let mut velocities: Vec<Vector3> = Vec::with_capacity(count);
+        let mut masses: Vec<f32> = Vec::with_capacity(count);
+        let mut fixed: Vec<bool> = Vec::with_capacity(count);
+        let mut forces: Vec<Vector3> = Vec::with_capacity(count);
+        for particle in self.particles.iter() {
+            let particle = particle.borrow();
+            let is_fixed: bool = particle.get_inverse_mass() <= 0.0;
+            positions.push(particle.get_position());
+            velocities.push(particle.get_velocity());
+            masses.push(if is_fixed { 1.0 } else { particle.get_mass() });
+            fixed.push(is_fixed);
+            forces.push(if is_fixed {
+                Vector3::default()
+            } else {
+                particle.force_accum + &(particle.get_acceleration() * particle.get_mass())
+            });
+        }
+
+        // Work out the spring forces and the system block for each spring
+        let mut blocks: Vec<Matrix3> = Vec::with_capacity(self.springs.len());
+        let mut stiffness_velocity: Vec<Vector3> = vec![Vector3::default(); count];
+        for spring in self.springs.iter() {
+            let Some((direction, length)) = add_damped_spring_force(
+                &positions,
+                &velocities,
+                &mut forces,
+                (spring.a, spring.b),
+                spring.spring_constant,
+                spring.damping,
+                spring.rest_length
+            ) else {
+                blocks.push(Matrix3::default());
+                continue;
+            };
+            let relative_velocity: Vector3 = velocities[spring.a] - &velocities[spring.b];
+
+            // df/dx = -k(^d^dT + (1 - l0/|d|)(I - ^d^dT)), the second term is dropped
+            // under compression to keep the system positive definite.
+            let along: Matrix3 = Matrix3::outer_product(&direction, &direction);
+            let across: Matrix3 = Matrix3::identity() + &(along * -1.0);
+            let stretch: f32 = (1.0 - spring.rest_length / length).max(0.0);
+            let stiffness: Matrix3 = (along + &(across * stretch)) * -spring.spring_constant;
+            // df/dv = -c^d^dT
+            let damping: Matrix3 = along * -spring.damping;
+
+            let stiffness_times_velocity: Vector3 = stiffness * &relative_velocity;
+            stiffness_velocity[spring.a] += &stiffness_times_velocity;
+            stiffness_velocity[spring.b] -= &stiffness_times_velocity;
+
+            blocks.push((damping * -duration) + &(stiffness * -(duration * duration)));
+        }
+
+        // b = h * (f + h * df/dx * v)
+        let mut rhs: Vec<Vector3> = Vec::with_capacity(count);
+        for i in 0..count {
+            if fixed[i] {
+                rhs.push(Vector3::default());
+            } else {
+                rhs.push((forces[i] + &(stiffness_velocity[i] * duration)) * duration);
+            }
+        }
+
+        // The diagonal of the system, used as the preconditioner
+        let mut diagonal: Vec<Vector3> = masses.iter().map(|m| Vector3::new(*m, *m, *m)).collect();
+        for (spring, block) in self.springs.iter().zip(blocks.iter()) {
+            let block_diagonal: Vector3 = Vector3::new(block.data[0], block.data[4], block.data[8]);
+            diagonal[spring.a] += &block_diagonal;
+            diagonal[spring.b] += &block_diagonal;
+        }
+
+        let (velocity_change, iterations) = self.solve(&blocks, &masses, &fixed, &diagonal, &rhs);
+
+        // Write the new state back
+        for (i, particle) in self.particles.iter().enumerate() {
+            let mut particle = particle.borrow_mut();
+            if !fixed[i] {
+                let mut velocity: Vector3 = velocities[i] + &velocity_change[i];
+                velocity *= real_pow(particle.damping, duration);
+                particle.velocity = velocity;
+                particle.position.add_scaled_vector(&velocity, duration);
+            }
+            particle.clear_accumulator();
+        }
+        return iterations;
+    }
+
+    /// Multiplies `x` by the system matrix `M - h * df/dv - h^2 * df/dx`
+    fn multiply(
+        &self,
+        blocks: &[Matrix3],
+        masses: &[f32],
+        fixed: &[bool],
+        x: &[Vector3]
+    ) -> Vec<Vector3> {
+        let mut result: Vec<Vector3> = x.iter().zip(masses.iter()).map(|(v, m)| *v * *m).collect();
+        for (spring, block) in self.springs.iter().zip(blocks.iter()) {
+            let product: Vector3 = *block * &(x[spring.a] - &x[spring.b]);
+            result[spring.a] += &product;
+            result[spring.b] -= &product;
+        }
+        for (value, is_fixed) in result.iter_mut().zip(fixed.iter()) {
+            if *is_fixed { *value = Vector3::default(); }
+        }
+        return result;
+    }
+
+    /// Preconditioned conjugate gradient, returns the solution and the iterations taken
+    fn solve(
+        &self,
+        blocks: &[Matrix3],
+        masses: &[f32],
+        fixed: &[bool],
+        diagonal: &[Vector3],
+        rhs: &[Vector3]
+    ) -> (Vec<Vector3>, usize) {
+        let count: usize = rhs.len();
+        let precondition = |r: &[Vector3]| -> Vec<Vector3> {
+            return r.iter().zip(diagonal.iter())
+                .map(|(v, d)| Vector3::new(v.x / d.x, v.y / d.y, v.z / d.z))
+                .collect();
+        };
+        let dot = |a: &[Vector3], b: &[Vector3]| -> f32 {
+            return a.iter().zip(b.iter()).map(|(u, v)| *u * v).sum();
+        };
+
+        let mut x: Vec<Vector3> = vec![Vector3::default(); count];
+        let mut residual: Vec<Vector3> = rhs.to_vec();
+        let target: f32 = self.tolerance * self.tolerance * dot(rhs, rhs);
+        if dot(&residual, &residual) <= target { return (x, 0); }
+
+        let mut z: Vec<Vector3> = precondition(&residual);
+        let mut direction: Vec<Vector3> = z.clone();
+        let mut rz: f32 = dot(&residual, &z);
+
+        for iteration in 0..self.max_iterations {
+            let product: Vec<Vector3> = self.multiply(blocks, masses, fixed, &direction);
+            let denominator: f32 = dot(&direction, &product);
+            if denominator <= 0.0 { return (x, iteration); }
+            let alpha: f32 = rz / denominator;
+
+            for i in 0..count {
+                x[i].add_scaled_vector(&direction[i], alpha);
+                residual[i].add_scaled_vector(&product[i], -alpha);
+            }
+            if dot(&residual, &residual) <= target { return (x, iteration + 1); }
+
+            z = precondition(&residual);
+            let rz_next: f32 = dot(&residual, &z);
+            let beta: f32 = rz_next / rz;
+            rz = rz_next;
+            for i in 0..count {
+                direction[i] = z[i] + &(direction[i] * beta);
+            }
+        }
+        return (x, self.max_iterations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle_force_gen::{ParticleForceGenerator, ParticleSpring};
+
+    fn particle(position: Vector3, velocity: Vector3, mass: f32) -> Rc<RefCell<Particle>> {
+        return Rc::new(RefCell::new(Particle::new(position, velocity, Vector3::default(), 1.0, mass)));
+    }
+
+    fn anchor() -> Rc<RefCell<Particle>> {
+        let anchor: Rc<RefCell<Particle>> = particle(Vector3::default(), Vector3::default(), 1.0);
+        anchor.borrow_mut().restore_mass(1.0, 0.0);
+        return anchor;
+    }
+
+    #[test]
+    fn stiff_springs_stay_bounded() {
+        let (spring_constant, duration): (f32, f32) = (1.0e5, 1.0 / 60.0);
+        let start: Vector3 = Vector3::new(1.1, 0.0, 0.0);
+
+        // The explicit spring overshoots further every step
+        let explicit: Rc<RefCell<Particle>> = particle(start, Vector3::default(), 1.0);
+        let mut spring: ParticleSpring = ParticleSpring::new(anchor(), spring_constant, 1.0);
+        for _ in 0..60 {
+            spring.update_force(&mut explicit.borrow_mut(), duration);
+            explicit.borrow_mut().integrate(duration);
+        }
+        let position: f32 = explicit.borrow().get_position().x;
+        assert!(!position.is_finite() || position.abs() > 1.0e3, "the explicit spring ended at {}", position);
+
+        let mut network: ParticleSpringNetwork = ParticleSpringNetwork::new(50, 1.0e-6);
+        let fixed: usize = network.add_particle(&anchor());
+        let moving: usize = network.add_particle(&particle(start, Vector3::default(), 1.0));
+        network.add_spring(fixed, moving, spring_constant, 0.0, 1.0);
+        for _ in 0..60 {
+            network.step(duration);
+            let stretch: f32 = network.get_particles()[moving].borrow().get_position().x - 1.0;
+            assert!(stretch.abs() <= 0.1 + 1.0e-4, "the implicit spring stretched to {}", stretch);
+        }
+    }
+
+    #[test]
+    fn velocity_changes_match_backward_euler() {
+        let (m1, m2, k, c, rest, h): (f32, f32, f32, f32, f32, f32) = (2.0, 3.0, 500.0, 4.0, 1.0, 0.05);
+        let (x1, x2, v1, v2): (f32, f32, f32, f32) = (1.5, 0.0, 1.0, -0.5);
+        let mut network: ParticleSpringNetwork = ParticleSpringNetwork::new(20, 1.0e-7);
+        let a: usize = network.add_particle(&particle(Vector3::new(x1, 0.0, 0.0), Vector3::new(v1, 0.0, 0.0), m1));
+        let b: usize = network.add_particle(&particle(Vector3::new(x2, 0.0, 0.0), Vector3::new(v2, 0.0, 0.0), m2));
+        network.add_spring(a, b, k, c, rest);
+        network.step(h);
+
+        // Along the spring df1/dx1 = -k and df1/dv1 = -c, with the opposite sign for the other end:
+        // [m1 + s, -s; -s, m2 + s] dv = h [f1 - h k (v1 - v2); -f1 + h k (v1 - v2)], s = h c + h^2 k
+        let s: f32 = h * c + h * h * k;
+        let f1: f32 = -k * (x1 - x2 - rest) - c * (v1 - v2);
+        let b1: f32 = h * (f1 - h * k * (v1 - v2));
+        let b2: f32 = -b1;
+        let determinant: f32 = (m1 + s) * (m2 + s) - s * s;
+        let dv1: f32 = (b1 * (m2 + s) + s * b2) / determinant;
+        let dv2: f32 = ((m1 + s) * b2 + s * b1) / determinant;
+
+        let particles: &[Rc<RefCell<Particle>>] = network.get_particles();
+        let (after1, after2) = (particles[a].borrow().get_velocity(), particles[b].borrow().get_velocity());
+        assert!((after1.x - (v1 + dv1)).abs() < 1.0e-4, "{} not {}", after1.x, v1 + dv1);
+        assert!((after2.x - (v2 + dv2)).abs() < 1.0e-4, "{} not {}", after2.x, v2 + dv2);
+        assert_eq!(after1.y, 0.0);
+        // Momentum is kept
+        assert!((m1 * after1.x + m2 * after2.x - (m1 * v1 + m2 * v2)).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn particles_with_infinite_mass_do_not_move() {
+        let mut network: ParticleSpringNetwork = ParticleSpringNetwork::new(20, 1.0e-6);
+        let fixed: usize = network.add_particle(&anchor());
+        let hanging: usize = network.add_particle(&particle(Vector3::new(0.0, -2.0, 0.0), Vector3::new(1.0, 0.0, 0.0), 1.0));
+        network.get_particles()[hanging].borrow_mut().acceleration = Vector3::new(0.0, -9.81, 0.0);
+        network.add_spring(fixed, hanging, 1000.0, 1.0, 1.0);
+        for _ in 0..30 {
+            network.step(1.0 / 60.0);
+        }
+        assert_eq!(network.get_particles()[fixed].borrow().get_position(), Vector3::default());
+        assert_eq!(network.get_particles()[fixed].borrow().get_velocity(), Vector3::default());
+        assert!(network.get_particles()[hanging].borrow().get_position() != Vector3::new(0.0, -2.0, 0.0));
+    }
+
+    #[test]
+    fn damped_springs_pull_both_ends_together() {
+        let positions: [Vector3; 2] = [Vector3::new(3.0, 0.0, 0.0), Vector3::default()];
+        let velocities: [Vector3; 2] = [Vector3::new(1.0, 0.0, 0.0), Vector3::default()];
+        let mut forces: [Vector3; 2] = [Vector3::default(); 2];
+        let (direction, length) = add_damped_spring_force(&positions, &velocities, &mut forces, (0, 1), 10.0, 2.0, 1.0).unwrap();
+        assert_eq!(direction, Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(length, 3.0);
+        // -10 (3 - 1) - 2 (1)
+        assert_eq!(forces[0], Vector3::new(-22.0, 0.0, 0.0));
+        assert_eq!(forces[1], Vector3::new(22.0, 0.0, 0.0));
+
+        let together: [Vector3; 2] = [Vector3::default(); 2];
+        assert!(add_damped_spring_force(&together, &velocities, &mut forces, (0, 1), 10.0, 2.0, 1.0).is_none());
+    }
+}
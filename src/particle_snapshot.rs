@@ -0,0 +1,167 @@
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+use crate::{
+    particle::Particle,
+    particle_force_gen::{ParticleForceGenerator, ParticleForceRegistration},
+    precision::Real,
+};
+
+/// A particle and the copy of it that was saved.
+struct SavedParticle<R: Real> {
+    particle: Rc<RefCell<Particle<R>>>,
+    state: Particle<R>,
+}
+
+/// A generator and the state it saved, if it has any.
+struct SavedForceGen<R: Real> {
+    force_gen: Rc<RefCell<dyn ParticleForceGenerator<R>>>,
+    state: Option<Box<dyn Any>>,
+}
+
+/// A copy of a simulation's state held in memory, made by `ParticleForceRegistry::snapshot`
+/// and put back with `ParticleForceRegistry::restore`, for rewinding or rolling back a simulation.
+///
+/// Particles are copied whole, including their mass and force accumulator. Generators are saved
+/// through `ParticleForceGenerator::save_state`. The snapshot keeps hold of the particles and
+/// generators themselves, and restoring writes the saved state back into them, so anything else
+/// holding the same `Rc` sees the restored state. A snapshot can be restored any number of times.
+pub struct ParticleSnapshot<R: Real = f32> {
+    registrations: Vec<ParticleForceRegistration<R>>,
+    particles: Vec<SavedParticle<R>>,
+    force_gens: Vec<SavedForceGen<R>>,
+}
+
+impl<R: Real> ParticleSnapshot<R> {
+    pub fn new(registrations: Vec<ParticleForceRegistration<R>>) -> ParticleSnapshot<R> {
+        return ParticleSnapshot {
+            registrations,
+            particles: Vec::new(),
+            force_gens: Vec::new()
+        };
+    }
+
+    /// Saves a copy of the particle, particles already in the snapshot are skipped
+    pub fn add_particle(&mut self, particle: &Rc<RefCell<Particle<R>>>) {
+        if self.particles.iter().any(|saved| Rc::ptr_eq(&saved.particle, particle)) { return; }
+        self.particles.push(SavedParticle {
+            particle: Rc::clone(particle),
+            state: particle.borrow().clone()
+        });
+    }
+
+    /// Saves the generator's state, generators already in the snapshot are skipped
+    pub fn add_force_gen(&mut self, force_gen: &Rc<RefCell<dyn ParticleForceGenerator<R>>>) {
+        if self.force_gens.iter().any(|saved| Rc::ptr_eq(&saved.force_gen, force_gen)) { return; }
+        self.force_gens.push(SavedForceGen {
+            force_gen: Rc::clone(force_gen),
+            state: force_gen.borrow().save_state()
+        });
+    }
+
+    pub fn get_registrations(&self) -> &[ParticleForceRegistration<R>] {
+        return &self.registrations;
+    }
+
+    /// Returns the saved copy of the particle, if it is in the snapshot
+    pub fn get_particle(&self, particle: &Rc<RefCell<Particle<R>>>) -> Option<&Particle<R>> {
+        return self.particles.iter()
+            .find(|saved| Rc::ptr_eq(&saved.particle, particle))
+            .map(|saved| &saved.state);
+    }
+
+    /// The particles saved, in the order they were added
+    pub fn get_particles(&self) -> impl Iterator<Item = &Rc<RefCell<Particle<R>>>> {
+        return self.particles.iter().map(|saved| &saved.particle);
+    }
+
+    /// Returns the number of particles saved
+    pub fn particle_count(&self) -> usize {
+        return self.particles.len();
+    }
+
+    /// Writes the saved copies back into the particles
+    pub fn restore_particles(&self) {
+        for saved in self.particles.iter() {
+            *saved.particle.borrow_mut() = saved.state.clone();
+        }
+    }
+
+    /// Hands each generator back the state it saved
+    pub fn restore_force_gens(&self) {
+        for saved in self.force_gens.iter() {
+            if let Some(state) = saved.state.as_ref() {
+                saved.force_gen.borrow_mut().restore_state(state.as_ref());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Vector3,
+        particle::ParticleIntegrator,
+        particle_force_gen::{ParticleDrag, ParticleForceRegistry, ParticleGravity, ParticleSpring},
+        particle_force_wrappers::{ParticleForceDuration, ParticleForceEnvelope},
+        state_hash::hash_particles,
+    };
+
+    fn shared_particle(position: Vector3, mass: f32) -> Rc<RefCell<Particle>> {
+        return Rc::new(RefCell::new(Particle::new(position, Vector3::default(), Vector3::default(), 0.99, mass)));
+    }
+
+    fn step(registry: &mut ParticleForceRegistry, particles: &[Rc<RefCell<Particle>>]) {
+        registry.update_forces(0.01);
+        for particle in particles.iter() {
+            particle.borrow_mut().integrate_with(ParticleIntegrator::SemiImplicitEuler, 0.01);
+        }
+    }
+
+    #[test]
+    fn restored_runs_repeat_bit_for_bit() {
+        let anchor = shared_particle(Vector3::new(0.0, 5.0, 0.0), 2.0);
+        let bob = shared_particle(Vector3::new(1.0, 3.0, 0.5), 0.5);
+        let particles: Vec<Rc<RefCell<Particle>>> = vec![Rc::clone(&anchor), Rc::clone(&bob)];
+
+        let gravity: Rc<RefCell<dyn ParticleForceGenerator>> =
+            Rc::new(RefCell::new(ParticleGravity::new(&Vector3::new(0.0, -9.81, 0.0))));
+        let spring: Rc<RefCell<dyn ParticleForceGenerator>> =
+            Rc::new(RefCell::new(ParticleSpring::new(Rc::clone(&anchor), 40.0, 1.0)));
+        // Timed generators, so the snapshot has to put their clocks back too. The gust expires
+        // between the snapshot and the end of the run, which drops its registration.
+        let gust: Rc<RefCell<dyn ParticleForceGenerator>> = Rc::new(RefCell::new(ParticleForceDuration::new(
+            |p: &mut Particle, _: f32| p.add_force(Vector3::new(3.0, 0.0, 0.0)),
+            0.8
+        )));
+        let drag: Rc<RefCell<dyn ParticleForceGenerator>> =
+            Rc::new(RefCell::new(ParticleForceEnvelope::new(ParticleDrag::new(0.3, 0.1), 0.5, 0.5, 0.5)));
+
+        let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+        registry.add(&anchor, &gravity);
+        registry.add(&bob, &gravity);
+        registry.add(&bob, &spring);
+        registry.add(&bob, &gust);
+        registry.add(&bob, &drag);
+
+        for _ in 0..50 {
+            step(&mut registry, &particles);
+        }
+        let snapshot: ParticleSnapshot = registry.snapshot(&particles);
+        let run = |registry: &mut ParticleForceRegistry| -> Vec<u64> {
+            let mut hashes: Vec<u64> = Vec::new();
+            for _ in 0..100 {
+                step(registry, &particles);
+                hashes.push(hash_particles(&particles));
+            }
+            return hashes;
+        };
+
+        let first: Vec<u64> = run(&mut registry);
+        assert_eq!(registry.len(), 4);
+        registry.restore(&snapshot);
+        assert_eq!(registry.len(), 5);
+        let second: Vec<u64> = run(&mut registry);
+        assert_eq!(first, second);
+    }
+}
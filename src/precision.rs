@@ -0,0 +1,361 @@
+//! The math functions the engine uses, in one place so their implementation can be swapped.
+//!
+//! Addition, subtraction, multiplication, division and square root are exactly rounded by
+//! IEEE 754, so they give the same bits on every platform Rust targets with SSE2 or better.
+//! The transcendental functions (`powf`, `sin`, `cos`, `exp`, `ln`, `atan`) come from the
+//! platform's maths library and can differ in the last bit between machines, so the engine calls
+//! them through the `real_` functions here rather than directly.
+//!
+//! With the `deterministic` feature enabled these are replaced by the implementations below,
+//! which only use exactly rounded operations, so a simulation gives the same bits everywhere.
+//! They are computed in `f64` and rounded once, and are accurate to about one unit in the
+//! last place of an `f32`.
+//!
+//! The `Real` trait is the scalar type `Vector3`, `Particle` and the core force generators are
+//! built on. It defaults to `f32` everywhere, and is also implemented for `f64` and for the
+//! fixed-point types in `fixed.rs`.
+
+use std::{
+    f64::consts::{FRAC_PI_2, FRAC_PI_4, LN_2, LOG2_E, PI},
+    fmt::Debug,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
+};
+
+/// The scalar operations the engine needs, so the simulation can run on `f32`, `f64`
+/// or fixed-point numbers.
+pub trait Real:
+    Copy + Debug + PartialEq + PartialOrd + Default + 'static
+    + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self> + Neg<Output = Self>
+    + AddAssign + SubAssign + MulAssign + DivAssign
+{
+    fn zero() -> Self;
+    fn one() -> Self;
+    /// Converts from an `f32`, rounding to the nearest representable value
+    fn from_f32(value: f32) -> Self;
+    fn to_f32(self) -> f32;
+    /// The largest representable value, stands in for infinity in types that do not have it
+    fn max_value() -> Self;
+    /// Returns false for NaN and infinities, always true for fixed-point types
+    fn is_finite(self) -> bool;
+    /// The raw bits of the value, used for hashing state
+    fn to_bits_u64(self) -> u64;
+    fn sqrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn exp(self) -> Self;
+    fn pow(self, exponent: Self) -> Self;
+}
+
+impl Real for f32 {
+    fn zero() -> Self { return 0.0; }
+    fn one() -> Self { return 1.0; }
+    fn from_f32(value: f32) -> Self { return value; }
+    fn to_f32(self) -> f32 { return self; }
+    fn max_value() -> Self { return f32::INFINITY; }
+    fn is_finite(self) -> bool { return f32::is_finite(self); }
+    fn to_bits_u64(self) -> u64 { return self.to_bits() as u64; }
+    fn sqrt(self) -> Self { return real_sqrt(self); }
+    fn abs(self) -> Self { return real_abs(self); }
+    fn sin(self) -> Self { return real_sin(self); }
+    fn cos(self) -> Self { return real_cos(self); }
+    fn exp(self) -> Self { return real_exp(self); }
+    fn pow(self, exponent: Self) -> Self { return real_pow(self, exponent); }
+}
+
+impl Real for f64 {
+    fn zero() -> Self { return 0.0; }
+    fn one() -> Self { return 1.0; }
+    fn from_f32(value: f32) -> Self { return value as f64; }
+    fn to_f32(self) -> f32 { return self as f32; }
+    fn max_value() -> Self { return f64::INFINITY; }
+    fn is_finite(self) -> bool { return f64::is_finite(self); }
+    fn to_bits_u64(self) -> u64 { return self.to_bits(); }
+    fn sqrt(self) -> Self { return f64::sqrt(self); }
+    fn abs(self) -> Self { return f64::abs(self); }
+    #[cfg(not(feature = "deterministic"))]
+    fn sin(self) -> Self { return f64::sin(self); }
+    #[cfg(not(feature = "deterministic"))]
+    fn cos(self) -> Self { return f64::cos(self); }
+    #[cfg(not(feature = "deterministic"))]
+    fn exp(self) -> Self { return f64::exp(self); }
+    #[cfg(not(feature = "deterministic"))]
+    fn pow(self, exponent: Self) -> Self { return self.powf(exponent); }
+    #[cfg(feature = "deterministic")]
+    fn sin(self) -> Self { return deterministic_sin(self); }
+    #[cfg(feature = "deterministic")]
+    fn cos(self) -> Self { return deterministic_cos(self); }
+    #[cfg(feature = "deterministic")]
+    fn exp(self) -> Self { return deterministic_exp(self); }
+    #[cfg(feature = "deterministic")]
+    fn pow(self, exponent: Self) -> Self { return deterministic_pow(self, exponent); }
+}
+
+/// Returns the square root. Exactly rounded on every platform, so shared by both modes.
+pub fn real_sqrt(value: f32) -> f32 {
+    return value.sqrt();
+}
+
+pub fn real_abs(value: f32) -> f32 {
+    return value.abs();
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn real_pow(base: f32, exponent: f32) -> f32 {
+    return base.powf(exponent);
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn real_exp(value: f32) -> f32 {
+    return value.exp();
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn real_sin(value: f32) -> f32 {
+    return value.sin();
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn real_cos(value: f32) -> f32 {
+    return value.cos();
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn real_ln(value: f32) -> f32 {
+    return value.ln();
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn real_atan(value: f32) -> f32 {
+    return value.atan();
+}
+
+#[cfg(not(feature = "deterministic"))]
+pub fn real_atan2(y: f32, x: f32) -> f32 {
+    return y.atan2(x);
+}
+
+#[cfg(feature = "deterministic")]
+pub fn real_pow(base: f32, exponent: f32) -> f32 {
+    return deterministic_pow(base as f64, exponent as f64) as f32;
+}
+
+#[cfg(feature = "deterministic")]
+pub fn real_exp(value: f32) -> f32 {
+    return deterministic_exp(value as f64) as f32;
+}
+
+#[cfg(feature = "deterministic")]
+pub fn real_sin(value: f32) -> f32 {
+    return deterministic_sin(value as f64) as f32;
+}
+
+#[cfg(feature = "deterministic")]
+pub fn real_cos(value: f32) -> f32 {
+    return deterministic_cos(value as f64) as f32;
+}
+
+#[cfg(feature = "deterministic")]
+pub fn real_ln(value: f32) -> f32 {
+    return deterministic_ln(value as f64) as f32;
+}
+
+#[cfg(feature = "deterministic")]
+pub fn real_atan(value: f32) -> f32 {
+    return deterministic_atan(value as f64) as f32;
+}
+
+#[cfg(feature = "deterministic")]
+pub fn real_atan2(y: f32, x: f32) -> f32 {
+    return deterministic_atan2(y as f64, x as f64) as f32;
+}
+
+/// The cube root, for spreading samples evenly through a volume
+pub fn real_cbrt(value: f32) -> f32 {
+    return real_pow(value, 1.0 / 3.0);
+}
+
+/// e^x by reducing to `x = k ln2 + r` with `|r| <= ln2 / 2`, then a Taylor series for e^r.
+pub fn deterministic_exp(value: f64) -> f64 {
+    if value.is_nan() { return value; }
+    // Past these the result overflows, or would be subnormal, which we flush to zero
+    if value > 709.0 { return f64::INFINITY; }
+    if value < -708.0 { return 0.0; }
+
+    let k: f64 = (value * LOG2_E + 0.5).floor();
+    let r: f64 = value - k * LN_2;
+
+    // Horner form of 1 + r + r^2/2! + ... + r^13/13!
+    let mut sum: f64 = 1.0;
+    for n in (1..=13).rev() {
+        sum = 1.0 + sum * r / n as f64;
+    }
+    return sum * f64::from_bits(((k as i64 + 1023) as u64) << 52);
+}
+
+/// ln(x) by splitting `x = m 2^e` with `m` in [sqrt(1/2), sqrt(2)), then
+/// `ln(m) = 2 atanh(s)` where `s = (m - 1) / (m + 1)`.
+pub fn deterministic_ln(value: f64) -> f64 {
+    if value.is_nan() || value < 0.0 { return f64::NAN; }
+    if value == 0.0 { return f64::NEG_INFINITY; }
+    if value.is_infinite() { return value; }
+
+    // Scale subnormals into the normal range first
+    let (mut mantissa, mut exponent): (f64, i64) = (value, 0);
+    if mantissa < f64::MIN_POSITIVE {
+        mantissa *= f64::from_bits((1023u64 + 54) << 52);
+        exponent -= 54;
+    }
+    let bits: u64 = mantissa.to_bits();
+    exponent += ((bits >> 52) & 0x7ff) as i64 - 1023;
+    mantissa = f64::from_bits((bits & 0x000f_ffff_ffff_ffff) | (1023u64 << 52));
+    if mantissa >= std::f64::consts::SQRT_2 {
+        mantissa *= 0.5;
+        exponent += 1;
+    }
+
+    let s: f64 = (mantissa - 1.0) / (mantissa + 1.0);
+    let s2: f64 = s * s;
+    // 2(s + s^3/3 + s^5/5 + ... + s^21/21), |s| < 0.172
+    let mut sum: f64 = 0.0;
+    for n in (0..=10).rev() {
+        sum = 1.0 / (2 * n + 1) as f64 + sum * s2;
+    }
+    return 2.0 * s * sum + exponent as f64 * LN_2;
+}
+
+/// x^y as e^(y ln x), with the usual special cases for zero and negative bases.
+pub fn deterministic_pow(base: f64, exponent: f64) -> f64 {
+    if exponent == 0.0 { return 1.0; }
+    if base == 1.0 { return 1.0; }
+    if base == 0.0 {
+        return if exponent > 0.0 { 0.0 } else { f64::INFINITY };
+    }
+    if base < 0.0 {
+        // Only integer powers of negative numbers are real
+        if exponent.floor() != exponent { return f64::NAN; }
+        let magnitude: f64 = deterministic_exp(exponent * deterministic_ln(-base));
+        return if exponent % 2.0 == 0.0 { magnitude } else { -magnitude };
+    }
+    return deterministic_exp(exponent * deterministic_ln(base));
+}
+
+/// Reduces the angle to `r` in [-PI/4, PI/4] and the quadrant it came from.
+fn reduce_angle(value: f64) -> (f64, i64) {
+    let quadrant: f64 = (value / FRAC_PI_2 + 0.5).floor();
+    let r: f64 = value - quadrant * FRAC_PI_2;
+    return (r, (quadrant as i64).rem_euclid(4));
+}
+
+/// Taylor series for sin(r), |r| <= PI/4
+fn sin_series(r: f64) -> f64 {
+    let r2: f64 = r * r;
+    let mut sum: f64 = 1.0;
+    for n in (1..=7).rev() {
+        sum = 1.0 - sum * r2 / ((2 * n) * (2 * n + 1)) as f64;
+    }
+    return r * sum;
+}
+
+/// Taylor series for cos(r), |r| <= PI/4
+fn cos_series(r: f64) -> f64 {
+    let r2: f64 = r * r;
+    let mut sum: f64 = 1.0;
+    for n in (1..=7).rev() {
+        sum = 1.0 - sum * r2 / ((2 * n - 1) * (2 * n)) as f64;
+    }
+    return sum;
+}
+
+pub fn deterministic_sin(value: f64) -> f64 {
+    if !value.is_finite() { return f64::NAN; }
+    let (r, quadrant) = reduce_angle(value);
+    return match quadrant {
+        0 => sin_series(r),
+        1 => cos_series(r),
+        2 => -sin_series(r),
+        _ => -cos_series(r),
+    };
+}
+
+pub fn deterministic_cos(value: f64) -> f64 {
+    if !value.is_finite() { return f64::NAN; }
+    let (r, quadrant) = reduce_angle(value);
+    return match quadrant {
+        0 => cos_series(r),
+        1 => -sin_series(r),
+        2 => -cos_series(r),
+        _ => sin_series(r),
+    };
+}
+
+/// atan(x) by reducing to |x| <= 1 with `atan(x) = PI/2 - atan(1/x)`, then halving the angle
+/// twice with `atan(x) = 2 atan(x / (1 + sqrt(1 + x^2)))` so a short Taylor series converges.
+pub fn deterministic_atan(value: f64) -> f64 {
+    if value.is_nan() { return value; }
+    if value.abs() > 1.0 { return FRAC_PI_2.copysign(value) - deterministic_atan(1.0 / value); }
+
+    let mut x: f64 = value;
+    for _ in 0..2 {
+        x /= 1.0 + (1.0 + x * x).sqrt();
+    }
+    // x - x^3/3 + x^5/5 - ... - x^25/25, |x| <= tan(PI/16) < 0.2
+    let x2: f64 = x * x;
+    let mut sum: f64 = 0.0;
+    for n in (0..=12).rev() {
+        let term: f64 = 1.0 / (2 * n + 1) as f64;
+        sum = if n % 2 == 0 { term } else { -term } + sum * x2;
+    }
+    return 4.0 * x * sum;
+}
+
+/// The angle of the point (x, y) from the x axis, in (-PI, PI].
+pub fn deterministic_atan2(y: f64, x: f64) -> f64 {
+    if y.is_nan() || x.is_nan() { return f64::NAN; }
+    if y.is_infinite() && x.is_infinite() {
+        let quarter: f64 = if x > 0.0 { FRAC_PI_4 } else { 3.0 * FRAC_PI_4 };
+        return quarter.copysign(y);
+    }
+    if x == 0.0 {
+        if y == 0.0 {
+            return if x.is_sign_negative() { PI.copysign(y) } else { y };
+        }
+        return FRAC_PI_2.copysign(y);
+    }
+    let angle: f64 = deterministic_atan(y / x);
+    if x > 0.0 { return angle; }
+    return angle + PI.copysign(y);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(found: f64, expected: f64, what: &str) {
+        assert!((found - expected).abs() <= 1.0e-14 * expected.abs().max(1.0), "{}: {} expected {}", what, found, expected);
+    }
+
+    #[test]
+    fn deterministic_functions_match_the_platform() {
+        for i in -400..=400 {
+            let x: f64 = i as f64 * 0.0371;
+            assert_close(deterministic_sin(x), x.sin(), "sin");
+            assert_close(deterministic_cos(x), x.cos(), "cos");
+            assert_close(deterministic_atan(x * 7.0), (x * 7.0).atan(), "atan");
+            assert_close(deterministic_exp(x), x.exp(), "exp");
+            if x > 0.0 {
+                assert_close(deterministic_ln(x), x.ln(), "ln");
+                assert_close(deterministic_pow(x, 1.0 / 3.0), x.cbrt(), "cbrt");
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_atan2_covers_every_quadrant() {
+        for (y, x) in [(1.0, 2.0), (1.0, -2.0), (-1.0, -2.0), (-1.0, 2.0), (3.0, 0.0), (-3.0, 0.0),
+            (0.0, 1.0), (0.0, -1.0), (-0.0, -1.0), (0.0, 0.0), (f64::INFINITY, -f64::INFINITY)] {
+            assert_close(deterministic_atan2(y, x), f64::atan2(y, x), &format!("atan2({}, {})", y, x));
+        }
+        assert!(deterministic_atan2(f64::NAN, 1.0).is_nan());
+    }
+}
@@ -0,0 +1,454 @@
+use std::{cell::RefCell, f32::consts::FRAC_PI_2, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::{Particle, ParticleIntegrator},
+    particle_force_gen::{ParticleDrag, ParticleForceGenerator},
+    precision::{real_atan, real_atan2, real_cos, real_sin},
+};
+
+/// The shots of the ballistics demo in Millington's Game Physics Engine Development, each fired
+/// along z. The masses and gravities are picked to look right on screen rather than to be real.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectilePreset {
+    /// 2 kg at 35 m/s with light gravity
+    Pistol,
+    /// 200 kg lobbed at 50 m/s under heavy gravity
+    Artillery,
+    /// A slow shot that floats upwards
+    Fireball,
+    /// Fast, light and unaffected by gravity, as a bolt rather than a beam
+    Laser,
+}
+
+impl ProjectilePreset {
+    pub fn get_settings(&self) -> ProjectileSettings {
+        let (mass, velocity, acceleration, damping) = match self {
+            ProjectilePreset::Pistol => (2.0, Vector3::new(0.0, 0.0, 35.0), Vector3::new(0.0, -1.0, 0.0), 0.99),
+            ProjectilePreset::Artillery => (200.0, Vector3::new(0.0, 30.0, 40.0), Vector3::new(0.0, -20.0, 0.0), 0.99),
+            ProjectilePreset::Fireball => (1.0, Vector3::new(0.0, 0.0, 10.0), Vector3::new(0.0, 0.6, 0.0), 0.9),
+            ProjectilePreset::Laser => (0.1, Vector3::new(0.0, 0.0, 100.0), Vector3::default(), 0.99)
+        };
+        return ProjectileSettings {
+            mass,
+            velocity,
+            acceleration,
+            damping,
+            ..Default::default()
+        };
+    }
+}
+
+/// How a projectile is fired and what acts on it in flight. Up is y, as in the demo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectileSettings {
+    pub mass: f32,
+    /// The launch velocity
+    pub velocity: Vector3,
+    /// Gravity, or whatever constant acceleration the shot is under
+    pub acceleration: Vector3,
+    /// As `Particle::damping`
+    pub damping: f32,
+    /// The quadratic drag coefficient given to a `ParticleDrag`, zero for none
+    pub drag: f32,
+    /// Seconds before a shot that has not landed is given up on
+    pub lifetime: f32,
+}
+
+impl Default for ProjectileSettings {
+    /// The pistol
+    fn default() -> Self {
+        return Self {
+            mass: 2.0,
+            velocity: Vector3::new(0.0, 0.0, 35.0),
+            acceleration: Vector3::new(0.0, -1.0, 0.0),
+            damping: 0.99,
+            drag: 0.0,
+            lifetime: 5.0
+        };
+    }
+}
+
+/// Where and when a projectile came down.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectileImpact {
+    pub position: Vector3,
+    pub velocity: Vector3,
+    /// Seconds from launch
+    pub time: f32,
+}
+
+/// The flight a projectile will take, found by flying it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectilePrediction {
+    /// The highest point reached
+    pub apex: Vector3,
+    pub apex_time: f32,
+    /// `None` if the shot is still in the air when its lifetime runs out
+    pub impact: Option<ProjectileImpact>,
+}
+
+/// Which of the two launch angles that reach a target to aim with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectileArc {
+    /// The flatter, quicker shot
+    Low,
+    /// The lob, which comes down more steeply
+    High,
+}
+
+/// A way to fire at a target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProjectileAim {
+    /// Radians above the horizontal
+    pub angle: f32,
+    /// The launch velocity, at the settings' speed and pointing at the target across the ground
+    pub velocity: Vector3,
+}
+
+impl ProjectileSettings {
+    /// Flies the shot from the position in steps of the duration until it falls below the ground
+    /// height or its lifetime is up, reporting its highest point and where it landed.
+    /// The flight matches a `Projectile` stepped by the same duration.
+    pub fn predict(&self, position: Vector3, ground: f32, duration: f32) -> ProjectilePrediction {
+        let mut apex: Vector3 = position;
+        let mut apex_time: f32 = 0.0;
+        let mut impact: Option<ProjectileImpact> = None;
+        self.fly(position, self.velocity, duration, |before, particle, time| {
+            if particle.position.y > apex.y {
+                apex = particle.position;
+                apex_time = time;
+            }
+            impact = crossing(before, particle, ground, time, duration);
+            return impact.is_none();
+        });
+        return ProjectilePrediction { apex, apex_time, impact };
+    }
+
+    /// Finds the angle to launch at, at the speed of the settings' velocity, to pass through the
+    /// target from the position. The shot is flown with drag and damping in steps of the
+    /// duration, the angle found by a sweep then bisection, so it works wherever the simple
+    /// formula of `solve_launch_angle` does not. `None` if the target is out of reach or
+    /// straight above or below.
+    pub fn aim(&self, position: Vector3, target: Vector3, arc: ProjectileArc, duration: f32) -> Option<ProjectileAim> {
+        let mut across: Vector3 = target - &position;
+        across.y = 0.0;
+        let distance: f32 = across.magnitude();
+        if distance <= 0.0 { return None; }
+        across *= 1.0 / distance;
+        let speed: f32 = self.velocity.magnitude();
+
+        // How far above the target the shot passes at the angle, a long way below if it never gets there
+        let miss = |angle: f32| -> f32 {
+            let mut velocity: Vector3 = across * (speed * real_cos(angle));
+            velocity.y = speed * real_sin(angle);
+            let mut height: f32 = f32::NEG_INFINITY;
+            self.fly(position, velocity, duration, |before, particle, _| {
+                let travelled: f32 = (particle.position - &position) * &across;
+                if travelled < distance { return true; }
+                let previous: f32 = (before.position - &position) * &across;
+                let fraction: f32 = (distance - previous) / (travelled - previous);
+                height = before.position.y + (particle.position.y - before.position.y) * fraction;
+                return false;
+            });
+            return if height.is_finite() { height - target.y } else { f32::MIN };
+        };
+
+        // Sweep up from straight down for the angles where the shot goes from passing under the
+        // target to over it, the low arc, and back under, the high arc
+        const SWEEP: usize = 180;
+        let limit: f32 = FRAC_PI_2 * 0.999;
+        let mut bracket: Option<(f32, f32)> = None;
+        let mut low: f32 = -limit;
+        let mut low_miss: f32 = miss(low);
+        for i in 1..=SWEEP {
+            let high: f32 = -limit + 2.0 * limit * i as f32 / SWEEP as f32;
+            let high_miss: f32 = miss(high);
+            let rising: bool = low_miss < 0.0 && high_miss >= 0.0;
+            let falling: bool = low_miss >= 0.0 && high_miss < 0.0;
+            if (rising && arc == ProjectileArc::Low) || falling {
+                bracket = Some((low, high));
+                if arc == ProjectileArc::Low { break; }
+            }
+            low = high;
+            low_miss = high_miss;
+        }
+        let (mut below, mut above) = bracket?;
+        if miss(below) >= 0.0 { std::mem::swap(&mut below, &mut above); }
+        for _ in 0..40 {
+            let middle: f32 = 0.5 * (below + above);
+            if miss(middle) < 0.0 { below = middle; } else { above = middle; }
+        }
+        let angle: f32 = 0.5 * (below + above);
+        let mut velocity: Vector3 = across * (speed * real_cos(angle));
+        velocity.y = speed * real_sin(angle);
+        return Some(ProjectileAim { angle, velocity });
+    }
+
+    /// Flies a copy of the shot, handing the visitor the particle before and after each step and
+    /// the time, until the visitor returns false or the lifetime is up
+    fn fly<F: FnMut(&Particle, &Particle, f32) -> bool>(&self, position: Vector3, velocity: Vector3, duration: f32, mut visit: F) {
+        assert!(duration > 0.0);
+        let mut particle: Particle = Particle::new(position, velocity, self.acceleration, self.damping, self.mass);
+        let mut drag: ParticleDrag = ParticleDrag::new(0.0, self.drag);
+        let mut time: f32 = 0.0;
+        while time < self.lifetime {
+            let before: Particle = particle.clone();
+            if self.drag > 0.0 { drag.update_force(&mut particle, duration); }
+            particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, duration);
+            time += duration;
+            if !visit(&before, &particle, time) { return; }
+        }
+    }
+}
+
+/// The impact if the step from before to after went below the ground, placed on the ground
+/// along the step
+fn crossing(before: &Particle, after: &Particle, ground: f32, time: f32, duration: f32) -> Option<ProjectileImpact> {
+    if after.position.y >= ground { return None; }
+    let drop: f32 = before.position.y - after.position.y;
+    let fraction: f32 = if drop > 0.0 { ((before.position.y - ground) / drop).clamp(0.0, 1.0) } else { 1.0 };
+    let mut position: Vector3 = before.position;
+    position.add_scaled_vector(&(after.position - &before.position), fraction);
+    return Some(ProjectileImpact {
+        position,
+        velocity: after.velocity,
+        time: time - duration * (1.0 - fraction)
+    });
+}
+
+/// The launch angle in radians above the horizontal to hit a point the distance away across the
+/// ground and the height above, at the speed under the gravity, ignoring drag and damping.
+/// `None` if the point is out of reach.
+pub fn solve_launch_angle(speed: f32, gravity: f32, distance: f32, height: f32, arc: ProjectileArc) -> Option<f32> {
+    assert!(distance > 0.0 && speed > 0.0);
+    if gravity == 0.0 { return Some(real_atan2(height, distance)); }
+    // tan a = (v^2 -+ sqrt(v^4 - g (g x^2 + 2 y v^2))) / (g x)
+    let speed_squared: f32 = speed * speed;
+    let discriminant: f32 = speed_squared * speed_squared - gravity * (gravity * distance * distance + 2.0 * height * speed_squared);
+    if discriminant < 0.0 { return None; }
+    let root: f32 = match arc {
+        ProjectileArc::Low => speed_squared - discriminant.sqrt(),
+        ProjectileArc::High => speed_squared + discriminant.sqrt()
+    };
+    return Some(real_atan(root / (gravity * distance)));
+}
+
+/// Seconds until a shot at the height above the ground going up at the vertical speed lands,
+/// under the gravity and ignoring drag and damping. Gravity pulls down when positive, and may be
+/// zero or negative for shots that float. `None` if it never comes down to the ground.
+pub fn time_of_flight(height: f32, vertical_speed: f32, gravity: f32) -> Option<f32> {
+    // height + v t - g t^2 / 2 = 0, the roots in order
+    let roots: [f32; 2] = if gravity == 0.0 {
+        if vertical_speed == 0.0 { return None; }
+        [-height / vertical_speed; 2]
+    } else {
+        // Below the ground and too slow to climb out, or above it and rising away, there is no root
+        let discriminant: f32 = vertical_speed * vertical_speed + 2.0 * gravity * height;
+        if discriminant < 0.0 { return None; }
+        let first: f32 = (vertical_speed - discriminant.sqrt()) / gravity;
+        let second: f32 = (vertical_speed + discriminant.sqrt()) / gravity;
+        if first < second { [first, second] } else { [second, first] }
+    };
+    // Landing is meeting the ground on the way down, passing it going up does not count
+    return roots.into_iter().find(|&t| t >= 0.0 && vertical_speed - gravity * t <= 0.0);
+}
+
+/// The highest point above the launch a shot going up at the vertical speed reaches under the
+/// gravity ignoring drag and damping, and the seconds it takes to get there
+pub fn apex(vertical_speed: f32, gravity: f32) -> (f32, f32) {
+    if vertical_speed <= 0.0 || gravity <= 0.0 { return (0.0, 0.0); }
+    return (vertical_speed * vertical_speed / (2.0 * gravity), vertical_speed / gravity);
+}
+
+/// A shot in flight.
+///
+/// The projectile moves its own particle with semi-implicit Euler, adding drag from a
+/// `ParticleDrag` when the settings have it, and stops moving once it lands.
+pub struct Projectile {
+    particle: Rc<RefCell<Particle>>,
+    settings: ProjectileSettings,
+    drag: ParticleDrag,
+    /// Height of the ground it lands on
+    ground: f32,
+    time: f32,
+    impact: Option<ProjectileImpact>,
+}
+
+impl Projectile {
+    /// Fires the shot from the position over ground at the height
+    pub fn new(settings: &ProjectileSettings, position: Vector3, ground: f32) -> Projectile {
+        return Projectile {
+            particle: Rc::new(RefCell::new(Particle::new(
+                position,
+                settings.velocity,
+                settings.acceleration,
+                settings.damping,
+                settings.mass
+            ))),
+            settings: *settings,
+            drag: ParticleDrag::new(0.0, settings.drag),
+            ground,
+            time: 0.0,
+            impact: None
+        };
+    }
+
+    pub fn from_preset(preset: ProjectilePreset, position: Vector3, ground: f32) -> Projectile {
+        return Projectile::new(&preset.get_settings(), position, ground);
+    }
+
+    pub fn get_particle(&self) -> &Rc<RefCell<Particle>> {
+        return &self.particle;
+    }
+
+    pub fn get_settings(&self) -> &ProjectileSettings {
+        return &self.settings;
+    }
+
+    /// Seconds since launch, not counting time after landing
+    pub fn get_time(&self) -> f32 {
+        return self.time;
+    }
+
+    pub fn get_impact(&self) -> Option<&ProjectileImpact> {
+        return self.impact.as_ref();
+    }
+
+    pub fn has_landed(&self) -> bool {
+        return self.impact.is_some();
+    }
+
+    /// True once the shot has landed or its lifetime is up
+    pub fn is_finished(&self) -> bool {
+        return self.impact.is_some() || self.time >= self.settings.lifetime;
+    }
+
+    /// Moves the shot on by the duration. Returns the impact on the step it lands, when the
+    /// particle is left on the ground where it came down.
+    pub fn step(&mut self, duration: f32) -> Option<ProjectileImpact> {
+        if self.is_finished() { return None; }
+        let mut particle = self.particle.borrow_mut();
+        let before: Particle = particle.clone();
+        if self.settings.drag > 0.0 { self.drag.update_force(&mut particle, duration); }
+        particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, duration);
+        self.time += duration;
+
+        self.impact = crossing(&before, &particle, self.ground, self.time, duration);
+        if let Some(impact) = self.impact {
+            particle.position = impact.position;
+            self.time = impact.time;
+        }
+        return self.impact;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A shot at 30 m/s under 9.81 m/s^2 with nothing slowing it
+    fn plain_settings() -> ProjectileSettings {
+        return ProjectileSettings {
+            mass: 1.0,
+            velocity: Vector3::new(0.0, 0.0, 30.0),
+            acceleration: Vector3::new(0.0, -9.81, 0.0),
+            damping: 1.0,
+            drag: 0.0,
+            lifetime: 10.0
+        };
+    }
+
+    #[test]
+    fn time_of_flight_matches_the_flight() {
+        let settings: ProjectileSettings = ProjectileSettings { damping: 1.0, ..ProjectilePreset::Artillery.get_settings() };
+        let impact: ProjectileImpact = settings.predict(Vector3::default(), 0.0, 0.001).impact.unwrap();
+        let expected: f32 = time_of_flight(0.0, 30.0, 20.0).unwrap();
+        assert!((expected - 3.0).abs() < 1.0e-6);
+        assert!((impact.time - expected).abs() < 0.01, "landed after {} s", impact.time);
+        assert!((impact.position.z - 40.0 * expected).abs() < 0.1);
+
+        assert_eq!(time_of_flight(10.0, 0.0, 0.0), None);
+        assert_eq!(time_of_flight(10.0, -2.0, 0.0), Some(5.0));
+        assert_eq!(apex(30.0, 20.0), (22.5, 1.5));
+    }
+
+    #[test]
+    fn shots_that_cannot_reach_the_ground_have_no_time_of_flight() {
+        // Starting 10 m below the ground going up at 5 m/s, which only climbs 1.3 m
+        assert_eq!(time_of_flight(-10.0, 5.0, 9.81), None);
+        assert!(time_of_flight(-1.0, 5.0, 9.81).is_some());
+        // Rising away from the ground with nothing to bring it back
+        assert_eq!(time_of_flight(-1.0, 5.0, 0.0), None);
+        assert_eq!(time_of_flight(-1.0, -5.0, 0.0), None);
+    }
+
+    #[test]
+    fn floating_shots_land_only_if_thrown_down_hard_enough() {
+        // The fireball accelerates upwards at 0.6 m/s^2, so its gravity is -0.6
+        let fireball: ProjectileSettings = ProjectilePreset::Fireball.get_settings();
+        let gravity: f32 = -fireball.acceleration.y;
+        assert_eq!(time_of_flight(3.0, fireball.velocity.y, gravity), None);
+        assert!(fireball.predict(Vector3::new(0.0, 3.0, 0.0), 0.0, 0.001).impact.is_none());
+
+        // Thrown down at 2 m/s from 3 m it reaches the ground before the lift turns it round
+        let thrown: ProjectileSettings = ProjectileSettings {
+            velocity: Vector3::new(0.0, -2.0, 10.0),
+            damping: 1.0,
+            ..fireball
+        };
+        let expected: f32 = time_of_flight(3.0, -2.0, gravity).unwrap();
+        assert!((expected - (2.0 - 0.4f32.sqrt()) / 0.6).abs() < 1.0e-5, "{}", expected);
+        let impact: ProjectileImpact = thrown.predict(Vector3::new(0.0, 3.0, 0.0), 0.0, 0.001).impact.unwrap();
+        assert!((impact.time - expected).abs() < 0.01, "landed after {} s", impact.time);
+
+        // At 1 m/s the lift stops it 0.83 m down, short of the ground
+        assert_eq!(time_of_flight(3.0, -1.0, gravity), None);
+        assert!(ProjectileSettings { velocity: Vector3::new(0.0, -1.0, 10.0), ..thrown }
+            .predict(Vector3::new(0.0, 3.0, 0.0), 0.0, 0.001).impact.is_none());
+    }
+
+    #[test]
+    fn launch_angles_match_the_range_formula() {
+        // On level ground sin 2a = g x / v^2
+        let (speed, gravity, distance): (f32, f32, f32) = (30.0, 9.81, 60.0);
+        let low: f32 = solve_launch_angle(speed, gravity, distance, 0.0, ProjectileArc::Low).unwrap();
+        let high: f32 = solve_launch_angle(speed, gravity, distance, 0.0, ProjectileArc::High).unwrap();
+        let expected: f32 = 0.5 * (gravity * distance / (speed * speed)).asin();
+        assert!((low - expected).abs() < 1.0e-4);
+        assert!((high - (FRAC_PI_2 - expected)).abs() < 1.0e-4);
+
+        assert_eq!(solve_launch_angle(speed, gravity, 200.0, 0.0, ProjectileArc::Low), None);
+        let flat: f32 = solve_launch_angle(speed, 0.0, 10.0, 10.0, ProjectileArc::Low).unwrap();
+        assert!((flat - FRAC_PI_2 * 0.5).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn aiming_without_drag_agrees_with_the_formula() {
+        let settings: ProjectileSettings = plain_settings();
+        let target: Vector3 = Vector3::new(0.0, 5.0, 60.0);
+        for arc in [ProjectileArc::Low, ProjectileArc::High] {
+            let aim: ProjectileAim = settings.aim(Vector3::default(), target, arc, 0.001).unwrap();
+            let expected: f32 = solve_launch_angle(30.0, 9.81, 60.0, 5.0, arc).unwrap();
+            assert!((aim.angle - expected).abs() < 0.005, "{:?} aimed at {} not {}", arc, aim.angle, expected);
+            assert!((aim.velocity.magnitude() - 30.0).abs() < 1.0e-3);
+        }
+        assert!(settings.aim(Vector3::default(), Vector3::new(0.0, 0.0, 200.0), ProjectileArc::Low, 0.001).is_none());
+        assert!(settings.aim(Vector3::default(), Vector3::new(0.0, 5.0, 0.0), ProjectileArc::Low, 0.001).is_none());
+    }
+
+    #[test]
+    fn aiming_with_drag_hits_the_target() {
+        let settings: ProjectileSettings = ProjectileSettings { drag: 0.002, ..plain_settings() };
+        let target: Vector3 = Vector3::new(20.0, 0.0, 40.0);
+        let aim: ProjectileAim = settings.aim(Vector3::default(), target, ProjectileArc::Low, 0.001).unwrap();
+        // Drag needs a steeper shot than the formula gives
+        let plain: f32 = solve_launch_angle(30.0, 9.81, target.magnitude(), 0.0, ProjectileArc::Low).unwrap();
+        assert!(aim.angle > plain);
+
+        let fired: ProjectileSettings = ProjectileSettings { velocity: aim.velocity, ..settings };
+        let impact: ProjectileImpact = fired.predict(Vector3::default(), 0.0, 0.001).impact.unwrap();
+        let miss: f32 = (impact.position - &target).magnitude();
+        assert!(miss < 0.05, "missed by {} m", miss);
+    }
+}
@@ -0,0 +1,87 @@
+use std::f32::consts::PI;
+
+use crate::{
+    core::Vector3,
+    precision::{real_cos, real_sin},
+};
+
+/// A seeded source of random numbers, the same seed always gives the same sequence so seeded
+/// effects replay exactly. The bits and reals are identical on every platform, `random_unit_vector`
+/// uses sine and cosine so it is only identical across platforms with the `deterministic` feature.
+/// Uses xorshift64*, which is plenty for scattering particles but not for anything that needs
+/// to be unpredictable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        let mut random: Random = Random { state: 0 };
+        random.seed(seed);
+        return random;
+    }
+
+    /// Restarts the sequence from the seed
+    pub fn seed(&mut self, seed: u64) {
+        // Spread the seed's bits so small seeds do not start with a run of small numbers,
+        // and keep the state off zero, which xorshift never leaves
+        let mut mixed: u64 = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        mixed ^= mixed >> 31;
+        self.state = if mixed == 0 { 0x9E37_79B9_7F4A_7C15 } else { mixed };
+    }
+
+    /// 32 random bits
+    pub fn random_bits(&mut self) -> u32 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        return (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32;
+    }
+
+    /// A number in [0, 1)
+    pub fn random_real(&mut self) -> f32 {
+        // 24 bits fill an f32 mantissa exactly, so the result never rounds up to 1
+        return (self.random_bits() >> 8) as f32 / (1u32 << 24) as f32;
+    }
+
+    /// A number in [min, max)
+    pub fn random_real_range(&mut self, min: f32, max: f32) -> f32 {
+        return min + (max - min) * self.random_real();
+    }
+
+    /// A whole number in [0, max)
+    pub fn random_int(&mut self, max: u32) -> u32 {
+        assert!(max > 0);
+        return ((self.random_bits() as u64 * max as u64) >> 32) as u32;
+    }
+
+    /// A number in (-scale, scale) more likely to be near zero
+    pub fn random_binomial(&mut self, scale: f32) -> f32 {
+        return (self.random_real() - self.random_real()) * scale;
+    }
+
+    /// A vector with each component a binomial of the scale
+    pub fn random_vector(&mut self, scale: f32) -> Vector3 {
+        return Vector3::new(self.random_binomial(scale), self.random_binomial(scale), self.random_binomial(scale));
+    }
+
+    /// A vector with each component uniform between those of min and max
+    pub fn random_vector_range(&mut self, min: &Vector3, max: &Vector3) -> Vector3 {
+        return Vector3::new(
+            self.random_real_range(min.x, max.x),
+            self.random_real_range(min.y, max.y),
+            self.random_real_range(min.z, max.z)
+        );
+    }
+
+    /// A direction uniformly spread over the sphere
+    pub fn random_unit_vector(&mut self) -> Vector3 {
+        let z: f32 = self.random_real_range(-1.0, 1.0);
+        let angle: f32 = self.random_real_range(0.0, 2.0 * PI);
+        let across: f32 = (1.0 - z * z).max(0.0).sqrt();
+        return Vector3::new(across * real_cos(angle), across * real_sin(angle), z);
+    }
+}
@@ -0,0 +1,286 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::Particle,
+    particle_implicit_spring::ParticleSpringNetwork,
+    xpbd::{XpbdDistanceConstraint, XpbdSolver},
+};
+
+/// How a `Rope` is built, whatever links it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RopeSettings {
+    /// Particles along the rope, including both ends
+    pub particles: usize,
+    /// The mass of each segment, split between the particles at its ends
+    pub segment_mass: f32,
+    /// The damping given to every particle, as `Particle::damping`
+    pub damping: f32,
+    /// Stiffness of the links between particles two apart, which resist folding.
+    /// Zero leaves the rope free to fold.
+    pub bend_stiffness: f32,
+    /// Anchored ends have infinite mass and stay where they are put
+    pub anchor_start: bool,
+    pub anchor_end: bool,
+}
+
+impl Default for RopeSettings {
+    fn default() -> Self {
+        return Self {
+            particles: 10,
+            segment_mass: 0.1,
+            damping: 0.99,
+            bend_stiffness: 0.0,
+            anchor_start: false,
+            anchor_end: false
+        };
+    }
+}
+
+/// Handles to the particles of a rope laid in a straight line between two points.
+///
+/// The rope is stepped by whatever it was built into, a `ParticleSpringNetwork` or an `XpbdSolver`.
+/// To attach a rope to something, move an anchored end with it each step, or link the end's
+/// index to the thing's particle with a spring or constraint of its own.
+pub struct Rope {
+    particles: Vec<Rc<RefCell<Particle>>>,
+    /// Where each particle sits in the network or solver the rope was built into
+    indices: Vec<usize>,
+    /// The mass each particle has when it is not anchored
+    masses: Vec<f32>,
+    segment_length: f32,
+}
+
+impl Rope {
+    /// Lays out the particles, unlinked
+    fn lay(start: Vector3, end: Vector3, settings: &RopeSettings) -> Rope {
+        assert!(settings.particles >= 2, "a rope needs at least two particles");
+        assert!(settings.segment_mass > 0.0, "segment mass must be positive");
+
+        let segments: usize = settings.particles - 1;
+        let step: Vector3 = (end - &start) * (1.0 / segments as f32);
+        let mut rope: Rope = Rope {
+            particles: Vec::with_capacity(settings.particles),
+            indices: Vec::with_capacity(settings.particles),
+            masses: Vec::with_capacity(settings.particles),
+            segment_length: step.magnitude()
+        };
+        for i in 0..settings.particles {
+            let mut position: Vector3 = start;
+            position.add_scaled_vector(&step, i as f32);
+            // The ends only carry half a segment each
+            let mass: f32 = if i == 0 || i == segments { 0.5 * settings.segment_mass } else { settings.segment_mass };
+            rope.particles.push(Rc::new(RefCell::new(Particle::new(
+                position,
+                Vector3::default(),
+                Vector3::default(),
+                settings.damping,
+                mass
+            ))));
+            rope.masses.push(mass);
+        }
+        if settings.anchor_start { rope.anchor_start(); }
+        if settings.anchor_end { rope.anchor_end(); }
+        return rope;
+    }
+
+    /// Builds a rope whose particles are joined by damped springs in the network.
+    /// Bend springs join particles two apart when the settings give them a stiffness.
+    pub fn with_springs(
+        start: Vector3,
+        end: Vector3,
+        spring_constant: f32,
+        spring_damping: f32,
+        settings: &RopeSettings,
+        network: &mut ParticleSpringNetwork
+    ) -> Rope {
+        let mut rope: Rope = Rope::lay(start, end, settings);
+        for particle in rope.particles.iter() {
+            rope.indices.push(network.add_particle(particle));
+        }
+        for pair in rope.indices.windows(2) {
+            network.add_spring(pair[0], pair[1], spring_constant, spring_damping, rope.segment_length);
+        }
+        if settings.bend_stiffness > 0.0 {
+            for triple in rope.indices.windows(3) {
+                network.add_spring(triple[0], triple[2], settings.bend_stiffness, spring_damping, 2.0 * rope.segment_length);
+            }
+        }
+        return rope;
+    }
+
+    /// Builds a rope whose particles are held exactly a segment apart by distance constraints
+    /// in the solver. Bend constraints join particles two apart with a compliance of one over the
+    /// bend stiffness when the settings give one.
+    pub fn with_constraints(start: Vector3, end: Vector3, settings: &RopeSettings, solver: &mut XpbdSolver) -> Rope {
+        let mut rope: Rope = Rope::lay(start, end, settings);
+        for particle in rope.particles.iter() {
+            rope.indices.push(solver.add_particle(particle));
+        }
+        for pair in rope.indices.windows(2) {
+            solver.add_constraint(Box::new(XpbdDistanceConstraint::new(pair[0], pair[1], rope.segment_length, 0.0)));
+        }
+        if settings.bend_stiffness > 0.0 {
+            for triple in rope.indices.windows(3) {
+                solver.add_constraint(Box::new(XpbdDistanceConstraint::new(
+                    triple[0],
+                    triple[2],
+                    2.0 * rope.segment_length,
+                    1.0 / settings.bend_stiffness
+                )));
+            }
+        }
+        return rope;
+    }
+
+    /// The particles from the start of the rope to the end
+    pub fn get_particles(&self) -> &[Rc<RefCell<Particle>>] {
+        return &self.particles;
+    }
+
+    pub fn get_particle(&self, index: usize) -> &Rc<RefCell<Particle>> {
+        return &self.particles[index];
+    }
+
+    pub fn get_start(&self) -> &Rc<RefCell<Particle>> {
+        return &self.particles[0];
+    }
+
+    pub fn get_end(&self) -> &Rc<RefCell<Particle>> {
+        return &self.particles[self.particles.len() - 1];
+    }
+
+    /// Where each particle sits in the network or solver, for linking it to other particles there
+    pub fn get_indices(&self) -> &[usize] {
+        return &self.indices;
+    }
+
+    /// The rest length of each link
+    pub fn get_segment_length(&self) -> f32 {
+        return self.segment_length;
+    }
+
+    /// The rest length of the whole rope
+    pub fn get_length(&self) -> f32 {
+        return self.segment_length * (self.particles.len() - 1) as f32;
+    }
+
+    /// Fixes the particle in place, it only moves when moved by hand.
+    /// Anchors have infinite mass, so gravity and the conservation sums leave them out.
+    pub fn anchor(&mut self, index: usize) {
+        let mut particle = self.particles[index].borrow_mut();
+        particle.restore_mass(f32::INFINITY, 0.0);
+        particle.velocity = Vector3::default();
+    }
+
+    /// Lets an anchored particle move again, with its mass from when the rope was built
+    pub fn release(&mut self, index: usize) {
+        self.particles[index].borrow_mut().restore_mass(self.masses[index], 1.0 / self.masses[index]);
+    }
+
+    pub fn anchor_start(&mut self) {
+        self.anchor(0);
+    }
+
+    pub fn anchor_end(&mut self) {
+        self.anchor(self.particles.len() - 1);
+    }
+
+    pub fn release_start(&mut self) {
+        self.release(0);
+    }
+
+    pub fn release_end(&mut self) {
+        self.release(self.particles.len() - 1);
+    }
+
+    pub fn is_anchored(&self, index: usize) -> bool {
+        return self.particles[index].borrow().get_inverse_mass() <= 0.0;
+    }
+
+    /// Moves an end or any other particle to the position, for dragging an anchored end along
+    /// with whatever it is attached to
+    pub fn move_particle(&mut self, index: usize, position: Vector3) {
+        self.particles[index].borrow_mut().position = position;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        conservation::ConservationSample,
+        particle_force_gen::{ParticleForceGenerator, ParticleForceRegistry, ParticleGravity},
+    };
+
+    fn settings(particles: usize, bend_stiffness: f32) -> RopeSettings {
+        return RopeSettings { particles, bend_stiffness, ..RopeSettings::default() };
+    }
+
+    #[test]
+    fn ropes_have_a_link_per_segment() {
+        let (start, end): (Vector3, Vector3) = (Vector3::default(), Vector3::new(3.0, 4.0, 0.0));
+        let mut network: ParticleSpringNetwork = ParticleSpringNetwork::new(20, 1.0e-6);
+        let rope: Rope = Rope::with_springs(start, end, 100.0, 1.0, &settings(6, 0.0), &mut network);
+        assert_eq!(rope.get_particles().len(), 6);
+        assert_eq!(network.get_springs().len(), 5);
+        assert!((rope.get_segment_length() - 1.0).abs() < 1.0e-6);
+        assert!((rope.get_length() - 5.0).abs() < 1.0e-6);
+        assert!(network.get_springs().iter().all(|s| (s.rest_length - 1.0).abs() < 1.0e-6));
+        assert_eq!(rope.get_end().borrow().get_position(), end);
+
+        // Bend links join particles two apart
+        let mut solver: XpbdSolver = XpbdSolver::new(4);
+        let rope: Rope = Rope::with_constraints(start, end, &settings(6, 50.0), &mut solver);
+        assert_eq!(solver.constraint_count(), 5 + 4);
+        assert_eq!(rope.get_indices(), &[0, 1, 2, 3, 4, 5]);
+        let bend: &XpbdDistanceConstraint = solver.get_constraint_as(5).unwrap();
+        assert!((bend.get_rest_length() - 2.0).abs() < 1.0e-6);
+
+        // The ends carry half a segment each
+        let masses: Vec<f32> = rope.get_particles().iter().map(|p| p.borrow().get_mass()).collect();
+        assert_eq!(masses, vec![0.05, 0.1, 0.1, 0.1, 0.1, 0.05]);
+    }
+
+    #[test]
+    fn anchored_ends_stay_put() {
+        let start: Vector3 = Vector3::new(0.0, 5.0, 0.0);
+        let rope_settings: RopeSettings = RopeSettings { anchor_start: true, ..settings(8, 0.0) };
+        let mut solver: XpbdSolver = XpbdSolver::new(10);
+        let rope: Rope = Rope::with_constraints(start, Vector3::new(3.5, 5.0, 0.0), &rope_settings, &mut solver);
+        assert!(rope.is_anchored(0) && !rope.is_anchored(7));
+
+        let gravity: Rc<RefCell<dyn ParticleForceGenerator>> = Rc::new(RefCell::new(ParticleGravity::new(&Vector3::new(0.0, -9.81, 0.0))));
+        let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+        for particle in rope.get_particles().iter() {
+            registry.add(particle, &gravity);
+        }
+        let mut lowest: f32 = start.y;
+        for _ in 0..120 {
+            registry.update_forces(1.0 / 60.0);
+            // Gravity leaves the anchor alone
+            assert_eq!(rope.get_start().borrow().force_accum, Vector3::default());
+            solver.step(1.0 / 60.0);
+            assert_eq!(rope.get_start().borrow().get_position(), start);
+            lowest = lowest.min(rope.get_end().borrow().get_position().y);
+        }
+        assert!(lowest < start.y - 2.0, "the free end should swing down, it only reached {}", lowest);
+
+        // Only the free particles count towards the total mass
+        let sample: ConservationSample = ConservationSample::measure(rope.get_particles(), &registry, &Vector3::default());
+        assert!((sample.mass - 0.65).abs() < 1.0e-6, "the moving mass is {}", sample.mass);
+    }
+
+    #[test]
+    fn released_ends_get_their_mass_back() {
+        let mut network: ParticleSpringNetwork = ParticleSpringNetwork::new(20, 1.0e-6);
+        let rope_settings: RopeSettings = RopeSettings { anchor_end: true, ..settings(3, 0.0) };
+        let mut rope: Rope = Rope::with_springs(Vector3::default(), Vector3::new(2.0, 0.0, 0.0), 10.0, 0.0, &rope_settings, &mut network);
+        assert!(!rope.get_end().borrow().has_finite_mass());
+        rope.release_end();
+        assert!(!rope.is_anchored(2));
+        assert_eq!(rope.get_end().borrow().get_mass(), 0.05);
+        assert!(rope.get_end().borrow().has_finite_mass());
+    }
+}
+
@@ -0,0 +1,888 @@
+//! A plain text format for describing scenes, so they do not have to be built in code.
+//!
+//! A scene is a list of sections. Each `[particle <name>]` section describes one particle and each
+//! `[force <kind>]` section one force generator, registered for every particle in its `particles` list.
+//! Lines are `key = value`, where values are numbers, vectors written as three numbers, or particle
+//! names. Everything after a `#` is a comment.
+//!
+//! ```text
+//! [particle anchor]
+//! position = 0 10 0
+//! mass = inf
+//!
+//! [particle ball]
+//! position = 0 8 0
+//! damping = 0.99
+//! mass = 2
+//!
+//! [force gravity]
+//! particles = ball
+//! gravity = 0 -9.81 0
+//!
+//! [force spring]
+//! particles = ball
+//! other = anchor
+//! spring_constant = 20
+//! rest_length = 1.5
+//! ```
+//!
+//! Particle keys are `position`, `velocity`, `acceleration` (default zero), `damping` (default 1),
+//! `mass` and `charge` (default none). A mass of `inf` makes the particle immovable, every other
+//! value must be finite.
+//! The force kinds and their keys are:
+//! - `gravity`: `gravity`
+//! - `drag`: `k1`, `k2`
+//! - `spring`, `bungee`: `other`, `spring_constant`, `rest_length`, where `other` cannot be one of `particles`
+//! - `anchored_spring`: `anchor`, `spring_constant`, `rest_length`
+//! - `fake_spring`: `anchor`, `spring_constant`, `damping`
+//! - `buoyancy`: `max_depth`, `volume`, `water_height`, `liquid_density` (default 1000)
+//! - `coulomb`: `other`, as for springs, and `coulomb_constant` (default `COULOMB_CONSTANT`)
+//! - `electric_field`, `magnetic_field`: `field`
+//! - `magnetic_dipole`: `position`, `moment`, `magnetic_constant` (default `MAGNETIC_CONSTANT_OVER_4PI`)
+//!
+//! Numbers are written in the shortest form that reads back as the same `f32`,
+//! so writing a scene and loading it again gives an identical scene.
+
+use std::{
+    cell::RefCell,
+    error,
+    fmt,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{
+    core::Vector3,
+    particle::{Particle, ParticleIntegrator},
+    particle_electromagnetic::{
+        ParticleCoulomb, ParticleElectricField, ParticleMagneticDipole, ParticleMagneticField,
+        COULOMB_CONSTANT, MAGNETIC_CONSTANT_OVER_4PI,
+    },
+    particle_force_gen::{
+        ParticleAnchoredSpring, ParticleBungee, ParticleBuoyancy, ParticleDrag, ParticleFakeSpring,
+        ParticleForceGenerator, ParticleForceRegistry, ParticleGravity, ParticleSpring,
+    },
+    particle_snapshot::ParticleSnapshot,
+};
+
+/// A problem found while loading a scene, with the line it was found on counting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl SceneError {
+    fn new(line: usize, message: String) -> SceneError {
+        return SceneError {
+            line,
+            message
+        };
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, "line {}: {}", self.line, self.message);
+    }
+}
+
+impl error::Error for SceneError {}
+
+/// The description of one particle in a scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneParticle {
+    /// Used by force sections to refer to the particle
+    pub name: String,
+    pub position: Vector3,
+    pub velocity: Vector3,
+    pub acceleration: Vector3,
+    pub damping: f32,
+    pub mass: f32,
+    pub charge: Option<f32>,
+}
+
+impl SceneParticle {
+    /// Creates the particle described
+    pub fn to_particle(&self) -> Particle {
+        let mut particle: Particle = Particle::new(
+            self.position,
+            self.velocity,
+            self.acceleration,
+            self.damping,
+            self.mass
+        );
+        if let Some(charge) = self.charge {
+            particle.set_charge(charge);
+        }
+        return particle;
+    }
+}
+
+/// A force generator and its parameters, other particles are referred to by name.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SceneForceKind {
+    Gravity { gravity: Vector3 },
+    Drag { k1: f32, k2: f32 },
+    Spring { other: String, spring_constant: f32, rest_length: f32 },
+    AnchoredSpring { anchor: Vector3, spring_constant: f32, rest_length: f32 },
+    Bungee { other: String, spring_constant: f32, rest_length: f32 },
+    Buoyancy { max_depth: f32, volume: f32, water_height: f32, liquid_density: f32 },
+    FakeSpring { anchor: Vector3, spring_constant: f32, damping: f32 },
+    Coulomb { other: String, coulomb_constant: f32 },
+    ElectricField { field: Vector3 },
+    MagneticField { field: Vector3 },
+    MagneticDipole { position: Vector3, moment: Vector3, magnetic_constant: f32 },
+}
+
+impl SceneForceKind {
+    /// The name used for the kind in section headers
+    pub fn get_keyword(&self) -> &'static str {
+        return match self {
+            SceneForceKind::Gravity { .. } => "gravity",
+            SceneForceKind::Drag { .. } => "drag",
+            SceneForceKind::Spring { .. } => "spring",
+            SceneForceKind::AnchoredSpring { .. } => "anchored_spring",
+            SceneForceKind::Bungee { .. } => "bungee",
+            SceneForceKind::Buoyancy { .. } => "buoyancy",
+            SceneForceKind::FakeSpring { .. } => "fake_spring",
+            SceneForceKind::Coulomb { .. } => "coulomb",
+            SceneForceKind::ElectricField { .. } => "electric_field",
+            SceneForceKind::MagneticField { .. } => "magnetic_field",
+            SceneForceKind::MagneticDipole { .. } => "magnetic_dipole",
+        };
+    }
+}
+
+/// A force generator registered for one or more particles of a scene.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneForce {
+    /// The names of the particles the force applies to
+    pub particles: Vec<String>,
+    pub kind: SceneForceKind,
+}
+
+/// A scene as read from, or written to, the text format.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Scene {
+    pub particles: Vec<SceneParticle>,
+    pub forces: Vec<SceneForce>,
+}
+
+/// The particles and registry built from a scene.
+pub struct SceneInstance {
+    /// The particles in the order the scene lists them, spawned particles go on the end
+    pub particles: Vec<Rc<RefCell<Particle>>>,
+    pub registry: ParticleForceRegistry,
+    /// The name of each particle, in the same order
+    names: Vec<String>,
+    /// Particles taken out of the instance and their names, kept so a snapshot can bring them back
+    removed: Vec<(Rc<RefCell<Particle>>, String)>,
+}
+
+impl Default for SceneInstance {
+    fn default() -> Self {
+        return Self {
+            particles: Vec::new(),
+            registry: ParticleForceRegistry::new(),
+            names: Vec::new(),
+            removed: Vec::new()
+        };
+    }
+}
+
+impl SceneInstance {
+    pub fn new() -> SceneInstance {
+        return SceneInstance::default();
+    }
+
+    /// Returns the particle with the given name
+    pub fn get_particle(&self, name: &str) -> Option<&Rc<RefCell<Particle>>> {
+        let index: usize = self.names.iter().position(|n| n == name)?;
+        return Some(&self.particles[index]);
+    }
+
+    pub fn get_names(&self) -> &[String] {
+        return &self.names;
+    }
+
+    /// Creates the particle and adds it to the end, returns `None` if the name is taken
+    pub fn add_particle(&mut self, particle: &SceneParticle) -> Option<Rc<RefCell<Particle>>> {
+        if self.get_particle(&particle.name).is_some() { return None; }
+        let created: Rc<RefCell<Particle>> = Rc::new(RefCell::new(particle.to_particle()));
+        self.particles.push(Rc::clone(&created));
+        self.names.push(particle.name.clone());
+        return Some(created);
+    }
+
+    /// Removes the particle and all of its registrations, returns `None` if there is no such particle.
+    /// Springs that use it as their other end keep hold of it.
+    pub fn remove_particle(&mut self, name: &str) -> Option<Rc<RefCell<Particle>>> {
+        let index: usize = self.names.iter().position(|n| n == name)?;
+        let name: String = self.names.remove(index);
+        let removed: Rc<RefCell<Particle>> = self.particles.remove(index);
+        self.registry.remove_particle(&removed);
+        self.removed.push((Rc::clone(&removed), name));
+        return Some(removed);
+    }
+
+    /// Saves the particles, generators and registrations, see `ParticleForceRegistry::snapshot`
+    pub fn snapshot(&self) -> ParticleSnapshot {
+        return self.registry.snapshot(&self.particles);
+    }
+
+    /// Puts the instance back as it was when the snapshot was taken. Particles spawned since are
+    /// taken out, and particles removed since come back under their names in their old places.
+    pub fn restore(&mut self, snapshot: &ParticleSnapshot) {
+        let mut known: Vec<(Rc<RefCell<Particle>>, String)> = self.particles.drain(..).zip(self.names.drain(..)).collect();
+        known.append(&mut self.removed);
+        for particle in snapshot.get_particles() {
+            let Some(index) = known.iter().position(|(p, _)| Rc::ptr_eq(p, particle)) else { continue; };
+            let (particle, name) = known.remove(index);
+            self.particles.push(particle);
+            self.names.push(name);
+        }
+        self.removed = known;
+        self.registry.restore(snapshot);
+    }
+
+    /// Creates the generator and registers it for each of the force's particles.
+    /// Returns false, and changes nothing, if the force names a particle that does not exist.
+    pub fn add_force(&mut self, force: &SceneForce) -> bool {
+        let other: Option<&String> = match &force.kind {
+            SceneForceKind::Spring { other, .. }
+            | SceneForceKind::Bungee { other, .. }
+            | SceneForceKind::Coulomb { other, .. } => Some(other),
+            _ => None,
+        };
+        let names_exist: bool = force.particles.iter().chain(other).all(|name| self.get_particle(name).is_some());
+        if !names_exist { return false; }
+        let find = |name: &str| -> Rc<RefCell<Particle>> {
+            return Rc::clone(self.get_particle(name).expect("checked above"));
+        };
+
+        let force_gen: Rc<RefCell<dyn ParticleForceGenerator>> = match &force.kind {
+            SceneForceKind::Gravity { gravity } => {
+                Rc::new(RefCell::new(ParticleGravity::new(gravity)))
+            }
+            SceneForceKind::Drag { k1, k2 } => {
+                Rc::new(RefCell::new(ParticleDrag::new(*k1, *k2)))
+            }
+            SceneForceKind::Spring { other, spring_constant, rest_length } => {
+                Rc::new(RefCell::new(ParticleSpring::new(find(other), *spring_constant, *rest_length)))
+            }
+            SceneForceKind::AnchoredSpring { anchor, spring_constant, rest_length } => {
+                Rc::new(RefCell::new(ParticleAnchoredSpring::new(*anchor, *spring_constant, *rest_length)))
+            }
+            SceneForceKind::Bungee { other, spring_constant, rest_length } => {
+                Rc::new(RefCell::new(ParticleBungee::new(find(other), *spring_constant, *rest_length)))
+            }
+            SceneForceKind::Buoyancy { max_depth, volume, water_height, liquid_density } => {
+                Rc::new(RefCell::new(ParticleBuoyancy::new(*max_depth, *volume, *water_height, *liquid_density)))
+            }
+            SceneForceKind::FakeSpring { anchor, spring_constant, damping } => {
+                Rc::new(RefCell::new(ParticleFakeSpring::new(*anchor, *spring_constant, *damping)))
+            }
+            SceneForceKind::Coulomb { other, coulomb_constant } => {
+                Rc::new(RefCell::new(ParticleCoulomb::new(find(other), *coulomb_constant)))
+            }
+            SceneForceKind::ElectricField { field } => {
+                Rc::new(RefCell::new(ParticleElectricField::new(*field)))
+            }
+            SceneForceKind::MagneticField { field } => {
+                Rc::new(RefCell::new(ParticleMagneticField::new(*field)))
+            }
+            SceneForceKind::MagneticDipole { position, moment, magnetic_constant } => {
+                Rc::new(RefCell::new(ParticleMagneticDipole::new(*position, *moment, *magnetic_constant)))
+            }
+        };
+        let particles: Vec<Rc<RefCell<Particle>>> = force.particles.iter().map(|name| find(name)).collect();
+        for particle in particles.iter() {
+            self.registry.add(particle, &force_gen);
+        }
+        return true;
+    }
+
+    /// Updates the forces then integrates every particle
+    pub fn step(&mut self, duration: f32) {
+        self.step_with(ParticleIntegrator::Euler, duration);
+    }
+
+    /// Updates the forces then integrates every particle with the chosen integrator
+    pub fn step_with(&mut self, integrator: ParticleIntegrator, duration: f32) {
+        self.registry.update_forces(duration);
+        for particle in self.particles.iter() {
+            particle.borrow_mut().integrate_with(integrator, duration);
+        }
+    }
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        return Scene::default();
+    }
+
+    /// Reads a scene from its text form.
+    /// Fails on the first problem found, such as an unknown key or a force naming a missing particle.
+    pub fn parse(text: &str) -> Result<Scene, SceneError> {
+        // Particles are read first so forces can name particles defined further down
+        let (particle_sections, force_sections): (Vec<Section>, Vec<Section>) =
+            read_sections(text)?.into_iter().partition(|s| s.kind == "particle");
+        let mut scene: Scene = Scene::new();
+
+        for section in particle_sections {
+            let line: usize = section.line;
+            let particle: SceneParticle = read_particle(section)?;
+            if scene.get_particle(&particle.name).is_some() {
+                return Err(SceneError::new(line, format!("particle '{}' is defined twice", particle.name)));
+            }
+            scene.particles.push(particle);
+        }
+        for section in force_sections {
+            let force: SceneForce = read_force(section, &scene)?;
+            scene.forces.push(force);
+        }
+        return Ok(scene);
+    }
+
+    /// Writes the scene in its text form, particles first and then forces
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for (i, particle) in self.particles.iter().enumerate() {
+            if i > 0 { writeln!(writer)?; }
+            writeln!(writer, "[particle {}]", particle.name)?;
+            writeln!(writer, "position = {}", format_vector(&particle.position))?;
+            writeln!(writer, "velocity = {}", format_vector(&particle.velocity))?;
+            writeln!(writer, "acceleration = {}", format_vector(&particle.acceleration))?;
+            writeln!(writer, "damping = {}", particle.damping)?;
+            writeln!(writer, "mass = {}", particle.mass)?;
+            if let Some(charge) = particle.charge {
+                writeln!(writer, "charge = {}", charge)?;
+            }
+        }
+        for force in self.forces.iter() {
+            writeln!(writer)?;
+            writeln!(writer, "[force {}]", force.kind.get_keyword())?;
+            writeln!(writer, "particles = {}", force.particles.join(" "))?;
+            match &force.kind {
+                SceneForceKind::Gravity { gravity } => {
+                    writeln!(writer, "gravity = {}", format_vector(gravity))?;
+                }
+                SceneForceKind::Drag { k1, k2 } => {
+                    writeln!(writer, "k1 = {}", k1)?;
+                    writeln!(writer, "k2 = {}", k2)?;
+                }
+                SceneForceKind::Spring { other, spring_constant, rest_length }
+                | SceneForceKind::Bungee { other, spring_constant, rest_length } => {
+                    writeln!(writer, "other = {}", other)?;
+                    writeln!(writer, "spring_constant = {}", spring_constant)?;
+                    writeln!(writer, "rest_length = {}", rest_length)?;
+                }
+                SceneForceKind::AnchoredSpring { anchor, spring_constant, rest_length } => {
+                    writeln!(writer, "anchor = {}", format_vector(anchor))?;
+                    writeln!(writer, "spring_constant = {}", spring_constant)?;
+                    writeln!(writer, "rest_length = {}", rest_length)?;
+                }
+                SceneForceKind::Buoyancy { max_depth, volume, water_height, liquid_density } => {
+                    writeln!(writer, "max_depth = {}", max_depth)?;
+                    writeln!(writer, "volume = {}", volume)?;
+                    writeln!(writer, "water_height = {}", water_height)?;
+                    writeln!(writer, "liquid_density = {}", liquid_density)?;
+                }
+                SceneForceKind::FakeSpring { anchor, spring_constant, damping } => {
+                    writeln!(writer, "anchor = {}", format_vector(anchor))?;
+                    writeln!(writer, "spring_constant = {}", spring_constant)?;
+                    writeln!(writer, "damping = {}", damping)?;
+                }
+                SceneForceKind::Coulomb { other, coulomb_constant } => {
+                    writeln!(writer, "other = {}", other)?;
+                    writeln!(writer, "coulomb_constant = {}", coulomb_constant)?;
+                }
+                SceneForceKind::ElectricField { field } | SceneForceKind::MagneticField { field } => {
+                    writeln!(writer, "field = {}", format_vector(field))?;
+                }
+                SceneForceKind::MagneticDipole { position, moment, magnetic_constant } => {
+                    writeln!(writer, "position = {}", format_vector(position))?;
+                    writeln!(writer, "moment = {}", format_vector(moment))?;
+                    writeln!(writer, "magnetic_constant = {}", magnetic_constant)?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// Returns the scene in its text form
+    pub fn to_text(&self) -> String {
+        let mut text: Vec<u8> = Vec::new();
+        self.write(&mut text).expect("writing to a Vec cannot fail");
+        return String::from_utf8(text).expect("the scene writer only writes UTF-8");
+    }
+
+    pub fn get_particle(&self, name: &str) -> Option<&SceneParticle> {
+        return self.particles.iter().find(|p| p.name == name);
+    }
+
+    /// Creates the particles and registers a generator for each force.
+    /// Each force section becomes one generator shared by all of its particles.
+    pub fn build(&self) -> SceneInstance {
+        let mut instance: SceneInstance = SceneInstance::new();
+        for particle in self.particles.iter() {
+            instance.add_particle(particle).expect("particle names in a scene are unique");
+        }
+        for force in self.forces.iter() {
+            assert!(instance.add_force(force), "forces in a scene only name particles in it");
+        }
+        return instance;
+    }
+
+    /// Copies the current state of running particles back into the scene, so it can be saved.
+    /// The particles must be in the scene's order, as `build` creates them.
+    pub fn update_particles(&mut self, particles: &[Rc<RefCell<Particle>>]) {
+        for (scene_particle, particle) in self.particles.iter_mut().zip(particles.iter()) {
+            let particle = particle.borrow();
+            scene_particle.position = particle.get_position();
+            scene_particle.velocity = particle.get_velocity();
+            scene_particle.acceleration = particle.get_acceleration();
+            scene_particle.damping = particle.damping;
+            scene_particle.mass = particle.get_mass();
+            scene_particle.charge = particle.get_charge();
+        }
+    }
+}
+
+fn format_vector(vector: &Vector3) -> String {
+    return format!("{} {} {}", vector.x, vector.y, vector.z);
+}
+
+/// One `key = value` line of a section
+struct Entry {
+    line: usize,
+    key: String,
+    value: String,
+    used: bool,
+}
+
+/// A `[kind name]` header and the lines under it
+struct Section {
+    line: usize,
+    kind: String,
+    name: String,
+    entries: Vec<Entry>,
+}
+
+impl Section {
+    fn has(&self, key: &str) -> bool {
+        return self.entries.iter().any(|e| e.key == key);
+    }
+
+    /// Finds the value for the key, marking it as used
+    fn take(&mut self, key: &str) -> Option<(usize, String)> {
+        let entry: &mut Entry = self.entries.iter_mut().find(|e| e.key == key)?;
+        entry.used = true;
+        return Some((entry.line, entry.value.clone()));
+    }
+
+    fn missing(&self, key: &str) -> SceneError {
+        return SceneError::new(self.line, format!("[{} {}] is missing '{}'", self.kind, self.name, key));
+    }
+
+    fn take_number(&mut self, key: &str, default: Option<f32>) -> Result<f32, SceneError> {
+        let Some((line, value)) = self.take(key) else {
+            return default.ok_or_else(|| self.missing(key));
+        };
+        return parse_number(line, &value);
+    }
+
+    /// As `take_number`, failing on infinities and NaN
+    fn take_finite_number(&mut self, key: &str, default: Option<f32>) -> Result<f32, SceneError> {
+        let line: usize = self.line_of(key);
+        let number: f32 = self.take_number(key, default)?;
+        if !number.is_finite() { return Err(self.not_finite(line, key)); }
+        return Ok(number);
+    }
+
+    fn take_vector(&mut self, key: &str, default: Option<Vector3>) -> Result<Vector3, SceneError> {
+        let Some((line, value)) = self.take(key) else {
+            return default.ok_or_else(|| self.missing(key));
+        };
+        let parts: Vec<&str> = value.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(SceneError::new(line, format!("expected three numbers for '{}', found '{}'", key, value)));
+        }
+        return Ok(Vector3::new(
+            parse_number(line, parts[0])?,
+            parse_number(line, parts[1])?,
+            parse_number(line, parts[2])?
+        ));
+    }
+
+    /// As `take_vector`, failing on infinities and NaN
+    fn take_finite_vector(&mut self, key: &str, default: Option<Vector3>) -> Result<Vector3, SceneError> {
+        let line: usize = self.line_of(key);
+        let vector: Vector3 = self.take_vector(key, default)?;
+        if !(vector.x.is_finite() && vector.y.is_finite() && vector.z.is_finite()) {
+            return Err(self.not_finite(line, key));
+        }
+        return Ok(vector);
+    }
+
+    /// The line the key is on, or the header's line if it is not given
+    fn line_of(&self, key: &str) -> usize {
+        return self.entries.iter().find(|e| e.key == key).map_or(self.line, |e| e.line);
+    }
+
+    fn not_finite(&self, line: usize, key: &str) -> SceneError {
+        return SceneError::new(line, format!("'{}' in [{} {}] must be finite", key, self.kind, self.name));
+    }
+
+    /// Reads the particle at the other end of a pair force, checking it exists and is not
+    /// one of the particles the force applies to, as a particle cannot act on itself
+    fn take_other(&mut self, scene: &Scene, particles: &[String]) -> Result<String, SceneError> {
+        let Some((line, value)) = self.take("other") else { return Err(self.missing("other")); };
+        check_particle(line, &value, scene)?;
+        if particles.contains(&value) {
+            return Err(SceneError::new(
+                line,
+                format!("'{}' cannot be both the other particle and one of the particles of [{} {}]", value, self.kind, self.name)
+            ));
+        }
+        return Ok(value);
+    }
+
+    /// Fails if the section has a key that was never read
+    fn check_all_used(&self) -> Result<(), SceneError> {
+        if let Some(entry) = self.entries.iter().find(|e| !e.used) {
+            return Err(SceneError::new(
+                entry.line,
+                format!("unknown key '{}' in [{} {}]", entry.key, self.kind, self.name)
+            ));
+        }
+        return Ok(());
+    }
+}
+
+fn parse_number(line: usize, value: &str) -> Result<f32, SceneError> {
+    return value.parse::<f32>()
+        .map_err(|_| SceneError::new(line, format!("'{}' is not a number", value)));
+}
+
+fn check_particle(line: usize, name: &str, scene: &Scene) -> Result<(), SceneError> {
+    if scene.get_particle(name).is_none() {
+        return Err(SceneError::new(line, format!("there is no particle named '{}'", name)));
+    }
+    return Ok(());
+}
+
+/// Names are letters, digits, `_` and `-`, so they never need quoting in the text formats
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    return !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+}
+
+/// Splits the text into sections, checking the layout of each line
+fn read_sections(text: &str) -> Result<Vec<Section>, SceneError> {
+    let mut sections: Vec<Section> = Vec::new();
+    for (i, raw_line) in text.lines().enumerate() {
+        let line_number: usize = i + 1;
+        let line: &str = raw_line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() { continue; }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let Some(header) = header.strip_suffix(']') else {
+                return Err(SceneError::new(line_number, "section header is missing its closing ']'".to_string()));
+            };
+            let parts: Vec<&str> = header.split_whitespace().collect();
+            if parts.len() != 2 || (parts[0] != "particle" && parts[0] != "force") {
+                return Err(SceneError::new(
+                    line_number,
+                    format!("expected [particle <name>] or [force <kind>], found '[{}]'", header)
+                ));
+            }
+            if !is_valid_name(parts[1]) {
+                return Err(SceneError::new(line_number, format!("'{}' is not a valid name", parts[1])));
+            }
+            sections.push(Section {
+                line: line_number,
+                kind: parts[0].to_string(),
+                name: parts[1].to_string(),
+                entries: Vec::new()
+            });
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(SceneError::new(line_number, format!("expected 'key = value', found '{}'", line)));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        let Some(section) = sections.last_mut() else {
+            return Err(SceneError::new(line_number, format!("'{}' is not inside a section", key)));
+        };
+        if section.entries.iter().any(|e| e.key == key) {
+            return Err(SceneError::new(line_number, format!("'{}' is given twice", key)));
+        }
+        section.entries.push(Entry {
+            line: line_number,
+            key: key.to_string(),
+            value: value.to_string(),
+            used: false
+        });
+    }
+    return Ok(sections);
+}
+
+fn read_particle(mut section: Section) -> Result<SceneParticle, SceneError> {
+    let particle: SceneParticle = SceneParticle {
+        name: section.name.clone(),
+        position: section.take_finite_vector("position", Some(Vector3::default()))?,
+        velocity: section.take_finite_vector("velocity", Some(Vector3::default()))?,
+        acceleration: section.take_finite_vector("acceleration", Some(Vector3::default()))?,
+        damping: section.take_finite_number("damping", Some(1.0))?,
+        mass: section.take_number("mass", None)?,
+        charge: if section.has("charge") {
+            Some(section.take_finite_number("charge", None)?)
+        } else {
+            None
+        },
+    };
+    if particle.mass.is_nan() || particle.mass <= 0.0 {
+        return Err(SceneError::new(section.line, format!("particle '{}' must have a positive mass", particle.name)));
+    }
+    section.check_all_used()?;
+    return Ok(particle);
+}
+
+/// The kinds of force a `[force <kind>]` section can hold
+const FORCE_KINDS: [&str; 11] = [
+    "gravity", "drag", "spring", "anchored_spring", "bungee", "buoyancy",
+    "fake_spring", "coulomb", "electric_field", "magnetic_field", "magnetic_dipole",
+];
+
+fn read_force(mut section: Section, scene: &Scene) -> Result<SceneForce, SceneError> {
+    if !FORCE_KINDS.contains(&section.name.as_str()) {
+        return Err(SceneError::new(section.line, format!("unknown force kind '{}'", section.name)));
+    }
+    let Some((line, value)) = section.take("particles") else { return Err(section.missing("particles")); };
+    let particles: Vec<String> = value.split_whitespace().map(|name| name.to_string()).collect();
+    if particles.is_empty() {
+        return Err(SceneError::new(line, "'particles' needs at least one particle name".to_string()));
+    }
+    for name in particles.iter() {
+        check_particle(line, name, scene)?;
+    }
+
+    let kind: SceneForceKind = match section.name.as_str() {
+        "gravity" => SceneForceKind::Gravity {
+            gravity: section.take_vector("gravity", None)?
+        },
+        "drag" => SceneForceKind::Drag {
+            k1: section.take_number("k1", None)?,
+            k2: section.take_number("k2", None)?
+        },
+        "spring" => SceneForceKind::Spring {
+            other: section.take_other(scene, &particles)?,
+            spring_constant: section.take_number("spring_constant", None)?,
+            rest_length: section.take_number("rest_length", None)?
+        },
+        "anchored_spring" => SceneForceKind::AnchoredSpring {
+            anchor: section.take_vector("anchor", None)?,
+            spring_constant: section.take_number("spring_constant", None)?,
+            rest_length: section.take_number("rest_length", None)?
+        },
+        "bungee" => SceneForceKind::Bungee {
+            other: section.take_other(scene, &particles)?,
+            spring_constant: section.take_number("spring_constant", None)?,
+            rest_length: section.take_number("rest_length", None)?
+        },
+        "buoyancy" => SceneForceKind::Buoyancy {
+            max_depth: section.take_number("max_depth", None)?,
+            volume: section.take_number("volume", None)?,
+            water_height: section.take_number("water_height", None)?,
+            liquid_density: section.take_number("liquid_density", Some(1000.0))?
+        },
+        "fake_spring" => SceneForceKind::FakeSpring {
+            anchor: section.take_vector("anchor", None)?,
+            spring_constant: section.take_number("spring_constant", None)?,
+            damping: section.take_number("damping", None)?
+        },
+        "coulomb" => SceneForceKind::Coulomb {
+            other: section.take_other(scene, &particles)?,
+            coulomb_constant: section.take_number("coulomb_constant", Some(COULOMB_CONSTANT))?
+        },
+        "electric_field" => SceneForceKind::ElectricField {
+            field: section.take_vector("field", None)?
+        },
+        "magnetic_field" => SceneForceKind::MagneticField {
+            field: section.take_vector("field", None)?
+        },
+        "magnetic_dipole" => SceneForceKind::MagneticDipole {
+            position: section.take_vector("position", None)?,
+            moment: section.take_vector("moment", None)?,
+            magnetic_constant: section.take_number("magnetic_constant", Some(MAGNETIC_CONSTANT_OVER_4PI))?
+        },
+        _ => unreachable!("checked against FORCE_KINDS above"),
+    };
+    section.check_all_used()?;
+    return Ok(SceneForce {
+        particles,
+        kind
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every force kind, with numbers that do not print exactly
+    const FULL_SCENE: &str = "\
+[particle anchor]
+position = 0 10 0
+mass = inf
+
+[particle ball]
+position = 0.1 8.3 -1e-7
+velocity = 1 2 3
+damping = 0.99
+mass = 2.5
+charge = -1.6e-19
+
+[particle other]
+position = 1 1 1
+mass = 0.3
+charge = 1e-6
+
+[force gravity]
+particles = ball other
+gravity = 0 -9.81 0
+
+[force drag]
+particles = ball
+k1 = 0.1
+k2 = 0.01
+
+[force spring]
+particles = ball
+other = anchor
+spring_constant = 20
+rest_length = 1.5
+
+[force anchored_spring]
+particles = other
+anchor = 1 2 3
+spring_constant = 4
+rest_length = 0.7
+
+[force bungee]
+particles = other
+other = ball
+spring_constant = 3
+rest_length = 2
+
+[force buoyancy]
+particles = ball
+max_depth = 0.5
+volume = 0.1
+water_height = 0
+
+[force fake_spring]
+particles = other
+anchor = 0 0 0
+spring_constant = 9
+damping = 0.3
+
+[force coulomb]
+particles = ball
+other = other
+
+[force electric_field]
+particles = ball
+field = 0 0 100
+
+[force magnetic_field]
+particles = ball other
+field = 0 0.5 0
+
+[force magnetic_dipole]
+particles = other
+position = 0 0 0
+moment = 0 0 1
+";
+
+    fn parse_error(text: &str) -> SceneError {
+        return Scene::parse(text).expect_err("the scene should not load");
+    }
+
+    #[test]
+    fn scenes_read_back_as_written() {
+        let scene: Scene = Scene::parse(FULL_SCENE).unwrap();
+        assert_eq!(scene.particles.len(), 3);
+        assert_eq!(scene.forces.len(), 11);
+
+        let text: String = scene.to_text();
+        let reread: Scene = Scene::parse(&text).unwrap();
+        assert_eq!(reread, scene);
+        assert_eq!(reread.to_text(), text);
+    }
+
+    #[test]
+    fn running_scenes_save_their_state() {
+        let mut scene: Scene = Scene::parse(FULL_SCENE).unwrap();
+        let mut instance: SceneInstance = scene.build();
+        for _ in 0..10 {
+            instance.step(0.01);
+        }
+        scene.update_particles(&instance.particles);
+
+        let reread: Scene = Scene::parse(&scene.to_text()).unwrap();
+        for (saved, particle) in reread.particles.iter().zip(instance.particles.iter()) {
+            assert_eq!(saved.position, particle.borrow().get_position());
+            assert_eq!(saved.velocity, particle.borrow().get_velocity());
+        }
+    }
+
+    #[test]
+    fn pair_forces_cannot_act_on_their_own_other_particle() {
+        for kind in ["spring", "bungee", "coulomb"] {
+            let text: String = format!(
+                "[particle a]\nmass = 1\n\n[force {}]\nparticles = a\nother = a\nspring_constant = 1\nrest_length = 1\n",
+                kind
+            );
+            let error: SceneError = parse_error(&text);
+            assert_eq!(error.line, 6, "{}: {}", kind, error);
+            assert!(error.message.contains("other particle"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn particles_need_a_positive_mass() {
+        for mass in ["0", "-2", "NaN"] {
+            let error: SceneError = parse_error(&format!("\n[particle a]\nmass = {}\n", mass));
+            assert_eq!(error.line, 2, "mass {}: {}", mass, error);
+        }
+        assert!(Scene::parse("[particle a]\nmass = inf\n").is_ok());
+    }
+
+    #[test]
+    fn particle_state_must_be_finite() {
+        for (key, value) in [("position", "0 inf 0"), ("velocity", "NaN 0 0"), ("acceleration", "0 0 -inf"),
+            ("damping", "NaN"), ("charge", "inf")] {
+            let error: SceneError = parse_error(&format!("[particle a]\nmass = 1\n{} = {}\n", key, value));
+            assert_eq!(error.line, 3, "{}: {}", key, error);
+            assert!(error.message.contains("finite"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn errors_give_the_line_of_the_problem() {
+        let cases: [(&str, usize); 8] = [
+            ("[particle a]\nmass = 1\nspeed = 3\n", 3),
+            ("[particle a]\nmass = one\n", 2),
+            ("[particle a]\nposition = 1 2\nmass = 1\n", 2),
+            ("[particle a]\nmass = 1\n[particle a]\nmass = 2\n", 3),
+            ("[particle a]\n", 1),
+            ("mass = 1\n", 1),
+            ("[particle a]\nmass = 1\n[force gravity]\nparticles = b\ngravity = 0 0 0\n", 4),
+            ("[particle a]\nmass = 1\n[force wind]\nparticles = a\n", 3),
+        ];
+        for (text, line) in cases {
+            assert_eq!(parse_error(text).line, line, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,589 @@
+use std::{cell::RefCell, error, fmt, rc::Rc};
+
+use crate::{
+    core::{Matrix3, Vector3},
+    particle::{Particle, ParticleIntegrator},
+    precision::{real_cos, real_sin},
+};
+
+/// A problem found while reading a TetGen mesh, with the file and the line it was found on
+/// counting from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TetMeshError {
+    /// `"node"` or `"ele"`, the extension of the file the problem is in
+    pub file: &'static str,
+    pub line: usize,
+    pub message: String,
+}
+
+impl TetMeshError {
+    fn new(file: &'static str, line: usize, message: String) -> TetMeshError {
+        return TetMeshError {
+            file,
+            line,
+            message
+        };
+    }
+}
+
+impl fmt::Display for TetMeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(f, ".{} line {}: {}", self.file, self.line, self.message);
+    }
+}
+
+impl error::Error for TetMeshError {}
+
+/// A solid made of tetrahedra, as nodes and the four nodes of each element.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TetMesh {
+    pub nodes: Vec<Vector3>,
+    /// Indices into `nodes`, counting from zero
+    pub elements: Vec<[usize; 4]>,
+}
+
+/// The numbers on each line of a TetGen file that has any, with the line number.
+/// Everything after a `#` is a comment.
+fn data_lines(text: &str) -> impl Iterator<Item = (usize, Vec<&str>)> {
+    return text.lines().enumerate().filter_map(|(index, line)| {
+        let content: &str = line.split('#').next().unwrap_or("");
+        let fields: Vec<&str> = content.split_whitespace().collect();
+        return if fields.is_empty() { None } else { Some((index + 1, fields)) };
+    });
+}
+
+fn parse_field<T: std::str::FromStr>(file: &'static str, line: usize, fields: &[&str], index: usize, what: &str) -> Result<T, TetMeshError> {
+    let Some(field) = fields.get(index) else {
+        return Err(TetMeshError::new(file, line, format!("missing {}", what)));
+    };
+    return field.parse::<T>().map_err(|_| TetMeshError::new(file, line, format!("'{}' is not a valid {}", field, what)));
+}
+
+impl TetMesh {
+    /// Reads the contents of a TetGen `.node` file and the `.ele` file that goes with it.
+    /// Nodes may be numbered from zero or one, as long as they are numbered in order.
+    /// Ten node elements are read as the four nodes at their corners.
+    pub fn parse_tetgen(node_text: &str, ele_text: &str) -> Result<TetMesh, TetMeshError> {
+        let mut mesh: TetMesh = TetMesh::default();
+
+        // <# of points> <dimension (3)> <# of attributes> <boundary markers (0 or 1)>
+        let mut lines = data_lines(node_text);
+        let Some((line, header)) = lines.next() else {
+            return Err(TetMeshError::new("node", 1, "the file is empty".to_string()));
+        };
+        let count: usize = parse_field("node", line, &header, 0, "node count")?;
+        let dimension: usize = parse_field("node", line, &header, 1, "dimension")?;
+        if dimension != 3 {
+            return Err(TetMeshError::new("node", line, format!("only 3 dimensional meshes are supported, not {}", dimension)));
+        }
+        let mut first_index: Option<usize> = None;
+        for (line, fields) in lines.by_ref().take(count) {
+            let index: usize = parse_field("node", line, &fields, 0, "node index")?;
+            let base: usize = *first_index.get_or_insert(index);
+            if index != base + mesh.nodes.len() {
+                return Err(TetMeshError::new("node", line, format!("expected node {}, found {}", base + mesh.nodes.len(), index)));
+            }
+            mesh.nodes.push(Vector3::new(
+                parse_field("node", line, &fields, 1, "x coordinate")?,
+                parse_field("node", line, &fields, 2, "y coordinate")?,
+                parse_field("node", line, &fields, 3, "z coordinate")?
+            ));
+        }
+        if mesh.nodes.len() < count {
+            let last: usize = node_text.lines().count().max(1);
+            return Err(TetMeshError::new("node", last, format!("expected {} nodes, found {}", count, mesh.nodes.len())));
+        }
+        let base: usize = first_index.unwrap_or(0);
+
+        // <# of tetrahedra> <nodes per tetrahedron (4 or 10)> <# of attributes>
+        let mut lines = data_lines(ele_text);
+        let Some((line, header)) = lines.next() else {
+            return Err(TetMeshError::new("ele", 1, "the file is empty".to_string()));
+        };
+        let count: usize = parse_field("ele", line, &header, 0, "element count")?;
+        let nodes_per_element: usize = parse_field("ele", line, &header, 1, "nodes per element")?;
+        if nodes_per_element != 4 && nodes_per_element != 10 {
+            return Err(TetMeshError::new("ele", line, format!("elements must have 4 or 10 nodes, not {}", nodes_per_element)));
+        }
+        for (line, fields) in lines.by_ref().take(count) {
+            let mut element: [usize; 4] = [0; 4];
+            for (corner, node) in element.iter_mut().enumerate() {
+                let index: usize = parse_field("ele", line, &fields, corner + 1, "node index")?;
+                if index < base || index - base >= mesh.nodes.len() {
+                    return Err(TetMeshError::new("ele", line, format!("there is no node {}", index)));
+                }
+                *node = index - base;
+            }
+            mesh.elements.push(element);
+        }
+        if mesh.elements.len() < count {
+            let last: usize = ele_text.lines().count().max(1);
+            return Err(TetMeshError::new("ele", last, format!("expected {} elements, found {}", count, mesh.elements.len())));
+        }
+        return Ok(mesh);
+    }
+
+    /// Writes the mesh as the contents of a `.node` and an `.ele` file, numbered from zero
+    pub fn to_tetgen(&self) -> (String, String) {
+        let mut node_text: String = format!("{} 3 0 0\n", self.nodes.len());
+        for (index, node) in self.nodes.iter().enumerate() {
+            node_text.push_str(&format!("{} {} {} {}\n", index, node.x, node.y, node.z));
+        }
+        let mut ele_text: String = format!("{} 4 0\n", self.elements.len());
+        for (index, [a, b, c, d]) in self.elements.iter().enumerate() {
+            ele_text.push_str(&format!("{} {} {} {} {}\n", index, a, b, c, d));
+        }
+        return (node_text, ele_text);
+    }
+}
+
+/// How element stress is worked out from the deformation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SoftBodyModel {
+    /// Linear elasticity on the raw deformation. Cheap, but rotating an element strains it,
+    /// so it only suits small deformations.
+    Linear,
+    /// Linear elasticity after taking out each element's rotation, so large rotations are free
+    #[default]
+    Corotated,
+}
+
+/// What a `SoftBody` is made of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoftBodyMaterial {
+    /// Stiffness in pascals
+    pub youngs_modulus: f32,
+    /// Between 0 and 0.5, how much the material thins when stretched
+    pub poisson_ratio: f32,
+    /// Kilograms per cubic metre, the mass is lumped equally onto each element's nodes
+    pub density: f32,
+    /// The damping given to every node particle, as `Particle::damping`
+    pub damping: f32,
+}
+
+impl Default for SoftBodyMaterial {
+    /// A soft rubber
+    fn default() -> Self {
+        return Self {
+            youngs_modulus: 1.0e5,
+            poisson_ratio: 0.3,
+            density: 1000.0,
+            damping: 0.9
+        };
+    }
+}
+
+/// One tetrahedron and what it remembers of its rest shape.
+#[derive(Debug, Clone)]
+struct SoftBodyElement {
+    nodes: [usize; 4],
+    /// Inverse of the matrix of rest edges from the first node
+    rest_inverse: Matrix3,
+    rest_volume: f32,
+    /// The rotation found last step, the starting guess for the next
+    rotation: Matrix3,
+    /// Cauchy stress from the last step, in world axes
+    stress: Matrix3,
+}
+
+/// A deformable solid simulated with the finite element method on a tetrahedral mesh,
+/// with one particle per node.
+///
+/// `step` moves the nodes with semi-implicit Euler, so do not also call `Particle::integrate`
+/// on them. Forces other generators leave in a node's accumulator, and its
+/// acceleration, are included. Stiff materials need short steps, around the time sound takes to
+/// cross the smallest element, so use substeps rather than shortening the whole frame.
+pub struct SoftBody {
+    particles: Vec<Rc<RefCell<Particle>>>,
+    elements: Vec<SoftBodyElement>,
+    model: SoftBodyModel,
+    /// Lame's first parameter
+    lambda: f32,
+    /// Shear modulus
+    mu: f32,
+    masses: Vec<f32>,
+    fixed: Vec<bool>,
+    gravity: Vector3,
+    substeps: usize,
+}
+
+impl SoftBody {
+    /// Builds the body at rest in the shape of the mesh.
+    /// Elements wound inside out are turned the right way, and flat elements are dropped.
+    pub fn new(mesh: &TetMesh, material: &SoftBodyMaterial, model: SoftBodyModel) -> SoftBody {
+        assert!(material.youngs_modulus > 0.0 && material.density > 0.0);
+        assert!(material.poisson_ratio >= 0.0 && material.poisson_ratio < 0.5, "the Poisson ratio must be in [0, 0.5)");
+        let nu: f32 = material.poisson_ratio;
+
+        let mut elements: Vec<SoftBodyElement> = Vec::with_capacity(mesh.elements.len());
+        let mut masses: Vec<f32> = vec![0.0; mesh.nodes.len()];
+        for element in mesh.elements.iter() {
+            let mut nodes: [usize; 4] = *element;
+            let mut rest: Matrix3 = edge_matrix(&mesh.nodes, &nodes);
+            if rest.determinant() < 0.0 {
+                nodes.swap(2, 3);
+                rest = edge_matrix(&mesh.nodes, &nodes);
+            }
+            let Some(rest_inverse) = rest.inverse() else { continue; };
+            let rest_volume: f32 = rest.determinant() / 6.0;
+            for node in nodes.iter() {
+                masses[*node] += 0.25 * material.density * rest_volume;
+            }
+            elements.push(SoftBodyElement {
+                nodes,
+                rest_inverse,
+                rest_volume,
+                rotation: Matrix3::identity(),
+                stress: Matrix3::default()
+            });
+        }
+
+        let particles: Vec<Rc<RefCell<Particle>>> = mesh.nodes.iter().zip(masses.iter_mut())
+            .map(|(node, mass)| {
+                // Nodes no element uses still need a mass to be a valid particle
+                if *mass <= 0.0 { *mass = 1.0; }
+                return Rc::new(RefCell::new(Particle::new(
+                    *node,
+                    Vector3::default(),
+                    Vector3::default(),
+                    material.damping,
+                    *mass
+                )));
+            })
+            .collect();
+
+        return SoftBody {
+            fixed: vec![false; particles.len()],
+            particles,
+            elements,
+            model,
+            lambda: material.youngs_modulus * nu / ((1.0 + nu) * (1.0 - 2.0 * nu)),
+            mu: material.youngs_modulus / (2.0 * (1.0 + nu)),
+            masses,
+            gravity: Vector3::default(),
+            substeps: 1
+        };
+    }
+
+    /// The node particles in mesh order
+    pub fn get_particles(&self) -> &[Rc<RefCell<Particle>>] {
+        return &self.particles;
+    }
+
+    pub fn get_particle(&self, node: usize) -> &Rc<RefCell<Particle>> {
+        return &self.particles[node];
+    }
+
+    pub fn element_count(&self) -> usize {
+        return self.elements.len();
+    }
+
+    /// Holds the node where it is, it will only move if moved by hand
+    pub fn fix_node(&mut self, node: usize) {
+        let mut particle = self.particles[node].borrow_mut();
+        particle.restore_mass(self.masses[node], 0.0);
+        particle.velocity = Vector3::default();
+        self.fixed[node] = true;
+    }
+
+    /// Lets a fixed node move again
+    pub fn free_node(&mut self, node: usize) {
+        self.particles[node].borrow_mut().restore_mass(self.masses[node], 1.0 / self.masses[node]);
+        self.fixed[node] = false;
+    }
+
+    /// Fixes every node whose position passes the test, and returns how many that was
+    pub fn fix_nodes_where(&mut self, test: impl Fn(&Vector3) -> bool) -> usize {
+        let mut count: usize = 0;
+        for node in 0..self.particles.len() {
+            if test(&self.particles[node].borrow().get_position()) {
+                self.fix_node(node);
+                count += 1;
+            }
+        }
+        return count;
+    }
+
+    pub fn is_fixed(&self, node: usize) -> bool {
+        return self.fixed[node];
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector3) {
+        self.gravity = gravity;
+    }
+
+    /// Splits every step into this many equal substeps
+    pub fn set_substeps(&mut self, substeps: usize) {
+        assert!(substeps > 0);
+        self.substeps = substeps;
+    }
+
+    /// Advances the body by the duration
+    pub fn step(&mut self, duration: f32) {
+        assert!(duration > 0.0);
+        let substep: f32 = duration / self.substeps as f32;
+        // Forces from other generators are held for the whole step
+        let external: Vec<Vector3> = self.particles.iter().map(|p| p.borrow().force_accum).collect();
+
+        for _ in 0..self.substeps {
+            let positions: Vec<Vector3> = self.particles.iter().map(|p| p.borrow().get_position()).collect();
+            let mut forces: Vec<Vector3> = external.clone();
+            for (force, mass) in forces.iter_mut().zip(self.masses.iter()) {
+                force.add_scaled_vector(&self.gravity, *mass);
+            }
+            for element in self.elements.iter_mut() {
+                element_forces(element, &positions, self.model, self.lambda, self.mu, &mut forces);
+            }
+
+            for (i, particle) in self.particles.iter().enumerate() {
+                let mut particle = particle.borrow_mut();
+                particle.clear_accumulator();
+                if self.fixed[i] { continue; }
+                particle.add_force(forces[i]);
+                particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, substep);
+            }
+        }
+    }
+
+    /// The Cauchy stress in the element from the last step, in pascals along the world axes.
+    /// Positive values are tension.
+    pub fn get_element_stress(&self, element: usize) -> Matrix3 {
+        return self.elements[element].stress;
+    }
+
+    /// The von Mises equivalent stress in the element from the last step, a single number
+    /// for colouring elements by how close they are to yielding
+    pub fn get_von_mises_stress(&self, element: usize) -> f32 {
+        let s: &[f32; 9] = &self.elements[element].stress.data;
+        let differences: [f32; 3] = [s[0] - s[4], s[4] - s[8], s[8] - s[0]];
+        let normal: f32 = differences.iter().map(|d| d * d).sum();
+        let shear: f32 = s[1] * s[1] + s[5] * s[5] + s[2] * s[2];
+        return (0.5 * normal + 3.0 * shear).sqrt();
+    }
+
+    /// The von Mises stress of every element, in element order
+    pub fn get_von_mises_stresses(&self) -> Vec<f32> {
+        return (0..self.elements.len()).map(|e| self.get_von_mises_stress(e)).collect();
+    }
+
+    /// The nodes of each element, some may be wound differently from the mesh they came from
+    pub fn get_elements(&self) -> Vec<[usize; 4]> {
+        return self.elements.iter().map(|e| e.nodes).collect();
+    }
+
+    pub fn get_rest_volume(&self) -> f32 {
+        return self.elements.iter().map(|e| e.rest_volume).sum();
+    }
+
+    /// The current volume, the sum of every element's signed volume
+    pub fn get_volume(&self) -> f32 {
+        let positions: Vec<Vector3> = self.particles.iter().map(|p| p.borrow().get_position()).collect();
+        return self.elements.iter().map(|e| edge_matrix(&positions, &e.nodes).determinant() / 6.0).sum();
+    }
+}
+
+/// The edges from an element's first node to the other three, as columns
+fn edge_matrix(positions: &[Vector3], nodes: &[usize; 4]) -> Matrix3 {
+    let origin: Vector3 = positions[nodes[0]];
+    return Matrix3::from_columns(
+        &(positions[nodes[1]] - &origin),
+        &(positions[nodes[2]] - &origin),
+        &(positions[nodes[3]] - &origin)
+    );
+}
+
+/// Adds the element's elastic forces on its nodes and records its stress
+fn element_forces(
+    element: &mut SoftBodyElement,
+    positions: &[Vector3],
+    model: SoftBodyModel,
+    lambda: f32,
+    mu: f32,
+    forces: &mut [Vector3]
+) {
+    // The deformation gradient F maps rest edges onto current ones
+    let deformation: Matrix3 = edge_matrix(positions, &element.nodes) * &element.rest_inverse;
+    let rotation: Matrix3 = match model {
+        SoftBodyModel::Linear => Matrix3::identity(),
+        SoftBodyModel::Corotated => {
+            element.rotation = extract_rotation(&deformation, &element.rotation);
+            element.rotation
+        }
+    };
+
+    // Small strain on the unrotated deformation, e = (R^T F + F^T R) / 2 - I
+    let unrotated: Matrix3 = rotation.transpose() * &deformation;
+    let strain: Matrix3 = (unrotated + &unrotated.transpose()) * 0.5 + &(Matrix3::identity() * -1.0);
+    // s = 2 mu e + lambda tr(e) I
+    let stress: Matrix3 = strain * (2.0 * mu) + &(Matrix3::identity() * (lambda * strain.trace()));
+    element.stress = rotation * &stress * &rotation.transpose();
+
+    // The forces on the last three nodes are the columns of -V0 P Dm^-T, with P = R s
+    let first_piola: Matrix3 = rotation * &stress;
+    let nodal: Matrix3 = first_piola * &element.rest_inverse.transpose() * -element.rest_volume;
+    let mut total: Vector3 = Vector3::default();
+    for corner in 0..3 {
+        let force: Vector3 = nodal.get_column(corner);
+        forces[element.nodes[corner + 1]] += &force;
+        total += &force;
+    }
+    forces[element.nodes[0]] -= &total;
+}
+
+/// Finds the rotation closest to the matrix, starting from a guess, following Muller et al.
+/// "A Robust Method to Extract the Rotational Part of Deformations". Unlike a polar decomposition
+/// this keeps working when the element is flattened or turned inside out.
+fn extract_rotation(matrix: &Matrix3, guess: &Matrix3) -> Matrix3 {
+    let mut rotation: Matrix3 = *guess;
+    for _ in 0..20 {
+        let mut torque: Vector3 = Vector3::default();
+        let mut alignment: f32 = 0.0;
+        for column in 0..3 {
+            let current: Vector3 = rotation.get_column(column);
+            let target: Vector3 = matrix.get_column(column);
+            torque += &current.vector_product(&target);
+            alignment += current * &target;
+        }
+        let mut axis: Vector3 = torque * (1.0 / (alignment.abs() + 1.0e-9));
+        let angle: f32 = axis.magnitude();
+        if angle < 1.0e-9 { break; }
+        axis *= 1.0 / angle;
+
+        // Rodrigues: cos I + sin K + (1 - cos) a a^T
+        let (sine, cosine) = (real_sin(angle), real_cos(angle));
+        let cross: Matrix3 = Matrix3 {
+            data: [
+                0.0, -axis.z, axis.y,
+                axis.z, 0.0, -axis.x,
+                -axis.y, axis.x, 0.0
+            ]
+        };
+        let turn: Matrix3 = Matrix3::identity() * cosine + &(cross * sine)
+            + &(Matrix3::outer_product(&axis, &axis) * (1.0 - cosine));
+        rotation = turn * &rotation;
+    }
+    return orthonormalize(&rotation);
+}
+
+/// Removes the drift repeated rotations build up, keeping the first column's direction
+fn orthonormalize(matrix: &Matrix3) -> Matrix3 {
+    let mut x: Vector3 = matrix.get_column(0);
+    x.normalize();
+    let mut z: Vector3 = x.vector_product(&matrix.get_column(1));
+    z.normalize();
+    let y: Vector3 = z.vector_product(&x);
+    return Matrix3::from_columns(&x, &y, &z);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODES: &str = "\
+# a single tetrahedron numbered from one
+4 3 0 0
+1 0 0 0
+2 1 0 0
+3 0 1 0
+4 0 0 1
+";
+
+    fn parse_error(node_text: &str, ele_text: &str) -> TetMeshError {
+        return TetMesh::parse_tetgen(node_text, ele_text).expect_err("the mesh should not load");
+    }
+
+    /// A beam of `length` cubes of side `size` along x, each split into six tetrahedra
+    fn beam(length: usize, size: f32) -> TetMesh {
+        let mut mesh: TetMesh = TetMesh::default();
+        let index = |x: usize, y: usize, z: usize| -> usize { return x * 4 + y * 2 + z; };
+        for x in 0..=length {
+            for y in 0..2 {
+                for z in 0..2 {
+                    mesh.nodes.push(Vector3::new(x as f32 * size, y as f32 * size, z as f32 * size));
+                }
+            }
+        }
+        // Each tetrahedron runs from one corner to the opposite one, a step along each axis at a time
+        let orders: [[usize; 3]; 6] = [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]];
+        for cube in 0..length {
+            for order in orders.iter() {
+                let mut corner: [usize; 3] = [cube, 0, 0];
+                let mut element: [usize; 4] = [index(corner[0], corner[1], corner[2]); 4];
+                for (step, axis) in order.iter().enumerate() {
+                    corner[*axis] += 1;
+                    element[step + 1] = index(corner[0], corner[1], corner[2]);
+                }
+                mesh.elements.push(element);
+            }
+        }
+        return mesh;
+    }
+
+    #[test]
+    fn tetgen_files_are_read() {
+        let ele_text: &str = "1 4 0\n1 1 2 3 4\n";
+        let mesh: TetMesh = TetMesh::parse_tetgen(NODES, ele_text).unwrap();
+        assert_eq!(mesh.nodes[1], Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.elements, vec![[0, 1, 2, 3]]);
+
+        // Only the corners of a ten node element are kept
+        let ele_text: &str = "1 10 0\n1 1 2 3 4 5 6 7 8 9 10\n";
+        let mesh: TetMesh = TetMesh::parse_tetgen(NODES, ele_text).unwrap();
+        assert_eq!(mesh.elements, vec![[0, 1, 2, 3]]);
+    }
+
+    #[test]
+    fn tetgen_errors_give_the_line_of_the_problem() {
+        let element: &str = "1 4 0\n1 1 2 3 4\n";
+        let cases: [(&str, &str, &str, usize); 7] = [
+            ("", element, "node", 1),
+            ("4 2 0 0\n", element, "node", 1),
+            ("# skipped\n2 3 0 0\n0 0 0 0\n2 1 0 0\n", element, "node", 4),
+            ("2 3 0 0\n1 0 0 0\n2 1 0 x\n", element, "node", 3),
+            (NODES, "1 6 0\n1 1 2 3 4\n", "ele", 1),
+            (NODES, "1 4 0\n\n1 1 2 3 5\n", "ele", 3),
+            (NODES, "2 4 0\n1 1 2 3 4\n", "ele", 2),
+        ];
+        for (node_text, ele_text, file, line) in cases {
+            let error: TetMeshError = parse_error(node_text, ele_text);
+            assert_eq!((error.file, error.line), (file, line), "{:?} {:?}: {}", node_text, ele_text, error);
+        }
+
+        // Numbering from one means there is no node 0
+        let error: TetMeshError = parse_error(NODES, "1 4 0\n1 0 1 2 3\n");
+        assert_eq!(error.message, "there is no node 0");
+    }
+
+    #[test]
+    fn tetgen_files_read_back_as_written() {
+        let mut mesh: TetMesh = beam(2, 0.1);
+        mesh.nodes[3] = Vector3::new(-1.0e-7, 0.3, 123.456);
+        let (node_text, ele_text) = mesh.to_tetgen();
+        assert_eq!(TetMesh::parse_tetgen(&node_text, &ele_text).unwrap(), mesh);
+    }
+
+    #[test]
+    fn cantilevers_are_stressed_at_the_root() {
+        let mut body: SoftBody = SoftBody::new(&beam(6, 0.1), &SoftBodyMaterial::default(), SoftBodyModel::Corotated);
+        assert_eq!(body.element_count(), 36);
+        assert_eq!(body.fix_nodes_where(|p| p.x == 0.0), 4);
+        body.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+        body.set_substeps(20);
+        let root: Vector3 = body.get_particle(0).borrow().get_position();
+
+        for _ in 0..60 {
+            body.step(1.0 / 60.0);
+        }
+        assert_eq!(body.get_particle(0).borrow().get_position(), root);
+        let tip: f32 = body.get_particle(24).borrow().get_position().y;
+        assert!(tip < 0.0, "the free end should sag, it is at {}", tip);
+
+        // The bending moment is largest where the beam is held
+        let stresses: Vec<f32> = body.get_von_mises_stresses();
+        let root_stress: f32 = stresses[..6].iter().copied().fold(0.0, f32::max);
+        let tip_stress: f32 = stresses[30..].iter().copied().fold(0.0, f32::max);
+        assert!(root_stress > 0.0);
+        assert!(root_stress > tip_stress, "the root carries {} Pa and the tip {} Pa", root_stress, tip_stress);
+    }
+}
+
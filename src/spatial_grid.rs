@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use crate::core::Vector3;
+
+/// Buckets points into cubic cells so finding the points near another one only looks at the
+/// 27 cells around it rather than at every point.
+///
+/// Points are stored by index, the grid does not own them. Queries list candidates in a fixed
+/// order, cell by cell and in insertion order within a cell, so results are repeatable.
+#[derive(Debug, Clone)]
+pub struct SpatialGrid {
+    /// The edge length of a cell, queries find everything within this distance
+    cell_size: f32,
+    cells: HashMap<(i32, i32, i32), Vec<usize>>,
+}
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> SpatialGrid {
+        assert!(cell_size > 0.0, "the cell size must be positive");
+        return SpatialGrid {
+            cell_size,
+            cells: HashMap::new()
+        };
+    }
+
+    pub fn get_cell_size(&self) -> f32 {
+        return self.cell_size;
+    }
+
+    /// The cell the position falls in
+    pub fn get_cell(&self, position: &Vector3) -> (i32, i32, i32) {
+        return (
+            (position.x / self.cell_size).floor() as i32,
+            (position.y / self.cell_size).floor() as i32,
+            (position.z / self.cell_size).floor() as i32
+        );
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    pub fn insert(&mut self, index: usize, position: &Vector3) {
+        let cell: (i32, i32, i32) = self.get_cell(position);
+        self.cells.entry(cell).or_default().push(index);
+    }
+
+    /// Empties the grid and inserts every position under its index in the slice
+    pub fn rebuild(&mut self, positions: &[Vector3]) {
+        self.clear();
+        for (index, position) in positions.iter().enumerate() {
+            self.insert(index, position);
+        }
+    }
+
+    /// Fills `candidates` with every index in the cells around the position.
+    /// This includes everything within one cell size of it, and some points further away.
+    pub fn get_candidates(&self, position: &Vector3, candidates: &mut Vec<usize>) {
+        candidates.clear();
+        let (x, y, z) = self.get_cell(position);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    if let Some(cell) = self.cells.get(&(x + dx, y + dy, z + dz)) {
+                        candidates.extend_from_slice(cell);
+                    }
+                }
+            }
+        }
+    }
+}
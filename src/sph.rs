@@ -0,0 +1,469 @@
+use std::{cell::RefCell, f32::consts::PI, rc::Rc};
+
+use crate::{
+    core::Vector3,
+    particle::{Particle, ParticleIntegrator},
+    precision::real_pow,
+    spatial_grid::SpatialGrid,
+};
+
+/// How the fluid's pressure follows from its density.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SphEquationOfState {
+    /// `p = stiffness ((density / rest density)^exponent - 1)`, the usual choice for water.
+    /// A stiffness of `c^2 rest density / exponent` keeps the density within about 1% of rest
+    /// when nothing moves faster than a tenth of the sound speed `c`.
+    /// Pressure below zero is clamped, since the thin density at a free surface would
+    /// otherwise pull the particles there into clumps.
+    Tait { stiffness: f32, exponent: f32 },
+    /// `p = stiffness (density - rest density)`, softer and cheaper, suits gases and splashy effects
+    IdealGas { stiffness: f32 },
+}
+
+impl SphEquationOfState {
+    pub fn get_pressure(&self, density: f32, rest_density: f32) -> f32 {
+        return match self {
+            SphEquationOfState::Tait { stiffness, exponent } => (stiffness * (real_pow(density / rest_density, *exponent) - 1.0)).max(0.0),
+            SphEquationOfState::IdealGas { stiffness } => stiffness * (density - rest_density),
+        };
+    }
+}
+
+/// How an `SphFluid` behaves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SphSettings {
+    /// The kernel radius, each particle feels the others within this distance
+    pub smoothing_length: f32,
+    /// Kilograms per cubic metre
+    pub rest_density: f32,
+    pub equation_of_state: SphEquationOfState,
+    /// Dynamic viscosity in pascal seconds
+    pub viscosity: f32,
+    /// Surface tension coefficient in newtons per metre, zero turns it off
+    pub surface_tension: f32,
+}
+
+impl Default for SphSettings {
+    /// Water at a resolution of a few centimetres
+    fn default() -> Self {
+        return Self {
+            smoothing_length: 0.04,
+            rest_density: 1000.0,
+            equation_of_state: SphEquationOfState::Tait { stiffness: 1000.0 * 20.0 * 20.0 / 7.0, exponent: 7.0 },
+            viscosity: 0.5,
+            surface_tension: 0.0
+        };
+    }
+}
+
+/// A wall fluid particles cannot pass, the points `x` with `normal . x >= offset` are inside
+/// the container.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SphPlane {
+    /// Unit normal pointing into the fluid
+    pub normal: Vector3,
+    pub offset: f32,
+    /// Fraction of the speed into the wall kept as a bounce, zero stops the particle dead
+    pub restitution: f32,
+}
+
+/// The smoothing kernels for a support radius `h`. Density and pressure use Monaghan's cubic
+/// spline, whose density keeps rising as particles close in, so a stiff equation of state can
+/// stop them clumping. The surface colour field uses the poly6 kernel of Muller et al.
+/// "Particle-Based Fluid Simulation for Interactive Applications".
+#[derive(Debug, Clone, Copy)]
+struct SphKernels {
+    h: f32,
+    cubic: f32,
+    cubic_gradient: f32,
+    poly6_gradient: f32,
+}
+
+impl SphKernels {
+    fn new(h: f32) -> SphKernels {
+        let h3: f32 = h * h * h;
+        return SphKernels {
+            h,
+            cubic: 8.0 / (PI * h3),
+            cubic_gradient: 48.0 / (PI * h3),
+            poly6_gradient: -945.0 / (32.0 * PI * h3 * h3 * h3)
+        };
+    }
+
+    /// W(q) = 8 / (pi h^3) (6q^3 - 6q^2 + 1) up to q = r/h = 1/2, then 16 / (pi h^3) (1 - q)^3
+    fn cubic(&self, distance: f32) -> f32 {
+        let q: f32 = distance / self.h;
+        if q >= 1.0 { return 0.0; }
+        if q <= 0.5 { return self.cubic * (6.0 * q * q * (q - 1.0) + 1.0); }
+        let remaining: f32 = 1.0 - q;
+        return self.cubic * 2.0 * remaining * remaining * remaining;
+    }
+
+    /// The gradient of the cubic spline with respect to the first particle, given the offset
+    /// from the second particle to the first
+    fn cubic_gradient(&self, offset: &Vector3, distance: f32) -> Vector3 {
+        let q: f32 = distance / self.h;
+        if distance <= 0.0 || q >= 1.0 { return Vector3::default(); }
+        let slope: f32 = if q <= 0.5 { q * (3.0 * q - 2.0) } else { -(1.0 - q) * (1.0 - q) };
+        return *offset * (self.cubic_gradient * slope / (distance * self.h));
+    }
+
+    /// The gradient and the laplacian of the poly6 kernel, for the surface colour field
+    fn poly6_derivatives(&self, offset: &Vector3, square_distance: f32) -> (Vector3, f32) {
+        let difference: f32 = self.h * self.h - square_distance;
+        if difference <= 0.0 { return (Vector3::default(), 0.0); }
+        let gradient: Vector3 = *offset * (self.poly6_gradient * difference * difference);
+        let laplacian: f32 = self.poly6_gradient * difference * (3.0 * self.h * self.h - 7.0 * square_distance);
+        return (gradient, laplacian);
+    }
+}
+
+/// A fluid simulated with smoothed-particle hydrodynamics, each particle carrying a small parcel
+/// of the fluid's mass.
+///
+/// Every step works out each particle's density from its neighbours, turns it into a pressure with
+/// the equation of state, and pushes particles from high pressure to low, with viscosity and
+/// surface tension on top. Fixed boundary particles and planes keep the fluid in.
+///
+/// Particles added to the fluid are advanced by `step` with semi-implicit Euler and must not be
+/// passed to `Particle::integrate` as well. Forces other generators leave in a particle's accumulator,
+/// and its acceleration, are included. The step must stay under about `0.4 h / c` for a kernel
+/// radius `h` and sound speed `c`, so use substeps for stiff fluids.
+pub struct SphFluid {
+    particles: Vec<Rc<RefCell<Particle>>>,
+    settings: SphSettings,
+    kernels: SphKernels,
+    /// Fixed particles lining solid walls, they add to the density and push back
+    boundary: Vec<Vector3>,
+    boundary_masses: Vec<f32>,
+    planes: Vec<SphPlane>,
+    gravity: Vector3,
+    substeps: usize,
+    densities: Vec<f32>,
+    pressures: Vec<f32>,
+    grid: SpatialGrid,
+}
+
+impl SphFluid {
+    pub fn new(settings: &SphSettings) -> SphFluid {
+        assert!(settings.smoothing_length > 0.0 && settings.rest_density > 0.0);
+        return SphFluid {
+            particles: Vec::new(),
+            settings: *settings,
+            kernels: SphKernels::new(settings.smoothing_length),
+            boundary: Vec::new(),
+            boundary_masses: Vec::new(),
+            planes: Vec::new(),
+            gravity: Vector3::default(),
+            substeps: 1,
+            densities: Vec::new(),
+            pressures: Vec::new(),
+            grid: SpatialGrid::new(settings.smoothing_length)
+        };
+    }
+
+    pub fn get_settings(&self) -> &SphSettings {
+        return &self.settings;
+    }
+
+    /// Adds a fluid particle and returns its index
+    pub fn add_particle(&mut self, particle: &Rc<RefCell<Particle>>) -> usize {
+        self.particles.push(Rc::clone(particle));
+        self.densities.push(self.settings.rest_density);
+        self.pressures.push(0.0);
+        return self.particles.len() - 1;
+    }
+
+    /// Fills the box between the corners with particles on a cubic lattice of the spacing,
+    /// each carrying the mass of its cube of fluid at rest. Returns the new particles.
+    pub fn fill_box(&mut self, min: Vector3, max: Vector3, spacing: f32, damping: f32) -> Vec<Rc<RefCell<Particle>>> {
+        assert!(spacing > 0.0);
+        let mass: f32 = self.settings.rest_density * spacing * spacing * spacing;
+        let count = |low: f32, high: f32| -> usize { return ((high - low) / spacing).floor().max(0.0) as usize + 1; };
+        let mut added: Vec<Rc<RefCell<Particle>>> = Vec::new();
+        for k in 0..count(min.z, max.z) {
+            for j in 0..count(min.y, max.y) {
+                for i in 0..count(min.x, max.x) {
+                    let position: Vector3 = min + &Vector3::new(i as f32 * spacing, j as f32 * spacing, k as f32 * spacing);
+                    let particle = Rc::new(RefCell::new(Particle::new(position, Vector3::default(), Vector3::default(), damping, mass)));
+                    self.add_particle(&particle);
+                    added.push(particle);
+                }
+            }
+        }
+        return added;
+    }
+
+    pub fn get_particles(&self) -> &[Rc<RefCell<Particle>>] {
+        return &self.particles;
+    }
+
+    /// Adds a fixed particle for lining walls. Give it the mass of a fluid particle at the
+    /// spacing the wall is sampled at.
+    pub fn add_boundary_particle(&mut self, position: Vector3, mass: f32) {
+        self.boundary.push(position);
+        self.boundary_masses.push(mass);
+    }
+
+    pub fn get_boundary_particles(&self) -> &[Vector3] {
+        return &self.boundary;
+    }
+
+    pub fn add_plane(&mut self, plane: SphPlane) {
+        self.planes.push(plane);
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector3) {
+        self.gravity = gravity;
+    }
+
+    /// Splits every step into this many equal substeps
+    pub fn set_substeps(&mut self, substeps: usize) {
+        assert!(substeps > 0);
+        self.substeps = substeps;
+    }
+
+    /// The density of each fluid particle from the last step, in particle order
+    pub fn get_densities(&self) -> &[f32] {
+        return &self.densities;
+    }
+
+    /// The pressure of each fluid particle from the last step, in particle order
+    pub fn get_pressures(&self) -> &[f32] {
+        return &self.pressures;
+    }
+
+    pub fn get_density(&self, index: usize) -> f32 {
+        return self.densities[index];
+    }
+
+    pub fn get_pressure(&self, index: usize) -> f32 {
+        return self.pressures[index];
+    }
+
+    /// Advances the fluid by the duration
+    pub fn step(&mut self, duration: f32) {
+        assert!(duration > 0.0);
+        if self.particles.is_empty() { return; }
+        let substep: f32 = duration / self.substeps as f32;
+        // Forces from other generators are held for the whole step
+        let external: Vec<Vector3> = self.particles.iter().map(|p| p.borrow().force_accum).collect();
+        for _ in 0..self.substeps {
+            let accelerations: Vec<Vector3> = self.calculate_accelerations();
+            for (i, particle) in self.particles.iter().enumerate() {
+                let mut particle = particle.borrow_mut();
+                particle.clear_accumulator();
+                let mass: f32 = particle.get_mass();
+                particle.add_force(accelerations[i] * mass + &external[i]);
+                particle.integrate_with(ParticleIntegrator::SemiImplicitEuler, substep);
+                self.enforce_planes(&mut particle);
+            }
+        }
+    }
+
+    /// Updates the densities and pressures, then returns the acceleration the fluid gives each particle
+    fn calculate_accelerations(&mut self) -> Vec<Vector3> {
+        let count: usize = self.particles.len();
+        let mut positions: Vec<Vector3> = Vec::with_capacity(count + self.boundary.len());
+        let mut velocities: Vec<Vector3> = Vec::with_capacity(count);
+        let mut masses: Vec<f32> = Vec::with_capacity(count + self.boundary.len());
+        for particle in self.particles.iter() {
+            let particle = particle.borrow();
+            positions.push(particle.get_position());
+            velocities.push(particle.get_velocity());
+            masses.push(particle.get_mass());
+        }
+        // Boundary particles go after the fluid ones, so an index past the fluid is a wall
+        positions.extend_from_slice(&self.boundary);
+        masses.extend_from_slice(&self.boundary_masses);
+        self.grid.rebuild(&positions);
+
+        let kernels: SphKernels = self.kernels;
+        let mut neighbours: Vec<Vec<usize>> = Vec::with_capacity(count);
+        let mut candidates: Vec<usize> = Vec::new();
+        for i in 0..count {
+            self.grid.get_candidates(&positions[i], &mut candidates);
+            let h_squared: f32 = kernels.h * kernels.h;
+            neighbours.push(candidates.iter().copied()
+                .filter(|j| (positions[*j] - &positions[i]).square_magnitude() < h_squared)
+                .collect());
+        }
+
+        for i in 0..count {
+            // Each particle counts itself, so the density never reaches zero
+            let density: f32 = neighbours[i].iter()
+                .map(|j| masses[*j] * kernels.cubic((positions[i] - &positions[*j]).magnitude()))
+                .sum();
+            self.densities[i] = density;
+            self.pressures[i] = self.settings.equation_of_state.get_pressure(density, self.settings.rest_density);
+        }
+
+        let mut accelerations: Vec<Vector3> = vec![self.gravity; count];
+        for i in 0..count {
+            let density: f32 = self.densities[i];
+            let pressure_term: f32 = self.pressures[i] / (density * density);
+            let mut pressure: Vector3 = Vector3::default();
+            let mut viscosity: Vector3 = Vector3::default();
+            let mut colour_gradient: Vector3 = Vector3::default();
+            let mut colour_laplacian: f32 = 0.0;
+
+            for &j in neighbours[i].iter() {
+                let offset: Vector3 = positions[i] - &positions[j];
+                let square_distance: f32 = offset.square_magnitude();
+                if self.settings.surface_tension > 0.0 && j < count {
+                    // The colour field includes the particle itself
+                    let (gradient, laplacian) = kernels.poly6_derivatives(&offset, square_distance);
+                    colour_gradient.add_scaled_vector(&gradient, masses[j] / self.densities[j]);
+                    colour_laplacian += masses[j] / self.densities[j] * laplacian;
+                }
+                if j == i { continue; }
+                let distance: f32 = square_distance.sqrt();
+                let gradient: Vector3 = kernels.cubic_gradient(&offset, distance);
+
+                if j >= count {
+                    // A wall particle pushes back with the fluid particle's own pressure
+                    pressure.add_scaled_vector(&gradient, -masses[j] * 2.0 * pressure_term.max(0.0));
+                    continue;
+                }
+                // a = -sum m_j (p_i / rho_i^2 + p_j / rho_j^2) grad W
+                let neighbour_term: f32 = self.pressures[j] / (self.densities[j] * self.densities[j]);
+                pressure.add_scaled_vector(&gradient, -masses[j] * (pressure_term + neighbour_term));
+                // a = 10 mu / rho_i sum m_j / rho_j (v_ij . x_ij) / (|x_ij|^2 + 0.01 h^2) grad W,
+                // the laplacian estimate of Monaghan's "Smoothed particle hydrodynamics" (2005)
+                let approach: f32 = (velocities[i] - &velocities[j]) * &offset;
+                viscosity.add_scaled_vector(
+                    &gradient,
+                    masses[j] / self.densities[j] * approach / (square_distance + 0.01 * kernels.h * kernels.h)
+                );
+            }
+
+            accelerations[i] += &pressure;
+            accelerations[i].add_scaled_vector(&viscosity, 10.0 * self.settings.viscosity / density);
+            // Only particles near the surface have a colour gradient worth following,
+            // a = -sigma lap(c) n / (|n| rho)
+            let surface: f32 = colour_gradient.magnitude();
+            if self.settings.surface_tension > 0.0 && surface > 1.0 / kernels.h {
+                accelerations[i].add_scaled_vector(&colour_gradient, -self.settings.surface_tension * colour_laplacian / (surface * density));
+            }
+        }
+        return accelerations;
+    }
+
+    /// Moves a particle back inside every plane and takes out its speed into the wall
+    fn enforce_planes(&self, particle: &mut Particle) {
+        for plane in self.planes.iter() {
+            let depth: f32 = plane.offset - plane.normal * &particle.get_position();
+            if depth <= 0.0 { continue; }
+            particle.position.add_scaled_vector(&plane.normal, depth);
+            let speed_in: f32 = particle.get_velocity() * &plane.normal;
+            if speed_in < 0.0 {
+                particle.velocity.add_scaled_vector(&plane.normal, -(1.0 + plane.restitution) * speed_in);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::Random;
+
+    /// A cube of fluid `cells` spacings across, at a spacing of 1/2.5 of the kernel radius
+    fn lattice(settings: &SphSettings, cells: f32) -> (SphFluid, f32) {
+        let spacing: f32 = settings.smoothing_length / 2.5;
+        let mut fluid: SphFluid = SphFluid::new(settings);
+        let corner: Vector3 = Vector3::new(cells * spacing, cells * spacing, cells * spacing);
+        fluid.fill_box(Vector3::default(), corner, spacing, 1.0);
+        return (fluid, spacing);
+    }
+
+    #[test]
+    fn lattices_at_rest_density_have_no_pressure() {
+        let states: [SphEquationOfState; 2] = [
+            SphSettings::default().equation_of_state,
+            SphEquationOfState::IdealGas { stiffness: 500.0 },
+        ];
+        for equation_of_state in states {
+            let settings: SphSettings = SphSettings { equation_of_state, ..SphSettings::default() };
+            let (mut fluid, spacing) = lattice(&settings, 10.0);
+            fluid.step(1.0e-6);
+
+            // The middle particle has a full set of neighbours
+            let centre: Vector3 = Vector3::new(5.0, 5.0, 5.0) * spacing;
+            let middle: usize = fluid.get_particles().iter()
+                .position(|p| (p.borrow().get_position() - &centre).magnitude() < 1.0e-4)
+                .unwrap();
+            let density: f32 = fluid.get_density(middle);
+            assert!((density / settings.rest_density - 1.0).abs() < 0.01, "the density is {}", density);
+            // Less than a one percent compression would give
+            let limit: f32 = equation_of_state.get_pressure(1.01 * settings.rest_density, settings.rest_density);
+            let pressure: f32 = fluid.get_pressure(middle);
+            assert!(pressure.abs() < limit, "{:?} gives a pressure of {}", equation_of_state, pressure);
+        }
+    }
+
+    #[test]
+    fn neighbours_match_a_brute_force_search() {
+        let settings: SphSettings = SphSettings::default();
+        let mut fluid: SphFluid = SphFluid::new(&settings);
+        let mut random: Random = Random::new(7);
+        for _ in 0..300 {
+            let position: Vector3 = random.random_vector_range(&Vector3::new(-0.1, -0.1, -0.1), &Vector3::new(0.1, 0.1, 0.1));
+            fluid.add_particle(&Rc::new(RefCell::new(Particle::new(position, Vector3::default(), Vector3::default(), 1.0, 0.01))));
+        }
+        let positions: Vec<Vector3> = fluid.get_particles().iter().map(|p| p.borrow().get_position()).collect();
+        fluid.step(1.0e-6);
+
+        // The densities only come out right if every particle found each of its neighbours
+        let kernels: SphKernels = SphKernels::new(settings.smoothing_length);
+        for (i, position) in positions.iter().enumerate() {
+            let density: f32 = positions.iter().map(|other| 0.01 * kernels.cubic((*position - other).magnitude())).sum();
+            let found: f32 = fluid.get_density(i);
+            assert!((found - density).abs() <= 1.0e-4 * density, "particle {} has {} but should have {}", i, found, density);
+        }
+    }
+
+    #[test]
+    fn particles_stay_inside_planes() {
+        let settings: SphSettings = SphSettings::default();
+        let (mut fluid, spacing) = lattice(&settings, 5.0);
+        fluid.set_gravity(Vector3::new(0.0, -9.81, 0.0));
+        fluid.set_substeps(10);
+        let walls: [(Vector3, f32); 3] = [
+            (Vector3::new(0.0, 1.0, 0.0), -0.05),
+            (Vector3::new(1.0, 0.0, 0.0), -spacing),
+            (Vector3::new(-1.0, 0.0, 0.0), -6.0 * spacing),
+        ];
+        for (normal, offset) in walls {
+            fluid.add_plane(SphPlane { normal, offset, restitution: 0.2 });
+        }
+
+        for _ in 0..60 {
+            fluid.step(1.0 / 60.0);
+            for particle in fluid.get_particles().iter() {
+                let position: Vector3 = particle.borrow().get_position();
+                for (normal, offset) in walls {
+                    assert!(normal * &position >= offset - 1.0e-5, "a particle got through to {:?}", position);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn densities_and_pressures_cover_every_particle() {
+        let (mut fluid, _) = lattice(&SphSettings::default(), 2.0);
+        fluid.add_particle(&Rc::new(RefCell::new(Particle::new(
+            Vector3::new(1.0, 0.0, 0.0), Vector3::default(), Vector3::default(), 1.0, 1.0
+        ))));
+        assert_eq!(fluid.get_particles().len(), 28);
+        assert_eq!(fluid.get_densities().len(), 28);
+        assert_eq!(fluid.get_pressures().len(), 28);
+
+        fluid.step(1.0e-3);
+        assert_eq!(fluid.get_densities().len(), 28);
+        assert_eq!(fluid.get_pressures().len(), 28);
+        // The lone particle only counts itself
+        assert_eq!(fluid.get_density(27), SphKernels::new(fluid.get_settings().smoothing_length).cubic(0.0));
+    }
+}
+
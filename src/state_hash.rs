@@ -0,0 +1,69 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{core::Vector3, particle::Particle, precision::Real};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Hashes simulation state bit for bit with 64-bit FNV-1a, so two machines running the same
+/// simulation can compare a single number each step to detect a desync.
+/// Floats are hashed by their bits, so `0.0` and `-0.0` hash differently, as do different NaNs.
+/// The hash does not depend on the platform or the Rust version.
+#[derive(Debug, Clone)]
+pub struct StateHasher {
+    state: u64,
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        return Self {
+            state: FNV_OFFSET_BASIS
+        };
+    }
+}
+
+impl StateHasher {
+    pub fn new() -> StateHasher {
+        return StateHasher::default();
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter() {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_f32(&mut self, value: f32) {
+        self.write_bytes(&value.to_bits().to_le_bytes());
+    }
+
+    /// Writes the raw bits of any `Real`, floats and fixed-point alike
+    pub fn write_real<R: Real>(&mut self, value: R) {
+        self.write_u64(value.to_bits_u64());
+    }
+
+    pub fn write_vector3<R: Real>(&mut self, vector: &Vector3<R>) {
+        self.write_real(vector.x);
+        self.write_real(vector.y);
+        self.write_real(vector.z);
+    }
+
+    pub fn finish(&self) -> u64 {
+        return self.state;
+    }
+}
+
+/// Hashes the state of the particles in order
+pub fn hash_particles<R: Real>(particles: &[Rc<RefCell<Particle<R>>>]) -> u64 {
+    let mut hasher: StateHasher = StateHasher::new();
+    hasher.write_u64(particles.len() as u64);
+    for particle in particles.iter() {
+        particle.borrow().hash_state(&mut hasher);
+    }
+    return hasher.finish();
+}
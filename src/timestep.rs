@@ -0,0 +1,211 @@
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use crate::{core::Vector3, particle::Particle, precision::Real};
+
+/// Turns variable frame times into a whole number of fixed physics steps.
+///
+/// Frame time is added to an accumulator and a step is taken for every full `step` it holds.
+/// What is left over is carried to the next frame and reported as the interpolation alpha.
+/// If a frame needs more than `max_substeps` steps the extra time is dropped rather than
+/// carried, otherwise a slow frame makes the next one slower (the "spiral of death").
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    /// The fixed duration of each physics step
+    step: Duration,
+    /// The most steps run in a single frame
+    max_substeps: u32,
+    /// Frame time not yet simulated, always less than one step after `advance`
+    accumulator: Duration,
+    /// Total time thrown away by the substep clamp
+    dropped: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, max_substeps: u32) -> FixedTimestep {
+        assert!(!step.is_zero());
+        assert!(max_substeps > 0);
+        return FixedTimestep {
+            step,
+            max_substeps,
+            accumulator: Duration::ZERO,
+            dropped: Duration::ZERO
+        };
+    }
+
+    /// Convenience constructor for a number of steps per second, e.g. 60.0
+    pub fn from_rate(steps_per_second: f32, max_substeps: u32) -> FixedTimestep {
+        return FixedTimestep::new(Duration::from_secs_f32(1.0 / steps_per_second), max_substeps);
+    }
+
+    /// Adds the frame time to the accumulator and calls `step_fn` with the step duration in seconds
+    /// once for each whole step. Returns the number of steps taken.
+    pub fn advance<F: FnMut(f32)>(&mut self, frame_time: Duration, mut step_fn: F) -> u32 {
+        self.accumulator += frame_time;
+        let duration: f32 = self.step.as_secs_f32();
+
+        let mut steps: u32 = 0;
+        while self.accumulator >= self.step && steps < self.max_substeps {
+            step_fn(duration);
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+
+        // Throw away the whole steps we did not have time for, keep the fraction
+        if self.accumulator >= self.step {
+            let remainder: Duration = Duration::from_nanos(
+                (self.accumulator.as_nanos() % self.step.as_nanos()) as u64
+            );
+            self.dropped += self.accumulator - remainder;
+            self.accumulator = remainder;
+        }
+        return steps;
+    }
+
+    /// How far between the last step and the next one the current frame is, between 0 and 1.
+    /// Render at `previous + (current - previous) * alpha`.
+    pub fn get_alpha(&self) -> f32 {
+        return self.accumulator.as_secs_f32() / self.step.as_secs_f32();
+    }
+
+    pub fn get_step(&self) -> Duration {
+        return self.step;
+    }
+
+    pub fn get_max_substeps(&self) -> u32 {
+        return self.max_substeps;
+    }
+
+    /// Returns the total simulation time thrown away by the substep clamp
+    pub fn get_dropped(&self) -> Duration {
+        return self.dropped;
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = Duration::ZERO;
+        self.dropped = Duration::ZERO;
+    }
+}
+
+/// Drives a fixed timestep and remembers where the tracked particles were before the last step,
+/// so a renderer can draw them between physics steps.
+/// The timestep keeps time in `f32` seconds and converts the step duration to the particles' scalar type.
+pub struct ParticleStepper<R: Real = f32> {
+    timestep: FixedTimestep,
+    particles: Vec<Rc<RefCell<Particle<R>>>>,
+    previous_positions: Vec<Vector3<R>>,
+}
+
+impl<R: Real> ParticleStepper<R> {
+    pub fn new(timestep: FixedTimestep) -> ParticleStepper<R> {
+        return ParticleStepper {
+            timestep,
+            particles: Vec::new(),
+            previous_positions: Vec::new()
+        };
+    }
+
+    /// Starts tracking the particle and returns its index for the position queries
+    pub fn track(&mut self, particle: &Rc<RefCell<Particle<R>>>) -> usize {
+        self.previous_positions.push(particle.borrow().get_position());
+        self.particles.push(Rc::clone(particle));
+        return self.particles.len() - 1;
+    }
+
+    /// Advances the simulation by the frame time, `step_fn` is called with the step duration
+    /// in seconds and should update forces and integrate. Returns the number of steps taken.
+    pub fn advance<F: FnMut(R)>(&mut self, frame_time: Duration, mut step_fn: F) -> u32 {
+        let particles: &[Rc<RefCell<Particle<R>>>] = &self.particles;
+        let previous_positions: &mut Vec<Vector3<R>> = &mut self.previous_positions;
+        return self.timestep.advance(frame_time, |duration| {
+            for (previous, particle) in previous_positions.iter_mut().zip(particles.iter()) {
+                *previous = particle.borrow().get_position();
+            }
+            step_fn(R::from_f32(duration));
+        });
+    }
+
+    pub fn get_alpha(&self) -> f32 {
+        return self.timestep.get_alpha();
+    }
+
+    pub fn get_timestep(&self) -> &FixedTimestep {
+        return &self.timestep;
+    }
+
+    /// Position of the tracked particle before the last step
+    pub fn get_previous_position(&self, index: usize) -> Vector3<R> {
+        return self.previous_positions[index];
+    }
+
+    /// Position of the tracked particle after the last step
+    pub fn get_current_position(&self, index: usize) -> Vector3<R> {
+        return self.particles[index].borrow().get_position();
+    }
+
+    /// Position of the tracked particle blended between the last two steps by the alpha
+    pub fn get_interpolated_position(&self, index: usize) -> Vector3<R> {
+        let previous: Vector3<R> = self.get_previous_position(index);
+        let current: Vector3<R> = self.get_current_position(index);
+        return previous + &((current - &previous) * R::from_f32(self.get_alpha()));
+    }
+
+    /// Interpolated positions of every tracked particle, in tracking order
+    pub fn get_interpolated_positions(&self) -> Vec<Vector3<R>> {
+        return (0..self.particles.len()).map(|i| self.get_interpolated_position(i)).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(milliseconds: u64) -> Duration {
+        return Duration::from_millis(milliseconds);
+    }
+
+    #[test]
+    fn uneven_frames_take_whole_steps() {
+        let mut timestep: FixedTimestep = FixedTimestep::new(millis(10), 4);
+        let mut durations: Vec<f32> = Vec::new();
+        // Each frame with the steps it should take and the time left over
+        for (frame, steps, left) in [(15, 1, 5), (7, 1, 2), (0, 0, 2), (31, 3, 3), (4, 0, 7)] {
+            assert_eq!(timestep.advance(millis(frame), |duration| durations.push(duration)), steps, "{} ms frame", frame);
+            assert!((timestep.get_alpha() - left as f32 / 10.0).abs() < 1.0e-6, "{} ms frame", frame);
+        }
+        assert_eq!(durations, vec![0.01; 5]);
+        assert_eq!(timestep.get_dropped(), Duration::ZERO);
+    }
+
+    #[test]
+    fn slow_frames_are_clamped_and_the_excess_dropped() {
+        let mut timestep: FixedTimestep = FixedTimestep::new(millis(10), 4);
+        timestep.advance(millis(2), |_| {});
+        assert_eq!(timestep.advance(millis(105), |_| {}), 4);
+        // 107 ms less the 40 simulated leaves 67, of which the 60 in whole steps are dropped
+        assert_eq!(timestep.get_dropped(), millis(60));
+        assert!((timestep.get_alpha() - 0.7).abs() < 1.0e-6);
+
+        assert_eq!(timestep.advance(millis(3), |_| {}), 1);
+        timestep.reset();
+        assert_eq!(timestep.get_dropped(), Duration::ZERO);
+        assert_eq!(timestep.get_alpha(), 0.0);
+    }
+
+    #[test]
+    fn positions_are_interpolated_between_steps() {
+        let particle: Rc<RefCell<Particle>> = Rc::new(RefCell::new(Particle::new(
+            Vector3::default(), Vector3::new(1.0, 0.0, 0.0), Vector3::default(), 1.0, 1.0
+        )));
+        let mut stepper: ParticleStepper = ParticleStepper::new(FixedTimestep::new(millis(10), 4));
+        let index: usize = stepper.track(&particle);
+
+        assert_eq!(stepper.advance(millis(25), |duration| particle.borrow_mut().integrate(duration)), 2);
+        let previous: f32 = stepper.get_previous_position(index).x;
+        let current: f32 = stepper.get_current_position(index).x;
+        assert!((previous - 0.01).abs() < 1.0e-6 && (current - 0.02).abs() < 1.0e-6);
+        // Half way through the third step
+        assert!((stepper.get_interpolated_position(index).x - 0.015).abs() < 1.0e-6);
+        assert_eq!(stepper.get_interpolated_positions(), vec![stepper.get_interpolated_position(index)]);
+    }
+}
+
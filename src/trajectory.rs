@@ -0,0 +1,359 @@
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{core::Vector3, particle::Particle, precision::Real};
+
+/// A value the trajectory recorder can write for each sampled particle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrajectoryColumn {
+    /// Simulated seconds since recording started
+    Time,
+    /// Index of the step, counted from when recording started
+    Step,
+    /// The name the particle was tracked under
+    Particle,
+    Position,
+    Velocity,
+    /// The acceleration the particle is integrated with, its own plus the accumulated force over its mass
+    Acceleration,
+    KineticEnergy,
+    /// The force accumulated for the current step
+    Force,
+}
+
+impl TrajectoryColumn {
+    /// Every column, in the default order
+    pub const ALL: [TrajectoryColumn; 8] = [
+        TrajectoryColumn::Time,
+        TrajectoryColumn::Step,
+        TrajectoryColumn::Particle,
+        TrajectoryColumn::Position,
+        TrajectoryColumn::Velocity,
+        TrajectoryColumn::Acceleration,
+        TrajectoryColumn::KineticEnergy,
+        TrajectoryColumn::Force,
+    ];
+
+    /// The name used in the CSV header and as the JSON key
+    pub fn get_name(&self) -> &'static str {
+        return match self {
+            TrajectoryColumn::Time => "time",
+            TrajectoryColumn::Step => "step",
+            TrajectoryColumn::Particle => "particle",
+            TrajectoryColumn::Position => "position",
+            TrajectoryColumn::Velocity => "velocity",
+            TrajectoryColumn::Acceleration => "acceleration",
+            TrajectoryColumn::KineticEnergy => "kinetic_energy",
+            TrajectoryColumn::Force => "force",
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrajectoryFormat {
+    /// A header row then one row per sample. Vectors take three columns, such as `position_x`.
+    Csv,
+    /// One JSON object per sample per line. Vectors are arrays of three numbers,
+    /// and numbers that are not finite are written as `null`.
+    JsonLines,
+}
+
+/// Samples chosen particles every few steps and streams what it sees to a writer,
+/// one row per particle per sample.
+///
+/// Call `record` once per step after the forces have been updated and before the particles
+/// are integrated, since integrating clears the accumulated force.
+/// Values are written as `f32` whatever scalar type the simulation uses.
+pub struct TrajectoryRecorder<W: Write, R: Real = f32> {
+    writer: W,
+    format: TrajectoryFormat,
+    columns: Vec<TrajectoryColumn>,
+    particles: Vec<(String, Rc<RefCell<Particle<R>>>)>,
+    /// Samples are taken every this many steps
+    interval: u64,
+    step: u64,
+    /// Kept in `f64` so long recordings do not drift
+    time: f64,
+    header_written: bool,
+}
+
+impl<W: Write, R: Real> TrajectoryRecorder<W, R> {
+    pub fn new(writer: W, format: TrajectoryFormat, interval: u64) -> TrajectoryRecorder<W, R> {
+        assert!(interval > 0);
+        return TrajectoryRecorder {
+            writer,
+            format,
+            columns: TrajectoryColumn::ALL.to_vec(),
+            particles: Vec::new(),
+            interval,
+            step: 0,
+            time: 0.0,
+            header_written: false
+        };
+    }
+
+    /// Chooses which columns are written and in what order.
+    /// Must be called before the first sample is written.
+    pub fn set_columns(&mut self, columns: &[TrajectoryColumn]) {
+        assert!(!self.header_written, "the columns cannot change once recording has started");
+        self.columns = columns.to_vec();
+    }
+
+    pub fn get_columns(&self) -> &[TrajectoryColumn] {
+        return &self.columns;
+    }
+
+    /// Adds a particle to sample, rows are written in the order particles are tracked
+    pub fn track(&mut self, name: &str, particle: &Rc<RefCell<Particle<R>>>) {
+        self.particles.push((name.to_string(), Rc::clone(particle)));
+    }
+
+    pub fn get_step(&self) -> u64 {
+        return self.step;
+    }
+
+    pub fn get_time(&self) -> f64 {
+        return self.time;
+    }
+
+    /// Samples the tracked particles if this is a sampled step, then moves the clock on by the duration
+    pub fn record(&mut self, duration: R) -> io::Result<()> {
+        if self.step.is_multiple_of(self.interval) {
+            self.sample()?;
+        }
+        self.step += 1;
+        self.time += duration.to_f32() as f64;
+        return Ok(());
+    }
+
+    /// Writes a row for every tracked particle now, whatever the step
+    pub fn sample(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            if self.format == TrajectoryFormat::Csv {
+                self.write_csv_header()?;
+            }
+            self.header_written = true;
+        }
+        for (name, particle) in self.particles.iter() {
+            let particle = particle.borrow();
+            match self.format {
+                TrajectoryFormat::Csv => write_csv_row(&mut self.writer, &self.columns, self.step, self.time, name, &particle)?,
+                TrajectoryFormat::JsonLines => write_json_row(&mut self.writer, &self.columns, self.step, self.time, name, &particle)?,
+            }
+        }
+        return Ok(());
+    }
+
+    fn write_csv_header(&mut self) -> io::Result<()> {
+        let mut names: Vec<String> = Vec::new();
+        for column in self.columns.iter() {
+            match column {
+                TrajectoryColumn::Position | TrajectoryColumn::Velocity
+                | TrajectoryColumn::Acceleration | TrajectoryColumn::Force => {
+                    for axis in ["x", "y", "z"] {
+                        names.push(format!("{}_{}", column.get_name(), axis));
+                    }
+                }
+                _ => names.push(column.get_name().to_string()),
+            }
+        }
+        return writeln!(self.writer, "{}", names.join(","));
+    }
+
+    /// Flushes the writer and hands it back
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+/// The acceleration `Particle::integrate` would use
+fn resulting_acceleration<R: Real>(particle: &Particle<R>) -> Vector3<R> {
+    let mut acceleration: Vector3<R> = particle.get_acceleration();
+    acceleration.add_scaled_vector(&particle.force_accum, particle.get_inverse_mass());
+    return acceleration;
+}
+
+fn write_csv_row<W: Write, R: Real>(
+    writer: &mut W,
+    columns: &[TrajectoryColumn],
+    step: u64,
+    time: f64,
+    name: &str,
+    particle: &Particle<R>
+) -> io::Result<()> {
+    let mut fields: Vec<String> = Vec::with_capacity(columns.len() * 3);
+    let push_vector = |fields: &mut Vec<String>, v: Vector3<R>| {
+        fields.push(v.x.to_f32().to_string());
+        fields.push(v.y.to_f32().to_string());
+        fields.push(v.z.to_f32().to_string());
+    };
+    for column in columns.iter() {
+        match column {
+            TrajectoryColumn::Time => fields.push(time.to_string()),
+            TrajectoryColumn::Step => fields.push(step.to_string()),
+            TrajectoryColumn::Particle => fields.push(format!("\"{}\"", name.replace('"', "\"\""))),
+            TrajectoryColumn::Position => push_vector(&mut fields, particle.get_position()),
+            TrajectoryColumn::Velocity => push_vector(&mut fields, particle.get_velocity()),
+            TrajectoryColumn::Acceleration => push_vector(&mut fields, resulting_acceleration(particle)),
+            TrajectoryColumn::KineticEnergy => fields.push(particle.calculate_kinetic_energy().to_f32().to_string()),
+            TrajectoryColumn::Force => push_vector(&mut fields, particle.force_accum),
+        }
+    }
+    return writeln!(writer, "{}", fields.join(","));
+}
+
+fn json_number(value: f64) -> String {
+    if !value.is_finite() { return "null".to_string(); }
+    return value.to_string();
+}
+
+/// Written in the shortest form that reads back as the same `f32`
+fn json_number_f32(value: f32) -> String {
+    if !value.is_finite() { return "null".to_string(); }
+    return value.to_string();
+}
+
+fn json_vector<R: Real>(vector: Vector3<R>) -> String {
+    return format!(
+        "[{},{},{}]",
+        json_number_f32(vector.x.to_f32()),
+        json_number_f32(vector.y.to_f32()),
+        json_number_f32(vector.z.to_f32())
+    );
+}
+
+/// Quotes the string for JSON, escaping quotes, backslashes and control characters
+fn json_string(value: &str) -> String {
+    let mut quoted: String = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    return quoted;
+}
+
+fn write_json_row<W: Write, R: Real>(
+    writer: &mut W,
+    columns: &[TrajectoryColumn],
+    step: u64,
+    time: f64,
+    name: &str,
+    particle: &Particle<R>
+) -> io::Result<()> {
+    let mut fields: Vec<String> = Vec::with_capacity(columns.len());
+    for column in columns.iter() {
+        let value: String = match column {
+            TrajectoryColumn::Time => json_number(time),
+            TrajectoryColumn::Step => step.to_string(),
+            TrajectoryColumn::Particle => json_string(name),
+            TrajectoryColumn::Position => json_vector(particle.get_position()),
+            TrajectoryColumn::Velocity => json_vector(particle.get_velocity()),
+            TrajectoryColumn::Acceleration => json_vector(resulting_acceleration(particle)),
+            TrajectoryColumn::KineticEnergy => json_number_f32(particle.calculate_kinetic_energy().to_f32()),
+            TrajectoryColumn::Force => json_vector(particle.force_accum),
+        };
+        fields.push(format!("\"{}\":{}", column.get_name(), value));
+    }
+    return writeln!(writer, "{{{}}}", fields.join(","));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A name that needs quoting in both formats
+    const AWKWARD: &str = "b \"q\", \\ x\ny";
+
+    /// Records two particles for three half second steps, one coasting and one pushed along z
+    fn record(format: TrajectoryFormat, columns: Option<&[TrajectoryColumn]>, interval: u64) -> String {
+        let coasting = Rc::new(RefCell::new(Particle::new(Vector3::default(), Vector3::new(1.0, 0.0, 0.0), Vector3::default(), 1.0, 2.0)));
+        let pushed = Rc::new(RefCell::new(Particle::new(Vector3::new(1.0, 2.0, 3.0), Vector3::default(), Vector3::default(), 1.0, 1.0)));
+        let mut recorder: TrajectoryRecorder<Vec<u8>> = TrajectoryRecorder::new(Vec::new(), format, interval);
+        if let Some(columns) = columns {
+            recorder.set_columns(columns);
+        }
+        recorder.track("a", &coasting);
+        recorder.track(AWKWARD, &pushed);
+        for _ in 0..3 {
+            pushed.borrow_mut().add_force(Vector3::new(0.0, 0.0, 4.0));
+            recorder.record(0.5).unwrap();
+            coasting.borrow_mut().integrate(0.5);
+            pushed.borrow_mut().integrate(0.5);
+        }
+        assert_eq!(recorder.get_step(), 3);
+        assert_eq!(recorder.get_time(), 1.5);
+        return String::from_utf8(recorder.finish().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn csv_rows_have_the_chosen_columns() {
+        let columns: [TrajectoryColumn; 5] = [
+            TrajectoryColumn::Step,
+            TrajectoryColumn::Time,
+            TrajectoryColumn::Particle,
+            TrajectoryColumn::Position,
+            TrajectoryColumn::Force,
+        ];
+        assert_eq!(record(TrajectoryFormat::Csv, Some(&columns), 1), "\
+step,time,particle,position_x,position_y,position_z,force_x,force_y,force_z
+0,0,\"a\",0,0,0,0,0,0
+0,0,\"b \"\"q\"\", \\ x
+y\",1,2,3,0,0,4
+1,0.5,\"a\",0.5,0,0,0,0,0
+1,0.5,\"b \"\"q\"\", \\ x
+y\",1,2,3,0,0,4
+2,1,\"a\",1,0,0,0,0,0
+2,1,\"b \"\"q\"\", \\ x
+y\",1,2,4,0,0,4
+");
+    }
+
+    #[test]
+    fn csv_samples_every_interval() {
+        assert_eq!(record(TrajectoryFormat::Csv, None, 2), "\
+time,step,particle,position_x,position_y,position_z,velocity_x,velocity_y,velocity_z,\
+acceleration_x,acceleration_y,acceleration_z,kinetic_energy,force_x,force_y,force_z
+0,0,\"a\",0,0,0,1,0,0,0,0,0,1,0,0,0
+0,0,\"b \"\"q\"\", \\ x
+y\",1,2,3,0,0,0,0,0,4,0,0,0,4
+1,2,\"a\",1,0,0,1,0,0,0,0,0,1,0,0,0
+1,2,\"b \"\"q\"\", \\ x
+y\",1,2,4,0,0,4,0,0,4,8,0,0,4
+");
+    }
+
+    #[test]
+    fn json_lines_escape_names() {
+        assert_eq!(record(TrajectoryFormat::JsonLines, None, 2), concat!(
+            "{\"time\":0,\"step\":0,\"particle\":\"a\",\"position\":[0,0,0],\"velocity\":[1,0,0],",
+            "\"acceleration\":[0,0,0],\"kinetic_energy\":1,\"force\":[0,0,0]}\n",
+            "{\"time\":0,\"step\":0,\"particle\":\"b \\\"q\\\", \\\\ x\\ny\",\"position\":[1,2,3],\"velocity\":[0,0,0],",
+            "\"acceleration\":[0,0,4],\"kinetic_energy\":0,\"force\":[0,0,4]}\n",
+            "{\"time\":1,\"step\":2,\"particle\":\"a\",\"position\":[1,0,0],\"velocity\":[1,0,0],",
+            "\"acceleration\":[0,0,0],\"kinetic_energy\":1,\"force\":[0,0,0]}\n",
+            "{\"time\":1,\"step\":2,\"particle\":\"b \\\"q\\\", \\\\ x\\ny\",\"position\":[1,2,4],\"velocity\":[0,0,4],",
+            "\"acceleration\":[0,0,4],\"kinetic_energy\":8,\"force\":[0,0,4]}\n",
+        ));
+
+        let columns: [TrajectoryColumn; 2] = [TrajectoryColumn::Particle, TrajectoryColumn::KineticEnergy];
+        let lines: Vec<String> = record(TrajectoryFormat::JsonLines, Some(&columns), 3).lines().map(String::from).collect();
+        assert_eq!(lines, vec![
+            "{\"particle\":\"a\",\"kinetic_energy\":1}".to_string(),
+            format!("{{\"particle\":{},\"kinetic_energy\":0}}", json_string(AWKWARD)),
+        ]);
+    }
+}
+
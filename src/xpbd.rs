@@ -0,0 +1,728 @@
+use std::{any::Any, cell::RefCell, rc::Rc};
+
+use crate::{core::Vector3, particle::Particle, precision::{real_atan2, real_pow}, spatial_grid::SpatialGrid};
+
+/// A constraint the `XpbdSolver` satisfies by moving particle positions directly.
+///
+/// Constraints refer to particles by the index `XpbdSolver::add_particle` returned.
+/// Compliance is the inverse of stiffness, in metres per newton for a distance,
+/// and zero makes the constraint rigid.
+pub trait XpbdConstraint: Any {
+    /// Moves the positions towards satisfying the constraint.
+    /// The duration is the length of the substep, compliance is divided by its square.
+    fn solve(&mut self, positions: &mut [Vector3], inverse_masses: &[f32], duration: f32);
+
+    /// Called at the start of every substep to clear the accumulated Lagrange multiplier
+    fn reset(&mut self) {}
+
+    /// Called at the end of every substep, once every pass has been made, for constraints that
+    /// read the force they carry back from their multiplier
+    fn finish(&mut self, _duration: f32) {}
+}
+
+/// Runs one XPBD update for a constraint `c`, given its gradient with respect to each particle
+/// it uses. `lambda` accumulates over the substep so the compliance is respected.
+fn apply_correction(
+    positions: &mut [Vector3],
+    inverse_masses: &[f32],
+    gradients: &[(usize, Vector3)],
+    c: f32,
+    compliance: f32,
+    lambda: &mut f32,
+    duration: f32
+) {
+    apply_bounded_correction(positions, inverse_masses, gradients, c, compliance, lambda, f32::INFINITY, duration);
+}
+
+/// As `apply_correction`, but the accumulated `lambda` never goes past `max_lambda`,
+/// which keeps a one-sided constraint from acting the wrong way
+#[allow(clippy::too_many_arguments)]
+fn apply_bounded_correction(
+    positions: &mut [Vector3],
+    inverse_masses: &[f32],
+    gradients: &[(usize, Vector3)],
+    c: f32,
+    compliance: f32,
+    lambda: &mut f32,
+    max_lambda: f32,
+    duration: f32
+) {
+    let weight: f32 = gradients.iter()
+        .map(|(i, g)| inverse_masses[*i] * g.square_magnitude())
+        .sum();
+    // alpha~ = alpha / h^2
+    let scaled_compliance: f32 = compliance / (duration * duration);
+    let denominator: f32 = weight + scaled_compliance;
+    if denominator <= 0.0 { return; }
+
+    let delta_lambda: f32 = ((-c - scaled_compliance * *lambda) / denominator).min(max_lambda - *lambda);
+    *lambda += delta_lambda;
+    for (i, gradient) in gradients.iter() {
+        positions[*i].add_scaled_vector(gradient, inverse_masses[*i] * delta_lambda);
+    }
+}
+
+/// Keeps two particles a set distance apart, or at most that far apart as a cable.
+///
+/// The force it carries is read back from its multiplier at the end of each substep, and the
+/// constraint breaks for good once that force passes its breaking force.
+#[derive(Debug, Clone)]
+pub struct XpbdDistanceConstraint {
+    a: usize,
+    b: usize,
+    rest_length: f32,
+    compliance: f32,
+    lambda: f32,
+    /// Only pulls, never pushes
+    cable: bool,
+    /// Newtons either way past which the constraint breaks, infinite for never
+    breaking_force: f32,
+    /// Newtons pulling the particles together at the peak since `clear_force`, negative for pushing
+    force: f32,
+    broken: bool,
+}
+
+impl XpbdDistanceConstraint {
+    pub fn new(a: usize, b: usize, rest_length: f32, compliance: f32) -> XpbdDistanceConstraint {
+        return XpbdDistanceConstraint {
+            a,
+            b,
+            rest_length,
+            compliance,
+            lambda: 0.0,
+            cable: false,
+            breaking_force: f32::INFINITY,
+            force: 0.0,
+            broken: false
+        };
+    }
+
+    /// A constraint that keeps the particles at most the length apart and goes slack when closer
+    pub fn new_cable(a: usize, b: usize, length: f32, compliance: f32) -> XpbdDistanceConstraint {
+        let mut constraint: XpbdDistanceConstraint = XpbdDistanceConstraint::new(a, b, length, compliance);
+        constraint.cable = true;
+        return constraint;
+    }
+
+    pub fn is_cable(&self) -> bool {
+        return self.cable;
+    }
+
+    pub fn set_breaking_force(&mut self, breaking_force: f32) {
+        assert!(breaking_force >= 0.0, "the breaking force cannot be negative");
+        self.breaking_force = breaking_force;
+    }
+
+    pub fn get_breaking_force(&self) -> f32 {
+        return self.breaking_force;
+    }
+
+    /// The force along the constraint at the peak since `clear_force`, positive when pulled
+    pub fn get_force(&self) -> f32 {
+        return self.force;
+    }
+
+    /// Starts looking for the peak force again
+    pub fn clear_force(&mut self) {
+        self.force = 0.0;
+    }
+
+    pub fn is_broken(&self) -> bool {
+        return self.broken;
+    }
+
+    /// Breaks the constraint, after which it holds nothing
+    pub fn break_constraint(&mut self) {
+        self.broken = true;
+        self.lambda = 0.0;
+    }
+
+    pub fn get_rest_length(&self) -> f32 {
+        return self.rest_length;
+    }
+
+    pub fn set_rest_length(&mut self, rest_length: f32) {
+        self.rest_length = rest_length;
+    }
+}
+
+impl XpbdConstraint for XpbdDistanceConstraint {
+    fn solve(&mut self, positions: &mut [Vector3], inverse_masses: &[f32], duration: f32) {
+        if self.broken { return; }
+        let mut direction: Vector3 = positions[self.a] - &positions[self.b];
+        let length: f32 = direction.magnitude();
+        if length == 0.0 { return; }
+        direction *= 1.0 / length;
+
+        // C = |a - b| - l0
+        let gradients: [(usize, Vector3); 2] = [(self.a, direction), (self.b, direction * -1.0)];
+        // A cable can only pull, so its multiplier never goes past zero
+        let max_lambda: f32 = if self.cable { 0.0 } else { f32::INFINITY };
+        apply_bounded_correction(
+            positions,
+            inverse_masses,
+            &gradients,
+            length - self.rest_length,
+            self.compliance,
+            &mut self.lambda,
+            max_lambda,
+            duration
+        );
+    }
+
+    fn reset(&mut self) {
+        self.lambda = 0.0;
+    }
+
+    fn finish(&mut self, duration: f32) {
+        if self.broken { return; }
+        // The force is lambda / h^2 along the gradient, so stretched constraints pull
+        let force: f32 = -self.lambda / (duration * duration);
+        if force.abs() > self.force.abs() { self.force = force; }
+        if force.abs() > self.breaking_force { self.break_constraint(); }
+    }
+}
+
+/// Keeps the angle between two triangles that share an edge at its rest value.
+///
+/// The triangles are `(a, c, d)` and `(b, d, c)`, sharing the edge from `c` to `d`.
+/// The angle is measured with `atan2` so it stays well behaved when the triangles are flat,
+/// which is where cloth spends most of its time.
+#[derive(Debug, Clone)]
+pub struct XpbdBendingConstraint {
+    /// The vertices off the shared edge, then the two on it
+    vertices: [usize; 4],
+    rest_angle: f32,
+    compliance: f32,
+    lambda: f32,
+}
+
+impl XpbdBendingConstraint {
+    /// Takes the current angle between the triangles as the rest angle
+    pub fn new(positions: &[Vector3], a: usize, b: usize, c: usize, d: usize, compliance: f32) -> XpbdBendingConstraint {
+        let rest_angle: f32 = dihedral_angle(&positions[a], &positions[b], &positions[c], &positions[d]).0;
+        return XpbdBendingConstraint {
+            vertices: [a, b, c, d],
+            rest_angle,
+            compliance,
+            lambda: 0.0
+        };
+    }
+
+    pub fn get_rest_angle(&self) -> f32 {
+        return self.rest_angle;
+    }
+}
+
+/// The signed angle between the triangles `(a, c, d)` and `(b, d, c)`, and its gradient with
+/// respect to each of the four vertices, following Bridson et al. "Simulation of Clothing with
+/// Folds and Wrinkles". The gradients are zero when either triangle is degenerate.
+fn dihedral_angle(a: &Vector3, b: &Vector3, c: &Vector3, d: &Vector3) -> (f32, [Vector3; 4]) {
+    let edge: Vector3 = *d - c;
+    let edge_length: f32 = edge.magnitude();
+    let first_normal: Vector3 = (*a - c).vector_product(&(*a - d));
+    let second_normal: Vector3 = (*b - d).vector_product(&(*b - c));
+    let first_square: f32 = first_normal.square_magnitude();
+    let second_square: f32 = second_normal.square_magnitude();
+    if edge_length == 0.0 || first_square == 0.0 || second_square == 0.0 {
+        return (0.0, [Vector3::default(); 4]);
+    }
+
+    let mut first_unit: Vector3 = first_normal;
+    first_unit.normalize();
+    let mut second_unit: Vector3 = second_normal;
+    second_unit.normalize();
+    let sine: f32 = first_unit.vector_product(&second_unit) * &edge * (1.0 / edge_length);
+    let cosine: f32 = first_unit * &second_unit;
+    let angle: f32 = real_atan2(sine, cosine);
+
+    // Each triangle's normal scaled by one over its height from the shared edge
+    let first_scaled: Vector3 = first_normal * (1.0 / first_square);
+    let second_scaled: Vector3 = second_normal * (1.0 / second_square);
+    let along = |v: &Vector3| -> f32 { return (*v * &edge) / edge_length; };
+    let gradient_a: Vector3 = first_scaled * edge_length;
+    let gradient_b: Vector3 = second_scaled * edge_length;
+    let gradient_c: Vector3 = first_scaled * along(&(*a - d)) + &(second_scaled * along(&(*b - d)));
+    let gradient_d: Vector3 = (first_scaled * along(&(*a - c)) + &(second_scaled * along(&(*b - c)))) * -1.0;
+    return (angle, [gradient_a * -1.0, gradient_b * -1.0, gradient_c * -1.0, gradient_d * -1.0]);
+}
+
+impl XpbdConstraint for XpbdBendingConstraint {
+    fn solve(&mut self, positions: &mut [Vector3], inverse_masses: &[f32], duration: f32) {
+        let [a, b, c, d] = self.vertices;
+        let (angle, [gradient_a, gradient_b, gradient_c, gradient_d]) =
+            dihedral_angle(&positions[a], &positions[b], &positions[c], &positions[d]);
+
+        // Take the short way round to the rest angle
+        let mut difference: f32 = angle - self.rest_angle;
+        if difference > std::f32::consts::PI {
+            difference -= 2.0 * std::f32::consts::PI;
+        } else if difference < -std::f32::consts::PI {
+            difference += 2.0 * std::f32::consts::PI;
+        }
+        apply_correction(
+            positions,
+            inverse_masses,
+            &[(a, gradient_a), (b, gradient_b), (c, gradient_c), (d, gradient_d)],
+            difference,
+            self.compliance,
+            &mut self.lambda,
+            duration
+        );
+    }
+
+    fn reset(&mut self) {
+        self.lambda = 0.0;
+    }
+}
+
+/// Keeps the volume inside a closed triangle mesh at a multiple of its rest volume.
+/// The triangles must be wound the same way, with the normals pointing out.
+#[derive(Debug, Clone)]
+pub struct XpbdVolumeConstraint {
+    triangles: Vec<[usize; 3]>,
+    /// The particles the triangles use, each once
+    vertices: Vec<usize>,
+    rest_volume: f32,
+    /// The volume is held at the rest volume times this, above one inflates the mesh
+    pressure: f32,
+    compliance: f32,
+    lambda: f32,
+}
+
+impl XpbdVolumeConstraint {
+    /// Takes the current volume of the mesh as the rest volume
+    pub fn new(positions: &[Vector3], triangles: Vec<[usize; 3]>, compliance: f32) -> XpbdVolumeConstraint {
+        let mut vertices: Vec<usize> = triangles.iter().flatten().copied().collect();
+        vertices.sort_unstable();
+        vertices.dedup();
+        return XpbdVolumeConstraint {
+            rest_volume: mesh_volume(positions, &triangles),
+            triangles,
+            vertices,
+            pressure: 1.0,
+            compliance,
+            lambda: 0.0
+        };
+    }
+
+    pub fn get_rest_volume(&self) -> f32 {
+        return self.rest_volume;
+    }
+
+    pub fn set_pressure(&mut self, pressure: f32) {
+        self.pressure = pressure;
+    }
+
+    pub fn get_volume(&self, positions: &[Vector3]) -> f32 {
+        return mesh_volume(positions, &self.triangles);
+    }
+}
+
+/// The signed volume of a closed mesh, from the tetrahedra each triangle makes with the origin
+fn mesh_volume(positions: &[Vector3], triangles: &[[usize; 3]]) -> f32 {
+    return triangles.iter()
+        .map(|[a, b, c]| positions[*a].vector_product(&positions[*b]) * &positions[*c])
+        .sum::<f32>() / 6.0;
+}
+
+/// The gradient of `mesh_volume` with respect to each of the vertices, which must be sorted.
+/// dV/dx for a vertex is the sum of (xj x xk) / 6 over the triangles around it.
+fn volume_gradients(positions: &[Vector3], triangles: &[[usize; 3]], vertices: &[usize]) -> Vec<(usize, Vector3)> {
+    let mut gradients: Vec<(usize, Vector3)> = vertices.iter().map(|i| (*i, Vector3::default())).collect();
+    let slot = |index: usize| -> usize { return vertices.binary_search(&index).unwrap(); };
+    for [a, b, c] in triangles.iter() {
+        gradients[slot(*a)].1 += &(positions[*b].vector_product(&positions[*c]) * (1.0 / 6.0));
+        gradients[slot(*b)].1 += &(positions[*c].vector_product(&positions[*a]) * (1.0 / 6.0));
+        gradients[slot(*c)].1 += &(positions[*a].vector_product(&positions[*b]) * (1.0 / 6.0));
+    }
+    return gradients;
+}
+
+impl XpbdConstraint for XpbdVolumeConstraint {
+    fn solve(&mut self, positions: &mut [Vector3], inverse_masses: &[f32], duration: f32) {
+        let gradients: Vec<(usize, Vector3)> = volume_gradients(positions, &self.triangles, &self.vertices);
+
+        // C = V - p V0
+        let c: f32 = mesh_volume(positions, &self.triangles) - self.pressure * self.rest_volume;
+        apply_correction(
+            positions,
+            inverse_masses,
+            &gradients,
+            c,
+            self.compliance,
+            &mut self.lambda,
+            duration
+        );
+    }
+
+    fn reset(&mut self) {
+        self.lambda = 0.0;
+    }
+}
+
+/// A rigid half-space particles cannot enter, the points `x` with `normal . x >= offset`
+/// are outside it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XpbdPlane {
+    /// Unit normal pointing out of the solid side
+    pub normal: Vector3,
+    pub offset: f32,
+    /// Fraction of the sliding motion removed on contact, zero is frictionless and one sticks
+    pub friction: f32,
+}
+
+/// Steps particles with extended position-based dynamics (XPBD), as described by Macklin et al.
+/// "Small Steps in Physics Simulation".
+///
+/// Instead of turning constraints into forces, each substep predicts positions from the velocities,
+/// moves them to satisfy the constraints, then works the velocities out from how far the particles
+/// moved. Stiff constraints stay stable at any step size, and compliance keeps the stiffness from
+/// depending on the step size or the number of substeps.
+///
+/// The solver integrates its own particles, so call `step` instead of `Particle::integrate` for them.
+/// A particle's acceleration and the forces in its accumulator are applied as a constant acceleration
+/// across the substeps. Particles with infinite mass do not move but still hold constraints in place.
+pub struct XpbdSolver {
+    particles: Vec<Rc<RefCell<Particle>>>,
+    constraints: Vec<Box<dyn XpbdConstraint>>,
+    planes: Vec<XpbdPlane>,
+    /// Substeps per step
+    substeps: usize,
+    /// Passes over the constraints each substep
+    iterations: usize,
+    /// Particles closer than twice this collide, zero turns particle collisions off
+    particle_radius: f32,
+    grid: Option<SpatialGrid>,
+}
+
+impl XpbdSolver {
+    pub fn new(substeps: usize) -> XpbdSolver {
+        assert!(substeps > 0, "the solver needs at least one substep");
+        return XpbdSolver {
+            particles: Vec::new(),
+            constraints: Vec::new(),
+            planes: Vec::new(),
+            substeps,
+            iterations: 1,
+            particle_radius: 0.0,
+            grid: None
+        };
+    }
+
+    /// Adds a particle to the solver and returns its index for use in constraints
+    pub fn add_particle(&mut self, particle: &Rc<RefCell<Particle>>) -> usize {
+        self.particles.push(Rc::clone(particle));
+        return self.particles.len() - 1;
+    }
+
+    pub fn get_particles(&self) -> &[Rc<RefCell<Particle>>] {
+        return &self.particles;
+    }
+
+    /// The current particle positions, in index order, for building constraints from
+    pub fn get_positions(&self) -> Vec<Vector3> {
+        return self.particles.iter().map(|p| p.borrow().get_position()).collect();
+    }
+
+    /// Adds a constraint and returns its index
+    pub fn add_constraint(&mut self, constraint: Box<dyn XpbdConstraint>) -> usize {
+        self.constraints.push(constraint);
+        return self.constraints.len() - 1;
+    }
+
+    pub fn get_constraint_mut(&mut self, index: usize) -> &mut dyn XpbdConstraint {
+        return self.constraints[index].as_mut();
+    }
+
+    /// The constraint at the index as the type it was added as, `None` if it is another type
+    pub fn get_constraint_as<C: XpbdConstraint>(&self, index: usize) -> Option<&C> {
+        let constraint: &dyn Any = self.constraints[index].as_ref();
+        return constraint.downcast_ref::<C>();
+    }
+
+    pub fn get_constraint_as_mut<C: XpbdConstraint>(&mut self, index: usize) -> Option<&mut C> {
+        let constraint: &mut dyn Any = self.constraints[index].as_mut();
+        return constraint.downcast_mut::<C>();
+    }
+
+    pub fn remove_constraint(&mut self, index: usize) -> Box<dyn XpbdConstraint> {
+        return self.constraints.remove(index);
+    }
+
+    pub fn constraint_count(&self) -> usize {
+        return self.constraints.len();
+    }
+
+    pub fn add_plane(&mut self, plane: XpbdPlane) {
+        self.planes.push(plane);
+    }
+
+    pub fn set_substeps(&mut self, substeps: usize) {
+        assert!(substeps > 0, "the solver needs at least one substep");
+        self.substeps = substeps;
+    }
+
+    pub fn get_substeps(&self) -> usize {
+        return self.substeps;
+    }
+
+    /// One pass is usually enough with substeps, rigid chains between fixed particles under
+    /// heavy loads need more before they hold their lengths and their forces settle
+    pub fn set_iterations(&mut self, iterations: usize) {
+        assert!(iterations > 0, "the solver needs at least one iteration");
+        self.iterations = iterations;
+    }
+
+    pub fn get_iterations(&self) -> usize {
+        return self.iterations;
+    }
+
+    /// Treats every particle as a sphere of the radius for collisions with planes and each other.
+    /// Zero makes them points and turns collisions between particles off.
+    pub fn set_particle_radius(&mut self, radius: f32) {
+        assert!(radius >= 0.0);
+        self.particle_radius = radius;
+        self.grid = if radius > 0.0 { Some(SpatialGrid::new(2.0 * radius)) } else { None };
+    }
+
+    /// Advances every particle in the solver by the duration
+    pub fn step(&mut self, duration: f32) {
+        assert!(duration > 0.0);
+        let count: usize = self.particles.len();
+        if count == 0 { return; }
+
+        // Copy the state out of the particles
+        let mut positions: Vec<Vector3> = Vec::with_capacity(count);
+        let mut velocities: Vec<Vector3> = Vec::with_capacity(count);
+        let mut inverse_masses: Vec<f32> = Vec::with_capacity(count);
+        let mut accelerations: Vec<Vector3> = Vec::with_capacity(count);
+        let mut dampings: Vec<f32> = Vec::with_capacity(count);
+        for particle in self.particles.iter() {
+            let particle = particle.borrow();
+            let inverse_mass: f32 = particle.get_inverse_mass().max(0.0);
+            let mut acceleration: Vector3 = particle.get_acceleration();
+            acceleration.add_scaled_vector(&particle.force_accum, inverse_mass);
+            positions.push(particle.get_position());
+            velocities.push(particle.get_velocity());
+            inverse_masses.push(inverse_mass);
+            accelerations.push(acceleration);
+            dampings.push(particle.damping);
+        }
+
+        let substep: f32 = duration / self.substeps as f32;
+        let mut previous: Vec<Vector3> = positions.clone();
+        for _ in 0..self.substeps {
+            // Predict where the particles go without the constraints
+            for i in 0..count {
+                previous[i] = positions[i];
+                if inverse_masses[i] == 0.0 { continue; }
+                velocities[i].add_scaled_vector(&accelerations[i], substep);
+                positions[i].add_scaled_vector(&velocities[i], substep);
+            }
+
+            for constraint in self.constraints.iter_mut() {
+                constraint.reset();
+            }
+            for _ in 0..self.iterations {
+                for constraint in self.constraints.iter_mut() {
+                    constraint.solve(&mut positions, &inverse_masses, substep);
+                }
+            }
+            for constraint in self.constraints.iter_mut() {
+                constraint.finish(substep);
+            }
+            self.solve_collisions(&mut positions, &previous, &inverse_masses);
+
+            // The velocity is whatever moves the particle from where it was to where it ended up
+            for i in 0..count {
+                if inverse_masses[i] == 0.0 { continue; }
+                velocities[i] = (positions[i] - &previous[i]) * (1.0 / substep);
+                velocities[i] *= real_pow(dampings[i], substep);
+            }
+        }
+
+        // Write the new state back
+        for (i, particle) in self.particles.iter().enumerate() {
+            let mut particle = particle.borrow_mut();
+            if inverse_masses[i] > 0.0 {
+                particle.position = positions[i];
+                particle.velocity = velocities[i];
+            }
+            particle.clear_accumulator();
+        }
+    }
+
+    /// Pushes particles out of the planes and out of each other
+    fn solve_collisions(&mut self, positions: &mut [Vector3], previous: &[Vector3], inverse_masses: &[f32]) {
+        for plane in self.planes.iter() {
+            for i in 0..positions.len() {
+                if inverse_masses[i] == 0.0 { continue; }
+                let depth: f32 = plane.offset + self.particle_radius - plane.normal * &positions[i];
+                if depth <= 0.0 { continue; }
+                positions[i].add_scaled_vector(&plane.normal, depth);
+
+                // Friction takes back part of the sliding this substep
+                let mut sliding: Vector3 = positions[i] - &previous[i];
+                sliding.add_scaled_vector(&plane.normal, -(sliding * &plane.normal));
+                positions[i].add_scaled_vector(&sliding, -plane.friction.clamp(0.0, 1.0));
+            }
+        }
+
+        let Some(grid) = self.grid.as_mut() else { return; };
+        let contact: f32 = 2.0 * self.particle_radius;
+        grid.rebuild(positions);
+        let mut candidates: Vec<usize> = Vec::new();
+        for i in 0..positions.len() {
+            grid.get_candidates(&positions[i], &mut candidates);
+            for &j in candidates.iter() {
+                if j <= i { continue; }
+                let total_inverse_mass: f32 = inverse_masses[i] + inverse_masses[j];
+                if total_inverse_mass == 0.0 { continue; }
+                let mut normal: Vector3 = positions[j] - &positions[i];
+                let distance: f32 = normal.magnitude();
+                if distance >= contact || distance == 0.0 { continue; }
+                normal *= 1.0 / distance;
+
+                // Rigid contact, C = |xj - xi| - 2r
+                let depth: f32 = contact - distance;
+                positions[i].add_scaled_vector(&normal, -depth * inverse_masses[i] / total_inverse_mass);
+                positions[j].add_scaled_vector(&normal, depth * inverse_masses[j] / total_inverse_mass);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particle(position: Vector3, mass: f32) -> Rc<RefCell<Particle>> {
+        let gravity: Vector3 = Vector3::new(0.0, -9.81, 0.0);
+        return Rc::new(RefCell::new(Particle::new(position, Vector3::default(), gravity, 1.0, mass)));
+    }
+
+    /// Checks each gradient against a central difference of the function, one axis at a time
+    fn assert_matches_differences<F: Fn(&[Vector3]) -> f32>(positions: &[Vector3], gradients: &[(usize, Vector3)], function: F) {
+        let step: f32 = 1.0e-3;
+        for (i, gradient) in gradients.iter() {
+            for axis in 0..3 {
+                let mut forward: Vec<Vector3> = positions.to_vec();
+                let mut backward: Vec<Vector3> = positions.to_vec();
+                let offset: Vector3 = match axis {
+                    0 => Vector3::new(step, 0.0, 0.0),
+                    1 => Vector3::new(0.0, step, 0.0),
+                    _ => Vector3::new(0.0, 0.0, step),
+                };
+                forward[*i] += &offset;
+                backward[*i] -= &offset;
+                let difference: f32 = (function(&forward) - function(&backward)) / (2.0 * step);
+                let analytic: f32 = match axis { 0 => gradient.x, 1 => gradient.y, _ => gradient.z };
+                assert!(
+                    (difference - analytic).abs() < 2.0e-3,
+                    "vertex {} axis {}: the gradient is {} but the difference is {}", i, axis, analytic, difference
+                );
+            }
+        }
+    }
+
+    /// Four corners of a tetrahedron and its faces wound with the normals out
+    fn tetrahedron() -> (Vec<Vector3>, Vec<[usize; 3]>) {
+        let positions: Vec<Vector3> = vec![
+            Vector3::new(0.0, 0.5, 0.0),
+            Vector3::new(1.0, 0.5, 0.0),
+            Vector3::new(0.0, 1.5, 0.0),
+            Vector3::new(0.0, 0.5, 1.0),
+        ];
+        return (positions, vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]]);
+    }
+
+    #[test]
+    fn bending_gradients_match_the_angle() {
+        let positions: Vec<Vector3> = vec![
+            Vector3::new(0.2, 1.0, 0.3),
+            Vector3::new(-0.1, -0.9, 0.6),
+            Vector3::new(-1.0, 0.1, 0.0),
+            Vector3::new(1.1, 0.0, 0.1),
+        ];
+        let angle = |p: &[Vector3]| -> f32 { return dihedral_angle(&p[0], &p[1], &p[2], &p[3]).0; };
+        let (value, gradients) = dihedral_angle(&positions[0], &positions[1], &positions[2], &positions[3]);
+        assert!(value.abs() > 0.1, "the test needs a bent pair of triangles, the angle is {}", value);
+        let gradients: Vec<(usize, Vector3)> = gradients.iter().copied().enumerate().collect();
+        assert_matches_differences(&positions, &gradients, angle);
+    }
+
+    #[test]
+    fn volume_gradients_match_the_volume() {
+        let (mut positions, triangles) = tetrahedron();
+        positions[3] = Vector3::new(0.3, 0.8, 1.2);
+        let vertices: Vec<usize> = vec![0, 1, 2, 3];
+        let gradients: Vec<(usize, Vector3)> = volume_gradients(&positions, &triangles, &vertices);
+        assert!(mesh_volume(&positions, &triangles) > 0.0, "the faces must be wound outwards");
+        assert_matches_differences(&positions, &gradients, |p| mesh_volume(p, &triangles));
+    }
+
+    #[test]
+    fn rigid_distance_constraints_hold_their_length() {
+        let mut solver: XpbdSolver = XpbdSolver::new(10);
+        let anchor: Rc<RefCell<Particle>> = particle(Vector3::new(0.0, 2.0, 0.0), 1.0);
+        anchor.borrow_mut().restore_mass(1.0, 0.0);
+        let a: usize = solver.add_particle(&anchor);
+        let b: usize = solver.add_particle(&particle(Vector3::new(1.0, 2.0, 0.0), 1.0));
+        let c: usize = solver.add_particle(&particle(Vector3::new(2.0, 2.0, 0.0), 3.0));
+        solver.add_constraint(Box::new(XpbdDistanceConstraint::new(a, b, 1.0, 0.0)));
+        solver.add_constraint(Box::new(XpbdDistanceConstraint::new(b, c, 1.0, 0.0)));
+
+        for _ in 0..240 {
+            solver.step(1.0 / 60.0);
+            let positions: Vec<Vector3> = solver.get_positions();
+            for (i, j) in [(a, b), (b, c)] {
+                let length: f32 = (positions[j] - &positions[i]).magnitude();
+                assert!((length - 1.0).abs() < 1.0e-2, "a link stretched to {}", length);
+            }
+            assert_eq!(positions[a], Vector3::new(0.0, 2.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn tetrahedra_keep_their_volume_under_gravity() {
+        let (positions, triangles) = tetrahedron();
+        let mut solver: XpbdSolver = XpbdSolver::new(10);
+        for position in positions.iter() {
+            solver.add_particle(&particle(*position, 1.0));
+        }
+        // Soft edges on their own would let the tetrahedron flatten against the floor
+        for [i, j] in [[0, 1], [0, 2], [0, 3], [1, 2], [1, 3], [2, 3]] {
+            let rest_length: f32 = (positions[j] - &positions[i]).magnitude();
+            solver.add_constraint(Box::new(XpbdDistanceConstraint::new(i, j, rest_length, 1.0e-2)));
+        }
+        let volume: usize = solver.add_constraint(Box::new(XpbdVolumeConstraint::new(&positions, triangles, 0.0)));
+        solver.add_plane(XpbdPlane { normal: Vector3::new(0.0, 1.0, 0.0), offset: 0.0, friction: 0.5 });
+
+        for _ in 0..180 {
+            solver.step(1.0 / 60.0);
+        }
+        let positions: Vec<Vector3> = solver.get_positions();
+        assert!(positions.iter().all(|p| p.y < 1.0), "the tetrahedron should have landed");
+        let constraint: &XpbdVolumeConstraint = solver.get_constraint_as(volume).unwrap();
+        let ratio: f32 = constraint.get_volume(&positions) / constraint.get_rest_volume();
+        assert!((ratio - 1.0).abs() < 1.0e-2, "the volume changed by a factor of {}", ratio);
+    }
+
+    #[test]
+    fn particles_rest_on_planes() {
+        let radius: f32 = 0.1;
+        let mut solver: XpbdSolver = XpbdSolver::new(4);
+        solver.set_particle_radius(radius);
+        solver.add_plane(XpbdPlane { normal: Vector3::new(0.0, 1.0, 0.0), offset: 0.0, friction: 0.0 });
+        let resting: Rc<RefCell<Particle>> = particle(Vector3::new(0.0, radius, 0.0), 1.0);
+        solver.add_particle(&resting);
+
+        for _ in 0..600 {
+            solver.step(1.0 / 60.0);
+            let height: f32 = resting.borrow().get_position().y;
+            assert!((height - radius).abs() < 1.0e-5, "the particle moved to a height of {}", height);
+        }
+        assert!(resting.borrow().get_velocity().magnitude() < 1.0e-3);
+    }
+}
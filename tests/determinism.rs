@@ -0,0 +1,168 @@
+//! Pins the state hash of a long run through every solver, so any change to the bits the engine
+//! produces with the `deterministic` feature shows up as a failure here rather than as a desync
+//! between machines. The pin only holds with the feature, since the platform maths library may
+//! round differently, but every build has to repeat itself within a process.
+#![allow(clippy::needless_return)]
+
+use std::{cell::RefCell, rc::Rc};
+
+use physics_engine::{
+    cloth::{Cloth, ClothSettings},
+    core::Vector3,
+    emitter::{EmitterSettings, EmitterShape, ParticleEmitter},
+    granular::{GranularMaterial, GranularPlane, GranularSystem},
+    particle::{Particle, ParticleIntegrator},
+    particle_force_gen::{ParticleFakeSpring, ParticleForceGenerator, ParticleForceRegistry},
+    particle_implicit_spring::ParticleSpringNetwork,
+    rope::{Rope, RopeSettings},
+    sph::{SphFluid, SphPlane, SphSettings},
+    state_hash::{hash_particles, StateHasher},
+    xpbd::{XpbdPlane, XpbdSolver},
+};
+
+/// Ten seconds of simulated time
+const STEPS: usize = 2400;
+const DURATION: f32 = 1.0 / 240.0;
+
+fn gravity() -> Vector3 {
+    return Vector3::new(0.0, -9.81, 0.0);
+}
+
+fn spring_end(integrator: ParticleIntegrator) -> Rc<RefCell<Particle>> {
+    let speed: f32 = if integrator == ParticleIntegrator::Euler { 2.0 } else { -2.0 };
+    return Rc::new(RefCell::new(Particle::new(
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, speed, 0.0),
+        Vector3::default(),
+        1.0,
+        0.5
+    )));
+}
+
+/// A rope hanging from its start, swinging down from the horizontal
+fn rope_settings() -> RopeSettings {
+    return RopeSettings { particles: 8, anchor_start: true, ..RopeSettings::default() };
+}
+
+fn fall(particles: &[Rc<RefCell<Particle>>]) {
+    for particle in particles.iter() {
+        particle.borrow_mut().acceleration = gravity();
+    }
+}
+
+/// Hashes the particles, checking they are all still somewhere sensible, since a run that blew
+/// up would repeat just as well
+fn hash_settled(particles: &[Rc<RefCell<Particle>>]) -> u64 {
+    for particle in particles.iter() {
+        let position: Vector3 = particle.borrow().get_position();
+        assert!(position.magnitude() < 100.0, "a particle ended up at {:?}", position);
+    }
+    return hash_particles(particles);
+}
+
+/// Runs emitters, damped springs under both integrators, a small block of fluid, a cloth, ropes
+/// held by constraints and by implicit springs, and a pile of grains, which between them use every
+/// solver and every function `precision` provides, and hashes the state of all of them
+fn run() -> u64 {
+    let settings: EmitterSettings = EmitterSettings {
+        rate: 200.0,
+        capacity: 64,
+        ..EmitterSettings::default()
+    };
+    let mut cone: ParticleEmitter = ParticleEmitter::new(
+        EmitterShape::Cone { axis: Vector3::new(0.0, 1.0, 0.0), angle: 0.4 },
+        &settings,
+        7
+    );
+    let mut sphere: ParticleEmitter = ParticleEmitter::new(EmitterShape::Sphere { radius: 0.5 }, &settings, 8);
+
+    let integrators: [ParticleIntegrator; 2] = [ParticleIntegrator::Euler, ParticleIntegrator::SemiImplicitEuler];
+    let spring_ends: Vec<Rc<RefCell<Particle>>> = integrators.iter().map(|&integrator| spring_end(integrator)).collect();
+    let spring: Rc<RefCell<dyn ParticleForceGenerator>> =
+        Rc::new(RefCell::new(ParticleFakeSpring::new(Vector3::default(), 30.0, 0.4)));
+    let mut registry: ParticleForceRegistry = ParticleForceRegistry::new();
+    for end in spring_ends.iter() {
+        registry.add(end, &spring);
+    }
+
+    let mut fluid: SphFluid = SphFluid::new(&SphSettings::default());
+    fluid.fill_box(Vector3::default(), Vector3::new(0.06, 0.06, 0.06), 0.02, 1.0);
+    fluid.add_plane(SphPlane { normal: Vector3::new(0.0, 1.0, 0.0), offset: 0.0, restitution: 0.0 });
+
+    let mut cloth: Cloth = Cloth::grid(
+        Vector3::new(0.0, 2.0, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        Vector3::new(0.0, 0.0, 1.0),
+        5,
+        5,
+        0.05,
+        &ClothSettings { thickness: 0.05, ..ClothSettings::default() }
+    );
+    cloth.pin(cloth.get_index(0, 0));
+    cloth.pin(cloth.get_index(4, 0));
+    cloth.set_gravity(gravity());
+    cloth.set_wind(Vector3::new(0.0, 0.0, 1.5));
+
+    let mut solver: XpbdSolver = XpbdSolver::new(8);
+    let rigid: Rope = Rope::with_constraints(Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.4, 1.0, 0.0), &rope_settings(), &mut solver);
+    solver.add_plane(XpbdPlane { normal: Vector3::new(0.0, 1.0, 0.0), offset: 0.0, friction: 0.3 });
+    fall(rigid.get_particles());
+
+    let mut network: ParticleSpringNetwork = ParticleSpringNetwork::new(50, 1.0e-6);
+    let stretchy: Rope = Rope::with_springs(
+        Vector3::new(0.0, 1.0, 0.0),
+        Vector3::new(1.4, 1.0, 0.0),
+        500.0,
+        1.0,
+        &rope_settings(),
+        &mut network
+    );
+    fall(stretchy.get_particles());
+
+    let material: GranularMaterial = GranularMaterial { youngs_modulus: 1.0e5, ..GranularMaterial::default() };
+    let mut grains: GranularSystem = GranularSystem::new(&material);
+    for i in 0..6 {
+        grains.add_grain(Vector3::new(0.03 * i as f32, 0.05 + 0.09 * i as f32, 0.0), 0.04);
+    }
+    grains.add_plane(GranularPlane { normal: Vector3::new(0.0, 1.0, 0.0), offset: 0.0 });
+    grains.set_gravity(gravity());
+
+    for _ in 0..STEPS {
+        cone.update(DURATION);
+        sphere.update(DURATION);
+        registry.update_forces(DURATION);
+        for (end, &integrator) in spring_ends.iter().zip(integrators.iter()) {
+            end.borrow_mut().integrate_with(integrator, DURATION);
+        }
+        fluid.step(DURATION);
+        cloth.step(DURATION);
+        solver.step(DURATION);
+        network.step(DURATION);
+        grains.step(DURATION);
+    }
+
+    let mut hasher: StateHasher = StateHasher::new();
+    for emitter in [&cone, &sphere] {
+        let alive: Vec<Rc<RefCell<Particle>>> =
+            emitter.get_alive_slots().into_iter().map(|slot| Rc::clone(emitter.get_particle(slot))).collect();
+        hasher.write_u64(hash_settled(&alive));
+    }
+    hasher.write_u64(hash_settled(&spring_ends));
+    hasher.write_u64(hash_settled(fluid.get_particles()));
+    hasher.write_u64(hash_settled(cloth.get_particles()));
+    hasher.write_u64(hash_settled(solver.get_particles()));
+    hasher.write_u64(hash_settled(network.get_particles()));
+    hasher.write_u64(hash_settled(grains.get_particles()));
+    return hasher.finish();
+}
+
+#[test]
+fn runs_repeat_within_a_process() {
+    assert_eq!(run(), run());
+}
+
+#[cfg(feature = "deterministic")]
+#[test]
+fn simulations_hash_to_the_pinned_value() {
+    assert_eq!(run(), 15734589918677510516);
+}